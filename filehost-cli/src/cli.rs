@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:38:17
  * Last edited:
- *   30 Mar 2022, 19:38:19
+ *   19 Oct 2026, 06:46:43
  * Auto updated?
  *   Yes
  *
@@ -12,4 +12,295 @@
  *   Module that handles the command-line interface part of the client.
 **/
 
+use std::path::PathBuf;
 
+use clap::Parser;
+
+use filehost_spc::login::Permissions;
+
+use crate::signing::SignaturePolicy;
+
+
+/***** CONSTANTS *****/
+// Lazy constants
+lazy_static! {
+    /// The standard location of the certificate that we trust the server to present
+    static ref DEFAULT_SERVER_CERT: String = dirs_2::home_dir().expect("Could not get home directory of user").join(".filehost/server.crt").to_str().expect("Could not convert server certificate path to a string").to_string();
+    /// The standard location of the keys of the publishers that we trust
+    static ref DEFAULT_TRUSTED_KEYS: String = dirs_2::home_dir().expect("Could not get home directory of user").join(".filehost/trusted_keys").to_str().expect("Could not convert trusted keys path to a string").to_string();
+}
+
+
+
+
+
+/***** ARGUMENTS *****/
+/// Defines the toplevel command-line interface by using clap's derive API.
+#[derive(Parser)]
+#[clap(author, version, about, long_about = None)]
+pub struct Arguments {
+    /// If given, displays logs messages to stdout/stderr
+    #[clap(long, help = "If given, displays logs messages to stdout and stderr.")]
+    pub debug        : bool,
+
+    /// The address of the server to connect to.
+    #[clap(short, long, default_value = "localhost:8719", help = "The address:port of the FileHost server to connect to.")]
+    pub server       : String,
+    /// The name of the server as it appears in its certificate.
+    #[clap(long, help = "The name of the server as it appears in its certificate. Uses the host part of '--server' if omitted.")]
+    pub server_name  : Option<String>,
+    /// The certificate that we trust the server to present.
+    #[clap(long, default_value = DEFAULT_SERVER_CERT.as_str(), help = "The certificate(s) file that we trust the server to present.")]
+    pub server_cert  : PathBuf,
+    /// The client certificate to log in with.
+    #[clap(long, requires = "key", help = "The certificate file to log in with. If omitted, logs in as the guest user.")]
+    pub cert         : Option<PathBuf>,
+    /// The client key to log in with.
+    #[clap(long, requires = "cert", help = "The private key file that belongs to '--cert'.")]
+    pub key          : Option<PathBuf>,
+    /// The API token to log in with.
+    #[clap(long, env = "FILEHOST_TOKEN", hide_env_values = true, conflicts_with = "cert", help = "The API token to log in with instead of a client certificate.")]
+    pub token        : Option<String>,
+
+    /// The keys of the publishers that we trust.
+    #[clap(long, default_value = DEFAULT_TRUSTED_KEYS.as_str(), help = "The file with the public keys of the publishers whose signed manifests we trust, one (hex) key per line, optionally followed by a name.")]
+    pub trusted_keys : PathBuf,
+    /// What to do with manifests that no trusted publisher signed.
+    #[clap(long, env = "FILEHOST_SIGNATURES", default_value = "warn", help = "What to do when downloading or syncing a version whose manifest is not signed by a trusted publisher: 'require' refuses it, 'warn' installs it with a warning and 'ignore' does not check signatures at all.")]
+    pub signatures   : SignaturePolicy,
+
+    /// The action to take from this point on (subcommand)
+    #[clap(subcommand)]
+    pub action : Action,
+}
+
+
+
+/// Defines the actions / subcommands that can be done with the client.
+#[derive(Parser)]
+pub enum Action {
+    /// Downloads a package version.
+    #[clap(name = "download", about = "Downloads a version of a package, resuming any earlier download that was interrupted.")]
+    Download {
+        /// The package to download.
+        #[clap(help = "The name of the package to download.")]
+        package : String,
+        /// The version to download.
+        #[clap(help = "The version of the package to download, or a channel (e.g., 'latest') that points to it.")]
+        version : String,
+        /// The directory to download to.
+        #[clap(short, long, default_value = ".", help = "The directory to download the package's files to.")]
+        output  : PathBuf,
+        /// The number of times to retry a file.
+        #[clap(short, long, default_value = "5", help = "The number of times to reconnect and resume a file before giving up on it.")]
+        retries : u32,
+    },
+
+    /// Syncs a directory from one package version to another.
+    #[clap(name = "sync", about = "Updates a directory that contains one version of a package to another version, fetching only the files that changed.")]
+    Sync {
+        /// The package to sync.
+        #[clap(help = "The name of the package to sync.")]
+        package : String,
+        /// The version currently in the directory.
+        #[clap(help = "The version of the package that is currently in the directory, or a channel that points to it.")]
+        from    : String,
+        /// The version to sync to.
+        #[clap(help = "The version of the package to sync to, or a channel (e.g., 'latest') that points to it.")]
+        to      : String,
+        /// The directory to sync.
+        #[clap(short, long, default_value = ".", help = "The directory with the package's files to sync.")]
+        output  : PathBuf,
+        /// The number of times to retry a file.
+        #[clap(short, long, default_value = "5", help = "The number of times to reconnect and resume a file before giving up on it.")]
+        retries : u32,
+    },
+
+    /// Publishes a package version.
+    #[clap(name = "publish", about = "Publishes a directory as a new version of a package. The version only becomes visible once all files have been uploaded.")]
+    Publish {
+        /// The package to publish.
+        #[clap(help = "The name of the package to publish.")]
+        package     : String,
+        /// The version to publish.
+        #[clap(help = "The version of the package to publish.")]
+        version     : String,
+        /// The directory to publish.
+        #[clap(short, long, default_value = ".", help = "The directory with the files to publish.")]
+        input       : PathBuf,
+        /// Whether to chunk the files.
+        #[clap(long, help = "If given, splits files into content-defined chunks, so only the parts that changed since an earlier version are stored and uploaded.")]
+        chunked     : bool,
+        /// The transaction to resume.
+        #[clap(short, long, help = "The identifier of an interrupted publish transaction to resume instead of opening a new one.")]
+        transaction : Option<String>,
+        /// The channels to move to the new version.
+        #[clap(short, long, multiple_occurrences = true, help = "A channel to point to the new version once it is published. May be given multiple times.")]
+        channel     : Vec<String>,
+        /// The number of times to retry a file.
+        #[clap(short, long, default_value = "5", help = "The number of times to reconnect and resume a file before giving up on it.")]
+        retries     : u32,
+        /// The key to sign the manifest with.
+        #[clap(long, env = "FILEHOST_SIGN_KEY", help = "The Ed25519 private key (PKCS#8 PEM) to sign the manifest of the new version with, so downloaders can check that its files were not swapped.")]
+        sign_key    : Option<PathBuf>,
+    },
+    /// Resolves a version or channel to a version.
+    #[clap(name = "resolve", about = "Resolves a version or channel of a package to the version it refers to.")]
+    Resolve {
+        /// The package to resolve in.
+        #[clap(help = "The name of the package.")]
+        package : String,
+        /// The name to resolve.
+        #[clap(help = "The version or channel to resolve.")]
+        name    : String,
+    },
+    /// Inspects or moves channels.
+    #[clap(name = "channel", about = "Inspects or moves the channels of a package.")]
+    Channel {
+        /// The channel action to take.
+        #[clap(subcommand)]
+        action : ChannelAction,
+    },
+
+    /// Deletes a package version.
+    #[clap(name = "delete", about = "Deletes a version of a package. Versions that a channel points to cannot be deleted.")]
+    Delete {
+        /// The package to delete from.
+        #[clap(help = "The name of the package.")]
+        package : String,
+        /// The version to delete.
+        #[clap(help = "The version to delete.")]
+        version : String,
+    },
+
+    /// Manages API tokens.
+    #[clap(name = "token", about = "Manages the API tokens with which you can log in without your client certificate (e.g., from a CI job). Requires logging in with a certificate.")]
+    Token {
+        /// The token action to take.
+        #[clap(subcommand)]
+        action : TokenAction,
+    },
+    /// Creates an account with an invite code.
+    #[clap(name = "redeem", about = "Creates your account with an invite code from the server's administrator. Afterwards, log in with '--cert' and '--key'.")]
+    Redeem {
+        /// The invite code.
+        #[clap(help = "The invite code you received (starting with 'fhi_').")]
+        code        : String,
+        /// The username to create.
+        #[clap(help = "The username you want.")]
+        username    : String,
+        /// The certificate to log in with.
+        #[clap(help = "The certificate file you will log in with from now on, or a certificate signing request for the server to sign. Only send the certificate; keep its key to yourself.")]
+        certificate : PathBuf,
+        /// Where to write the issued certificate.
+        #[clap(short, long, help = "Where to write the certificate that the server issues for a certificate signing request. Uses '<certificate>' with a '.crt' extension if omitted.")]
+        output      : Option<PathBuf>,
+    },
+    /// Has the server issue a certificate.
+    #[clap(name = "sign", about = "Has the certificate authority of the server sign a certificate signing request, e.g., for a new device or to renew an expiring certificate. Requires logging in with a certificate.")]
+    Sign {
+        /// The request to sign.
+        #[clap(help = "The certificate signing request file. Its subject is ignored; the certificate is always issued to you.")]
+        csr    : PathBuf,
+        /// Where to write the issued certificate.
+        #[clap(short, long, help = "Where to write the certificate. Uses '<csr>' with a '.crt' extension if omitted.")]
+        output : Option<PathBuf>,
+    },
+
+    /// Aborts a publish transaction.
+    #[clap(name = "abort", about = "Aborts an interrupted publish transaction, discarding everything that was uploaded in it.")]
+    Abort {
+        /// The transaction to abort.
+        #[clap(help = "The identifier of the publish transaction to abort.")]
+        transaction : String,
+    },
+
+    /// Manages the keys that manifests are signed with.
+    #[clap(name = "key", about = "Generates and shows the Ed25519 keys with which publishers sign the manifests of the versions they publish.")]
+    Key {
+        /// The key action to take.
+        #[clap(subcommand)]
+        action : KeyAction,
+    },
+}
+
+
+
+/// Defines the actions that can be done with signing keys.
+#[derive(Parser)]
+pub enum KeyAction {
+    /// Generates a new key.
+    #[clap(name = "generate", about = "Generates a new Ed25519 signing key, writes it to a file and prints its public key, which downloaders should add to their trusted keys.")]
+    Generate {
+        /// Where to write the key.
+        #[clap(help = "The file to write the private key to (in PKCS#8 PEM format). Existing files are never overwritten.")]
+        path : PathBuf,
+    },
+    /// Shows the public key of a key.
+    #[clap(name = "show", about = "Prints the public key of an Ed25519 signing key, in the form that trusted keys files expect.")]
+    Show {
+        /// The key to show.
+        #[clap(help = "The file with the private key (in PKCS#8 PEM format).")]
+        path : PathBuf,
+    },
+}
+
+
+
+/// Defines the actions that can be done with channels.
+#[derive(Parser)]
+pub enum ChannelAction {
+    /// Shows a channel and its history.
+    #[clap(name = "show", about = "Shows which version a channel points to, and how it was moved over time.")]
+    Show {
+        /// The package of the channel.
+        #[clap(help = "The name of the package.")]
+        package : String,
+        /// The channel to show.
+        #[clap(help = "The name of the channel.")]
+        channel : String,
+    },
+    /// Moves a channel to another version.
+    #[clap(name = "set", about = "Points a channel to a version, creating the channel if it does not exist yet.")]
+    Set {
+        /// The package of the channel.
+        #[clap(help = "The name of the package.")]
+        package : String,
+        /// The channel to move.
+        #[clap(help = "The name of the channel.")]
+        channel : String,
+        /// The version to point the channel to.
+        #[clap(help = "The version to point the channel to.")]
+        version : String,
+    },
+}
+
+
+
+/// Defines the actions that can be done with API tokens.
+#[derive(Parser)]
+pub enum TokenAction {
+    /// Creates a token.
+    #[clap(name = "create", about = "Creates an API token and prints it. Store it somewhere safe, since it cannot be shown again.")]
+    Create {
+        /// The permissions of the token.
+        #[clap(help = "The permissions that sessions logged in with the token are limited to: 'none', 'all' or a comma-separated list of 'download', 'upload', 'channel' and 'delete'. Never more than your own.")]
+        permissions : Permissions,
+        /// The number of days the token is valid.
+        #[clap(short, long, default_value = "30", help = "The number of days the token remains valid.")]
+        days        : u64,
+        /// What the token is for.
+        #[clap(short = 'm', long, default_value = "", help = "A description of what the token is used for.")]
+        description : String,
+    },
+    /// Lists the tokens.
+    #[clap(name = "list", about = "Lists your API tokens, with their permissions and when they expire.")]
+    List{},
+    /// Revokes a token.
+    #[clap(name = "revoke", about = "Revokes one of your API tokens, after which it can no longer be used to log in.")]
+    Revoke {
+        /// The token to revoke.
+        #[clap(help = "The identifier of the token (the part between 'fht_' and the '.').")]
+        id : String,
+    },
+}
@@ -0,0 +1,557 @@
+/* CONNECTION.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:26:37
+ * Last edited:
+ *   19 Oct 2026, 05:54:06
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the client side of the (TLS) connection with the server.
+**/
+
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::debug;
+use rustls::{Certificate, ClientConfig, ClientConnection, PrivateKey, RootCertStore, ServerName, StreamOwned};
+use serde::de::DeserializeOwned;
+
+use filehost_spc::channel::Channel;
+use filehost_spc::client_messages::{
+    DATA_CHUNK_SIZE,
+    AuthenticateRequest, BeginPublish, ChannelRequest, Committed, CreateTokenRequest, DeleteRequest, Diff, DiffRequest, DownloadHeader, DownloadRequest, ErrorReply, ManifestRequest,
+    NewToken, Opcode, PublishState, Redeemed, RedeemRequest, Resolved, ResolveRequest, SetChannelRequest, SignedCertificate, SignRequest, TokenRequest, TransactionRequest, UploadComplete,
+    UploadReady, UploadRequest,
+};
+use filehost_spc::frames::{flush, read_frame, write_frame, write_json, Frame};
+use filehost_spc::login::{Permissions, TokenInfo};
+use filehost_spc::manifest::{BlobHash, Manifest};
+
+pub use crate::errors::ConnectionError as Error;
+
+
+/***** CONSTANTS *****/
+/// The time the server may remain silent before we give up on it.
+const SERVER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The time we wait before reconnecting after the connection failed.
+pub const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Loads all certificates from the given PEM file.
+///
+/// # Errors
+/// This function errors if the file could not be read or contains no certificates.
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let mut handle = match File::open(path) {
+        Ok(handle) => BufReader::new(handle),
+        Err(err)   => { return Err(Error::CertOpenError{ path: path.into(), err }); }
+    };
+    let certs: Vec<Certificate> = match rustls_pemfile::certs(&mut handle) {
+        Ok(certs) => certs.into_iter().map(Certificate).collect(),
+        Err(err)  => { return Err(Error::CertParseError{ path: path.into(), err }); }
+    };
+    if certs.is_empty() { return Err(Error::NoCertsFound{ path: path.into() }); }
+    Ok(certs)
+}
+
+/// Loads the first private key from the given PEM file.
+///
+/// # Errors
+/// This function errors if the file could not be read or contains no keys.
+fn load_key(path: &Path) -> Result<PrivateKey, Error> {
+    let mut handle = match File::open(path) {
+        Ok(handle) => BufReader::new(handle),
+        Err(err)   => { return Err(Error::KeyOpenError{ path: path.into(), err }); }
+    };
+    loop { match rustls_pemfile::read_one(&mut handle) {
+        Ok(key)  => match key {
+            Some(rustls_pemfile::Item::RSAKey(key))   => { return Ok(PrivateKey(key)); },
+            Some(rustls_pemfile::Item::PKCS8Key(key)) => { return Ok(PrivateKey(key)); },
+            Some(rustls_pemfile::Item::ECKey(key))    => { return Ok(PrivateKey(key)); },
+            None                                      => { return Err(Error::NoKeysFound{ path: path.into() }); },
+            _                                         => { continue; },
+        },
+        Err(err) => { return Err(Error::KeyParseError{ path: path.into(), err }); }
+    } }
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Collects everything needed to (re)connect to the server.
+#[derive(Clone)]
+pub struct ConnectOptions {
+    /// The address:port of the server.
+    pub address     : String,
+    /// The name of the server as it appears in its certificate.
+    pub server_name : ServerName,
+    /// The TLS configuration to connect with.
+    pub config      : Arc<ClientConfig>,
+    /// The API token to log in with after connecting, if any.
+    pub token       : Option<String>,
+}
+
+impl ConnectOptions {
+    /// Constructor for the ConnectOptions.
+    ///
+    /// # Arguments
+    /// - `address`: The address:port of the server.
+    /// - `server_name`: The name of the server as it appears in its certificate. Uses the host part of the address if omitted.
+    /// - `server_cert`: The file with the certificate(s) we trust the server to present.
+    /// - `identity`: The client certificate and key to log in with. Logs in as the guest user if omitted.
+    /// - `token`: The API token to log in with after connecting. Only makes sense without an `identity`.
+    ///
+    /// # Errors
+    /// This function errors if any of the certificates or keys could not be loaded, or if the server name is illegal.
+    pub fn new(address: impl Into<String>, server_name: Option<&str>, server_cert: &Path, identity: Option<(&Path, &Path)>, token: Option<String>) -> Result<Self, Error> {
+        let address: String = address.into();
+
+        // Resolve the server name
+        let server_name: &str = match server_name {
+            Some(name) => name,
+            None       => address.rsplit_once(':').map(|(host, _)| host).unwrap_or(&address),
+        };
+        let server_name: ServerName = match ServerName::try_from(server_name) {
+            Ok(name) => name,
+            Err(_)   => { return Err(Error::IllegalServerName{ name: server_name.into() }); }
+        };
+
+        // Load the certificates we trust
+        let mut roots: RootCertStore = RootCertStore::empty();
+        for cert in load_certs(server_cert)? {
+            if let Err(err) = roots.add(&cert) { return Err(Error::CertAddError{ path: server_cert.into(), err }); }
+        }
+
+        // Build the config, with or without client certificate
+        let builder = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let config: ClientConfig = match identity {
+            Some((cert, key)) => match builder.with_single_cert(load_certs(cert)?, load_key(key)?) {
+                Ok(config) => config,
+                Err(err)   => { return Err(Error::ConfigError{ err }); }
+            },
+            None => builder.with_no_client_auth(),
+        };
+
+        // Done
+        Ok(Self {
+            address,
+            server_name,
+            config : Arc::new(config),
+            token,
+        })
+    }
+}
+
+
+
+/// An open connection with the server.
+pub struct Connection {
+    /// The TLS stream to the server.
+    stream : StreamOwned<ClientConnection, TcpStream>,
+}
+
+impl Connection {
+    /// Connects to the server.
+    ///
+    /// # Arguments
+    /// - `opts`: The ConnectOptions that describe how to connect.
+    ///
+    /// # Errors
+    /// This function errors if we could not connect or if the TLS handshake failed.
+    pub fn connect(opts: &ConnectOptions) -> Result<Self, Error> {
+        // Open the socket
+        debug!("Connecting to '{}'...", opts.address);
+        let socket: TcpStream = match TcpStream::connect(&opts.address) {
+            Ok(socket) => socket,
+            Err(err)   => { return Err(Error::ConnectError{ addr: opts.address.clone(), err }); }
+        };
+        if let Err(err) = socket.set_read_timeout(Some(SERVER_TIMEOUT)) { return Err(Error::TimeoutSetError{ err }); }
+        if let Err(err) = socket.set_write_timeout(Some(SERVER_TIMEOUT)) { return Err(Error::TimeoutSetError{ err }); }
+
+        // Wrap it in a TLS session and complete the handshake
+        let session: ClientConnection = match ClientConnection::new(opts.config.clone(), opts.server_name.clone()) {
+            Ok(session) => session,
+            Err(err)    => { return Err(Error::SessionCreateError{ err }); }
+        };
+        let mut stream = StreamOwned::new(session, socket);
+        while stream.conn.is_handshaking() {
+            if let Err(err) = stream.conn.complete_io(&mut stream.sock) { return Err(Error::HandshakeError{ err }); }
+        }
+
+        // Log in with the token, if any
+        let mut res = Self {
+            stream,
+        };
+        if let Some(token) = &opts.token {
+            let info: TokenInfo = res.authenticate(token)?;
+            debug!("Logged in as user '{}' with API token '{}' (permissions {})", info.username, info.id, info.permissions);
+        }
+
+        // Done
+        debug!("Connected to '{}'", opts.address);
+        Ok(res)
+    }
+
+
+
+    /// Reads the next frame from the server, checking that it has the expected opcode.
+    ///
+    /// # Errors
+    /// This function errors if we could not read the frame, if the server replied with an error or if it sent a frame with another opcode.
+    fn expect(&mut self, expected: Opcode) -> Result<Frame, Error> {
+        // Read the frame
+        let frame: Frame = match read_frame(&mut self.stream) {
+            Ok(Some(frame)) => frame,
+            Ok(None)        => { return Err(Error::ConnectionClosed); },
+            Err(err)        => { return Err(Error::FrameError{ err }); },
+        };
+
+        // Check its opcode
+        if frame.opcode == u8::from(expected) { return Ok(frame); }
+        if frame.opcode == u8::from(Opcode::Error) {
+            let reply: ErrorReply = frame.parse().map_err(|err| Error::FrameError{ err })?;
+            return Err(Error::ServerError{ reply });
+        }
+        Err(Error::UnexpectedReply{ got: frame.opcode, expected })
+    }
+
+    /// Reads the next frame from the server as a JSON message with the expected opcode.
+    ///
+    /// # Errors
+    /// This function errors if we could not read or parse the frame, if the server replied with an error or if it sent a frame with another opcode.
+    #[inline]
+    fn expect_json<T: DeserializeOwned>(&mut self, expected: Opcode) -> Result<T, Error> {
+        self.expect(expected)?.parse().map_err(|err| Error::FrameError{ err })
+    }
+
+
+
+    /// Asks the server for the manifest of the given package version.
+    ///
+    /// # Arguments
+    /// - `package`: The name of the package.
+    /// - `version`: The version of the package.
+    ///
+    /// # Returns
+    /// The Manifest of that version.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn manifest(&mut self, package: &str, version: &str) -> Result<Manifest, Error> {
+        let req = ManifestRequest{ package: package.into(), version: version.into() };
+        write_json(&mut self.stream, Opcode::GetManifest, &req).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::Manifest)
+    }
+
+    /// Downloads a range of the given blob, writing it to the given writer as it comes in.
+    ///
+    /// # Arguments
+    /// - `hash`: The hash of the blob to download.
+    /// - `offset`: The offset of the first byte to download.
+    /// - `length`: The number of bytes to download. Downloads until the end of the blob if omitted.
+    /// - `writer`: The Write-capable writer to write the data to.
+    ///
+    /// # Returns
+    /// The DownloadHeader the server sent, which describes the range that was downloaded.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server, if it replied with an error or if we could not write to the writer. Note that, in the latter case, some of the range may already have been written.
+    pub fn download<W: Write>(&mut self, hash: &BlobHash, offset: u64, length: Option<u64>, writer: &mut W) -> Result<DownloadHeader, Error> {
+        // Send the request
+        let req = DownloadRequest{ hash: *hash, offset, length };
+        write_json(&mut self.stream, Opcode::Download, &req).map_err(|err| Error::FrameError{ err })?;
+
+        // Read the header
+        let header: DownloadHeader = self.expect_json(Opcode::DownloadHeader)?;
+        if header.offset != offset || length.map(|l| l != header.length).unwrap_or(false) {
+            return Err(Error::UnexpectedRange{ got: (header.offset, header.length), expected: (offset, length) });
+        }
+
+        // Read the data until we have it all
+        let mut received: u64 = 0;
+        while received < header.length {
+            let frame: Frame = self.expect(Opcode::Data)?;
+            if let Err(err) = writer.write_all(&frame.payload) { return Err(Error::DataWriteError{ err }); }
+            received += frame.payload.len() as u64;
+        }
+        if let Err(err) = writer.flush() { return Err(Error::DataWriteError{ err }); }
+
+        // Done
+        Ok(header)
+    }
+
+
+
+    /// Opens a new publish transaction for the version described by the given manifest.
+    ///
+    /// # Returns
+    /// The PublishState of the new transaction, which lists the blobs the server needs.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn begin_publish(&mut self, manifest: Manifest) -> Result<PublishState, Error> {
+        write_json(&mut self.stream, Opcode::BeginPublish, &BeginPublish{ manifest }).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::PublishState)
+    }
+
+    /// Asks the server for the state of an open publish transaction.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn publish_status(&mut self, transaction: &str) -> Result<PublishState, Error> {
+        write_json(&mut self.stream, Opcode::PublishStatus, &TransactionRequest{ transaction: transaction.into() }).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::PublishState)
+    }
+
+    /// Uploads (the rest of) a blob in a publish transaction, reading it from the given reader.
+    ///
+    /// # Arguments
+    /// - `transaction`: The identifier of the transaction.
+    /// - `hash`: The hash of the blob to upload.
+    /// - `offset`: The offset to upload from. Must be the number of bytes the server already has.
+    /// - `reader`: The Read-capable reader that produces the blob from `offset` onwards.
+    ///
+    /// # Returns
+    /// The number of bytes that were uploaded.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server, if it replied with an error (e.g., because the blob did not match its hash) or if we could not read from the reader.
+    pub fn upload<R: Read>(&mut self, transaction: &str, hash: &BlobHash, offset: u64, reader: &mut R) -> Result<u64, Error> {
+        // Send the request
+        let req = UploadRequest{ transaction: transaction.into(), hash: *hash, offset };
+        write_json(&mut self.stream, Opcode::Upload, &req).map_err(|err| Error::FrameError{ err })?;
+
+        // Wait until the server is ready
+        let ready: UploadReady = self.expect_json(Opcode::UploadReady)?;
+        if ready.offset != offset { return Err(Error::UnexpectedRange{ got: (ready.offset, ready.length), expected: (offset, None) }); }
+
+        // Send the data
+        let mut buffer: Vec<u8> = vec![ 0; DATA_CHUNK_SIZE ];
+        let mut remaining: u64 = ready.length;
+        while remaining > 0 {
+            let n_bytes: usize = std::cmp::min(remaining, buffer.len() as u64) as usize;
+            let n_bytes: usize = match reader.read(&mut buffer[..n_bytes]) {
+                Ok(0)       => { return Err(Error::DataReadError{ err: std::io::ErrorKind::UnexpectedEof.into() }); },
+                Ok(n_bytes) => n_bytes,
+                Err(err)    => { return Err(Error::DataReadError{ err }); },
+            };
+            write_frame(&mut self.stream, Opcode::Data, &buffer[..n_bytes]).map_err(|err| Error::FrameError{ err })?;
+            remaining -= n_bytes as u64;
+        }
+        flush(&mut self.stream).map_err(|err| Error::FrameError{ err })?;
+
+        // Wait for the server to verify it
+        let _: UploadComplete = self.expect_json(Opcode::UploadComplete)?;
+        Ok(ready.length)
+    }
+
+    /// Commits a publish transaction, which makes the published version visible.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn commit(&mut self, transaction: &str) -> Result<Committed, Error> {
+        write_json(&mut self.stream, Opcode::Commit, &TransactionRequest{ transaction: transaction.into() }).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::Committed)
+    }
+
+    /// Aborts a publish transaction, which discards everything that was uploaded in it.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn abort(&mut self, transaction: &str) -> Result<(), Error> {
+        write_json(&mut self.stream, Opcode::Abort, &TransactionRequest{ transaction: transaction.into() }).map_err(|err| Error::FrameError{ err })?;
+        let _: TransactionRequest = self.expect_json(Opcode::Aborted)?;
+        Ok(())
+    }
+
+
+
+    /// Asks the server which version the given version or channel name refers to.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn resolve(&mut self, package: &str, name: &str) -> Result<Resolved, Error> {
+        write_json(&mut self.stream, Opcode::Resolve, &ResolveRequest{ package: package.into(), name: name.into() }).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::Resolved)
+    }
+
+    /// Asks the server for the given channel and its history.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn channel(&mut self, package: &str, channel: &str) -> Result<Channel, Error> {
+        write_json(&mut self.stream, Opcode::GetChannel, &ChannelRequest{ package: package.into(), channel: channel.into() }).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::Channel)
+    }
+
+    /// Points the given channel to the given version, creating it if it does not exist yet.
+    ///
+    /// # Returns
+    /// The Channel after the move.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn set_channel(&mut self, package: &str, channel: &str, version: &str) -> Result<Channel, Error> {
+        let req = SetChannelRequest{ package: package.into(), channel: channel.into(), version: version.into() };
+        write_json(&mut self.stream, Opcode::SetChannel, &req).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::Channel)
+    }
+
+    /// Asks the server how two versions (or channels) of the given package differ.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn diff(&mut self, package: &str, from: &str, to: &str) -> Result<Diff, Error> {
+        write_json(&mut self.stream, Opcode::GetDiff, &DiffRequest{ package: package.into(), from: from.into(), to: to.into() }).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::Diff)
+    }
+
+    /// Deletes the given version of the given package.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn delete(&mut self, package: &str, version: &str) -> Result<(), Error> {
+        write_json(&mut self.stream, Opcode::Delete, &DeleteRequest{ package: package.into(), version: version.into() }).map_err(|err| Error::FrameError{ err })?;
+        let _: DeleteRequest = self.expect_json(Opcode::Deleted)?;
+        Ok(())
+    }
+
+
+
+    /// Logs in with the given API token instead of as the guest user.
+    ///
+    /// # Returns
+    /// The TokenInfo that describes the token.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it rejected the token.
+    pub fn authenticate(&mut self, token: &str) -> Result<TokenInfo, Error> {
+        write_json(&mut self.stream, Opcode::Authenticate, &AuthenticateRequest{ token: token.into() }).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::Authenticated)
+    }
+
+    /// Creates a new API token.
+    ///
+    /// # Arguments
+    /// - `permissions`: The permissions that sessions logged in with the token are limited to.
+    /// - `valid_for`: The number of seconds the token remains valid.
+    /// - `description`: What the token is used for.
+    ///
+    /// # Returns
+    /// The NewToken with the full token, which the server cannot give us again.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn create_token(&mut self, permissions: Permissions, valid_for: u64, description: &str) -> Result<NewToken, Error> {
+        write_json(&mut self.stream, Opcode::CreateToken, &CreateTokenRequest{ permissions, valid_for, description: description.into() }).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::TokenCreated)
+    }
+
+    /// Asks the server for our API tokens.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn tokens(&mut self) -> Result<Vec<TokenInfo>, Error> {
+        write_json(&mut self.stream, Opcode::GetTokens, &()).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::Tokens)
+    }
+
+    /// Revokes one of our API tokens.
+    ///
+    /// # Errors
+    /// This function errors if we could not communicate with the server or if it replied with an error.
+    pub fn revoke_token(&mut self, id: &str) -> Result<TokenInfo, Error> {
+        write_json(&mut self.stream, Opcode::RevokeToken, &TokenRequest{ id: id.into() }).map_err(|err| Error::FrameError{ err })?;
+        self.expect_json(Opcode::TokenRevoked)
+    }
+
+
+
+    /// Creates a new user with an invite code. Only works if we are logged in as the guest user.
+    ///
+    /// # Arguments
+    /// - `code`: The invite code.
+    /// - `username`: The name of the new user.
+    /// - `certificate`: The path to the certificate file that the new user will log in with, or to a certificate signing request for the server to sign.
+    /// - `output`: The path to write the certificate to that the server issues if `certificate` is a certificate signing request.
+    ///
+    /// # Returns
+    /// The Redeemed reply that describes the new user.
+    ///
+    /// # Errors
+    /// This function errors if we could not read the certificate, could not communicate with the server, if it replied with an error or if we could not write the issued certificate.
+    pub fn redeem(&mut self, code: &str, username: &str, certificate: &Path, output: &Path) -> Result<Redeemed, Error> {
+        let pem: String = match fs::read_to_string(certificate) {
+            Ok(pem)  => pem,
+            Err(err) => { return Err(Error::CertOpenError{ path: certificate.into(), err }); }
+        };
+        write_json(&mut self.stream, Opcode::Redeem, &RedeemRequest{ code: code.into(), username: username.into(), certificate: pem }).map_err(|err| Error::FrameError{ err })?;
+        let redeemed: Redeemed = self.expect_json(Opcode::Redeemed)?;
+        if let Some(cert) = &redeemed.certificate {
+            if let Err(err) = fs::write(output, cert) { return Err(Error::CertWriteError{ path: output.into(), err }); }
+        }
+        Ok(redeemed)
+    }
+
+    /// Has the certificate authority of the server sign a certificate signing request for us.
+    ///
+    /// # Arguments
+    /// - `csr`: The path to the certificate signing request file.
+    /// - `output`: The path to write the issued certificate to.
+    ///
+    /// # Returns
+    /// The SignedCertificate reply that describes the certificate.
+    ///
+    /// # Errors
+    /// This function errors if we could not read the request, could not communicate with the server, if it replied with an error or if we could not write the issued certificate.
+    pub fn sign_certificate(&mut self, csr: &Path, output: &Path) -> Result<SignedCertificate, Error> {
+        let pem: String = match fs::read_to_string(csr) {
+            Ok(pem)  => pem,
+            Err(err) => { return Err(Error::CsrOpenError{ path: csr.into(), err }); }
+        };
+        write_json(&mut self.stream, Opcode::SignCertificate, &SignRequest{ csr: pem }).map_err(|err| Error::FrameError{ err })?;
+        let signed: SignedCertificate = self.expect_json(Opcode::CertificateSigned)?;
+        if let Err(err) = fs::write(output, &signed.certificate) { return Err(Error::CertWriteError{ path: output.into(), err }); }
+        Ok(signed)
+    }
+}
+
+
+
+/// Returns the current connection, reconnecting first if there is none.
+///
+/// # Errors
+/// This function errors if we had to reconnect but failed to do so.
+pub fn connected<'c>(opts: &ConnectOptions, conn: &'c mut Option<Connection>) -> Result<&'c mut Connection, Error> {
+    if conn.is_none() { *conn = Some(Connection::connect(opts)?); }
+    Ok(conn.as_mut().expect("Connection is None after connecting; this should never happen!"))
+}
+
+/// Collects the paths of the client certificate and key into an identity, if both are given.
+///
+/// # Errors
+/// This function errors if only one of the two is given.
+pub fn identity<'a>(cert: &'a Option<PathBuf>, key: &'a Option<PathBuf>) -> Result<Option<(&'a Path, &'a Path)>, Error> {
+    match (cert, key) {
+        (Some(cert), Some(key)) => Ok(Some((cert, key))),
+        (None, None)            => Ok(None),
+        _                       => Err(Error::IncompleteIdentity),
+    }
+}
@@ -0,0 +1,486 @@
+/* DOWNLOAD.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:26:37
+ * Last edited:
+ *   19 Oct 2026, 07:30:33
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements resumable downloads of package versions, and syncing a
+ *   directory from one version to another.
+**/
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use log::{debug, info, warn};
+
+use filehost_spc::chunking::{chunk_file, MAX_CHUNK_SIZE};
+use filehost_spc::client_messages::{Diff, Resolved};
+use filehost_spc::manifest::{is_valid_entry_path, BlobHash, Manifest, ManifestChunk, ManifestEntry};
+
+pub use crate::errors::DownloadError as Error;
+use crate::connection::{connected, ConnectOptions, Connection, RETRY_DELAY};
+use crate::errors::ConnectionError;
+use crate::signing::{SignaturePolicy, Trust};
+
+
+/***** CONSTANTS *****/
+/// The extension we give to files that are still being downloaded.
+pub const PARTIAL_EXTENSION: &str = "part";
+
+
+
+
+
+/***** HELPER TYPES *****/
+/// Maps the hashes of chunks to a local file and the offset in it where their contents may be found.
+type LocalChunks = HashMap<BlobHash, (PathBuf, u64)>;
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Checks that the given manifest is that of the version we asked for, and that it is signed by a trusted publisher.
+///
+/// A signature alone is not enough, since the server could send another manifest that is signed just as well (e.g., that of an older version).
+///
+/// # Errors
+/// This function errors if the manifest belongs to another package or version, or if it is not signed by a trusted publisher (and the policy says to refuse it).
+fn check_manifest(trust: &Trust, manifest: &Manifest, package: &str, version: &str) -> Result<(), Error> {
+    if manifest.package != package || manifest.version != version {
+        return Err(Error::WrongManifest{ expected: format!("{}@{}", package, version), got: format!("{}@{}", manifest.package, manifest.version) });
+    }
+    trust.check(manifest).map_err(|err| Error::UntrustedManifest{ err })
+}
+
+/// Returns the path of the partial file that belongs to the given target file.
+#[inline]
+fn partial_path(target: &Path) -> PathBuf {
+    let mut name: OsString = target.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(".");
+    name.push(PARTIAL_EXTENSION);
+    target.with_file_name(name)
+}
+
+/// Returns the current size of the given file, or 0 if it does not exist.
+///
+/// # Errors
+/// This function errors if the file exists but we could not read its metadata.
+fn file_size(path: &Path) -> Result<u64, Error> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(err)     => if err.kind() == std::io::ErrorKind::NotFound { Ok(0) } else { Err(Error::MetadataError{ path: path.into(), err }) },
+    }
+}
+
+/// Removes the given file.
+///
+/// # Errors
+/// This function errors if we failed to remove the file.
+#[inline]
+fn remove_file(path: &Path) -> Result<(), Error> {
+    fs::remove_file(path).map_err(|err| Error::FileRemoveError{ path: path.into(), err })
+}
+
+/// Returns whether the given file exists and has the given hash.
+///
+/// # Errors
+/// This function errors if the file exists but we could not hash it.
+fn has_hash(path: &Path, hash: &BlobHash) -> Result<bool, Error> {
+    if !path.is_file() { return Ok(false); }
+    match BlobHash::of_file(path) {
+        Ok(got)  => Ok(got == *hash),
+        Err(err) => Err(Error::HashError{ err }),
+    }
+}
+
+/// Remembers that the given chunks can be found in the given local file.
+fn add_local_chunks(local: &mut LocalChunks, path: &Path, chunks: &[ManifestChunk]) {
+    let mut offset: u64 = 0;
+    for chunk in chunks {
+        local.entry(chunk.hash).or_insert_with(|| (path.into(), offset));
+        offset += chunk.size;
+    }
+}
+
+/// Tries to copy the given chunk from a local file instead of downloading it.
+///
+/// The local contents are checked against the hash of the chunk first, since the file may have changed since we found the chunk in it.
+///
+/// # Arguments
+/// - `local`: The chunks that are available locally.
+/// - `hash`: The hash of the chunk to copy.
+/// - `size`: The size of the chunk to copy.
+/// - `handle`: The file to append the chunk to.
+///
+/// # Returns
+/// Whether the chunk was copied. If not, it should be downloaded instead.
+fn copy_local_chunk(local: &LocalChunks, hash: &BlobHash, size: u64, handle: &mut File) -> bool {
+    let (path, offset): &(PathBuf, u64) = match local.get(hash) {
+        Some(source) => source,
+        None         => { return false; }
+    };
+    if size > MAX_CHUNK_SIZE as u64 { return false; }
+
+    // Read it, and check it's (still) what we're looking for
+    let mut data: Vec<u8> = vec![ 0; size as usize ];
+    if let Err(err) = File::open(path).and_then(|mut source| { source.seek(SeekFrom::Start(*offset))?; source.read_exact(&mut data) }) {
+        debug!("Could not read chunk '{}' from '{}': {}", hash, path.display(), err);
+        return false;
+    }
+    if BlobHash::of_bytes(&data) != *hash {
+        debug!("Chunk '{}' is no longer in '{}'", hash, path.display());
+        return false;
+    }
+
+    // Append it
+    match handle.write_all(&data) {
+        Ok(_)    => { debug!("Copied chunk '{}' from '{}'", hash, path.display()); true },
+        Err(err) => { debug!("Could not write chunk '{}': {}", hash, err); false },
+    }
+}
+
+/// Fetches the blobs of the given entry from the given offset onwards, appending them to the given file.
+///
+/// Chunks that are available locally are copied instead of downloaded.
+///
+/// # Arguments
+/// - `opts`: The options to reconnect to the server with.
+/// - `conn`: The current connection to the server, if any.
+/// - `entry`: The ManifestEntry to fetch the blobs of.
+/// - `local`: The chunks that are available locally.
+/// - `offset`: The offset in the file where the given file ends.
+/// - `handle`: The file to append the blobs to.
+/// - `transferred`: Counts the number of bytes that were actually transferred.
+///
+/// # Errors
+/// This function errors if we failed to download a blob.
+fn fetch_blobs(opts: &ConnectOptions, conn: &mut Option<Connection>, entry: &ManifestEntry, local: &LocalChunks, offset: u64, handle: &mut File, transferred: &mut u64) -> Result<(), ConnectionError> {
+    let mut start: u64 = 0;
+    for (hash, size) in entry.blobs() {
+        let end: u64 = start + size;
+        if end <= offset { start = end; continue; }
+
+        // Copy it if we can
+        let skip: u64 = offset.saturating_sub(start);
+        if skip == 0 && entry.is_chunked() && copy_local_chunk(local, &hash, size, handle) { start = end; continue; }
+
+        // Otherwise, download (the rest of) it
+        if let Err(err) = connected(opts, conn).and_then(|conn| conn.download(&hash, skip, None, handle)).map(|header| { *transferred += header.length; }) {
+            *transferred += handle.metadata().map(|m| m.len()).unwrap_or(start + skip).saturating_sub(start + skip);
+            return Err(err);
+        }
+        start = end;
+    }
+    Ok(())
+}
+
+/// Removes the directories between the given file and the given root, as long as they are empty.
+fn remove_empty_parents(root: &Path, path: &Path) {
+    let mut dir: Option<&Path> = path.parent();
+    while let Some(current) = dir {
+        if current == root || !current.starts_with(root) || fs::remove_dir(current).is_err() { break; }
+        dir = current.parent();
+    }
+}
+
+
+/// Downloads a single manifest entry, resuming from any partial file left by an earlier attempt.
+///
+/// # Arguments
+/// - `opts`: The options to reconnect to the server with.
+/// - `conn`: The current connection to the server, if any. Will be replaced if it breaks.
+/// - `entry`: The ManifestEntry to download.
+/// - `local`: The chunks that are available locally, which are copied instead of downloaded.
+/// - `target`: The path to download the file to.
+/// - `retries`: The number of times we may reconnect before giving up.
+///
+/// # Returns
+/// The number of bytes that were actually transferred.
+///
+/// # Errors
+/// This function errors if we could not download the file or if it does not match its hash, even after retrying.
+fn download_entry(opts: &ConnectOptions, conn: &mut Option<Connection>, entry: &ManifestEntry, local: &LocalChunks, target: &Path, retries: u32) -> Result<u64, Error> {
+    let partial: PathBuf = partial_path(target);
+    let mut transferred: u64 = 0;
+    let mut attempt: u32 = 0;
+    loop {
+        // Find out where we left off, starting over if the partial file somehow grew too large
+        let mut offset: u64 = file_size(&partial)?;
+        if offset > entry.size {
+            warn!("Partial file '{}' is larger than expected; starting over", partial.display());
+            remove_file(&partial)?;
+            offset = 0;
+        }
+
+        // Open the partial file in append mode (which also creates it for empty files)
+        let mut handle: File = match OpenOptions::new().create(true).append(true).open(&partial) {
+            Ok(handle) => handle,
+            Err(err)   => { return Err(Error::FileOpenError{ path: partial, err }); }
+        };
+
+        // Download what's left
+        if offset < entry.size {
+            if offset > 0 { info!("Resuming '{}' at byte {} of {}", entry.path, offset, entry.size); }
+
+            if let Err(err) = fetch_blobs(opts, conn, entry, local, offset, &mut handle, &mut transferred) {
+                // Retry if the connection failed, otherwise give up
+                if !err.is_transient() || attempt >= retries { return Err(Error::ConnectionError{ err }); }
+                attempt += 1;
+                warn!("{} (retrying {}/{})", err, attempt, retries);
+                *conn = None;
+                thread::sleep(RETRY_DELAY);
+                continue;
+            }
+        }
+        drop(handle);
+
+        // Check the hash of what we got
+        let hash: BlobHash = match BlobHash::of_file(&partial) {
+            Ok(hash) => hash,
+            Err(err) => { return Err(Error::HashError{ err }); }
+        };
+        if hash != entry.hash {
+            // Throw the file away, since we cannot know which part is wrong
+            remove_file(&partial)?;
+            if attempt >= retries { return Err(Error::HashMismatch{ path: target.into(), got: hash, expected: entry.hash }); }
+            attempt += 1;
+            warn!("Downloaded file '{}' does not match its hash; starting over (retrying {}/{})", target.display(), attempt, retries);
+            continue;
+        }
+
+        // Move it in place
+        if let Err(err) = fs::rename(&partial, target) { return Err(Error::FileRenameError{ from: partial, to: target.into(), err }); }
+        return Ok(transferred);
+    }
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Downloads the given package version to the given directory.
+///
+/// Files that are already present with the correct hash are skipped, and files that were partially downloaded before are resumed. For chunked files, chunks that are already present in the output directory are copied instead of downloaded.
+///
+/// # Arguments
+/// - `opts`: The options to connect to the server with.
+/// - `trust`: The publishers whose signatures we trust, and what to do if none of them signed the manifest.
+/// - `package`: The name of the package to download.
+/// - `version`: The version of the package to download, or a channel that points to it.
+/// - `output`: The directory to download the package to.
+/// - `retries`: The number of times we may reconnect per file before giving up.
+///
+/// # Returns
+/// The manifest of the downloaded version.
+///
+/// # Errors
+/// This function errors if we could not get the manifest, if the server sent that of another version, if it is not signed by a trusted publisher (and the policy says to refuse it), if any of the files could not be downloaded or if the manifest contains illegal paths.
+pub fn download_package(opts: &ConnectOptions, trust: &Trust, package: &str, version: &str, output: &Path, retries: u32) -> Result<Manifest, Error> {
+    // Find out which version we're talking about, since it may be a channel
+    let mut conn: Connection = Connection::connect(opts).map_err(|err| Error::ConnectionError{ err })?;
+    let resolved: Resolved = conn.resolve(package, version).map_err(|err| Error::ConnectionError{ err })?;
+    if let Some(channel) = &resolved.channel { println!("Channel '{}' points to version '{}'", channel, resolved.version); }
+    let version: &str = &resolved.version;
+
+    // Fetch the manifest first
+    let manifest: Manifest = conn.manifest(package, version).map_err(|err| Error::ConnectionError{ err })?;
+    debug!("Manifest of {}@{} has {} entries ({} bytes)", package, version, manifest.entries.len(), manifest.total_size());
+
+    // Make sure the manifest is what its publisher published before we install anything
+    check_manifest(trust, &manifest, package, version)?;
+
+    // Refuse to do anything if the manifest tries to escape the output directory
+    if let Some(entry) = manifest.entries.iter().find(|e| !is_valid_entry_path(&e.path)) {
+        return Err(Error::IllegalEntryPath{ path: entry.path.clone() });
+    }
+
+    // See which files we already have, and which chunks are in the files we have
+    let mut local: LocalChunks = HashMap::new();
+    let mut todo: Vec<&ManifestEntry> = Vec::with_capacity(manifest.entries.len());
+    for entry in &manifest.entries {
+        let target: PathBuf = output.join(&entry.path);
+        if target.is_file() {
+            let hash: BlobHash = if entry.is_chunked() {
+                let (hash, _, chunks) = chunk_file(&target).map_err(|err| Error::HashError{ err })?;
+                add_local_chunks(&mut local, &target, &chunks);
+                hash
+            } else {
+                BlobHash::of_file(&target).map_err(|err| Error::HashError{ err })?
+            };
+            if hash == entry.hash { debug!("'{}' is up-to-date", entry.path); continue; }
+        }
+        todo.push(entry);
+    }
+
+    // Download the entries one-by-one
+    let mut conn: Option<Connection> = Some(conn);
+    for entry in todo {
+        let target: PathBuf = output.join(&entry.path);
+
+        // Make sure its directory exists
+        if let Some(parent) = target.parent() {
+            if let Err(err) = fs::create_dir_all(parent) { return Err(Error::DirCreateError{ path: parent.into(), err }); }
+        }
+
+        // Download it
+        println!("Downloading '{}' ({} bytes)...", entry.path, entry.size);
+        let transferred: u64 = download_entry(opts, &mut conn, entry, &local, &target, retries)?;
+        debug!("Transferred {} bytes for '{}'", transferred, entry.path);
+    }
+
+    // Done
+    Ok(manifest)
+}
+
+/// Brings a directory that contains one version of a package up-to-date with another version.
+///
+/// Only files that were added or changed between the versions are fetched, and removed files are deleted. Unchanged files are left alone. If the contents of a file (or of a chunk) we need are already somewhere in the directory (e.g., because it was moved), they are copied instead of downloaded.
+///
+/// # Arguments
+/// - `opts`: The options to connect to the server with.
+/// - `trust`: The publishers whose signatures we trust, and what to do if none of them signed the manifests of `from` and `to`.
+/// - `package`: The name of the package to sync.
+/// - `from`: The version (or channel) that is currently in the directory.
+/// - `to`: The version (or channel) to sync the directory to.
+/// - `output`: The directory to sync.
+/// - `retries`: The number of times we may reconnect per file before giving up.
+///
+/// # Returns
+/// The differences between the two versions, as reported by the server.
+///
+/// # Errors
+/// This function errors if we could not get the differences, if the manifest of `from` or `to` is not signed by a trusted publisher (and the policy says to refuse it) or does not match the differences, if any of the files could not be fetched or removed or if the diff contains illegal paths.
+pub fn sync_package(opts: &ConnectOptions, trust: &Trust, package: &str, from: &str, to: &str, output: &Path, retries: u32) -> Result<Diff, Error> {
+    // Ask the server what changed
+    let mut conn: Connection = Connection::connect(opts).map_err(|err| Error::ConnectionError{ err })?;
+    let diff: Diff = conn.diff(package, from, to).map_err(|err| Error::ConnectionError{ err })?;
+    debug!("{}@{} -> {}: {} added, {} removed, {} changed", package, diff.from, diff.to, diff.diff.added.len(), diff.diff.removed.len(), diff.diff.changed.len());
+
+    // Refuse to do anything if the diff tries to escape the output directory
+    let old: Vec<&ManifestEntry> = diff.diff.removed.iter().chain(diff.diff.changed.iter().map(|c| &c.from)).collect();
+    let new: Vec<&ManifestEntry> = diff.diff.added.iter().chain(diff.diff.changed.iter().map(|c| &c.to)).collect();
+    if let Some(entry) = old.iter().chain(new.iter()).find(|e| !is_valid_entry_path(&e.path)) {
+        return Err(Error::IllegalEntryPath{ path: entry.path.clone() });
+    }
+
+    // The diff itself is not signed, so check it against the (signed) manifests of both versions: we only fetch what the version we sync to contains, and only remove what the version we sync from contains and the other does not
+    let removed: Vec<ManifestEntry> = if trust.policy() != SignaturePolicy::Ignore {
+        let from_manifest: Manifest = conn.manifest(package, &diff.from).map_err(|err| Error::ConnectionError{ err })?;
+        let to_manifest: Manifest = conn.manifest(package, &diff.to).map_err(|err| Error::ConnectionError{ err })?;
+        check_manifest(trust, &from_manifest, package, &diff.from)?;
+        check_manifest(trust, &to_manifest, package, &diff.to)?;
+        if let Some(entry) = new.iter().find(|e| !to_manifest.entries.contains(e)) { return Err(Error::DiffMismatch{ path: entry.path.clone() }); }
+
+        // Refuse a diff that removes what the new version still has, and then take what to remove from the manifests instead
+        let kept: HashSet<&str> = to_manifest.entries.iter().map(|e| e.path.as_str()).collect();
+        if let Some(entry) = diff.diff.removed.iter().find(|e| kept.contains(e.path.as_str())) { return Err(Error::DiffMismatch{ path: entry.path.clone() }); }
+        let removed: Vec<ManifestEntry> = from_manifest.entries.into_iter().filter(|e| !kept.contains(e.path.as_str())).collect();
+        if let Some(entry) = removed.iter().find(|e| !is_valid_entry_path(&e.path)) { return Err(Error::IllegalEntryPath{ path: entry.path.clone() }); }
+        removed
+    } else {
+        diff.diff.removed.clone()
+    };
+
+    // Find the files we're about to lose that have contents (or chunks) we need
+    let needed: HashSet<BlobHash> = new.iter().map(|e| e.hash).collect();
+    let mut sources: HashMap<BlobHash, PathBuf> = HashMap::new();
+    let mut local: LocalChunks = HashMap::new();
+    for entry in &old {
+        if (!needed.contains(&entry.hash) || sources.contains_key(&entry.hash)) && !entry.is_chunked() { continue; }
+        let path: PathBuf = output.join(&entry.path);
+        if !has_hash(&path, &entry.hash)? { continue; }
+        add_local_chunks(&mut local, &path, &entry.chunks);
+        if needed.contains(&entry.hash) { sources.entry(entry.hash).or_insert(path); }
+    }
+
+    // Copy those to the partial files of the new entries first, before anything is overwritten
+    let mut todo: Vec<&ManifestEntry> = Vec::with_capacity(new.len());
+    for entry in new {
+        let target: PathBuf = output.join(&entry.path);
+        if has_hash(&target, &entry.hash)? { debug!("'{}' is up-to-date", entry.path); continue; }
+
+        // Make sure its directory exists
+        if let Some(parent) = target.parent() {
+            if let Err(err) = fs::create_dir_all(parent) { return Err(Error::DirCreateError{ path: parent.into(), err }); }
+        }
+
+        // Seed the partial file if we can
+        if let Some(source) = sources.get(&entry.hash) {
+            let partial: PathBuf = partial_path(&target);
+            debug!("Copying '{}' to '{}'", source.display(), partial.display());
+            if let Err(err) = fs::copy(source, &partial) { return Err(Error::FileCopyError{ from: source.clone(), to: partial, err }); }
+        }
+        todo.push(entry);
+    }
+
+    // Fetch whatever's missing
+    let mut conn: Option<Connection> = Some(conn);
+    for entry in todo {
+        let target: PathBuf = output.join(&entry.path);
+        println!("Updating '{}' ({} bytes)...", entry.path, entry.size);
+        let transferred: u64 = download_entry(opts, &mut conn, entry, &local, &target, retries)?;
+        debug!("Transferred {} bytes for '{}'", transferred, entry.path);
+    }
+
+    // Finally, remove the files that are gone, unless they were changed locally
+    for entry in &removed {
+        let target: PathBuf = output.join(&entry.path);
+        if !target.exists() { continue; }
+        if !has_hash(&target, &entry.hash)? {
+            warn!("Not removing '{}', since it was changed locally", target.display());
+            continue;
+        }
+        println!("Removing '{}'...", entry.path);
+        remove_file(&target)?;
+        remove_empty_parents(output, &target);
+    }
+
+    // Done
+    Ok(diff)
+}
+
+
+
+
+
+/***** TESTS *****/
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair as _};
+    use tempfile::NamedTempFile;
+
+    use filehost_spc::manifest::PublicKey;
+
+    use super::*;
+
+    #[test]
+    fn test_check_manifest() {
+        // Sign a manifest with a key we trust
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key: Ed25519KeyPair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let mut public: [u8; 32] = [ 0; 32 ];
+        public.copy_from_slice(key.public_key().as_ref());
+        let keys: NamedTempFile = NamedTempFile::new().unwrap();
+        fs::write(keys.path(), format!("{} publisher\n", PublicKey(public))).unwrap();
+        let trust: Trust = Trust::load(keys.path(), SignaturePolicy::Require).unwrap();
+        let mut manifest = Manifest{ package: "game".into(), version: "1.0.0".into(), entries: vec![], publisher: None, signature: None };
+        manifest.sign(&key);
+
+        // It passes for what it is, but not for anything else
+        assert!(check_manifest(&trust, &manifest, "game", "1.0.0").is_ok());
+        assert!(matches!(check_manifest(&trust, &manifest, "game", "1.0.1"), Err(Error::WrongManifest{ .. })));
+        assert!(matches!(check_manifest(&trust, &manifest, "tools", "1.0.0"), Err(Error::WrongManifest{ .. })));
+    }
+}
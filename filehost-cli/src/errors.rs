@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:34:48
  * Last edited:
- *   30 Mar 2022, 19:35:11
+ *   19 Oct 2026, 07:30:33
  * Auto updated?
  *   Yes
  *
@@ -14,7 +14,262 @@
 
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FResult};
+use std::path::PathBuf;
+
+use filehost_spc::client_messages::{ErrorReply, Opcode};
+use filehost_spc::frames::FrameError;
+use filehost_spc::manifest::{BlobHash, HashError, PublicKey, SignatureError};
 
 
 /***** ERRORS *****/
+/// Errors that relate to the connection with the server.
+#[derive(Debug)]
+pub enum ConnectionError {
+    /// Could not open a certificate file.
+    CertOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not write a certificate file.
+    CertWriteError{ path: PathBuf, err: std::io::Error },
+    /// Could not open a certificate signing request file.
+    CsrOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not parse a certificate file.
+    CertParseError{ path: PathBuf, err: std::io::Error },
+    /// The given certificate file did not contain any certificates.
+    NoCertsFound{ path: PathBuf },
+    /// Could not add a server certificate to the trusted roots.
+    CertAddError{ path: PathBuf, err: webpki::Error },
+    /// Could not open the client key file.
+    KeyOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not parse the client key file.
+    KeyParseError{ path: PathBuf, err: std::io::Error },
+    /// The given key file did not contain any keys.
+    NoKeysFound{ path: PathBuf },
+    /// Only one of the client certificate and key was given.
+    IncompleteIdentity,
+    /// Failed to build the client config.
+    ConfigError{ err: rustls::Error },
+    /// The given server name is not a valid DNS name.
+    IllegalServerName{ name: String },
+
+    /// Could not connect to the server.
+    ConnectError{ addr: String, err: std::io::Error },
+    /// Could not set the timeouts on the socket.
+    TimeoutSetError{ err: std::io::Error },
+    /// Could not create a new TLS session.
+    SessionCreateError{ err: rustls::Error },
+    /// The TLS handshake failed.
+    HandshakeError{ err: std::io::Error },
+
+    /// Could not send or receive a frame.
+    FrameError{ err: FrameError },
+    /// The server closed the connection while we expected a reply.
+    ConnectionClosed,
+    /// The server replied with an error.
+    ServerError{ reply: ErrorReply },
+    /// The server replied with a frame we did not expect.
+    UnexpectedReply{ got: u8, expected: Opcode },
+    /// The server sent a different range than we asked for.
+    UnexpectedRange{ got: (u64, u64), expected: (u64, Option<u64>) },
+    /// Could not write downloaded data.
+    DataWriteError{ err: std::io::Error },
+    /// Could not read data to upload.
+    DataReadError{ err: std::io::Error },
+}
+
+impl ConnectionError {
+    /// Returns whether this error is due to a (possibly temporary) failure of the connection, such that it makes sense to reconnect and try again.
+    #[inline]
+    pub fn is_transient(&self) -> bool {
+        use ConnectionError::*;
+        matches!(self, ConnectError{ .. } | HandshakeError{ .. } | FrameError{ .. } | ConnectionClosed)
+    }
+}
+
+impl Display for ConnectionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use ConnectionError::*;
+        match self {
+            CertOpenError{ path, err }  => write!(f, "Could not open certificate file '{}': {}", path.display(), err),
+            CertWriteError{ path, err } => write!(f, "Could not write certificate file '{}': {}", path.display(), err),
+            CsrOpenError{ path, err }   => write!(f, "Could not open certificate signing request file '{}': {}", path.display(), err),
+            CertParseError{ path, err } => write!(f, "Could not parse certificate file '{}': {}", path.display(), err),
+            NoCertsFound{ path }        => write!(f, "Certificate file '{}' does not contain any certificates", path.display()),
+            CertAddError{ path, err }   => write!(f, "Could not trust server certificate(s) in '{}': {}", path.display(), err),
+            KeyOpenError{ path, err }   => write!(f, "Could not open key file '{}': {}", path.display(), err),
+            KeyParseError{ path, err }  => write!(f, "Could not parse key file '{}': {}", path.display(), err),
+            NoKeysFound{ path }         => write!(f, "Key file '{}' does not contain any keys", path.display()),
+            IncompleteIdentity          => write!(f, "Both a client certificate and a client key must be given to log in"),
+            ConfigError{ err }          => write!(f, "Failed to create client SSL/TLS config: {}", err),
+            IllegalServerName{ name }   => write!(f, "'{}' is not a valid server name", name),
+
+            ConnectError{ addr, err }  => write!(f, "Could not connect to '{}': {}", addr, err),
+            TimeoutSetError{ err }     => write!(f, "Could not set socket timeouts: {}", err),
+            SessionCreateError{ err }  => write!(f, "Could not create TLS session: {}", err),
+            HandshakeError{ err }      => write!(f, "TLS handshake failed: {}", err),
+
+            FrameError{ err }                => write!(f, "{}", err),
+            ConnectionClosed                 => write!(f, "Server closed the connection unexpectedly"),
+            ServerError{ reply }             => write!(f, "Server replied with an error: {}", reply),
+            UnexpectedReply{ got, expected } => write!(f, "Server replied with opcode {} instead of {}", got, expected),
+            UnexpectedRange{ got, expected } => write!(f, "Server sent range (offset {}, length {}) instead of (offset {}, length {:?})", got.0, got.1, expected.0, expected.1),
+            DataWriteError{ err }            => write!(f, "Could not write downloaded data: {}", err),
+            DataReadError{ err }             => write!(f, "Could not read data to upload: {}", err),
+        }
+    }
+}
+
+impl Error for ConnectionError {}
+
+
+
+/// Errors that relate to downloading packages.
+#[derive(Debug)]
+pub enum DownloadError {
+    /// Failed to talk to the server.
+    ConnectionError{ err: ConnectionError },
+    /// The manifest contains a path that would escape the output directory.
+    IllegalEntryPath{ path: String },
+    /// Could not create a directory.
+    DirCreateError{ path: PathBuf, err: std::io::Error },
+    /// Could not hash an (already or partially) downloaded file.
+    HashError{ err: HashError },
+    /// Could not open a (partially) downloaded file.
+    FileOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not get the metadata of a (partially) downloaded file.
+    MetadataError{ path: PathBuf, err: std::io::Error },
+    /// Could not remove a (partially) downloaded file.
+    FileRemoveError{ path: PathBuf, err: std::io::Error },
+    /// Could not move a downloaded file to its final location.
+    FileRenameError{ from: PathBuf, to: PathBuf, err: std::io::Error },
+    /// Could not copy a local file that has the contents of a file we need.
+    FileCopyError{ from: PathBuf, to: PathBuf, err: std::io::Error },
+    /// The downloaded file does not match the hash in the manifest.
+    HashMismatch{ path: PathBuf, got: BlobHash, expected: BlobHash },
+    /// The manifest is not signed by a trusted publisher.
+    UntrustedManifest{ err: SigningError },
+    /// The server sent the manifest of another package or version than the one we asked for (both as `package@version`).
+    WrongManifest{ expected: String, got: String },
+    /// The server sent a file to sync to that is not in the manifest of the version, or wants us to remove a file that the version still has.
+    DiffMismatch{ path: String },
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use DownloadError::*;
+        match self {
+            ConnectionError{ err }              => write!(f, "{}", err),
+            IllegalEntryPath{ path }            => write!(f, "Manifest contains illegal path '{}'", path),
+            DirCreateError{ path, err }         => write!(f, "Could not create directory '{}': {}", path.display(), err),
+            HashError{ err }                    => write!(f, "{}", err),
+            FileOpenError{ path, err }          => write!(f, "Could not open file '{}': {}", path.display(), err),
+            MetadataError{ path, err }          => write!(f, "Could not get metadata of file '{}': {}", path.display(), err),
+            FileRemoveError{ path, err }        => write!(f, "Could not remove file '{}': {}", path.display(), err),
+            FileRenameError{ from, to, err }    => write!(f, "Could not move '{}' to '{}': {}", from.display(), to.display(), err),
+            FileCopyError{ from, to, err }      => write!(f, "Could not copy '{}' to '{}': {}", from.display(), to.display(), err),
+            HashMismatch{ path, got, expected } => write!(f, "Downloaded file '{}' has hash '{}', but the manifest says '{}'", path.display(), got, expected),
+            UntrustedManifest{ err }            => write!(f, "{}", err),
+            WrongManifest{ expected, got }      => write!(f, "Server sent the manifest of {} instead of {}", got, expected),
+            DiffMismatch{ path }                => write!(f, "Server wants to sync '{}' in a way that the signed manifests do not agree with", path),
+        }
+    }
+}
+
+impl Error for DownloadError {}
+
+
+
+/// Errors that relate to publishing packages.
+#[derive(Debug)]
+pub enum PublishError {
+    /// Failed to talk to the server.
+    ConnectionError{ err: ConnectionError },
+    /// Could not read a directory to publish.
+    DirReadError{ path: PathBuf, err: std::io::Error },
+    /// Could not get the metadata of a file to publish.
+    MetadataError{ path: PathBuf, err: std::io::Error },
+    /// A file to publish has a name that cannot be sent to the server.
+    IllegalFileName{ path: PathBuf },
+    /// Could not hash a file to publish.
+    HashError{ err: HashError },
+    /// Could not open a file to publish.
+    FileOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not seek in a file to publish.
+    FileSeekError{ path: PathBuf, err: std::io::Error },
+    /// The server needs a blob that is not in the directory we publish.
+    UnknownBlob{ hash: BlobHash },
+    /// The transaction we resume publishes another version than we were asked to.
+    TransactionMismatch{ id: String, package: String, version: String },
+}
+
+impl Display for PublishError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use PublishError::*;
+        match self {
+            ConnectionError{ err }                      => write!(f, "{}", err),
+            DirReadError{ path, err }                   => write!(f, "Could not read directory '{}': {}", path.display(), err),
+            MetadataError{ path, err }                  => write!(f, "Could not get metadata of '{}': {}", path.display(), err),
+            IllegalFileName{ path }                     => write!(f, "'{}' is not a legal path for a package file", path.display()),
+            HashError{ err }                            => write!(f, "{}", err),
+            FileOpenError{ path, err }                  => write!(f, "Could not open file '{}': {}", path.display(), err),
+            FileSeekError{ path, err }                  => write!(f, "Could not seek in file '{}': {}", path.display(), err),
+            UnknownBlob{ hash }                         => write!(f, "Server needs blob '{}', but no file to publish has that hash (did the files change?)", hash),
+            TransactionMismatch{ id, package, version } => write!(f, "Transaction '{}' publishes {}@{} instead", id, package, version),
+        }
+    }
+}
+
+impl Error for PublishError {}
+
+
+
+/// Errors that relate to signing manifests and checking their signatures.
+#[derive(Debug)]
+pub enum SigningError {
+    /// Could not open the signing key file.
+    KeyOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not parse the signing key file.
+    KeyParseError{ path: PathBuf, err: std::io::Error },
+    /// The given key file did not contain a PKCS#8 key.
+    NoKeyFound{ path: PathBuf },
+    /// The key in the given file is not an Ed25519 key.
+    KeyRejected{ path: PathBuf, err: ring::error::KeyRejected },
+    /// Could not generate a new key.
+    KeyGenerateError,
+    /// Could not write a new key file.
+    KeyWriteError{ path: PathBuf, err: std::io::Error },
+    /// Could not read the file with trusted keys.
+    TrustedKeysReadError{ path: PathBuf, err: std::io::Error },
+    /// A line in the file with trusted keys does not hold a valid key.
+    IllegalTrustedKey{ path: PathBuf, line: usize, err: SignatureError },
+    /// The given signature policy does not exist.
+    UnknownPolicy{ raw: String },
+
+    /// The manifest is not signed.
+    Unsigned{ package: String, version: String },
+    /// The manifest is signed, but its signature does not match it.
+    InvalidSignature{ package: String, version: String, err: SignatureError },
+    /// The manifest is signed with a key that we do not trust.
+    UntrustedKey{ package: String, version: String, key: PublicKey },
+}
+
+impl Display for SigningError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use SigningError::*;
+        match self {
+            KeyOpenError{ path, err }            => write!(f, "Could not open signing key file '{}': {}", path.display(), err),
+            KeyParseError{ path, err }           => write!(f, "Could not parse signing key file '{}': {}", path.display(), err),
+            NoKeyFound{ path }                   => write!(f, "Signing key file '{}' does not contain a PKCS#8 key", path.display()),
+            KeyRejected{ path, err }             => write!(f, "Key in '{}' is not a valid Ed25519 key: {}", path.display(), err),
+            KeyGenerateError                     => write!(f, "Could not generate a new Ed25519 key"),
+            KeyWriteError{ path, err }           => write!(f, "Could not write signing key file '{}': {}", path.display(), err),
+            TrustedKeysReadError{ path, err }    => write!(f, "Could not read trusted keys file '{}': {}", path.display(), err),
+            IllegalTrustedKey{ path, line, err } => write!(f, "Line {} of trusted keys file '{}' is not valid: {}", line, path.display(), err),
+            UnknownPolicy{ raw }                 => write!(f, "Unknown signature policy '{}' (options are: require, warn, ignore)", raw),
+
+            Unsigned{ package, version }              => write!(f, "Manifest of {}@{} is not signed", package, version),
+            InvalidSignature{ package, version, err } => write!(f, "Manifest of {}@{} was changed after it was signed: {}", package, version, err),
+            UntrustedKey{ package, version, key }     => write!(f, "Manifest of {}@{} is signed with untrusted key '{}'", package, version, key),
+        }
+    }
+}
 
+impl Error for SigningError {}
@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:31:58
  * Last edited:
- *   30 Mar 2022, 19:38:42
+ *   19 Oct 2026, 06:46:43
  * Auto updated?
  *   Yes
  *
@@ -12,7 +12,18 @@
  *   Entrypoint to the library part of the FileHost client.
 **/
 
+// Use the macros from some external crates
+#[macro_use] extern crate lazy_static;
+
 /// Module that collects the errors in the crate.
 pub mod errors;
 /// Module that handles the Command-Line Interface parsing.
 pub mod cli;
+/// Module that handles the connection with the server.
+pub mod connection;
+/// Module that downloads packages.
+pub mod download;
+/// Module that publishes package versions.
+pub mod publish;
+/// Module that signs manifests and checks their signatures.
+pub mod signing;
@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:32:25
  * Last edited:
- *   30 Mar 2022, 19:37:40
+ *   19 Oct 2026, 06:46:43
  * Auto updated?
  *   Yes
  *
@@ -12,8 +12,237 @@
  *   Entrypoint to the client executable.
 **/
 
+use std::path::PathBuf;
+
+use clap::Parser;
+use log::{error, info, LevelFilter};
+use simplelog::{ColorChoice, TermLogger, TerminalMode};
+
+use filehost_cli::cli::{Action, Arguments, ChannelAction, KeyAction, TokenAction};
+use filehost_cli::connection::{identity, ConnectOptions, Connection};
+use filehost_cli::download::{download_package, sync_package};
+use filehost_cli::publish::{abort_publish, build_manifest, publish_package};
+use filehost_cli::signing::{generate_key, load_key, show_key, Trust};
+
 
 /***** ENTRYPOINT *****/
 fn main() {
-    println!("Hello, world!");
+    // Read the CLI
+    let args = Arguments::parse();
+
+    // Setup the logger
+    TermLogger::init(if args.debug { LevelFilter::Debug } else { LevelFilter::Warn }, Default::default(), TerminalMode::Mixed, ColorChoice::Auto)
+        .unwrap_or_else(|err| panic!("Could not create logger: {}", err));
+    info!("Initializing FileHost client v{}", env!("CARGO_PKG_VERSION"));
+
+    // Managing signing keys does not involve the server
+    if let Action::Key{ action } = &args.action {
+        match action {
+            KeyAction::Generate{ path } => match generate_key(path) {
+                Ok(key)  => {
+                    println!("Wrote new signing key to '{}'; keep it secret", path.display());
+                    println!("Its public key, which downloaders should add to their trusted keys, is:");
+                    println!();
+                    println!("{}", key);
+                },
+                Err(err) => { error!("{}", err); std::process::exit(1); }
+            },
+            KeyAction::Show{ path } => match show_key(path) {
+                Ok(key)  => { println!("{}", key); },
+                Err(err) => { error!("{}", err); std::process::exit(1); }
+            },
+        }
+        return;
+    }
+
+    // Prepare the connection settings
+    let opts: ConnectOptions = match identity(&args.cert, &args.key).and_then(|identity| ConnectOptions::new(&args.server, args.server_name.as_deref(), &args.server_cert, identity, args.token.clone())) {
+        Ok(opts) => opts,
+        Err(err) => { error!("{}", err); std::process::exit(1); }
+    };
+    let trust: Trust = match Trust::load(&args.trusted_keys, args.signatures) {
+        Ok(trust) => trust,
+        Err(err)  => { error!("{}", err); std::process::exit(1); }
+    };
+
+
+
+    // Switch on the action
+    match args.action {
+        Action::Download{ package, version, output, retries } => {
+            info!("Downloading {}@{} to '{}'...", package, version, output.display());
+
+            // Download the files
+            match download_package(&opts, &trust, &package, &version, &output, retries) {
+                Ok(manifest) => { println!("Downloaded {}@{} ({} files, {} bytes)", manifest.package, manifest.version, manifest.entries.len(), manifest.total_size()); },
+                Err(err)     => { error!("{}", err); std::process::exit(1); }
+            }
+        },
+
+        Action::Sync{ package, from, to, output, retries } => {
+            info!("Syncing '{}' from {}@{} to {}@{}...", output.display(), package, from, package, to);
+
+            // Fetch what changed
+            match sync_package(&opts, &trust, &package, &from, &to, &output, retries) {
+                Ok(diff) => { println!("Synced {}@{} to {} ({} added, {} removed, {} changed)", diff.package, diff.from, diff.to, diff.diff.added.len(), diff.diff.removed.len(), diff.diff.changed.len()); },
+                Err(err) => { error!("{}", err); std::process::exit(1); }
+            }
+        },
+
+        Action::Publish{ package, version, input, chunked, transaction, channel, retries, sign_key } => {
+            info!("Publishing '{}' as {}@{}...", input.display(), package, version);
+
+            // Find out what we're publishing
+            let (mut manifest, sources) = match build_manifest(&package, &version, &input, chunked) {
+                Ok(res)  => res,
+                Err(err) => { error!("{}", err); std::process::exit(1); }
+            };
+
+            // Sign it, if we're asked to
+            if let Some(path) = sign_key {
+                match load_key(&path) {
+                    Ok(key)  => { manifest.sign(&key); },
+                    Err(err) => { error!("{}", err); std::process::exit(1); }
+                }
+            }
+
+            // Upload the files & commit
+            match publish_package(&opts, manifest, &sources, transaction.as_deref(), &channel, retries) {
+                Ok(committed) => { println!("Published {}@{}", committed.package, committed.version); },
+                Err(err)      => { error!("{}", err); std::process::exit(1); }
+            }
+        },
+
+        Action::Resolve{ package, name } => {
+            info!("Resolving {}@{}...", package, name);
+
+            // Ask the server
+            match Connection::connect(&opts).and_then(|mut conn| conn.resolve(&package, &name)) {
+                Ok(resolved) => match resolved.channel {
+                    Some(channel) => { println!("{}@{} (via channel '{}')", resolved.package, resolved.version, channel); },
+                    None          => { println!("{}@{}", resolved.package, resolved.version); },
+                },
+                Err(err) => { error!("{}", err); std::process::exit(1); }
+            }
+        },
+
+        Action::Channel{ action } => match action {
+            ChannelAction::Show{ package, channel } => {
+                info!("Fetching channel '{}' of package '{}'...", channel, package);
+
+                // Ask the server
+                let channel = match Connection::connect(&opts).and_then(|mut conn| conn.channel(&package, &channel)) {
+                    Ok(channel) => channel,
+                    Err(err)    => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Show it
+                println!("Channel '{}' of package '{}' points to version '{}'", channel.name, channel.package, channel.version);
+                println!();
+                println!("History:");
+                for change in &channel.history {
+                    println!(" - {}: {} -> {} (by '{}')", change.time, change.from.as_deref().unwrap_or("<new>"), change.to, change.by);
+                }
+            },
+
+            ChannelAction::Set{ package, channel, version } => {
+                info!("Pointing channel '{}' of package '{}' to version '{}'...", channel, package, version);
+
+                match Connection::connect(&opts).and_then(|mut conn| conn.set_channel(&package, &channel, &version)) {
+                    Ok(channel) => { println!("Channel '{}' now points to version '{}'", channel.name, channel.version); },
+                    Err(err)    => { error!("{}", err); std::process::exit(1); }
+                }
+            },
+        },
+
+        Action::Delete{ package, version } => {
+            info!("Deleting {}@{}...", package, version);
+
+            match Connection::connect(&opts).and_then(|mut conn| conn.delete(&package, &version)) {
+                Ok(_)    => { println!("Deleted {}@{}", package, version); },
+                Err(err) => { error!("{}", err); std::process::exit(1); }
+            }
+        },
+
+        Action::Token{ action } => match action {
+            TokenAction::Create{ permissions, days, description } => {
+                info!("Creating API token with permissions {} for {} day(s)...", permissions, days);
+
+                let token = match Connection::connect(&opts).and_then(|mut conn| conn.create_token(permissions, days.saturating_mul(24 * 60 * 60), &description)) {
+                    Ok(token) => token,
+                    Err(err)  => { error!("{}", err); std::process::exit(1); }
+                };
+                println!("Created API token '{}' with permissions {}, valid until {}", token.info.id, token.info.permissions, token.info.expires);
+                println!("Store it somewhere safe; it cannot be shown again:");
+                println!();
+                println!("{}", token.token);
+            },
+
+            TokenAction::List{} => {
+                info!("Fetching API tokens...");
+
+                let tokens = match Connection::connect(&opts).and_then(|mut conn| conn.tokens()) {
+                    Ok(tokens) => tokens,
+                    Err(err)   => { error!("{}", err); std::process::exit(1); }
+                };
+                if tokens.is_empty() { println!("No API tokens"); }
+                for token in tokens {
+                    println!("Token '{}': permissions {}, created {}, expires {}{}", token.id, token.permissions, token.created, token.expires, if token.description.is_empty() { String::new() } else { format!(" ({})", token.description) });
+                }
+            },
+
+            TokenAction::Revoke{ id } => {
+                info!("Revoking API token '{}'...", id);
+
+                match Connection::connect(&opts).and_then(|mut conn| conn.revoke_token(&id)) {
+                    Ok(token) => { println!("Revoked API token '{}'", token.id); },
+                    Err(err)  => { error!("{}", err); std::process::exit(1); }
+                }
+            },
+        },
+
+        Action::Redeem{ code, username, certificate, output } => {
+            info!("Redeeming invite code as user '{}'...", username);
+
+            let output: PathBuf = output.unwrap_or_else(|| certificate.with_extension("crt"));
+            let user = match Connection::connect(&opts).and_then(|mut conn| conn.redeem(&code, &username, &certificate, &output)) {
+                Ok(user) => user,
+                Err(err) => { error!("{}", err); std::process::exit(1); }
+            };
+            println!("Welcome, {}! Your account has permissions {}{}", user.username, user.permissions, if user.groups.is_empty() { String::new() } else { format!(" and is a member of {}", user.groups.join(", ")) });
+            if user.certificate.is_some() {
+                println!("Wrote the certificate that the server issued to you to '{}'", output.display());
+                println!("From now on, log in with '--cert {}' and the '--key' of your certificate signing request", output.display());
+            } else {
+                println!("From now on, log in with '--cert {}' and the '--key' that belongs to it", certificate.display());
+            }
+        },
+
+        Action::Sign{ csr, output } => {
+            info!("Requesting certificate for '{}'...", csr.display());
+
+            let output: PathBuf = output.unwrap_or_else(|| csr.with_extension("crt"));
+            match Connection::connect(&opts).and_then(|mut conn| conn.sign_certificate(&csr, &output)) {
+                Ok(signed) => { println!("Wrote certificate to '{}', valid until {}", output.display(), signed.expires); },
+                Err(err)   => { error!("{}", err); std::process::exit(1); }
+            }
+        },
+
+        Action::Abort{ transaction } => {
+            info!("Aborting transaction '{}'...", transaction);
+
+            match abort_publish(&opts, &transaction) {
+                Ok(_)    => { println!("Aborted transaction '{}'", transaction); },
+                Err(err) => { error!("{}", err); std::process::exit(1); }
+            }
+        },
+
+        // Already handled before connecting
+        Action::Key{ .. } => {},
+    }
+
+
+
+    // Done!
+    info!("Done.");
 }
@@ -0,0 +1,273 @@
+/* PUBLISH.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:37:44
+ * Last edited:
+ *   19 Oct 2026, 06:46:43
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements publishing package versions in a transaction, which may be
+ *   resumed if it is interrupted.
+**/
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use log::{debug, info, warn};
+
+use filehost_spc::chunking::chunk_file;
+use filehost_spc::client_messages::{Committed, PublishState};
+use filehost_spc::manifest::{is_valid_entry_path, BlobHash, Manifest, ManifestChunk, ManifestEntry};
+
+pub use crate::errors::PublishError as Error;
+use crate::connection::{connected, ConnectOptions, Connection, RETRY_DELAY};
+
+
+/***** TYPES *****/
+/// Defines where the contents of a blob can be found locally.
+#[derive(Clone, Debug)]
+pub struct BlobSource {
+    /// The file that contains the blob.
+    pub path   : PathBuf,
+    /// The offset of the blob in that file. Only non-zero for chunks.
+    pub offset : u64,
+}
+
+
+/***** HELPER FUNCTIONS *****/
+/// Recursively collects the files in the given directory as manifest entries.
+///
+/// # Arguments
+/// - `root`: The directory that is being published.
+/// - `dir`: The directory to collect the files of.
+/// - `entries`: The list of entries to add the files to.
+/// - `chunked`: Whether to split the files into content-defined chunks.
+/// - `entries`: The list of entries to add the files to.
+/// - `sources`: Maps the hashes of the blobs to where they live, so we can upload them later.
+///
+/// # Errors
+/// This function errors if we failed to read a directory or to hash a file, or if a file has a name that is not allowed in a package.
+fn collect_entries(root: &Path, dir: &Path, chunked: bool, entries: &mut Vec<ManifestEntry>, sources: &mut HashMap<BlobHash, BlobSource>) -> Result<(), Error> {
+    // Read the directory in a predictable order
+    let mut paths: Vec<PathBuf> = Vec::new();
+    let iter = match fs::read_dir(dir) {
+        Ok(iter) => iter,
+        Err(err) => { return Err(Error::DirReadError{ path: dir.into(), err }); }
+    };
+    for entry in iter {
+        match entry {
+            Ok(entry) => { paths.push(entry.path()); },
+            Err(err)  => { return Err(Error::DirReadError{ path: dir.into(), err }); }
+        }
+    }
+    paths.sort();
+
+    // Recurse into directories and add files
+    for path in paths {
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(err)     => { return Err(Error::MetadataError{ path, err }); }
+        };
+        if metadata.is_dir() {
+            collect_entries(root, &path, chunked, entries, sources)?;
+        } else if metadata.is_file() {
+            // Convert the path to the '/'-separated form in the manifest
+            let relative: Vec<&str> = match path.strip_prefix(root).ok().map(|p| p.iter().map(|c| c.to_str()).collect::<Option<Vec<&str>>>()) {
+                Some(Some(relative)) => relative,
+                _                    => { return Err(Error::IllegalFileName{ path }); }
+            };
+            let relative: String = relative.join("/");
+            if !is_valid_entry_path(&relative) { return Err(Error::IllegalFileName{ path }); }
+
+            // Hash it, chunking it along the way if told to
+            let (hash, size, chunks): (BlobHash, u64, Vec<ManifestChunk>) = match if chunked {
+                chunk_file(&path)
+            } else {
+                BlobHash::of_file(&path).map(|hash| (hash, metadata.len(), vec![]))
+            } {
+                Ok(res)  => res,
+                Err(err) => { return Err(Error::HashError{ err }); }
+            };
+            debug!("'{}' has hash '{}' ({} chunks)", relative, hash, chunks.len());
+
+            // Remember where its blobs live
+            let entry = ManifestEntry{ path: relative, hash, size, chunks };
+            let mut offset: u64 = 0;
+            for (hash, size) in entry.blobs() {
+                sources.entry(hash).or_insert_with(|| BlobSource{ path: path.clone(), offset });
+                offset += size;
+            }
+            entries.push(entry);
+        } else {
+            warn!("Skipping '{}', since it is neither a file nor a directory", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Uploads (the rest of) a single blob, resuming where the server left off if the connection fails.
+///
+/// # Arguments
+/// - `opts`: The options to reconnect to the server with.
+/// - `conn`: The current connection to the server, if any. Will be replaced if it breaks.
+/// - `transaction`: The identifier of the transaction to upload in.
+/// - `hash`: The hash of the blob to upload.
+/// - `offset`: The number of bytes the server already has.
+/// - `source`: Where the blob can be found locally.
+/// - `retries`: The number of times we may reconnect before giving up.
+///
+/// # Returns
+/// The number of bytes that were actually transferred.
+///
+/// # Errors
+/// This function errors if we could not upload the blob, even after retrying.
+fn upload_blob(opts: &ConnectOptions, conn: &mut Option<Connection>, transaction: &str, hash: &BlobHash, mut offset: u64, source: &BlobSource, retries: u32) -> Result<u64, Error> {
+    let mut transferred: u64 = 0;
+    let mut attempt: u32 = 0;
+    loop {
+        // Open the file at the right position
+        let mut handle: File = match File::open(&source.path) {
+            Ok(handle) => handle,
+            Err(err)   => { return Err(Error::FileOpenError{ path: source.path.clone(), err }); }
+        };
+        if let Err(err) = handle.seek(SeekFrom::Start(source.offset + offset)) { return Err(Error::FileSeekError{ path: source.path.clone(), err }); }
+
+        // Upload the rest
+        match connected(opts, conn).and_then(|conn| conn.upload(transaction, hash, offset, &mut handle)) {
+            Ok(n_bytes) => { return Ok(transferred + n_bytes); },
+            Err(err)    => {
+                // Retry if the connection failed, otherwise give up
+                if !err.is_transient() || attempt >= retries { return Err(Error::ConnectionError{ err }); }
+                attempt += 1;
+                warn!("{} (retrying {}/{})", err, attempt, retries);
+                *conn = None;
+                thread::sleep(RETRY_DELAY);
+            },
+        }
+
+        // Ask the server how much it got
+        let state: PublishState = match connected(opts, conn).and_then(|conn| conn.publish_status(transaction)) {
+            Ok(state) => state,
+            Err(err)  => {
+                if !err.is_transient() { return Err(Error::ConnectionError{ err }); }
+                *conn = None;
+                continue;
+            },
+        };
+        match state.needed.iter().find(|n| &n.hash == hash) {
+            Some(needed) => {
+                info!("Resuming blob '{}' at byte {} of {}", hash, needed.offset, needed.size);
+                transferred += needed.offset.saturating_sub(offset);
+                offset = needed.offset;
+            },
+            None => { return Ok(transferred); },
+        }
+    }
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Builds the manifest of the given directory.
+///
+/// # Arguments
+/// - `package`: The name of the package to build the manifest for.
+/// - `version`: The version of the package to build the manifest for.
+/// - `dir`: The directory with the package's files.
+/// - `chunked`: Whether to split the files into content-defined chunks, so that versions that differ by a few bytes share most of their blobs.
+///
+/// # Returns
+/// The Manifest of the directory, and a map from the blobs in it to where they can be found.
+///
+/// # Errors
+/// This function errors if we failed to read or hash the files in the directory, or if any of them has an illegal name.
+pub fn build_manifest(package: &str, version: &str, dir: &Path, chunked: bool) -> Result<(Manifest, HashMap<BlobHash, BlobSource>), Error> {
+    let mut entries: Vec<ManifestEntry> = Vec::new();
+    let mut sources: HashMap<BlobHash, BlobSource> = HashMap::new();
+    collect_entries(dir, dir, chunked, &mut entries, &mut sources)?;
+    Ok((Manifest{ package: package.into(), version: version.into(), entries, publisher: None, signature: None }, sources))
+}
+
+
+
+/// Publishes a directory as a new package version.
+///
+/// The files are uploaded in a transaction, so the version only becomes visible once all of them arrived intact. If the upload is interrupted, it may be resumed by passing the transaction identifier that is printed when it is opened.
+///
+/// Only the blobs that the server does not have yet are uploaded, which for chunked files means only the chunks that changed.
+///
+/// # Arguments
+/// - `opts`: The options to connect to the server with.
+/// - `manifest`: The Manifest of the directory to publish, as built by `build_manifest()`.
+/// - `sources`: The map from blobs to where they can be found, as built by `build_manifest()`.
+/// - `transaction`: The identifier of an earlier transaction to resume, if any.
+/// - `channels`: The channels to point to the new version once it's published.
+/// - `retries`: The number of times we may reconnect per file before giving up.
+///
+/// # Returns
+/// The Committed reply of the server.
+///
+/// # Errors
+/// This function errors if we could not read the files, if the server refused the transaction or if any of the files could not be uploaded.
+pub fn publish_package(opts: &ConnectOptions, manifest: Manifest, sources: &HashMap<BlobHash, BlobSource>, transaction: Option<&str>, channels: &[String], retries: u32) -> Result<Committed, Error> {
+    let (package, version): (String, String) = (manifest.package.clone(), manifest.version.clone());
+    let chunked: bool = manifest.entries.iter().any(|e| e.is_chunked());
+    debug!("Manifest of {}@{} has {} entries ({} bytes)", package, version, manifest.entries.len(), manifest.total_size());
+
+    // Open (or reopen) the transaction
+    let mut conn: Connection = Connection::connect(opts).map_err(|err| Error::ConnectionError{ err })?;
+    let state: PublishState = match transaction {
+        Some(transaction) => conn.publish_status(transaction),
+        None              => conn.begin_publish(manifest),
+    }.map_err(|err| Error::ConnectionError{ err })?;
+    if state.package != package || state.version != version {
+        return Err(Error::TransactionMismatch{ id: state.transaction, package: state.package, version: state.version });
+    }
+    println!("Publishing {}@{} in transaction '{}'", package, version, state.transaction);
+
+    // Upload what the server needs
+    let mut conn: Option<Connection> = Some(conn);
+    for needed in &state.needed {
+        let source: &BlobSource = match sources.get(&needed.hash) {
+            Some(source) => source,
+            None         => { return Err(Error::UnknownBlob{ hash: needed.hash }); }
+        };
+        if chunked {
+            println!("Uploading chunk of '{}' at byte {} ({} bytes)...", source.path.display(), source.offset, needed.size - needed.offset);
+        } else {
+            println!("Uploading '{}' ({} bytes)...", source.path.display(), needed.size - needed.offset);
+        }
+        let transferred: u64 = upload_blob(opts, &mut conn, &state.transaction, &needed.hash, needed.offset, source, retries)?;
+        debug!("Transferred {} bytes of blob '{}'", transferred, needed.hash);
+    }
+
+    // Make it visible
+    let conn: &mut Connection = connected(opts, &mut conn).map_err(|err| Error::ConnectionError{ err })?;
+    let committed: Committed = conn.commit(&state.transaction).map_err(|err| Error::ConnectionError{ err })?;
+
+    // Move the channels along
+    for channel in channels {
+        conn.set_channel(&package, channel, &version).map_err(|err| Error::ConnectionError{ err })?;
+        println!("Channel '{}' now points to version '{}'", channel, version);
+    }
+
+    // Done
+    Ok(committed)
+}
+
+/// Aborts the given publish transaction, discarding everything that was uploaded in it.
+///
+/// # Errors
+/// This function errors if we could not connect to the server or if it refused to abort the transaction.
+pub fn abort_publish(opts: &ConnectOptions, transaction: &str) -> Result<(), Error> {
+    let mut conn: Connection = Connection::connect(opts).map_err(|err| Error::ConnectionError{ err })?;
+    conn.abort(transaction).map_err(|err| Error::ConnectionError{ err })
+}
@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:38:01
  * Last edited:
- *   06 Jun 2022, 12:39:21
+ *   19 Oct 2026, 06:41:27
  * Auto updated?
  *   Yes
  *
@@ -12,10 +12,14 @@
  *   Module that handles the command-line interface part of the ctl.
 **/
 
+use std::net::IpAddr;
 use std::path::PathBuf;
 
 use clap::Parser;
 
+use filehost_spc::audit::AuditOperation;
+use filehost_spc::login::Permissions;
+
 
 /***** CONSTANTS *****/
 // Lazy constants
@@ -41,6 +45,9 @@ pub struct Arguments {
     /// The configuration file for the CTL.
     #[clap(short, long, default_value = "/etc/filehost/config.json", help = "The config file from which to read the server connection settings.")]
     pub config_path : PathBuf,
+    /// The virtual host to manage instead of the main host, if any.
+    #[clap(long, help = "The name of the virtual host (as it appears in the daemon's config) to manage instead of the main host.")]
+    pub vhost       : Option<String>,
 
     /// The action to take from this point on (subcommand)
     #[clap(subcommand)]
@@ -55,4 +62,361 @@ pub enum Action {
     /// Checks if the remote server is alive and well.
     #[clap(name = "health", about = "Checks if the daemon is alive and well.")]
     Health{},
+
+    /// Resolves a version or channel to a version.
+    #[clap(name = "resolve", about = "Resolves a version or channel of a package to the version it refers to.")]
+    Resolve {
+        /// The package to resolve in.
+        #[clap(help = "The name of the package.")]
+        package : String,
+        /// The name to resolve.
+        #[clap(help = "The version or channel to resolve.")]
+        name    : String,
+    },
+    /// Shows a channel and its history.
+    #[clap(name = "channel", about = "Shows which version a channel of a package points to, and how it was moved over time.")]
+    Channel {
+        /// The package of the channel.
+        #[clap(help = "The name of the package.")]
+        package : String,
+        /// The channel to show.
+        #[clap(help = "The name of the channel.")]
+        channel : String,
+    },
+
+    /// Manages the users.
+    #[clap(name = "user", about = "Inspects and suspends the users known to the daemon.")]
+    User {
+        /// The user action to take.
+        #[clap(subcommand)]
+        action : UserAction,
+    },
+    /// Manages the groups.
+    #[clap(name = "group", about = "Manages the groups of users. Members of a group get its permissions on top of their own.")]
+    Group {
+        /// The group action to take.
+        #[clap(subcommand)]
+        action : GroupAction,
+    },
+    /// Manages the API tokens.
+    #[clap(name = "token", about = "Inspects and revokes the API tokens that users log in with instead of a client certificate.")]
+    Token {
+        /// The token action to take.
+        #[clap(subcommand)]
+        action : TokenAction,
+    },
+    /// Manages the invite codes.
+    #[clap(name = "invite", about = "Manages the one-time invite codes with which new users create their own account.")]
+    Invite {
+        /// The invite action to take.
+        #[clap(subcommand)]
+        action : InviteAction,
+    },
+    /// Manages the client certificates.
+    #[clap(name = "cert", alias = "certs", about = "Issues client certificates with the certificate authority of the daemon, revokes client certificates and shows which certificates expire soon.")]
+    Cert {
+        /// The certificate action to take.
+        #[clap(subcommand)]
+        action : CertAction,
+    },
+    /// Manages the banned addresses.
+    #[clap(name = "ban", alias = "bans", about = "Lists and lifts the bans of addresses that failed to authenticate or sent invalid messages too often.")]
+    Ban {
+        /// The ban action to take.
+        #[clap(subcommand)]
+        action : BanAction,
+    },
+    /// Inspects the audit log.
+    #[clap(name = "audit", about = "Shows who changed what on the daemon, and checks that its audit log was not tampered with.")]
+    Audit {
+        /// The audit action to take.
+        #[clap(subcommand)]
+        action : AuditAction,
+    },
+    /// Manages the blob storage.
+    #[clap(name = "storage", about = "Manages the storage in which the daemon keeps the contents of all package versions.")]
+    Storage {
+        /// The storage action to take.
+        #[clap(subcommand)]
+        action : StorageAction,
+    },
+    /// Manages the metadata index.
+    #[clap(name = "index", about = "Manages the metadata index in which the daemon keeps track of packages, versions, channels and blob references.")]
+    Index {
+        /// The index action to take.
+        #[clap(subcommand)]
+        action : IndexAction,
+    },
+}
+
+
+
+/// Defines the actions that can be done on users.
+#[derive(Parser)]
+pub enum UserAction {
+    /// Shows a user.
+    #[clap(name = "show", about = "Shows the permissions, groups and quota of a user, and how much of the quota is in use.")]
+    Show {
+        /// The user to show.
+        #[clap(help = "The name of the user.")]
+        username : String,
+    },
+    /// Suspends a user.
+    #[clap(name = "suspend", about = "Suspends a user, after which it can no longer log in with its certificates or API tokens. Sessions that are already open are not affected.")]
+    Suspend {
+        /// The user to suspend.
+        #[clap(help = "The name of the user.")]
+        username : String,
+        /// The number of days the suspension lasts.
+        #[clap(short, long, help = "The number of days after which the suspension ends by itself. It lasts until lifted if omitted.")]
+        days     : Option<u64>,
+    },
+    /// Lifts the suspension of a user.
+    #[clap(name = "unsuspend", about = "Lifts the suspension of a user.")]
+    Unsuspend {
+        /// The user to lift the suspension of.
+        #[clap(help = "The name of the user.")]
+        username : String,
+    },
+}
+
+
+
+/// Defines the actions that can be done on groups.
+#[derive(Parser)]
+pub enum GroupAction {
+    /// Lists the groups.
+    #[clap(name = "list", about = "Lists all groups with their permissions and members.")]
+    List{},
+    /// Creates a group or changes its permissions.
+    #[clap(name = "set", about = "Creates a group with the given permissions, or changes the permissions of an existing group.")]
+    Set {
+        /// The group to create or change.
+        #[clap(help = "The name of the group.")]
+        name        : String,
+        /// The permissions to give the group.
+        #[clap(help = "The permissions of the group: 'none', 'all' or a comma-separated list of 'download', 'upload', 'channel' and 'delete'.")]
+        permissions : Permissions,
+    },
+    /// Removes a group.
+    #[clap(name = "remove", about = "Removes a group. Its members lose the permissions they got from it.")]
+    Remove {
+        /// The group to remove.
+        #[clap(help = "The name of the group.")]
+        name : String,
+    },
+    /// Adds a user to a group.
+    #[clap(name = "add-member", about = "Adds a user to a group.")]
+    AddMember {
+        /// The group to add to.
+        #[clap(help = "The name of the group.")]
+        group    : String,
+        /// The user to add.
+        #[clap(help = "The name of the user.")]
+        username : String,
+    },
+    /// Removes a user from a group.
+    #[clap(name = "remove-member", about = "Removes a user from a group.")]
+    RemoveMember {
+        /// The group to remove from.
+        #[clap(help = "The name of the group.")]
+        group    : String,
+        /// The user to remove.
+        #[clap(help = "The name of the user.")]
+        username : String,
+    },
+}
+
+
+
+/// Defines the actions that can be done on API tokens.
+#[derive(Parser)]
+pub enum TokenAction {
+    /// Lists the tokens of a user.
+    #[clap(name = "list", about = "Lists the API tokens of a user, with their permissions and when they expire.")]
+    List {
+        /// The user to list the tokens of.
+        #[clap(help = "The name of the user.")]
+        username : String,
+    },
+    /// Revokes a token.
+    #[clap(name = "revoke", about = "Revokes an API token, after which it can no longer be used to log in.")]
+    Revoke {
+        /// The token to revoke.
+        #[clap(help = "The identifier of the token (the part between 'fht_' and the '.').")]
+        id : String,
+    },
+}
+
+
+
+/// Defines the actions that can be done on invite codes.
+#[derive(Parser)]
+pub enum InviteAction {
+    /// Creates an invite code.
+    #[clap(name = "create", about = "Creates a one-time invite code and prints it. Store it somewhere safe, since it cannot be shown again.")]
+    Create {
+        /// The permissions of the new user.
+        #[clap(help = "The permissions the new user gets: 'none', 'all' or a comma-separated list of 'download', 'upload', 'channel' and 'delete'.")]
+        permissions : Permissions,
+        /// The groups of the new user.
+        #[clap(short, long = "group", help = "A group the new user is added to. May be given multiple times.")]
+        groups      : Vec<String>,
+        /// The number of days the code is valid.
+        #[clap(short, long, default_value = "7", help = "The number of days the invite code remains valid.")]
+        days        : u64,
+        /// Who the code is for.
+        #[clap(short = 'm', long, default_value = "", help = "A description of who or what the invite code is for.")]
+        description : String,
+    },
+    /// Lists the invite codes.
+    #[clap(name = "list", about = "Lists the invite codes that have not been redeemed yet, with their permissions and when they expire.")]
+    List{},
+    /// Revokes an invite code.
+    #[clap(name = "revoke", about = "Revokes an invite code, after which it can no longer be redeemed.")]
+    Revoke {
+        /// The code to revoke.
+        #[clap(help = "The identifier of the invite code (the part between 'fhi_' and the '.').")]
+        id : String,
+    },
+}
+
+
+
+/// Defines the actions that can be done with client certificates.
+#[derive(Parser)]
+pub enum CertAction {
+    /// Issues a certificate to a user.
+    #[clap(name = "issue", about = "Issues a client certificate to a user and writes it (and its private key) to '<username>.crt' (and '<username>.key').")]
+    Issue {
+        /// The user to issue the certificate to.
+        #[clap(help = "The name of the user.")]
+        username : String,
+        /// A certificate signing request to sign.
+        #[clap(long, help = "If given, signs this certificate signing request of the user instead of generating a new private key.")]
+        csr      : Option<PathBuf>,
+        /// The directory to write the files to.
+        #[clap(short, long, default_value = ".", help = "The directory to write the certificate (and key) to. Existing files are never overwritten.")]
+        output   : PathBuf,
+    },
+    /// Revokes a certificate.
+    #[clap(name = "revoke", about = "Revokes a client certificate, after which it can no longer be used to log in. Sessions that are already open are not affected.")]
+    Revoke {
+        /// The fingerprint or serial number of the certificate.
+        #[clap(help = "The SHA-256 fingerprint of the certificate in hex (e.g., as printed by 'openssl x509 -noout -fingerprint -sha256'), or its serial number if '--serial' is given.")]
+        value  : String,
+        /// Whether the value is a serial number.
+        #[clap(short, long, help = "If given, revokes the certificate by its serial number instead of its fingerprint.")]
+        serial : bool,
+        /// Why the certificate is revoked.
+        #[clap(short = 'm', long, default_value = "", help = "Why the certificate is revoked.")]
+        reason : String,
+    },
+    /// Takes a certificate off the revocation list.
+    #[clap(name = "unrevoke", about = "Takes a client certificate off the revocation list, after which it can be used to log in again.")]
+    Unrevoke {
+        /// The fingerprint or serial number of the certificate.
+        #[clap(help = "The SHA-256 fingerprint of the certificate in hex, or its serial number if '--serial' is given.")]
+        value  : String,
+        /// Whether the value is a serial number.
+        #[clap(short, long, help = "If given, refers to the certificate by its serial number instead of its fingerprint.")]
+        serial : bool,
+    },
+    /// Lists the revoked certificates.
+    #[clap(name = "revoked", about = "Lists the revoked client certificates.")]
+    Revoked{},
+    /// Lists the certificates that expire soon.
+    #[clap(name = "status", about = "Lists the certificates of the daemon, its certificate authority and its users that have expired or expire soon, the most urgent first.")]
+    Status {
+        /// The number of days to look ahead.
+        #[clap(short, long, help = "Lists the certificates that expire within this many days. Uses 'cert_warn_days' from the config file if omitted.")]
+        days : Option<u64>,
+        /// Whether to list all certificates.
+        #[clap(short, long, help = "If given, lists all certificates, regardless of when they expire.")]
+        all  : bool,
+    },
+}
+
+
+
+/// Defines the actions that can be done on bans.
+#[derive(Parser)]
+pub enum BanAction {
+    /// Lists the banned addresses.
+    #[clap(name = "list", about = "Lists the banned addresses, why they were banned and until when.")]
+    List{},
+    /// Lifts bans.
+    #[clap(name = "clear", about = "Lifts the ban of an address, or of all addresses.")]
+    Clear {
+        /// The address to lift the ban of.
+        #[clap(required_unless_present = "all", help = "The banned (IPv4 or IPv6) address.")]
+        address : Option<IpAddr>,
+        /// Whether to lift all bans.
+        #[clap(short, long, conflicts_with = "address", help = "If given, lifts the bans of all addresses.")]
+        all     : bool,
+    },
+}
+
+
+
+/// Defines the actions that can be done on the audit log.
+#[derive(Parser)]
+pub enum AuditAction {
+    /// Shows entries of the audit log.
+    #[clap(name = "show", about = "Shows the entries of the audit log that match the given filters, oldest first.")]
+    Show {
+        /// Only show the entries of this user.
+        #[clap(short, long = "user", help = "If given, only shows the entries of the user with this name.")]
+        username  : Option<String>,
+        /// Only show the entries of this operation.
+        #[clap(short, long, help = "If given, only shows the entries of this operation (e.g., 'publish', 'delete' or 'set-channel').")]
+        operation : Option<AuditOperation>,
+        /// Only show the entries with this target.
+        #[clap(short, long, help = "If given, only shows the entries whose target contains this text (e.g., a package name).")]
+        target    : Option<String>,
+        /// Only show recent entries.
+        #[clap(short, long, help = "If given, only shows the entries of the last this many days.")]
+        days      : Option<u64>,
+        /// Only show the most recent entries.
+        #[clap(short = 'n', long, help = "If given, only shows this many of the most recent matching entries.")]
+        limit     : Option<usize>,
+    },
+    /// Checks the audit log.
+    #[clap(name = "verify", about = "Checks that every entry of the audit log refers to the hash of the one before it, and prints the hash of the last entry. Exits with 1 if the log was damaged or tampered with.")]
+    Verify{},
+}
+
+
+
+/// Defines the actions that can be done on the storage.
+#[derive(Parser)]
+pub enum StorageAction {
+    /// Removes unreferenced blobs.
+    #[clap(name = "gc", about = "Removes the blobs that no package version or publish transaction refers to anymore, and reports how many bytes were freed.")]
+    Gc {
+        /// Whether to only report what would be removed.
+        #[clap(short, long, help = "If given, only reports what would be removed without removing anything.")]
+        dry_run      : bool,
+        /// The grace period to use instead of the configured one.
+        #[clap(short, long, help = "The number of seconds an unreferenced blob is kept after it was last touched. Uses 'gc_grace_period' from the config file if omitted.")]
+        grace_period : Option<u64>,
+    },
+
+    /// Checks the integrity of the storage.
+    #[clap(name = "fsck", about = "Re-hashes every blob in the storage and checks that every package version refers only to existing blobs. Reports corrupt, missing and orphaned blobs, and exits with 1 if anything is corrupt or missing.")]
+    Fsck {
+        /// Whether to move corrupt blobs out of the storage.
+        #[clap(short, long, help = "If given, moves corrupt blobs to the 'quarantine' directory in the daemon's data directory.")]
+        quarantine : bool,
+    },
+}
+
+
+
+/// Defines the actions that can be done on the metadata index.
+#[derive(Parser)]
+pub enum IndexAction {
+    /// Rebuilds the index.
+    #[clap(name = "rebuild", about = "Throws away the metadata index and rebuilds it from the package manifests and channels on disk. Use this if the index got lost or out of sync.")]
+    Rebuild{},
 }
@@ -0,0 +1,68 @@
+/* CONNECTION.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:41:58
+ * Last edited:
+ *   19 Oct 2026, 06:20:02
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements sending framed requests to the daemon over the CTL socket.
+**/
+
+use std::os::unix::net::UnixStream;
+
+use log::debug;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use filehost_spc::client_messages::ErrorReply;
+use filehost_spc::ctl_messages::{HostRequest, Opcode};
+use filehost_spc::frames::{read_frame, write_json, Frame};
+
+pub use crate::errors::CtlError as Error;
+
+
+/***** LIBRARY *****/
+/// Tells the daemon that the next request is about a virtual host instead of the main host.
+///
+/// # Errors
+/// This function errors if we could not communicate with the daemon or if it does not know the host.
+#[inline]
+pub fn select_host(conn: &mut UnixStream, name: String) -> Result<(), Error> {
+    request(conn, Opcode::SelectHost, &HostRequest{ name }, Opcode::HostSelected)
+}
+
+/// Sends a single framed request to the daemon and waits for its reply.
+///
+/// # Arguments
+/// - `conn`: The connection to the daemon's CTL socket.
+/// - `opcode`: The opcode of the request.
+/// - `req`: The request itself.
+/// - `expected`: The opcode of the reply we expect.
+///
+/// # Returns
+/// The parsed reply.
+///
+/// # Errors
+/// This function errors if we could not communicate with the daemon, if it replied with an error or if it sent a reply we did not expect.
+pub fn request<Req: Serialize, Rep: DeserializeOwned>(conn: &mut UnixStream, opcode: Opcode, req: &Req, expected: Opcode) -> Result<Rep, Error> {
+    // Send the request
+    debug!("Sending '{:?}' to server...", opcode);
+    write_json(conn, opcode, req).map_err(|err| Error::FrameError{ err })?;
+
+    // Read the reply
+    let frame: Frame = match read_frame(conn) {
+        Ok(Some(frame)) => frame,
+        Ok(None)        => { return Err(Error::ConnectionClosed); },
+        Err(err)        => { return Err(Error::FrameError{ err }); },
+    };
+    if frame.opcode == u8::from(Opcode::Error) {
+        let reply: ErrorReply = frame.parse().map_err(|err| Error::FrameError{ err })?;
+        return Err(Error::ServerError{ reply });
+    }
+    if frame.opcode != u8::from(expected) { return Err(Error::UnexpectedReply{ got: frame.opcode, expected }); }
+    frame.parse().map_err(|err| Error::FrameError{ err })
+}
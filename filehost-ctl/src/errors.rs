@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:34:34
  * Last edited:
- *   06 Jun 2022, 15:32:49
+ *   19 Oct 2026, 05:54:06
  * Auto updated?
  *   Yes
  *
@@ -16,6 +16,10 @@ use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FResult};
 use std::path::PathBuf;
 
+use filehost_spc::client_messages::ErrorReply;
+use filehost_spc::ctl_messages::Opcode;
+use filehost_spc::frames::FrameError;
+
 
 /***** ERRORS *****/
 /// General toplevel errors.
@@ -30,6 +34,20 @@ pub enum CtlError {
     SocketWriteError{ err: std::io::Error },
     /// Could not flush the server stream.
     SocketFlushError{ err: std::io::Error },
+
+    /// Could not send or receive a frame.
+    FrameError{ err: FrameError },
+    /// The server closed the connection while we expected a reply.
+    ConnectionClosed,
+    /// The server replied with an error.
+    ServerError{ reply: ErrorReply },
+    /// The server replied with a frame we did not expect.
+    UnexpectedReply{ got: u8, expected: Opcode },
+
+    /// Could not read the given certificate signing request.
+    CsrReadError{ path: PathBuf, err: std::io::Error },
+    /// Could not write an issued certificate or key.
+    FileWriteError{ path: PathBuf, err: std::io::Error },
 }
 
 impl Display for CtlError {
@@ -42,6 +60,14 @@ impl Display for CtlError {
             SocketReadError{ err }  => write!(f, "Could not read from server socket: {}", err),
             SocketWriteError{ err } => write!(f, "Could not write to server socket: {}", err),
             SocketFlushError{ err } => write!(f, "Could not flush server socket: {}", err),
+
+            FrameError{ err }                => write!(f, "{}", err),
+            ConnectionClosed                 => write!(f, "Server closed the connection unexpectedly"),
+            ServerError{ reply }             => write!(f, "Server replied with an error: {}", reply),
+            UnexpectedReply{ got, expected } => write!(f, "Server replied with opcode {} instead of {}", got, expected),
+
+            CsrReadError{ path, err }   => write!(f, "Could not read certificate signing request '{}': {}", path.display(), err),
+            FileWriteError{ path, err } => write!(f, "Could not write '{}': {}", path.display(), err),
         }
     }
 }
@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:32:50
  * Last edited:
- *   16 Apr 2022, 16:41:06
+ *   19 Oct 2026, 04:41:58
  * Auto updated?
  *   Yes
  *
@@ -19,3 +19,5 @@
 pub mod errors;
 /// Module that handles the Command-Line Interface parsing.
 pub mod cli;
+/// Module that sends framed requests to the daemon.
+pub mod connection;
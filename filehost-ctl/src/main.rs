@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:32:15
  * Last edited:
- *   11 Jun 2022, 15:22:36
+ *   19 Oct 2026, 07:03:36
  * Auto updated?
  *   Yes
  *
@@ -12,17 +12,30 @@
  *   Entrypoint to the CTL executable.
 **/
 
+use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 
+use chrono::{DateTime, Utc};
 use clap::Parser;
-use log::{debug, error, info, LevelFilter};
+use log::{debug, error, info, warn, LevelFilter};
 use simplelog::{ColorChoice, TermLogger, TerminalMode};
 
 pub use filehost_ctl::errors::CtlError as Error;
-use filehost_ctl::cli::{Action, Arguments};
+use filehost_ctl::cli::{Action, Arguments, AuditAction, BanAction, CertAction, GroupAction, IndexAction, InviteAction, StorageAction, TokenAction, UserAction};
+use filehost_ctl::connection::{request, select_host};
+use filehost_spc::audit::AuditEntry;
+use filehost_spc::channel::Channel;
+use filehost_spc::client_messages::{ChannelRequest, ErrorReply, ResolveRequest, Resolved, TokenRequest};
 use filehost_spc::config::Config;
-use filehost_spc::ctl_messages::{HEALTH_REPLY, Opcode};
+use filehost_spc::ctl_messages::{
+    AuditReport, AuditRequest, BanInfo, CertificateStatus, CertStatusRequest, ClearBansRequest, CreateInviteRequest, FsckReport, FsckRequest, GcReport, GcRequest, GroupInfo, GroupRequest, HEALTH_REPLY, IndexReport,
+    InviteInfo, InviteRequest, IssuedCertificate, IssueRequest, MemberRequest, NewInvite, Opcode, RevocationInfo, RevocationKind, RevocationRequest, RevokeRequest, SetGroupRequest, SuspendRequest, UserInfo, UserRequest,
+};
+use filehost_spc::frames::read_frame_body;
+use filehost_spc::login::TokenInfo;
 
 
 // /***** HELPER MACROS *****/
@@ -56,6 +69,28 @@ use filehost_spc::ctl_messages::{HEALTH_REPLY, Opcode};
 
 
 
+/***** HELPER FUNCTIONS *****/
+/// Writes the given contents to a file that must not exist yet.
+///
+/// # Arguments
+/// - `path`: The path of the file to create.
+/// - `contents`: The contents to write.
+/// - `mode`: The permissions of the new file.
+///
+/// # Errors
+/// This function errors if the file already exists or could not be written.
+fn write_new(path: &Path, contents: &str, mode: u32) -> Result<(), Error> {
+    let mut handle: File = match OpenOptions::new().write(true).create_new(true).mode(mode).open(path) {
+        Ok(handle) => handle,
+        Err(err)   => { return Err(Error::FileWriteError{ path: path.into(), err }); }
+    };
+    handle.write_all(contents.as_bytes()).map_err(|err| Error::FileWriteError{ path: path.into(), err })
+}
+
+
+
+
+
 /***** ENTRYPOINT *****/
 fn main() {
     // Read the CLI
@@ -81,6 +116,16 @@ fn main() {
         Err(err) => { error!("{}", Error::SocketConnectError{ addr: config.socket_path, err }); std::process::exit(1); }
     };
 
+    // Tell the daemon which host we're talking about, if not the main one (the health check is about the daemon as a whole)
+    if let Some(vhost) = args.vhost {
+        if matches!(args.action, Action::Health{}) {
+            warn!("Ignoring '--vhost' for the health check");
+        } else if let Err(err) = select_host(&mut conn, vhost) {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
 
 
     // Switch on the action
@@ -96,8 +141,16 @@ fn main() {
             let mut buffer: [u8; HEALTH_REPLY.len()] = [ 0; HEALTH_REPLY.len() ];
             if let Err(err) = conn.read(&mut buffer) { error!("{}", Error::SocketReadError{ err }); std::process::exit(1); }
 
-            // Compare it
+            // Compare it (the server may refuse us with an error frame instead)
             debug!("Checking server reply...");
+            if buffer[0] == u8::from(Opcode::Error) {
+                let reply: ErrorReply = match read_frame_body(&mut conn, buffer[0]).and_then(|frame| frame.parse()) {
+                    Ok(reply) => reply,
+                    Err(err)  => { error!("{}", Error::FrameError{ err }); std::process::exit(1); }
+                };
+                error!("{}", Error::ServerError{ reply });
+                std::process::exit(1);
+            }
             if buffer != HEALTH_REPLY {
                 error!("Server replied, but with incorrect response:\n\n    Expected:\n     > {:?}\n\n    Got:\n     > {:?}\n", HEALTH_REPLY, buffer);
                 std::process::exit(1);
@@ -106,6 +159,408 @@ fn main() {
             // Otherwise, success
             println!("Server OK");
         },
+
+        Action::Resolve{ package, name } => {
+            info!("Resolving {}@{}...", package, name);
+
+            // Ask the server
+            let resolved: Resolved = match request(&mut conn, Opcode::Resolve, &ResolveRequest{ package, name: name.clone() }, Opcode::Resolved) {
+                Ok(resolved) => resolved,
+                Err(err)     => { error!("{}", err); std::process::exit(1); }
+            };
+
+            // Show it
+            match resolved.channel {
+                Some(channel) => println!("{}@{} (via channel '{}')", resolved.package, resolved.version, channel),
+                None          => println!("{}@{}", resolved.package, resolved.version),
+            }
+        },
+
+        Action::Channel{ package, channel } => {
+            info!("Fetching channel '{}' of package '{}'...", channel, package);
+
+            // Ask the server
+            let channel: Channel = match request(&mut conn, Opcode::GetChannel, &ChannelRequest{ package, channel }, Opcode::Channel) {
+                Ok(channel) => channel,
+                Err(err)    => { error!("{}", err); std::process::exit(1); }
+            };
+
+            // Show it
+            println!("Channel '{}' of package '{}' points to version '{}'", channel.name, channel.package, channel.version);
+            println!();
+            println!("History:");
+            for change in &channel.history {
+                println!(" - {}: {} -> {} (by '{}')", change.time, change.from.as_deref().unwrap_or("<new>"), change.to, change.by);
+            }
+        },
+
+        Action::User{ action } => {
+            // Ask the server
+            let res: Result<UserInfo, _> = match action {
+                UserAction::Show{ username } => {
+                    info!("Retrieving user '{}'...", username);
+                    request(&mut conn, Opcode::GetUser, &UserRequest{ username }, Opcode::User)
+                },
+                UserAction::Suspend{ username, days } => {
+                    info!("Suspending user '{}'{}...", username, days.map(|d| format!(" for {} day(s)", d)).unwrap_or_default());
+                    request(&mut conn, Opcode::SetSuspended, &SuspendRequest{ username, suspended: true, duration: days.map(|d| d.saturating_mul(24 * 60 * 60)) }, Opcode::User)
+                },
+                UserAction::Unsuspend{ username } => {
+                    info!("Lifting suspension of user '{}'...", username);
+                    request(&mut conn, Opcode::SetSuspended, &SuspendRequest{ username, suspended: false, duration: None }, Opcode::User)
+                },
+            };
+            let user: UserInfo = match res {
+                Ok(user) => user,
+                Err(err) => { error!("{}", err); std::process::exit(1); }
+            };
+
+            // Show it
+            println!("User '{}' (ID {})", user.username, user.id);
+            match (user.suspended, user.suspended_until) {
+                (true, Some(until)) => println!("Suspended until {}", until),
+                (true, None)        => println!("Suspended until lifted"),
+                (false, _)          => {},
+            }
+            println!("Permissions: {}", user.permissions);
+            println!("Groups: {}", if user.groups.is_empty() { "none".into() } else { user.groups.join(", ") });
+            println!("Effective permissions: {}", user.effective);
+            println!("Bytes: {} in use, {} pending, limit {}", user.usage.bytes, user.pending.bytes, user.quota.max_bytes.map(|l| l.to_string()).unwrap_or_else(|| "none".into()));
+            println!("Versions: {} in use, {} pending, limit {}", user.usage.versions, user.pending.versions, user.quota.max_versions.map(|l| l.to_string()).unwrap_or_else(|| "none".into()));
+        },
+
+        Action::Group{ action } => {
+            // Ask the server
+            let res: Result<Vec<GroupInfo>, _> = match action {
+                GroupAction::List{} => {
+                    info!("Retrieving groups...");
+                    request(&mut conn, Opcode::GetGroups, &(), Opcode::Groups)
+                },
+                GroupAction::Set{ name, permissions } => {
+                    info!("Setting permissions of group '{}' to {}...", name, permissions);
+                    request(&mut conn, Opcode::SetGroup, &SetGroupRequest{ name, permissions }, Opcode::Group).map(|g| vec![ g ])
+                },
+                GroupAction::Remove{ name } => {
+                    info!("Removing group '{}'...", name);
+                    request(&mut conn, Opcode::RemoveGroup, &GroupRequest{ name }, Opcode::Group).map(|g| vec![ g ])
+                },
+                GroupAction::AddMember{ group, username } => {
+                    info!("Adding user '{}' to group '{}'...", username, group);
+                    request(&mut conn, Opcode::AddMember, &MemberRequest{ group, username }, Opcode::Group).map(|g| vec![ g ])
+                },
+                GroupAction::RemoveMember{ group, username } => {
+                    info!("Removing user '{}' from group '{}'...", username, group);
+                    request(&mut conn, Opcode::RemoveMember, &MemberRequest{ group, username }, Opcode::Group).map(|g| vec![ g ])
+                },
+            };
+            let groups: Vec<GroupInfo> = match res {
+                Ok(groups) => groups,
+                Err(err)   => { error!("{}", err); std::process::exit(1); }
+            };
+
+            // Show them
+            for group in groups {
+                println!("Group '{}': permissions {}, members: {}", group.name, group.permissions, if group.members.is_empty() { "none".into() } else { group.members.join(", ") });
+            }
+        },
+
+        Action::Token{ action } => {
+            // Ask the server
+            let res: Result<Vec<TokenInfo>, _> = match action {
+                TokenAction::List{ username } => {
+                    info!("Retrieving API tokens of user '{}'...", username);
+                    request(&mut conn, Opcode::GetTokens, &UserRequest{ username }, Opcode::Tokens)
+                },
+                TokenAction::Revoke{ id } => {
+                    info!("Revoking API token '{}'...", id);
+                    request(&mut conn, Opcode::RevokeToken, &TokenRequest{ id }, Opcode::TokenRevoked).map(|t| vec![ t ])
+                },
+            };
+            let tokens: Vec<TokenInfo> = match res {
+                Ok(tokens) => tokens,
+                Err(err)   => { error!("{}", err); std::process::exit(1); }
+            };
+
+            // Show them
+            if tokens.is_empty() { println!("No API tokens"); }
+            for token in tokens {
+                println!("Token '{}' of user '{}': permissions {}, created {}, expires {}{}", token.id, token.username, token.permissions, token.created, token.expires, if token.description.is_empty() { String::new() } else { format!(" ({})", token.description) });
+            }
+        },
+
+        Action::Invite{ action } => match action {
+            InviteAction::Create{ permissions, groups, days, description } => {
+                info!("Creating invite code with permissions {} for {} day(s)...", permissions, days);
+
+                // Ask the server
+                let invite: NewInvite = match request(&mut conn, Opcode::CreateInvite, &CreateInviteRequest{ permissions, groups, valid_for: days.saturating_mul(24 * 60 * 60), description }, Opcode::InviteCreated) {
+                    Ok(invite) => invite,
+                    Err(err)   => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Show it
+                println!("Created invite code '{}' with permissions {}, valid until {}", invite.info.id, invite.info.permissions, invite.info.expires);
+                println!("Give it to the new user; it cannot be shown again:");
+                println!();
+                println!("{}", invite.code);
+            },
+            InviteAction::List{} => {
+                info!("Retrieving invite codes...");
+
+                // Ask the server
+                let invites: Vec<InviteInfo> = match request(&mut conn, Opcode::GetInvites, &(), Opcode::Invites) {
+                    Ok(invites) => invites,
+                    Err(err)    => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Show them
+                if invites.is_empty() { println!("No invite codes"); }
+                for invite in invites {
+                    println!("Invite code '{}': permissions {}, groups: {}, created {}, expires {}{}", invite.id, invite.permissions, if invite.groups.is_empty() { "none".into() } else { invite.groups.join(", ") }, invite.created, invite.expires, if invite.description.is_empty() { String::new() } else { format!(" ({})", invite.description) });
+                }
+            },
+            InviteAction::Revoke{ id } => {
+                info!("Revoking invite code '{}'...", id);
+
+                // Ask the server
+                match request::<_, InviteInfo>(&mut conn, Opcode::RevokeInvite, &InviteRequest{ id }, Opcode::InviteRevoked) {
+                    Ok(invite) => { println!("Revoked invite code '{}'", invite.id); },
+                    Err(err)   => { error!("{}", err); std::process::exit(1); }
+                }
+            },
+        },
+
+        Action::Cert{ action } => match action {
+            CertAction::Issue{ username, csr, output } => {
+                info!("Issuing certificate to user '{}'...", username);
+
+                // Read the request, if any
+                let csr: Option<String> = match csr {
+                    Some(path) => match fs::read_to_string(&path) {
+                        Ok(csr)  => Some(csr),
+                        Err(err) => { error!("{}", Error::CsrReadError{ path, err }); std::process::exit(1); }
+                    },
+                    None => None,
+                };
+                for ext in [ "crt", "key" ] {
+                    let path: PathBuf = output.join(format!("{}.{}", username, ext));
+                    if path.exists() { error!("{}", Error::FileWriteError{ path, err: std::io::ErrorKind::AlreadyExists.into() }); std::process::exit(1); }
+                }
+
+                // Ask the server
+                let issued: IssuedCertificate = match request(&mut conn, Opcode::IssueCertificate, &IssueRequest{ username, csr }, Opcode::CertificateIssued) {
+                    Ok(issued) => issued,
+                    Err(err)   => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Write it (and its key) to disk
+                let cert_path: PathBuf = output.join(format!("{}.crt", issued.username));
+                if let Err(err) = write_new(&cert_path, &issued.certificate, 0o644) { error!("{}", err); std::process::exit(1); }
+                println!("Wrote certificate of user '{}' to '{}', valid until {}", issued.username, cert_path.display(), issued.expires);
+                println!("Its serial number is {} and its fingerprint {}", issued.serial, issued.fingerprint);
+                if let Some(key) = issued.key {
+                    let key_path: PathBuf = output.join(format!("{}.key", issued.username));
+                    if let Err(err) = write_new(&key_path, &key, 0o600) { error!("{}", err); std::process::exit(1); }
+                    println!("Wrote its private key to '{}'; hand both to the user over a secure channel", key_path.display());
+                }
+            },
+            CertAction::Revoke{ value, serial, reason } => {
+                let kind: RevocationKind = if serial { RevocationKind::Serial } else { RevocationKind::Fingerprint };
+                info!("Revoking certificate with {} '{}'...", kind, value);
+
+                // Ask the server
+                match request::<_, RevocationInfo>(&mut conn, Opcode::RevokeCertificate, &RevokeRequest{ kind, value, reason }, Opcode::CertificateRevoked) {
+                    Ok(revocation) => { println!("Revoked certificate with {} {}", revocation.kind, revocation.value); },
+                    Err(err)       => { error!("{}", err); std::process::exit(1); }
+                }
+            },
+            CertAction::Unrevoke{ value, serial } => {
+                let kind: RevocationKind = if serial { RevocationKind::Serial } else { RevocationKind::Fingerprint };
+                info!("Taking certificate with {} '{}' off the revocation list...", kind, value);
+
+                // Ask the server
+                match request::<_, RevocationInfo>(&mut conn, Opcode::UnrevokeCertificate, &RevocationRequest{ kind, value }, Opcode::CertificateUnrevoked) {
+                    Ok(revocation) => { println!("Took certificate with {} {} off the revocation list", revocation.kind, revocation.value); },
+                    Err(err)       => { error!("{}", err); std::process::exit(1); }
+                }
+            },
+            CertAction::Revoked{} => {
+                info!("Retrieving revocation list...");
+
+                // Ask the server
+                let revocations: Vec<RevocationInfo> = match request(&mut conn, Opcode::GetRevocations, &(), Opcode::Revocations) {
+                    Ok(revocations) => revocations,
+                    Err(err)        => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Show them
+                if revocations.is_empty() { println!("No revoked certificates"); }
+                for revocation in revocations {
+                    println!("Certificate with {} {}: revoked {}{}", revocation.kind, revocation.value, revocation.revoked, if revocation.reason.is_empty() { String::new() } else { format!(" ({})", revocation.reason) });
+                }
+            },
+            CertAction::Status{ days, all } => {
+                info!("Retrieving certificate expiry times...");
+
+                // Ask the server
+                let statuses: Vec<CertificateStatus> = match request(&mut conn, Opcode::GetCertificates, &CertStatusRequest{ within: days, all }, Opcode::Certificates) {
+                    Ok(statuses) => statuses,
+                    Err(err)     => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Show them
+                if statuses.is_empty() { println!("No certificates expire within {} day(s)", days.unwrap_or(config.cert_warn_days)); }
+                let now: DateTime<Utc> = Utc::now();
+                for status in statuses {
+                    let owner: String = match &status.username {
+                        Some(username) => format!(" '{}'", username),
+                        None           => String::new(),
+                    };
+                    let origin: String = match &status.path {
+                        Some(path) => format!("in '{}'", path.display()),
+                        None       => "issued by the certificate authority".into(),
+                    };
+                    let when: String = if status.not_after <= now { format!("EXPIRED on {}", status.not_after) } else { format!("expires in {} day(s), on {}", (status.not_after - now).num_days(), status.not_after) };
+                    println!("'{}' ({}{}, serial {}, {}): {}", status.subject, status.role, owner, status.serial, origin, when);
+                }
+            },
+        },
+
+        Action::Ban{ action } => {
+            // Ask the server
+            let lifting: bool = matches!(action, BanAction::Clear{ .. });
+            let res: Result<Vec<BanInfo>, _> = match action {
+                BanAction::List{} => {
+                    info!("Retrieving banned addresses...");
+                    request(&mut conn, Opcode::GetBans, &(), Opcode::Bans)
+                },
+                BanAction::Clear{ address, .. } => {
+                    match address {
+                        Some(address) => info!("Lifting the ban of '{}'...", address),
+                        None          => info!("Lifting all bans..."),
+                    }
+                    request(&mut conn, Opcode::ClearBans, &ClearBansRequest{ address }, Opcode::BansCleared)
+                },
+            };
+            let bans: Vec<BanInfo> = match res {
+                Ok(bans) => bans,
+                Err(err) => { error!("{}", err); std::process::exit(1); }
+            };
+
+            // Show them
+            if bans.is_empty() { println!("No banned addresses"); }
+            for ban in bans {
+                if lifting {
+                    println!("Lifted the ban of address '{}' (banned {} until {}: {})", ban.address, ban.since, ban.until, ban.reason);
+                } else {
+                    println!("Address '{}': banned {} until {} ({})", ban.address, ban.since, ban.until, ban.reason);
+                }
+            }
+        },
+
+        Action::Audit{ action } => match action {
+            AuditAction::Show{ username, operation, target, days, limit } => {
+                info!("Retrieving audit log entries...");
+
+                // Ask the server
+                let since: Option<DateTime<Utc>> = days.map(|days| Utc::now() - chrono::Duration::days(i64::try_from(days).unwrap_or(i64::MAX / 86400)));
+                let entries: Vec<AuditEntry> = match request(&mut conn, Opcode::GetAudit, &AuditRequest{ username, operation, target, since, limit }, Opcode::Audit) {
+                    Ok(entries) => entries,
+                    Err(err)    => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Show them
+                if entries.is_empty() { println!("No matching audit log entries"); }
+                for entry in entries {
+                    let address: String = match entry.address {
+                        Some(address) => format!("{}", address),
+                        None          => "CTL".into(),
+                    };
+                    println!("#{} {}: '{}' ({}) {} {}: {}", entry.seq, entry.time, entry.username, address, entry.operation, entry.target, entry.outcome);
+                }
+            },
+            AuditAction::Verify{} => {
+                info!("Verifying audit log...");
+
+                // Ask the server
+                let report: AuditReport = match request(&mut conn, Opcode::VerifyAudit, &(), Opcode::AuditVerified) {
+                    Ok(report) => report,
+                    Err(err)   => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Show it
+                for problem in &report.problems {
+                    println!("Line {}: {}", problem.line, problem.problem);
+                }
+                println!("Checked {} entries; the last one hashes to {}", report.entries, report.head);
+                if !report.problems.is_empty() {
+                    println!("Audit log is damaged or was tampered with ({} problem(s))", report.problems.len());
+                    std::process::exit(1);
+                }
+                println!("Audit log is intact");
+            },
+        },
+
+        Action::Storage{ action } => match action {
+            StorageAction::Gc{ dry_run, grace_period } => {
+                info!("Collecting garbage{}...", if dry_run { " (dry run)" } else { "" });
+
+                // Ask the server
+                let report: GcReport = match request(&mut conn, Opcode::CollectGarbage, &GcRequest{ dry_run, grace_period }, Opcode::GarbageCollected) {
+                    Ok(report) => report,
+                    Err(err)   => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Show it
+                if report.dry_run {
+                    println!("Would free {} bytes by removing {} of {} blob(s)", report.freed, report.removed, report.blobs);
+                } else {
+                    println!("Freed {} bytes by removing {} of {} blob(s)", report.freed, report.removed, report.blobs);
+                }
+                println!("Kept {} referenced blob(s), and {} unreferenced blob(s) within the grace period", report.kept, report.young);
+            },
+
+            StorageAction::Fsck{ quarantine } => {
+                info!("Checking storage integrity{}...", if quarantine { " (quarantining corrupt blobs)" } else { "" });
+
+                // Ask the server
+                let report: FsckReport = match request(&mut conn, Opcode::Fsck, &FsckRequest{ quarantine }, Opcode::FsckReport) {
+                    Ok(report) => report,
+                    Err(err)   => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Show it
+                println!("Checked {} blob(s) ({} bytes)", report.blobs, report.bytes);
+                println!("Corrupt: {}", report.corrupt.len());
+                for blob in &report.corrupt {
+                    println!(" - {} (contents hash to {}){}", blob.hash, blob.got.map(|h| h.to_string()).unwrap_or_else(|| "<unreadable>".into()), if blob.quarantined { ", quarantined" } else { "" });
+                }
+                println!("Missing: {}", report.missing.len());
+                for blob in &report.missing {
+                    println!(" - {} (referred to by '{}' in {}@{})", blob.hash, blob.path, blob.package, blob.version);
+                }
+                println!("Orphaned: {}", report.orphaned.len());
+                for hash in &report.orphaned {
+                    println!(" - {}", hash);
+                }
+                if !report.is_healthy() { std::process::exit(1); }
+            },
+        },
+
+        Action::Index{ action } => match action {
+            IndexAction::Rebuild{} => {
+                info!("Rebuilding metadata index...");
+
+                // Ask the server
+                let report: IndexReport = match request(&mut conn, Opcode::RebuildIndex, &(), Opcode::IndexRebuilt) {
+                    Ok(report) => report,
+                    Err(err)   => { error!("{}", err); std::process::exit(1); }
+                };
+
+                // Show it
+                println!("Indexed {} package(s), {} version(s) and {} channel(s), referring to {} blob(s)", report.packages, report.versions, report.channels, report.blobs);
+            },
+        },
     }
 
 
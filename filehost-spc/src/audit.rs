@@ -0,0 +1,237 @@
+/* AUDIT.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 06:41:27
+ * Last edited:
+ *   19 Oct 2026, 06:41:27
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Defines the entries of the audit log, in which the daemon records who
+ *   changed what and when. Every entry includes the hash of the one
+ *   before it, so that changing or removing entries breaks the chain.
+**/
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::client_messages::ErrorCode;
+
+
+/***** CONSTANTS *****/
+/// The hash that the first entry of an audit log refers to as its previous one.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+
+
+
+
+/***** ERRORS *****/
+/// Defines the errors that occur when parsing an operation.
+#[derive(Debug)]
+pub enum AuditOperationError {
+    /// The given operation does not exist.
+    UnknownOperation{ raw: String },
+}
+
+impl Display for AuditOperationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use AuditOperationError::*;
+        match self {
+            UnknownOperation{ raw } => write!(f, "Unknown operation '{}' (options are: {})", raw, AuditOperation::ALL.iter().map(|op| op.to_string()).collect::<Vec<String>>().join(", ")),
+        }
+    }
+}
+
+impl Error for AuditOperationError {}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Computes the hash that the next entry refers to, from the line in which an entry is written to the audit log.
+///
+/// # Returns
+/// The SHA-256 hash of the line (without its newline), in lowercase hex.
+#[inline]
+pub fn hash_line(line: &str) -> String { hex::encode(Sha256::digest(line.as_bytes())) }
+
+
+
+/// Defines a single entry of the audit log.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditEntry {
+    /// The position of the entry in the log, starting at 0.
+    pub seq       : u64,
+    /// The moment the operation was done.
+    pub time      : DateTime<Utc>,
+    /// The name of the user that did the operation.
+    pub username  : String,
+    /// The address the user connected from, or `None` if it used the CTL socket.
+    pub address   : Option<IpAddr>,
+    /// What the user did.
+    pub operation : AuditOperation,
+    /// What the user did it to (e.g., `package@version` for a publish).
+    pub target    : String,
+    /// How it went.
+    pub outcome   : AuditOutcome,
+    /// The hash of the line of the previous entry (see `hash_line()`), or `GENESIS_HASH` for the first entry.
+    pub prev      : String,
+}
+
+
+
+/// Defines the operations that the audit log records.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditOperation {
+    /// Publishes a new version of a package.
+    Publish,
+    /// Aborts a publish transaction.
+    Abort,
+    /// Deletes a version of a package.
+    Delete,
+    /// Moves a channel of a package.
+    SetChannel,
+    /// Creates an API token.
+    CreateToken,
+    /// Revokes an API token.
+    RevokeToken,
+    /// Redeems an invite code, creating a user.
+    Redeem,
+    /// Has the certificate authority sign a certificate signing request.
+    SignCertificate,
+
+    /// Removes unreferenced blobs.
+    CollectGarbage,
+    /// Moves corrupt blobs to the quarantine directory.
+    Quarantine,
+    /// Rebuilds the metadata index.
+    RebuildIndex,
+    /// Suspends a user or lifts its suspension.
+    SetSuspended,
+    /// Creates a group or changes its permissions.
+    SetGroup,
+    /// Removes a group.
+    RemoveGroup,
+    /// Adds a user to a group.
+    AddMember,
+    /// Removes a user from a group.
+    RemoveMember,
+    /// Creates an invite code.
+    CreateInvite,
+    /// Revokes an invite code.
+    RevokeInvite,
+    /// Issues a client certificate.
+    IssueCertificate,
+    /// Revokes a client certificate.
+    RevokeCertificate,
+    /// Takes a client certificate off the revocation list.
+    UnrevokeCertificate,
+    /// Lifts bans of addresses.
+    ClearBans,
+}
+
+impl AuditOperation {
+    /// All operations, in the order they are defined.
+    pub const ALL: [Self; 22] = [
+        Self::Publish, Self::Abort, Self::Delete, Self::SetChannel, Self::CreateToken, Self::RevokeToken, Self::Redeem, Self::SignCertificate,
+        Self::CollectGarbage, Self::Quarantine, Self::RebuildIndex, Self::SetSuspended, Self::SetGroup, Self::RemoveGroup, Self::AddMember, Self::RemoveMember,
+        Self::CreateInvite, Self::RevokeInvite, Self::IssueCertificate, Self::RevokeCertificate, Self::UnrevokeCertificate, Self::ClearBans,
+    ];
+}
+
+impl Display for AuditOperation {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use AuditOperation::*;
+        match self {
+            Publish         => write!(f, "publish"),
+            Abort           => write!(f, "abort"),
+            Delete          => write!(f, "delete"),
+            SetChannel      => write!(f, "set-channel"),
+            CreateToken     => write!(f, "create-token"),
+            RevokeToken     => write!(f, "revoke-token"),
+            Redeem          => write!(f, "redeem"),
+            SignCertificate => write!(f, "sign-certificate"),
+
+            CollectGarbage      => write!(f, "collect-garbage"),
+            Quarantine          => write!(f, "quarantine"),
+            RebuildIndex        => write!(f, "rebuild-index"),
+            SetSuspended        => write!(f, "set-suspended"),
+            SetGroup            => write!(f, "set-group"),
+            RemoveGroup         => write!(f, "remove-group"),
+            AddMember           => write!(f, "add-member"),
+            RemoveMember        => write!(f, "remove-member"),
+            CreateInvite        => write!(f, "create-invite"),
+            RevokeInvite        => write!(f, "revoke-invite"),
+            IssueCertificate    => write!(f, "issue-certificate"),
+            RevokeCertificate   => write!(f, "revoke-certificate"),
+            UnrevokeCertificate => write!(f, "unrevoke-certificate"),
+            ClearBans           => write!(f, "clear-bans"),
+        }
+    }
+}
+
+impl FromStr for AuditOperation {
+    type Err = AuditOperationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match Self::ALL.iter().find(|op| op.to_string() == s) {
+            Some(op) => Ok(*op),
+            None     => Err(AuditOperationError::UnknownOperation{ raw: s.into() }),
+        }
+    }
+}
+
+
+
+/// Defines how an audited operation went.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    /// The operation succeeded.
+    Success,
+    /// The operation failed (or was refused) with the error that was sent back.
+    Failure{ code: ErrorCode, message: String },
+    /// The connection failed while the operation was handled, so whether it succeeded is not known.
+    Interrupted{ message: String },
+}
+
+impl Display for AuditOutcome {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use AuditOutcome::*;
+        match self {
+            Success                  => write!(f, "success"),
+            Failure{ code, message } => write!(f, "failed ({}: {})", code, message),
+            Interrupted{ message }   => write!(f, "interrupted ({})", message),
+        }
+    }
+}
+
+
+
+
+
+/***** TESTS *****/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_line() {
+        assert_eq!(hash_line(""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hash_line("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hash_line(GENESIS_HASH).len(), GENESIS_HASH.len());
+    }
+}
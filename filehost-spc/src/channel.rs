@@ -0,0 +1,47 @@
+/* CHANNEL.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:41:58
+ * Last edited:
+ *   19 Oct 2026, 04:41:58
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Defines channels, which are named pointers (like "latest" or
+ *   "stable") to a version of a package that may be moved over time.
+**/
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+
+/***** LIBRARY *****/
+/// Defines a channel of a package, together with its history.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Channel {
+    /// The name of the package this channel belongs to.
+    pub package : String,
+    /// The name of the channel.
+    pub name    : String,
+    /// The version the channel currently points to.
+    pub version : String,
+    /// Every time the channel was moved, oldest first.
+    pub history : Vec<ChannelMove>,
+}
+
+
+
+/// Defines a single move of a channel.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChannelMove {
+    /// The version the channel pointed to before, if any.
+    pub from : Option<String>,
+    /// The version the channel pointed to after.
+    pub to   : String,
+    /// The name of the user that moved the channel.
+    pub by   : String,
+    /// The moment the channel was moved.
+    pub time : DateTime<Utc>,
+}
@@ -0,0 +1,171 @@
+/* CHUNKING.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:59:41
+ * Last edited:
+ *   19 Oct 2026, 04:59:41
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements content-defined chunking (FastCDC) of files, which lets
+ *   files that change by a few bytes share most of their blobs.
+**/
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use fastcdc::v2020::{ChunkData, StreamCDC};
+use sha2::{Digest, Sha256};
+
+use crate::manifest::{BlobHash, HashError, ManifestChunk};
+
+
+/***** CONSTANTS *****/
+/// The minimum size of a chunk, in bytes.
+pub const MIN_CHUNK_SIZE: u32 = 16 * 1024;
+/// The size a chunk will have on average, in bytes.
+pub const AVG_CHUNK_SIZE: u32 = 64 * 1024;
+/// The maximum size of a chunk, in bytes.
+pub const MAX_CHUNK_SIZE: u32 = 256 * 1024;
+
+
+
+
+
+/***** LIBRARY *****/
+/// Splits everything in the given reader into content-defined chunks.
+///
+/// Because the cut points only depend on the contents around them, a change to a file only changes the chunks near it.
+///
+/// # Arguments
+/// - `reader`: The Read-capable reader to chunk until it's empty.
+///
+/// # Returns
+/// The hash of the complete contents, their size and the chunks they consist of (in order). Empty contents have no chunks.
+///
+/// # Errors
+/// This function errors if we failed to read from the reader.
+pub fn chunk_reader<R: Read>(reader: R) -> Result<(BlobHash, u64, Vec<ManifestChunk>), std::io::Error> {
+    let mut hasher = Sha256::new();
+    let mut size: u64 = 0;
+    let mut chunks: Vec<ManifestChunk> = Vec::new();
+    for chunk in StreamCDC::new(reader, MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE) {
+        let chunk: ChunkData = chunk?;
+        hasher.update(&chunk.data);
+        size += chunk.length as u64;
+        chunks.push(ManifestChunk{ hash: BlobHash::of_bytes(&chunk.data), size: chunk.length as u64 });
+    }
+    Ok((BlobHash(hasher.finalize().into()), size, chunks))
+}
+
+/// Splits the given file into content-defined chunks.
+///
+/// # Arguments
+/// - `path`: The Path(-like) of the file to chunk.
+///
+/// # Returns
+/// The hash of the file, its size and the chunks it consists of (in order).
+///
+/// # Errors
+/// This function errors if we failed to open or read the file.
+pub fn chunk_file<P: AsRef<Path>>(path: P) -> Result<(BlobHash, u64, Vec<ManifestChunk>), HashError> {
+    let path: &Path = path.as_ref();
+
+    // Open the file
+    let handle = match File::open(path) {
+        Ok(handle) => handle,
+        Err(err)   => { return Err(HashError::FileOpenError{ path: path.into(), err }); }
+    };
+
+    // Chunk it
+    match chunk_reader(handle) {
+        Ok(res)  => Ok(res),
+        Err(err) => Err(HashError::FileReadError{ path: path.into(), err }),
+    }
+}
+
+
+
+
+
+/***** TESTS *****/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Generates the given number of bytes that look random, but are the same for every run.
+    fn noise(n: usize, seed: u64) -> Vec<u8> {
+        let mut state: u64 = seed | 1;
+        (0..n).map(|_| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        }).collect()
+    }
+
+    /// Splits the given data into the slices that the given chunks describe.
+    fn split<'d>(data: &'d [u8], chunks: &[ManifestChunk]) -> Vec<&'d [u8]> {
+        let mut offset: usize = 0;
+        chunks.iter().map(|c| {
+            let slice: &[u8] = &data[offset..offset + c.size as usize];
+            offset += c.size as usize;
+            slice
+        }).collect()
+    }
+
+    #[test]
+    fn test_empty() {
+        let (hash, size, chunks) = chunk_reader(&[][..]).unwrap();
+        assert_eq!(hash, BlobHash::of_bytes(b""));
+        assert_eq!(size, 0);
+        assert!(chunks.is_empty());
+    }
+
+    #[test]
+    fn test_bounds() {
+        let data: Vec<u8> = noise(4 * 1024 * 1024, 42);
+        let (hash, size, chunks) = chunk_reader(&data[..]).unwrap();
+        assert_eq!(hash, BlobHash::of_bytes(&data));
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(chunks.iter().map(|c| c.size).sum::<u64>(), size);
+
+        // Only the last chunk may be smaller than the minimum
+        assert!(chunks.len() > 1);
+        for (i, chunk) in chunks.iter().enumerate() {
+            assert!(chunk.size <= MAX_CHUNK_SIZE as u64, "Chunk {} has {} bytes", i, chunk.size);
+            if i < chunks.len() - 1 { assert!(chunk.size >= MIN_CHUNK_SIZE as u64, "Chunk {} has {} bytes", i, chunk.size); }
+        }
+
+        // The chunks describe the data in order, so gluing them together gives back the original
+        let pieces: Vec<&[u8]> = split(&data, &chunks);
+        for (piece, chunk) in pieces.iter().zip(&chunks) { assert_eq!(BlobHash::of_bytes(piece), chunk.hash); }
+        assert_eq!(pieces.concat(), data);
+
+        // Data smaller than the minimum is a single chunk
+        let (_, _, chunks) = chunk_reader(&data[..1000]).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].hash, BlobHash::of_bytes(&data[..1000]));
+    }
+
+    #[test]
+    fn test_local_change() {
+        let data: Vec<u8> = noise(2 * 1024 * 1024, 7);
+        let mut changed: Vec<u8> = data.clone();
+        for byte in &mut changed[1024 * 1024..1024 * 1024 + 8] { *byte = !*byte; }
+        changed.splice(1536 * 1024..1536 * 1024, b"inserted".iter().copied());
+
+        // Only the chunks around the changes differ
+        let (_, _, before) = chunk_reader(&data[..]).unwrap();
+        let (_, _, after) = chunk_reader(&changed[..]).unwrap();
+        let new: Vec<&ManifestChunk> = after.iter().filter(|c| !before.contains(c)).collect();
+        assert!(!new.is_empty());
+        assert!(new.len() <= 4, "{} of {} chunks changed", new.len(), after.len());
+        assert_eq!(before.first(), after.first());
+        assert_eq!(before.last(), after.last());
+    }
+}
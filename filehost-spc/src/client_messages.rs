@@ -0,0 +1,603 @@
+/* CLIENT MESSAGES.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:26:37
+ * Last edited:
+ *   19 Oct 2026, 06:32:31
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Defines the layout & opcodes for messages between the client and the
+ *   daemon. These are sent as frames (see `frames.rs`) over the TLS
+ *   connection.
+**/
+
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter, Result as FResult};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::login::{Permissions, TokenInfo};
+use crate::manifest::{BlobHash, Manifest, ManifestDiff};
+
+
+/***** CONSTANTS *****/
+/// The maximum number of bytes of blob data the server sends in a single Data frame.
+pub const DATA_CHUNK_SIZE: usize = 64 * 1024;
+
+
+
+
+
+/***** ERRORS *****/
+#[derive(Debug)]
+pub enum OpcodeError {
+    /// Encountered an illegal value
+    UnknownValue{ raw: u8 },
+}
+
+impl Display for OpcodeError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use OpcodeError::*;
+        match self {
+            UnknownValue{ raw } => write!(f, "Encountered unknown client Opcode '{}'", raw),
+        }
+    }
+}
+
+impl Error for OpcodeError {}
+
+
+
+
+
+/***** ENUMS *****/
+/// Defines the opcodes of the frames sent between the client and the daemon.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Opcode {
+    /// Sent by the server if a request failed. Carries an `ErrorReply`.
+    Error = 0,
+
+    /// Asks the server for the manifest of a package version. Carries a `ManifestRequest`.
+    GetManifest = 1,
+    /// Sent by the server in response to `GetManifest`. Carries a `Manifest`.
+    Manifest    = 2,
+
+    /// Asks the server for a range of bytes of a blob. Carries a `DownloadRequest`.
+    Download       = 3,
+    /// Sent by the server in response to `Download`, before the data itself. Carries a `DownloadHeader`.
+    DownloadHeader = 4,
+    /// Carries a raw chunk of blob data.
+    Data           = 5,
+
+    /// Opens a new publish transaction. Carries a `BeginPublish`.
+    BeginPublish   = 6,
+    /// Asks the server for the state of an open publish transaction. Carries a `TransactionRequest`.
+    PublishStatus  = 7,
+    /// Sent by the server in response to `BeginPublish` and `PublishStatus`. Carries a `PublishState`.
+    PublishState   = 8,
+    /// Asks the server to accept (the rest of) a blob in a publish transaction. Carries an `UploadRequest`.
+    Upload         = 9,
+    /// Sent by the server when it is ready to receive the blob data of an `Upload` as `Data` frames. Carries an `UploadReady`.
+    UploadReady    = 10,
+    /// Sent by the server when it received and verified the blob of an `Upload`. Carries an `UploadComplete`.
+    UploadComplete = 11,
+    /// Asks the server to make the staged version visible. Carries a `TransactionRequest`.
+    Commit         = 12,
+    /// Sent by the server in response to `Commit`. Carries a `Committed`.
+    Committed      = 13,
+    /// Asks the server to discard a publish transaction. Carries a `TransactionRequest`.
+    Abort          = 14,
+    /// Sent by the server in response to `Abort`. Carries a `TransactionRequest`.
+    Aborted        = 15,
+
+    /// Asks the server which version a version or channel name refers to. Carries a `ResolveRequest`.
+    Resolve    = 16,
+    /// Sent by the server in response to `Resolve`. Carries a `Resolved`.
+    Resolved   = 17,
+    /// Asks the server for a channel and its history. Carries a `ChannelRequest`.
+    GetChannel = 18,
+    /// Sent by the server in response to `GetChannel` and `SetChannel`. Carries a `Channel`.
+    Channel    = 19,
+    /// Asks the server to point a channel to another version, creating it if needed. Carries a `SetChannelRequest`.
+    SetChannel = 20,
+
+    /// Asks the server how two versions of a package differ. Carries a `DiffRequest`.
+    GetDiff = 21,
+    /// Sent by the server in response to `GetDiff`. Carries a `Diff`.
+    Diff    = 22,
+
+    /// Asks the server to delete a package version. Carries a `DeleteRequest`.
+    Delete  = 23,
+    /// Sent by the server in response to `Delete`. Carries the `DeleteRequest`.
+    Deleted = 24,
+
+    /// Logs in with an API token instead of a client certificate. Only allowed as the guest user. Carries an `AuthenticateRequest`.
+    Authenticate  = 25,
+    /// Sent by the server in response to `Authenticate`. Carries the `login::TokenInfo` of the token.
+    Authenticated = 26,
+    /// Asks the server to create an API token for the user. Carries a `CreateTokenRequest`.
+    CreateToken   = 27,
+    /// Sent by the server in response to `CreateToken`. Carries a `NewToken`.
+    TokenCreated  = 28,
+    /// Asks the server for the API tokens of the user. Its payload is ignored.
+    GetTokens     = 29,
+    /// Sent by the server in response to `GetTokens`. Carries a list of `login::TokenInfo`s.
+    Tokens        = 30,
+    /// Asks the server to revoke an API token of the user. Carries a `TokenRequest`.
+    RevokeToken   = 31,
+    /// Sent by the server in response to `RevokeToken`. Carries the `login::TokenInfo` of the revoked token.
+    TokenRevoked  = 32,
+
+    /// Creates a new user with an invite code. Only allowed as the guest user. Carries a `RedeemRequest`.
+    Redeem   = 33,
+    /// Sent by the server in response to `Redeem`. Carries a `Redeemed`.
+    Redeemed = 34,
+
+    /// Asks the server's certificate authority to sign a certificate signing request for the user. Carries a `SignRequest`.
+    SignCertificate   = 35,
+    /// Sent by the server in response to `SignCertificate`. Carries a `SignedCertificate`.
+    CertificateSigned = 36,
+}
+
+impl Debug for Opcode {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(f, "{}", *self as u8)
+    }
+}
+
+impl Display for Opcode {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use Opcode::*;
+        match self {
+            Error => write!(f, "Opcode::Error"),
+
+            GetManifest => write!(f, "Opcode::GetManifest"),
+            Manifest    => write!(f, "Opcode::Manifest"),
+
+            Download       => write!(f, "Opcode::Download"),
+            DownloadHeader => write!(f, "Opcode::DownloadHeader"),
+            Data           => write!(f, "Opcode::Data"),
+
+            BeginPublish   => write!(f, "Opcode::BeginPublish"),
+            PublishStatus  => write!(f, "Opcode::PublishStatus"),
+            PublishState   => write!(f, "Opcode::PublishState"),
+            Upload         => write!(f, "Opcode::Upload"),
+            UploadReady    => write!(f, "Opcode::UploadReady"),
+            UploadComplete => write!(f, "Opcode::UploadComplete"),
+            Commit         => write!(f, "Opcode::Commit"),
+            Committed      => write!(f, "Opcode::Committed"),
+            Abort          => write!(f, "Opcode::Abort"),
+            Aborted        => write!(f, "Opcode::Aborted"),
+
+            Resolve    => write!(f, "Opcode::Resolve"),
+            Resolved   => write!(f, "Opcode::Resolved"),
+            GetChannel => write!(f, "Opcode::GetChannel"),
+            Channel    => write!(f, "Opcode::Channel"),
+            SetChannel => write!(f, "Opcode::SetChannel"),
+
+            GetDiff => write!(f, "Opcode::GetDiff"),
+            Diff    => write!(f, "Opcode::Diff"),
+
+            Delete  => write!(f, "Opcode::Delete"),
+            Deleted => write!(f, "Opcode::Deleted"),
+
+            Authenticate  => write!(f, "Opcode::Authenticate"),
+            Authenticated => write!(f, "Opcode::Authenticated"),
+            CreateToken   => write!(f, "Opcode::CreateToken"),
+            TokenCreated  => write!(f, "Opcode::TokenCreated"),
+            GetTokens     => write!(f, "Opcode::GetTokens"),
+            Tokens        => write!(f, "Opcode::Tokens"),
+            RevokeToken   => write!(f, "Opcode::RevokeToken"),
+            TokenRevoked  => write!(f, "Opcode::TokenRevoked"),
+
+            Redeem   => write!(f, "Opcode::Redeem"),
+            Redeemed => write!(f, "Opcode::Redeemed"),
+
+            SignCertificate   => write!(f, "Opcode::SignCertificate"),
+            CertificateSigned => write!(f, "Opcode::CertificateSigned"),
+        }
+    }
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = OpcodeError;
+
+    #[inline]
+    fn try_from(value: u8) -> Result<Self, OpcodeError> {
+        if value == u8::from(Opcode::Error) { Ok(Opcode::Error) }
+        else if value == u8::from(Opcode::GetManifest) { Ok(Opcode::GetManifest) }
+        else if value == u8::from(Opcode::Manifest) { Ok(Opcode::Manifest) }
+        else if value == u8::from(Opcode::Download) { Ok(Opcode::Download) }
+        else if value == u8::from(Opcode::DownloadHeader) { Ok(Opcode::DownloadHeader) }
+        else if value == u8::from(Opcode::Data) { Ok(Opcode::Data) }
+        else if value == u8::from(Opcode::BeginPublish) { Ok(Opcode::BeginPublish) }
+        else if value == u8::from(Opcode::PublishStatus) { Ok(Opcode::PublishStatus) }
+        else if value == u8::from(Opcode::PublishState) { Ok(Opcode::PublishState) }
+        else if value == u8::from(Opcode::Upload) { Ok(Opcode::Upload) }
+        else if value == u8::from(Opcode::UploadReady) { Ok(Opcode::UploadReady) }
+        else if value == u8::from(Opcode::UploadComplete) { Ok(Opcode::UploadComplete) }
+        else if value == u8::from(Opcode::Commit) { Ok(Opcode::Commit) }
+        else if value == u8::from(Opcode::Committed) { Ok(Opcode::Committed) }
+        else if value == u8::from(Opcode::Abort) { Ok(Opcode::Abort) }
+        else if value == u8::from(Opcode::Aborted) { Ok(Opcode::Aborted) }
+        else if value == u8::from(Opcode::Resolve) { Ok(Opcode::Resolve) }
+        else if value == u8::from(Opcode::Resolved) { Ok(Opcode::Resolved) }
+        else if value == u8::from(Opcode::GetChannel) { Ok(Opcode::GetChannel) }
+        else if value == u8::from(Opcode::Channel) { Ok(Opcode::Channel) }
+        else if value == u8::from(Opcode::SetChannel) { Ok(Opcode::SetChannel) }
+        else if value == u8::from(Opcode::GetDiff) { Ok(Opcode::GetDiff) }
+        else if value == u8::from(Opcode::Diff) { Ok(Opcode::Diff) }
+        else if value == u8::from(Opcode::Delete) { Ok(Opcode::Delete) }
+        else if value == u8::from(Opcode::Deleted) { Ok(Opcode::Deleted) }
+        else if value == u8::from(Opcode::Authenticate) { Ok(Opcode::Authenticate) }
+        else if value == u8::from(Opcode::Authenticated) { Ok(Opcode::Authenticated) }
+        else if value == u8::from(Opcode::CreateToken) { Ok(Opcode::CreateToken) }
+        else if value == u8::from(Opcode::TokenCreated) { Ok(Opcode::TokenCreated) }
+        else if value == u8::from(Opcode::GetTokens) { Ok(Opcode::GetTokens) }
+        else if value == u8::from(Opcode::Tokens) { Ok(Opcode::Tokens) }
+        else if value == u8::from(Opcode::RevokeToken) { Ok(Opcode::RevokeToken) }
+        else if value == u8::from(Opcode::TokenRevoked) { Ok(Opcode::TokenRevoked) }
+        else if value == u8::from(Opcode::Redeem) { Ok(Opcode::Redeem) }
+        else if value == u8::from(Opcode::Redeemed) { Ok(Opcode::Redeemed) }
+        else if value == u8::from(Opcode::SignCertificate) { Ok(Opcode::SignCertificate) }
+        else if value == u8::from(Opcode::CertificateSigned) { Ok(Opcode::CertificateSigned) }
+        else { Err(OpcodeError::UnknownValue{ raw: value }) }
+    }
+}
+
+impl From<Opcode> for u8 {
+    #[inline]
+    fn from(value: Opcode) -> Self {
+        value as u8
+    }
+}
+
+
+
+/// Defines the kinds of errors the server may reply with.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum ErrorCode {
+    /// The request was malformed or not expected at this point.
+    InvalidRequest,
+    /// The user is not allowed to perform this request.
+    PermissionDenied,
+    /// The requested package, version or blob does not exist.
+    NotFound,
+    /// The requested byte range is out of bounds for the blob.
+    InvalidRange,
+    /// The request conflicts with the state of the server (e.g., the version already exists).
+    Conflict,
+    /// Uploaded data does not match the hash it was announced with.
+    HashMismatch,
+    /// The request would exceed the quota of the user or the package.
+    QuotaExceeded,
+    /// The client sent more requests than its rate limit allows; it may try again later.
+    RateLimited,
+    /// Something went wrong on the server's end.
+    Internal,
+}
+
+impl Display for ErrorCode {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use ErrorCode::*;
+        match self {
+            InvalidRequest   => write!(f, "invalid request"),
+            PermissionDenied => write!(f, "permission denied"),
+            NotFound         => write!(f, "not found"),
+            InvalidRange     => write!(f, "invalid range"),
+            Conflict         => write!(f, "conflict"),
+            HashMismatch     => write!(f, "hash mismatch"),
+            QuotaExceeded    => write!(f, "quota exceeded"),
+            RateLimited      => write!(f, "rate limited"),
+            Internal         => write!(f, "internal server error"),
+        }
+    }
+}
+
+
+
+
+
+/***** MESSAGES *****/
+/// Sent by the server when a request fails.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ErrorReply {
+    /// The kind of error that occurred.
+    pub code    : ErrorCode,
+    /// A human-readable explanation of the error.
+    pub message : String,
+}
+
+impl Display for ErrorReply {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+
+
+/// Asks the server for the manifest of a specific package version.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ManifestRequest {
+    /// The name of the package.
+    pub package : String,
+    /// The version of the package.
+    pub version : String,
+}
+
+
+
+/// Asks the server for a range of bytes of a blob.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DownloadRequest {
+    /// The hash of the blob to download.
+    pub hash   : BlobHash,
+    /// The offset (in bytes) of the first byte to download.
+    pub offset : u64,
+    /// The number of bytes to download. If omitted, downloads until the end of the blob.
+    pub length : Option<u64>,
+}
+
+/// Sent by the server before it streams the requested range as `Data` frames.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DownloadHeader {
+    /// The hash of the blob that is being sent.
+    pub hash   : BlobHash,
+    /// The total size of the blob, in bytes.
+    pub size   : u64,
+    /// The offset of the first byte that will be sent.
+    pub offset : u64,
+    /// The number of bytes that will be sent in total.
+    pub length : u64,
+}
+
+
+
+/// Opens a new publish transaction for a package version.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BeginPublish {
+    /// The manifest of the version to publish. Its blobs will have to be uploaded before it can be committed.
+    pub manifest : Manifest,
+}
+
+/// Refers to an open publish transaction.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TransactionRequest {
+    /// The identifier of the transaction.
+    pub transaction : String,
+}
+
+/// Describes the state of an open publish transaction.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PublishState {
+    /// The identifier of the transaction.
+    pub transaction : String,
+    /// The package that is being published.
+    pub package     : String,
+    /// The version that is being published.
+    pub version     : String,
+    /// The blobs that the server still needs before the transaction can be committed.
+    pub needed      : Vec<NeededBlob>,
+}
+
+/// Describes a blob that the server still needs to receive in a publish transaction.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NeededBlob {
+    /// The hash of the blob.
+    pub hash   : BlobHash,
+    /// The total size of the blob.
+    pub size   : u64,
+    /// The number of bytes the server already received, i.e., the offset to continue uploading at.
+    pub offset : u64,
+}
+
+
+
+/// Asks the server to accept (the rest of) a blob in a publish transaction.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UploadRequest {
+    /// The identifier of the transaction.
+    pub transaction : String,
+    /// The hash of the blob to upload.
+    pub hash        : BlobHash,
+    /// The offset of the first byte that will be uploaded. Must equal the number of bytes the server already has.
+    pub offset      : u64,
+}
+
+/// Sent by the server when it is ready to receive the data of an upload.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UploadReady {
+    /// The hash of the blob that will be received.
+    pub hash   : BlobHash,
+    /// The offset of the first byte that will be received.
+    pub offset : u64,
+    /// The number of bytes the server expects to receive as `Data` frames.
+    pub length : u64,
+}
+
+/// Sent by the server when it received and verified a blob.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UploadComplete {
+    /// The hash of the blob that was received.
+    pub hash : BlobHash,
+}
+
+
+
+/// Sent by the server when a publish transaction was committed.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Committed {
+    /// The package that was published.
+    pub package : String,
+    /// The version that was published.
+    pub version : String,
+}
+
+
+
+/// Asks the server which version the given name refers to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ResolveRequest {
+    /// The name of the package.
+    pub package : String,
+    /// The name to resolve, which is either a version or a channel.
+    pub name    : String,
+}
+
+/// Sent by the server with the version that a name refers to.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Resolved {
+    /// The name of the package.
+    pub package : String,
+    /// The version the name refers to.
+    pub version : String,
+    /// The channel the name was resolved through, if it was not a version itself.
+    pub channel : Option<String>,
+}
+
+/// Asks the server for a specific channel.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChannelRequest {
+    /// The name of the package.
+    pub package : String,
+    /// The name of the channel.
+    pub channel : String,
+}
+
+/// Asks the server to point a channel to another version.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetChannelRequest {
+    /// The name of the package.
+    pub package : String,
+    /// The name of the channel.
+    pub channel : String,
+    /// The version to point the channel to. Must exist.
+    pub version : String,
+}
+
+
+
+/// Asks the server how two versions of a package differ.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DiffRequest {
+    /// The name of the package.
+    pub package : String,
+    /// The version (or channel) to compare from.
+    pub from    : String,
+    /// The version (or channel) to compare to.
+    pub to      : String,
+}
+
+/// Sent by the server with the differences between two versions.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Diff {
+    /// The name of the package.
+    pub package : String,
+    /// The version that was compared from, with any channel resolved.
+    pub from    : String,
+    /// The version that was compared to, with any channel resolved.
+    pub to      : String,
+    /// The differences between the two versions.
+    pub diff    : ManifestDiff,
+}
+
+
+
+/// Asks the server to delete a package version. Versions that a channel points to cannot be deleted.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeleteRequest {
+    /// The name of the package.
+    pub package : String,
+    /// The version to delete.
+    pub version : String,
+}
+
+
+
+/// Logs in with an API token.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuthenticateRequest {
+    /// The full token, as it was given when it was created.
+    pub token : String,
+}
+
+/// Asks the server to create an API token.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateTokenRequest {
+    /// The permissions that sessions logged in with the token are limited to. They never exceed those of the user itself.
+    pub permissions : Permissions,
+    /// The number of seconds the token remains valid.
+    pub valid_for   : u64,
+    /// What the token is used for.
+    pub description : String,
+}
+
+/// Sent by the server with a newly created API token.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NewToken {
+    /// Describes the token.
+    pub info  : TokenInfo,
+    /// The full token to log in with. The server only stores its hash, so it cannot be retrieved again.
+    pub token : String,
+}
+
+/// Refers to an API token.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenRequest {
+    /// The public identifier of the token.
+    pub id : String,
+}
+
+
+
+/// Creates a new user with an invite code.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RedeemRequest {
+    /// The invite code, as it was given by an administrator.
+    pub code        : String,
+    /// The username of the new user.
+    pub username    : String,
+    /// The PEM-encoded certificate(s) that the new user will log in with, or a PEM-encoded certificate signing request for the server's certificate authority to sign.
+    pub certificate : String,
+}
+
+/// Sent by the server when a new user was created with an invite code.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Redeemed {
+    /// The username of the new user.
+    pub username    : String,
+    /// The permissions of the new user.
+    pub permissions : Permissions,
+    /// The groups the new user was added to.
+    pub groups      : Vec<String>,
+    /// The PEM-encoded certificate that the server's certificate authority issued, if the request carried a certificate signing request.
+    pub certificate : Option<String>,
+}
+
+
+
+/// Asks the server's certificate authority to sign a certificate signing request. The subject of the request is ignored; the certificate is always issued to the user that asks.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SignRequest {
+    /// The PEM-encoded certificate signing request.
+    pub csr : String,
+}
+
+/// Sent by the server with a certificate that its certificate authority issued.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SignedCertificate {
+    /// The PEM-encoded certificate.
+    pub certificate : String,
+    /// The moment after which the certificate is no longer valid.
+    pub expires     : DateTime<Utc>,
+}
@@ -4,7 +4,7 @@
  * Created:
  *   06 Jun 2022, 10:06:15
  * Last edited:
- *   11 Jun 2022, 15:30:25
+ *   19 Oct 2026, 06:59:44
  * Auto updated?
  *   Yes
  *
@@ -13,6 +13,7 @@
  *   between the daemon and the CTL.
 **/
 
+use std::collections::HashMap;
 use std::error;
 use std::fmt::{Display, Formatter, Result as FResult};
 use std::fs::File;
@@ -22,6 +23,9 @@ use std::path::{Path, PathBuf};
 use log::LevelFilter;
 use serde::{Deserialize, Serialize};
 
+use crate::login::Permissions;
+use crate::quota::Quota;
+
 
 /***** ERRORS *****/
 /// Defines errors that relate to parsing the Config.
@@ -88,6 +92,55 @@ impl error::Error for Error {}
 
 
 
+/***** DEFAULTS *****/
+/// The default directory where the blobs and package manifests are stored (the same as `setup.sh` uses).
+#[inline]
+fn default_data_dir() -> PathBuf { PathBuf::from("/var/lib/filehost") }
+
+/// The default number of seconds a publish transaction may remain idle before it is discarded.
+#[inline]
+fn default_publish_timeout() -> u64 { 3600 }
+
+/// The default number of seconds a blob is kept after it was last touched, even if nothing refers to it.
+#[inline]
+fn default_gc_grace_period() -> u64 { 86400 }
+
+/// The default number of seconds between two applications of the retention policies.
+#[inline]
+fn default_retention_interval() -> u64 { 3600 }
+
+/// The default number of seconds that client certificates issued by the certificate authority remain valid.
+#[inline]
+fn default_cert_validity() -> u64 { 365 * 86400 }
+
+/// The default local users that may manage the daemon over the CTL socket.
+#[inline]
+fn default_ctl_root_users() -> Vec<String> { vec![ "root".into() ] }
+
+/// The default number of days before a certificate expires at which the daemon starts warning about it.
+#[inline]
+fn default_cert_warn_days() -> u64 { 30 }
+
+/// The default number of days before a certificate expires at which the daemon starts logging it as an error.
+#[inline]
+fn default_cert_critical_days() -> u64 { 7 }
+
+/// The default number of failed authentications and invalid messages after which an address is banned.
+#[inline]
+fn default_ban_threshold() -> u32 { 10 }
+
+/// The default number of seconds within which those failures must happen for a ban.
+#[inline]
+fn default_ban_window() -> u64 { 600 }
+
+/// The default number of seconds that an address remains banned.
+#[inline]
+fn default_ban_duration() -> u64 { 3600 }
+
+
+
+
+
 /***** LIBRARY *****/
 /// Defines the parsed configuration file of the server, which is shared between the daemon and the CTL.
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -95,17 +148,84 @@ pub struct Config {
     /// The log level to apply.
     pub log_level   : LevelFilter,
 
-    /// The location of the users database.
+    /// The location of the users database. If the users live in the metadata database, the users in this file are imported once when that database has none yet.
     pub user_db     : PathBuf,
     /// The location of the server SSL certificate.
     pub server_cert : PathBuf,
     /// The location of the server private key.
     pub server_key  : PathBuf,
+    /// Where to find the passphrase of `server_key`, if it is an encrypted PKCS#8 key.
+    #[serde(default)]
+    pub server_key_passphrase : Option<PassphraseSource>,
+    /// The location of the certificate of the certificate authority that issues client certificates, if the daemon has one.
+    #[serde(default)]
+    pub ca_cert     : Option<PathBuf>,
+    /// The location of the (PKCS#8) private key that belongs to `ca_cert`.
+    #[serde(default)]
+    pub ca_key      : Option<PathBuf>,
+    /// The directory where the blobs and package manifests are stored.
+    #[serde(default = "default_data_dir")]
+    pub data_dir    : PathBuf,
+
+    /// Where the daemon keeps its users.
+    #[serde(default)]
+    pub user_backend : UserBackend,
 
     /// The socket path to listen for.
     pub socket_path : PathBuf,
+    /// Which local users may use the CTL socket, and as whom.
+    #[serde(default)]
+    pub ctl_access  : CtlAccess,
     /// The address:port to listen on.
     pub listen_addr : String,
+    /// The TLS policy for client connections.
+    #[serde(default)]
+    pub tls         : TlsConfig,
+    /// How much clients may ask of the daemon, and when misbehaving clients are banned. Applies to the daemon as a whole, so only that of the main host counts.
+    #[serde(default)]
+    pub limits      : Limits,
+
+    /// The number of seconds a publish transaction may remain idle before it is discarded.
+    #[serde(default = "default_publish_timeout")]
+    pub publish_timeout : u64,
+    /// The number of seconds the garbage collector keeps a blob after it was last touched, even if nothing refers to it.
+    #[serde(default = "default_gc_grace_period")]
+    pub gc_grace_period : u64,
+    /// If given, the daemon scrubs the storage every this many seconds.
+    #[serde(default)]
+    pub fsck_interval   : Option<u64>,
+    /// Whether scheduled scrubs move corrupt blobs to the quarantine directory.
+    #[serde(default)]
+    pub fsck_quarantine : bool,
+
+    /// The retention policy of every package that has one, by package name.
+    #[serde(default)]
+    pub retention          : HashMap<String, RetentionPolicy>,
+    /// The number of seconds between two applications of the retention policies.
+    #[serde(default = "default_retention_interval")]
+    pub retention_interval : u64,
+
+    /// The number of seconds that client certificates issued by the certificate authority remain valid.
+    #[serde(default = "default_cert_validity")]
+    pub cert_validity      : u64,
+    /// The number of days before a certificate (of the server, the certificate authority or a user) expires at which the daemon starts warning about it.
+    #[serde(default = "default_cert_warn_days")]
+    pub cert_warn_days     : u64,
+    /// The number of days before a certificate expires at which the daemon logs it as an error instead.
+    #[serde(default = "default_cert_critical_days")]
+    pub cert_critical_days : u64,
+
+    /// The quota of every package that has one, by package name.
+    #[serde(default)]
+    pub quotas : HashMap<String, Quota>,
+
+    /// The access control list of every package that has one, by package name.
+    #[serde(default)]
+    pub acls : HashMap<String, Acl>,
+
+    /// The virtual hosts that the daemon serves next to this (main) host, by name. Clients select one with the server name they send (SNI); clients that send none, or one that no virtual host serves, get the main host.
+    #[serde(default)]
+    pub vhosts : HashMap<String, VirtualHost>,
 }
 
 impl Config {
@@ -333,4 +453,277 @@ impl Config {
         // Nothing for now
         Ok(())
     }
+
+
+
+    /// Derives the configuration of a virtual host, i.e., this config with the settings that the virtual host overrides.
+    /// 
+    /// # Arguments
+    /// - `vhost`: The virtual host to derive the configuration of.
+    /// 
+    /// # Returns
+    /// A new Config, which has no virtual hosts itself.
+    pub fn for_vhost(&self, vhost: &VirtualHost) -> Self {
+        Self {
+            user_db               : vhost.user_db.clone().unwrap_or_else(|| self.user_db.clone()),
+            server_cert           : vhost.server_cert.clone(),
+            server_key            : vhost.server_key.clone(),
+            server_key_passphrase : vhost.server_key_passphrase.clone(),
+            ca_cert               : if vhost.has_own_users() { vhost.ca_cert.clone() } else { self.ca_cert.clone() },
+            ca_key                : if vhost.has_own_users() { vhost.ca_key.clone() } else { self.ca_key.clone() },
+            data_dir              : vhost.data_dir.clone(),
+            user_backend          : if vhost.has_own_users() { vhost.user_backend } else { self.user_backend },
+
+            retention : vhost.retention.clone(),
+            quotas    : vhost.quotas.clone(),
+            acls      : vhost.acls.clone(),
+            vhosts    : HashMap::new(),
+
+            ..self.clone()
+        }
+    }
+}
+
+
+
+/// Defines a virtual host, which the daemon serves on the same address as the main host but with its own certificate and its own packages.
+///
+/// Everything that is not given here (e.g., the TLS policy and the timeouts) is taken from the main host.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct VirtualHost {
+    /// The server names that clients send (with SNI) to reach this host.
+    pub server_names : Vec<String>,
+    /// The location of the SSL certificate of this host.
+    pub server_cert  : PathBuf,
+    /// The location of the private key of this host.
+    pub server_key   : PathBuf,
+    /// Where to find the passphrase of `server_key`, if it is an encrypted PKCS#8 key.
+    #[serde(default)]
+    pub server_key_passphrase : Option<PassphraseSource>,
+    /// The directory where the blobs, packages and metadata of this host are stored. No other host may use it.
+    pub data_dir     : PathBuf,
+
+    /// If given, this host has its own users, which live in this file (or are imported from it if they live in the metadata database). Otherwise, it shares the users of the main host.
+    #[serde(default)]
+    pub user_db      : Option<PathBuf>,
+    /// Where this host keeps its own users. Ignored if it shares the users of the main host.
+    #[serde(default)]
+    pub user_backend : UserBackend,
+    /// The location of the certificate of the certificate authority of this host, if it has one. Only used if the host has its own users; otherwise it shares the certificate authority of the main host too.
+    #[serde(default)]
+    pub ca_cert      : Option<PathBuf>,
+    /// The location of the (PKCS#8) private key that belongs to `ca_cert`.
+    #[serde(default)]
+    pub ca_key       : Option<PathBuf>,
+
+    /// The retention policy of every package of this host that has one, by package name.
+    #[serde(default)]
+    pub retention : HashMap<String, RetentionPolicy>,
+    /// The quota of every package of this host that has one, by package name.
+    #[serde(default)]
+    pub quotas    : HashMap<String, Quota>,
+    /// The access control list of every package of this host that has one, by package name.
+    #[serde(default)]
+    pub acls      : HashMap<String, Acl>,
+}
+
+impl VirtualHost {
+    /// Returns whether this host has its own users instead of sharing those of the main host.
+    #[inline]
+    pub fn has_own_users(&self) -> bool { self.user_db.is_some() }
+
+    /// Returns whether clients reach this host with the given server name. Like DNS names, server names are case-insensitive.
+    #[inline]
+    pub fn serves(&self, server_name: &str) -> bool { self.server_names.iter().any(|name| name.eq_ignore_ascii_case(server_name)) }
+}
+
+
+
+/// Defines which versions of a package are kept when old versions are pruned.
+///
+/// A version is kept if any of the rules keeps it. Versions that a channel points to are always kept. If neither `keep_last` nor `keep_days` is given, nothing is pruned.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RetentionPolicy {
+    /// If given, keeps this many of the most recently published versions.
+    #[serde(default)]
+    pub keep_last : Option<usize>,
+    /// If given, keeps the versions that were published less than this many days ago.
+    #[serde(default)]
+    pub keep_days : Option<u64>,
+    /// Versions that are always kept.
+    #[serde(default)]
+    pub pinned    : Vec<String>,
+}
+
+impl RetentionPolicy {
+    /// Returns whether this policy prunes anything at all.
+    #[inline]
+    pub fn prunes(&self) -> bool { self.keep_last.is_some() || self.keep_days.is_some() }
+}
+
+
+
+/// Defines who may do what with a package.
+///
+/// Once a package has an access control list, the permissions of the users themselves no longer count for it: only the root user and the grants in the list do. The permissions are those of `login::Permissions`, where `download` means reading the package and `upload` means publishing to it.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Acl {
+    /// Whether anonymous clients (i.e., the guest user) may read the package.
+    #[serde(default)]
+    pub guest_read : bool,
+    /// What the users with the given names may do.
+    #[serde(default)]
+    pub users      : HashMap<String, Permissions>,
+    /// What the members of the groups with the given names may do.
+    #[serde(default)]
+    pub groups     : HashMap<String, Permissions>,
+}
+
+
+
+/// Defines which local (unix) users may use the CTL socket, and as whom.
+///
+/// The daemon recognizes local users by the credentials of their connection (`SO_PEERCRED`), so this relies on the permissions of the socket itself only to keep out other machines.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CtlAccess {
+    /// The local users that manage the daemon as the root user, by name.
+    #[serde(default = "default_ctl_root_users")]
+    pub root_users  : Vec<String>,
+    /// The local groups whose members manage the daemon as the root user, by name.
+    #[serde(default)]
+    pub root_groups : Vec<String>,
+    /// Maps other local users (by name) to the user they act as (by name). They may only look at the packages they may download and at their own account.
+    #[serde(default)]
+    pub users       : HashMap<String, String>,
+}
+
+impl Default for CtlAccess {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            root_users  : default_ctl_root_users(),
+            root_groups : vec![],
+            users       : HashMap::new(),
+        }
+    }
+}
+
+
+
+/// Defines how the daemon secures client connections.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TlsConfig {
+    /// The oldest TLS version that clients may use.
+    #[serde(default)]
+    pub min_version    : TlsVersion,
+    /// The cipher suites that clients may use, by their IANA name (e.g., `TLS13_AES_256_GCM_SHA384`). If empty, all cipher suites that rustls deems safe are allowed.
+    #[serde(default)]
+    pub cipher_suites  : Vec<String>,
+    /// The ALPN protocols the daemon accepts, in order of preference. If empty, ALPN is not used.
+    #[serde(default)]
+    pub alpn_protocols : Vec<String>,
+    /// Whether clients must present a certificate.
+    #[serde(default)]
+    pub client_auth    : ClientAuth,
+    /// Whether to write the secrets of every TLS session to the file named by the `SSLKEYLOGFILE` environment variable. Only meant for debugging: anyone who can read that file can decrypt all traffic.
+    #[serde(default)]
+    pub key_log        : bool,
+}
+
+
+
+/// Defines how much clients may ask of the daemon, and when misbehaving clients are banned.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Limits {
+    /// The rate limit of every source address.
+    #[serde(default)]
+    pub per_address   : RateLimit,
+    /// The rate limit of every authenticated user. Anonymous clients (i.e., the guest user) are only limited by their address.
+    #[serde(default)]
+    pub per_user      : RateLimit,
+    /// The number of failed authentications and invalid messages after which an address is banned. 0 disables banning.
+    #[serde(default = "default_ban_threshold")]
+    pub ban_threshold : u32,
+    /// The number of seconds within which those must happen.
+    #[serde(default = "default_ban_window")]
+    pub ban_window    : u64,
+    /// The number of seconds that an address remains banned.
+    #[serde(default = "default_ban_duration")]
+    pub ban_duration  : u64,
+}
+
+impl Default for Limits {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            per_address   : RateLimit::default(),
+            per_user      : RateLimit::default(),
+            ban_threshold : default_ban_threshold(),
+            ban_window    : default_ban_window(),
+            ban_duration  : default_ban_duration(),
+        }
+    }
+}
+
+/// Defines a rate limit as token buckets, which allow bursts of up to a second's worth. Omitted rates are unlimited.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct RateLimit {
+    /// The number of requests per second. For addresses, new connections count as requests too.
+    #[serde(default)]
+    pub requests_per_sec : Option<u32>,
+    /// The number of bytes per second with which blobs are downloaded and uploaded.
+    #[serde(default)]
+    pub bytes_per_sec    : Option<u64>,
+}
+
+
+
+/// Defines where the daemon finds the passphrase of an encrypted private key.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PassphraseSource {
+    /// The passphrase is the contents of the file at the given path, without any trailing newline.
+    File(PathBuf),
+    /// The passphrase is the value of the environment variable with the given name.
+    Env(String),
+    /// The passphrase is the systemd credential with the given name (see `LoadCredential=` in `systemd.exec(5)`), i.e., the file with that name in `$CREDENTIALS_DIRECTORY`.
+    Credential(String),
+}
+
+
+
+/// Defines the TLS versions that the daemon can be limited to.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum TlsVersion {
+    /// TLS 1.2 and newer.
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    /// TLS 1.3 only.
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// Defines whether clients must present a certificate.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuth {
+    /// Clients without a certificate are let in as the guest user.
+    #[default]
+    Optional,
+    /// Clients without a certificate are refused, so the guest user (and API tokens and invite codes, which need it to log in) cannot be used.
+    Required,
+}
+
+
+
+/// Defines where the daemon keeps its users.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserBackend {
+    /// The users live in the JSON file at `user_db`, which is rewritten on every change. Fine for small installs.
+    #[default]
+    Json,
+    /// The users live in the SQLite metadata database in the data directory.
+    Sqlite,
 }
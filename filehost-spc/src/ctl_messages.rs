@@ -4,7 +4,7 @@
  * Created:
  *   06 Jun 2022, 14:22:44
  * Last edited:
- *   11 Jun 2022, 11:28:21
+ *   19 Oct 2026, 07:03:36
  * Auto updated?
  *   Yes
  *
@@ -15,6 +15,16 @@
 
 use std::error::Error;
 use std::fmt::{Debug, Display, Formatter, Result as FResult};
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditOperation;
+use crate::login::{Permissions, UserId};
+use crate::manifest::BlobHash;
+use crate::quota::{Quota, Usage};
 
 
 /***** CONSTANTS *****/
@@ -53,11 +63,129 @@ impl Error for OpcodeError {}
 
 /***** ENUMS *****/
 /// Defines operational opcodes.
+///
+/// Apart from `Health`, which is a single byte answered by `HEALTH_REPLY`, every message is sent as a frame (see `frames.rs`) whose opcode is one of these.
 #[derive(Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Opcode {
     /// Asks the server if it's alive
     Health = 0,
+    /// Sent by the server if a request failed. Carries a `client_messages::ErrorReply`.
+    Error  = 1,
+
+    /// Asks the server which version a version or channel name refers to. Carries a `client_messages::ResolveRequest`.
+    Resolve    = 2,
+    /// Sent by the server in response to `Resolve`. Carries a `client_messages::Resolved`.
+    Resolved   = 3,
+    /// Asks the server for a channel and its history. Carries a `client_messages::ChannelRequest`.
+    GetChannel = 4,
+    /// Sent by the server in response to `GetChannel`. Carries a `channel::Channel`.
+    Channel    = 5,
+
+    /// Asks the server to remove the blobs that no package version or transaction refers to. Carries a `GcRequest`.
+    CollectGarbage   = 6,
+    /// Sent by the server in response to `CollectGarbage`. Carries a `GcReport`.
+    GarbageCollected = 7,
+    /// Asks the server to check the integrity of its storage. Carries a `FsckRequest`.
+    Fsck             = 8,
+    /// Sent by the server in response to `Fsck`. Carries a `FsckReport`.
+    FsckReport       = 9,
+
+    /// Asks the server for a user, its quota and its usage. Carries a `UserRequest`.
+    GetUser = 10,
+    /// Sent by the server in response to `GetUser`. Carries a `UserInfo`.
+    User    = 11,
+
+    /// Asks the server to rebuild its metadata index from the package database. Its payload is ignored.
+    RebuildIndex = 12,
+    /// Sent by the server in response to `RebuildIndex`. Carries an `IndexReport`.
+    IndexRebuilt = 13,
+
+    /// Asks the server for all groups. Its payload is ignored.
+    GetGroups    = 14,
+    /// Sent by the server in response to `GetGroups`. Carries a list of `GroupInfo`s.
+    Groups       = 15,
+    /// Asks the server to create a group or change its permissions. Carries a `SetGroupRequest`.
+    SetGroup     = 16,
+    /// Sent by the server in response to `SetGroup`, `RemoveGroup`, `AddMember` and `RemoveMember`. Carries the `GroupInfo` of the group as it is now (or was, if it was removed).
+    Group        = 17,
+    /// Asks the server to remove a group. Carries a `GroupRequest`.
+    RemoveGroup  = 18,
+    /// Asks the server to add a user to a group. Carries a `MemberRequest`.
+    AddMember    = 19,
+    /// Asks the server to remove a user from a group. Carries a `MemberRequest`.
+    RemoveMember = 20,
+
+    /// Asks the server for the API tokens of a user. Carries a `UserRequest`.
+    GetTokens    = 21,
+    /// Sent by the server in response to `GetTokens`. Carries a list of `login::TokenInfo`s.
+    Tokens       = 22,
+    /// Asks the server to revoke an API token. Carries a `client_messages::TokenRequest`.
+    RevokeToken  = 23,
+    /// Sent by the server in response to `RevokeToken`. Carries the `login::TokenInfo` of the revoked token.
+    TokenRevoked = 24,
+
+    /// Asks the server for all invite codes that have not been redeemed yet. Its payload is ignored.
+    GetInvites    = 25,
+    /// Sent by the server in response to `GetInvites`. Carries a list of `InviteInfo`s.
+    Invites       = 26,
+    /// Asks the server to create an invite code. Carries a `CreateInviteRequest`.
+    CreateInvite  = 27,
+    /// Sent by the server in response to `CreateInvite`. Carries a `NewInvite`.
+    InviteCreated = 28,
+    /// Asks the server to revoke an invite code. Carries an `InviteRequest`.
+    RevokeInvite  = 29,
+    /// Sent by the server in response to `RevokeInvite`. Carries the `InviteInfo` of the revoked code.
+    InviteRevoked = 30,
+
+    /// Asks the server's certificate authority to issue a client certificate to a user. Carries an `IssueRequest`.
+    IssueCertificate  = 31,
+    /// Sent by the server in response to `IssueCertificate`. Carries an `IssuedCertificate`.
+    CertificateIssued = 32,
+
+    /// Asks the server for its certificate revocation list. Its payload is ignored.
+    GetRevocations       = 33,
+    /// Sent by the server in response to `GetRevocations`. Carries a list of `RevocationInfo`s.
+    Revocations          = 34,
+    /// Asks the server to revoke a client certificate. Carries a `RevokeRequest`.
+    RevokeCertificate    = 35,
+    /// Sent by the server in response to `RevokeCertificate`. Carries the `RevocationInfo` of the revocation.
+    CertificateRevoked   = 36,
+    /// Asks the server to take a certificate off its revocation list. Carries a `RevocationRequest`.
+    UnrevokeCertificate  = 37,
+    /// Sent by the server in response to `UnrevokeCertificate`. Carries the `RevocationInfo` of the removed revocation.
+    CertificateUnrevoked = 38,
+
+    /// Asks the server to suspend a user or lift its suspension. Carries a `SuspendRequest`. Answered with a `User`.
+    SetSuspended = 39,
+
+    /// Tells the server that the next request on this connection is about the virtual host with the given name instead of the main host. Carries a `HostRequest`.
+    SelectHost   = 40,
+    /// Sent by the server in response to `SelectHost`. Its payload is empty.
+    HostSelected = 41,
+
+    /// Asks the server for the certificates it uses or trusts and when they expire. Carries a `CertStatusRequest`.
+    GetCertificates = 42,
+    /// Sent by the server in response to `GetCertificates`. Carries a list of `CertificateStatus`es, the one that expires first first.
+    Certificates    = 43,
+
+    /// Asks the server for the addresses it has banned. Its payload is ignored.
+    GetBans     = 44,
+    /// Sent by the server in response to `GetBans`. Carries a list of `BanInfo`s.
+    Bans        = 45,
+    /// Asks the server to lift bans. Carries a `ClearBansRequest`.
+    ClearBans   = 46,
+    /// Sent by the server in response to `ClearBans`. Carries a list of the `BanInfo`s of the lifted bans.
+    BansCleared = 47,
+
+    /// Asks the server for entries of its audit log. Carries an `AuditRequest`.
+    GetAudit      = 48,
+    /// Sent by the server in response to `GetAudit`. Carries a list of `audit::AuditEntry`s, oldest first.
+    Audit         = 49,
+    /// Asks the server to check the hash chain of its audit log. Its payload is ignored.
+    VerifyAudit   = 50,
+    /// Sent by the server in response to `VerifyAudit`. Carries an `AuditReport`.
+    AuditVerified = 51,
 }
 
 impl Debug for Opcode {
@@ -73,6 +201,71 @@ impl Display for Opcode {
         use Opcode::*;
         match self {
             Health => write!(f, "Opcode::Health"),
+            Error  => write!(f, "Opcode::Error"),
+
+            Resolve    => write!(f, "Opcode::Resolve"),
+            Resolved   => write!(f, "Opcode::Resolved"),
+            GetChannel => write!(f, "Opcode::GetChannel"),
+            Channel    => write!(f, "Opcode::Channel"),
+
+            CollectGarbage   => write!(f, "Opcode::CollectGarbage"),
+            GarbageCollected => write!(f, "Opcode::GarbageCollected"),
+            Fsck             => write!(f, "Opcode::Fsck"),
+            FsckReport       => write!(f, "Opcode::FsckReport"),
+
+            GetUser => write!(f, "Opcode::GetUser"),
+            User    => write!(f, "Opcode::User"),
+
+            RebuildIndex => write!(f, "Opcode::RebuildIndex"),
+            IndexRebuilt => write!(f, "Opcode::IndexRebuilt"),
+
+            GetGroups    => write!(f, "Opcode::GetGroups"),
+            Groups       => write!(f, "Opcode::Groups"),
+            SetGroup     => write!(f, "Opcode::SetGroup"),
+            Group        => write!(f, "Opcode::Group"),
+            RemoveGroup  => write!(f, "Opcode::RemoveGroup"),
+            AddMember    => write!(f, "Opcode::AddMember"),
+            RemoveMember => write!(f, "Opcode::RemoveMember"),
+
+            GetTokens    => write!(f, "Opcode::GetTokens"),
+            Tokens       => write!(f, "Opcode::Tokens"),
+            RevokeToken  => write!(f, "Opcode::RevokeToken"),
+            TokenRevoked => write!(f, "Opcode::TokenRevoked"),
+
+            GetInvites    => write!(f, "Opcode::GetInvites"),
+            Invites       => write!(f, "Opcode::Invites"),
+            CreateInvite  => write!(f, "Opcode::CreateInvite"),
+            InviteCreated => write!(f, "Opcode::InviteCreated"),
+            RevokeInvite  => write!(f, "Opcode::RevokeInvite"),
+            InviteRevoked => write!(f, "Opcode::InviteRevoked"),
+
+            IssueCertificate  => write!(f, "Opcode::IssueCertificate"),
+            CertificateIssued => write!(f, "Opcode::CertificateIssued"),
+
+            GetRevocations       => write!(f, "Opcode::GetRevocations"),
+            Revocations          => write!(f, "Opcode::Revocations"),
+            RevokeCertificate    => write!(f, "Opcode::RevokeCertificate"),
+            CertificateRevoked   => write!(f, "Opcode::CertificateRevoked"),
+            UnrevokeCertificate  => write!(f, "Opcode::UnrevokeCertificate"),
+            CertificateUnrevoked => write!(f, "Opcode::CertificateUnrevoked"),
+
+            SetSuspended => write!(f, "Opcode::SetSuspended"),
+
+            SelectHost   => write!(f, "Opcode::SelectHost"),
+            HostSelected => write!(f, "Opcode::HostSelected"),
+
+            GetCertificates => write!(f, "Opcode::GetCertificates"),
+            Certificates    => write!(f, "Opcode::Certificates"),
+
+            GetBans     => write!(f, "Opcode::GetBans"),
+            Bans        => write!(f, "Opcode::Bans"),
+            ClearBans   => write!(f, "Opcode::ClearBans"),
+            BansCleared => write!(f, "Opcode::BansCleared"),
+
+            GetAudit      => write!(f, "Opcode::GetAudit"),
+            Audit         => write!(f, "Opcode::Audit"),
+            VerifyAudit   => write!(f, "Opcode::VerifyAudit"),
+            AuditVerified => write!(f, "Opcode::AuditVerified"),
         }
     }
 }
@@ -81,8 +274,59 @@ impl TryFrom<u8> for Opcode {
     type Error = OpcodeError;
 
     #[inline]
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
+    fn try_from(value: u8) -> Result<Self, OpcodeError> {
         if value == u8::from(Opcode::Health) { Ok(Opcode::Health) }
+        else if value == u8::from(Opcode::Error) { Ok(Opcode::Error) }
+        else if value == u8::from(Opcode::Resolve) { Ok(Opcode::Resolve) }
+        else if value == u8::from(Opcode::Resolved) { Ok(Opcode::Resolved) }
+        else if value == u8::from(Opcode::GetChannel) { Ok(Opcode::GetChannel) }
+        else if value == u8::from(Opcode::Channel) { Ok(Opcode::Channel) }
+        else if value == u8::from(Opcode::CollectGarbage) { Ok(Opcode::CollectGarbage) }
+        else if value == u8::from(Opcode::GarbageCollected) { Ok(Opcode::GarbageCollected) }
+        else if value == u8::from(Opcode::Fsck) { Ok(Opcode::Fsck) }
+        else if value == u8::from(Opcode::FsckReport) { Ok(Opcode::FsckReport) }
+        else if value == u8::from(Opcode::GetUser) { Ok(Opcode::GetUser) }
+        else if value == u8::from(Opcode::User) { Ok(Opcode::User) }
+        else if value == u8::from(Opcode::RebuildIndex) { Ok(Opcode::RebuildIndex) }
+        else if value == u8::from(Opcode::IndexRebuilt) { Ok(Opcode::IndexRebuilt) }
+        else if value == u8::from(Opcode::GetGroups) { Ok(Opcode::GetGroups) }
+        else if value == u8::from(Opcode::Groups) { Ok(Opcode::Groups) }
+        else if value == u8::from(Opcode::SetGroup) { Ok(Opcode::SetGroup) }
+        else if value == u8::from(Opcode::Group) { Ok(Opcode::Group) }
+        else if value == u8::from(Opcode::RemoveGroup) { Ok(Opcode::RemoveGroup) }
+        else if value == u8::from(Opcode::AddMember) { Ok(Opcode::AddMember) }
+        else if value == u8::from(Opcode::RemoveMember) { Ok(Opcode::RemoveMember) }
+        else if value == u8::from(Opcode::GetTokens) { Ok(Opcode::GetTokens) }
+        else if value == u8::from(Opcode::Tokens) { Ok(Opcode::Tokens) }
+        else if value == u8::from(Opcode::RevokeToken) { Ok(Opcode::RevokeToken) }
+        else if value == u8::from(Opcode::TokenRevoked) { Ok(Opcode::TokenRevoked) }
+        else if value == u8::from(Opcode::GetInvites) { Ok(Opcode::GetInvites) }
+        else if value == u8::from(Opcode::Invites) { Ok(Opcode::Invites) }
+        else if value == u8::from(Opcode::CreateInvite) { Ok(Opcode::CreateInvite) }
+        else if value == u8::from(Opcode::InviteCreated) { Ok(Opcode::InviteCreated) }
+        else if value == u8::from(Opcode::RevokeInvite) { Ok(Opcode::RevokeInvite) }
+        else if value == u8::from(Opcode::InviteRevoked) { Ok(Opcode::InviteRevoked) }
+        else if value == u8::from(Opcode::IssueCertificate) { Ok(Opcode::IssueCertificate) }
+        else if value == u8::from(Opcode::CertificateIssued) { Ok(Opcode::CertificateIssued) }
+        else if value == u8::from(Opcode::GetRevocations) { Ok(Opcode::GetRevocations) }
+        else if value == u8::from(Opcode::Revocations) { Ok(Opcode::Revocations) }
+        else if value == u8::from(Opcode::RevokeCertificate) { Ok(Opcode::RevokeCertificate) }
+        else if value == u8::from(Opcode::CertificateRevoked) { Ok(Opcode::CertificateRevoked) }
+        else if value == u8::from(Opcode::UnrevokeCertificate) { Ok(Opcode::UnrevokeCertificate) }
+        else if value == u8::from(Opcode::CertificateUnrevoked) { Ok(Opcode::CertificateUnrevoked) }
+        else if value == u8::from(Opcode::SetSuspended) { Ok(Opcode::SetSuspended) }
+        else if value == u8::from(Opcode::SelectHost) { Ok(Opcode::SelectHost) }
+        else if value == u8::from(Opcode::HostSelected) { Ok(Opcode::HostSelected) }
+        else if value == u8::from(Opcode::GetCertificates) { Ok(Opcode::GetCertificates) }
+        else if value == u8::from(Opcode::Certificates) { Ok(Opcode::Certificates) }
+        else if value == u8::from(Opcode::GetBans) { Ok(Opcode::GetBans) }
+        else if value == u8::from(Opcode::Bans) { Ok(Opcode::Bans) }
+        else if value == u8::from(Opcode::ClearBans) { Ok(Opcode::ClearBans) }
+        else if value == u8::from(Opcode::BansCleared) { Ok(Opcode::BansCleared) }
+        else if value == u8::from(Opcode::GetAudit) { Ok(Opcode::GetAudit) }
+        else if value == u8::from(Opcode::Audit) { Ok(Opcode::Audit) }
+        else if value == u8::from(Opcode::VerifyAudit) { Ok(Opcode::VerifyAudit) }
+        else if value == u8::from(Opcode::AuditVerified) { Ok(Opcode::AuditVerified) }
         else { Err(OpcodeError::UnknownValue{ raw: value }) }
     }
 }
@@ -94,3 +338,460 @@ impl From<Opcode> for u8 {
     }
 }
 
+
+
+/// Defines what the server uses a certificate for.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificateRole {
+    /// The certificate that the server presents to clients.
+    Server,
+    /// The certificate of the certificate authority that issues client certificates.
+    Authority,
+    /// A client certificate that a user registered or that the certificate authority issued to it.
+    User,
+}
+
+impl Display for CertificateRole {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use CertificateRole::*;
+        match self {
+            Server    => write!(f, "server"),
+            Authority => write!(f, "certificate authority"),
+            User      => write!(f, "user"),
+        }
+    }
+}
+
+
+
+/// Defines how a revoked client certificate is recognized.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RevocationKind {
+    /// By the SHA-256 hash of the (DER-encoded) certificate.
+    Fingerprint,
+    /// By the serial number of the certificate.
+    Serial,
+}
+
+impl RevocationKind {
+    /// Normalizes a fingerprint or serial number as users may copy it from, e.g., OpenSSL (`AB:CD:...`) to the lowercase hex the server compares.
+    ///
+    /// # Returns
+    /// The normalized value, or `None` if it is not hex (or empty).
+    pub fn normalize(&self, value: &str) -> Option<String> {
+        let mut res: String = value.chars().filter(|c| *c != ':').collect::<String>().to_lowercase();
+        if res.is_empty() || !res.chars().all(|c| c.is_ascii_hexdigit()) { return None; }
+        // Serial numbers are numbers, so leading zeroes (e.g., a sign byte) don't matter
+        if *self == RevocationKind::Serial {
+            res = res.trim_start_matches('0').into();
+            if res.is_empty() { res.push('0'); }
+        }
+        Some(res)
+    }
+}
+
+impl Display for RevocationKind {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use RevocationKind::*;
+        match self {
+            Fingerprint => write!(f, "fingerprint"),
+            Serial      => write!(f, "serial"),
+        }
+    }
+}
+
+
+
+
+
+/***** MESSAGES *****/
+/// Asks the server to collect garbage in its storage.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GcRequest {
+    /// If true, only reports what would be removed without removing anything.
+    pub dry_run      : bool,
+    /// The number of seconds an unreferenced blob is kept after it was last touched. Uses the server's configured grace period if omitted.
+    pub grace_period : Option<u64>,
+}
+
+/// Sent by the server with the results of a garbage collection.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GcReport {
+    /// Whether this was a dry run, in which case nothing was actually removed.
+    pub dry_run : bool,
+    /// The number of blobs in the storage.
+    pub blobs   : u64,
+    /// The number of blobs that are still referenced.
+    pub kept    : u64,
+    /// The number of unreferenced blobs that were kept, because they were touched within the grace period.
+    pub young   : u64,
+    /// The number of blobs that were (or would be) removed.
+    pub removed : u64,
+    /// The number of bytes that were (or would be) freed.
+    pub freed   : u64,
+}
+
+
+
+/// Asks the server to check the integrity of its storage.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FsckRequest {
+    /// If true, moves corrupt blobs out of the storage into the quarantine directory.
+    pub quarantine : bool,
+}
+
+/// Sent by the server with the results of an integrity check.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FsckReport {
+    /// The number of blobs that were checked.
+    pub blobs    : u64,
+    /// The number of bytes that were checked.
+    pub bytes    : u64,
+    /// The blobs whose contents do not match their hash.
+    pub corrupt  : Vec<CorruptBlob>,
+    /// The references of package versions to blobs that are not in the storage.
+    pub missing  : Vec<MissingBlob>,
+    /// The blobs that no package version or transaction refers to.
+    pub orphaned : Vec<BlobHash>,
+}
+
+impl FsckReport {
+    /// Returns whether the storage is healthy, i.e., nothing is corrupt or missing.
+    #[inline]
+    pub fn is_healthy(&self) -> bool { self.corrupt.is_empty() && self.missing.is_empty() }
+}
+
+/// Describes a blob whose contents do not match its hash.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CorruptBlob {
+    /// The hash the blob should have.
+    pub hash        : BlobHash,
+    /// The hash the blob actually has, or None if it could not be read at all.
+    pub got         : Option<BlobHash>,
+    /// Whether the blob was moved to the quarantine directory.
+    pub quarantined : bool,
+}
+
+/// Describes a reference of a package version to a blob that does not exist.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MissingBlob {
+    /// The package that refers to the blob.
+    pub package : String,
+    /// The version that refers to the blob.
+    pub version : String,
+    /// The path of the file in that version that refers to the blob.
+    pub path    : String,
+    /// The hash of the missing blob.
+    pub hash    : BlobHash,
+}
+
+
+
+/// Asks the server for a user, its quota and its usage.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UserRequest {
+    /// The name of the user.
+    pub username : String,
+}
+
+/// Sent by the server with a user, its quota and its usage.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UserInfo {
+    /// The ID of the user.
+    pub id              : UserId,
+    /// The name of the user.
+    pub username        : String,
+    /// The permissions given to the user itself.
+    pub permissions     : Permissions,
+    /// The groups the user is a member of.
+    pub groups          : Vec<String>,
+    /// The permissions of the user and its groups combined, which is what the server checks.
+    pub effective       : Permissions,
+    /// The quota of the user.
+    pub quota           : Quota,
+    /// What the versions the user published take up.
+    pub usage           : Usage,
+    /// What the versions the user is still publishing will take up.
+    pub pending         : Usage,
+    /// Whether the user is suspended right now.
+    pub suspended       : bool,
+    /// The moment the suspension of the user ends, if it is suspended until a given moment.
+    pub suspended_until : Option<DateTime<Utc>>,
+}
+
+
+
+/// Asks the server to suspend a user or to lift its suspension.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SuspendRequest {
+    /// The name of the user.
+    pub username  : String,
+    /// Whether to suspend the user (true) or lift its suspension (false).
+    pub suspended : bool,
+    /// The number of seconds after which the suspension ends by itself. It lasts until lifted if omitted.
+    pub duration  : Option<u64>,
+}
+
+
+
+/// Asks the server to create a group or change its permissions.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SetGroupRequest {
+    /// The name of the group.
+    pub name        : String,
+    /// The permissions that the members of the group get.
+    pub permissions : Permissions,
+}
+
+/// Asks the server to do something with a group.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GroupRequest {
+    /// The name of the group.
+    pub name : String,
+}
+
+/// Asks the server to add a user to or remove a user from a group.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MemberRequest {
+    /// The name of the group.
+    pub group    : String,
+    /// The name of the user.
+    pub username : String,
+}
+
+/// Sent by the server with a group and its members.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GroupInfo {
+    /// The name of the group.
+    pub name        : String,
+    /// The permissions that the members of the group get.
+    pub permissions : Permissions,
+    /// The names of the members of the group.
+    pub members     : Vec<String>,
+}
+
+
+
+/// Asks the server to create an invite code, with which a new user can create its own account.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CreateInviteRequest {
+    /// The permissions the new user gets.
+    pub permissions : Permissions,
+    /// The groups the new user is added to. They must exist.
+    pub groups      : Vec<String>,
+    /// The number of seconds the code remains valid.
+    pub valid_for   : u64,
+    /// Who or what the code is for.
+    pub description : String,
+}
+
+/// Refers to an invite code.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InviteRequest {
+    /// The public identifier of the code.
+    pub id : String,
+}
+
+/// Describes an invite code, without the secret part that is needed to redeem it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InviteInfo {
+    /// The public identifier of the code.
+    pub id          : String,
+    /// The permissions the new user gets.
+    pub permissions : Permissions,
+    /// The groups the new user is added to.
+    pub groups      : Vec<String>,
+    /// Who or what the code is for.
+    pub description : String,
+    /// The moment the code was created.
+    pub created     : DateTime<Utc>,
+    /// The moment after which the code can no longer be redeemed.
+    pub expires     : DateTime<Utc>,
+}
+
+/// Sent by the server with a newly created invite code.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NewInvite {
+    /// Describes the code.
+    pub info : InviteInfo,
+    /// The full code to give to the new user. The server only stores its hash, so it cannot be retrieved again.
+    pub code : String,
+}
+
+
+
+/// Asks the server's certificate authority to issue a client certificate to a user.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IssueRequest {
+    /// The name of the user to issue the certificate to.
+    pub username : String,
+    /// The PEM-encoded certificate signing request to sign. If omitted, the server generates a new key pair.
+    pub csr      : Option<String>,
+}
+
+/// Sent by the server with a client certificate that its certificate authority issued.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IssuedCertificate {
+    /// The name of the user the certificate was issued to.
+    pub username    : String,
+    /// The PEM-encoded certificate.
+    pub certificate : String,
+    /// The PEM-encoded (PKCS#8) private key of the certificate, if the server generated it. It is not stored anywhere.
+    pub key         : Option<String>,
+    /// The moment after which the certificate is no longer valid.
+    pub expires     : DateTime<Utc>,
+    /// The serial number of the certificate, in hex.
+    pub serial      : String,
+    /// The SHA-256 fingerprint of the certificate, in hex.
+    pub fingerprint : String,
+}
+
+
+
+/// Asks the server to revoke a client certificate.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RevokeRequest {
+    /// How the certificate is recognized.
+    pub kind   : RevocationKind,
+    /// The fingerprint or serial number of the certificate, in hex.
+    pub value  : String,
+    /// Why the certificate is revoked.
+    pub reason : String,
+}
+
+/// Refers to an entry on the revocation list.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RevocationRequest {
+    /// How the certificate is recognized.
+    pub kind  : RevocationKind,
+    /// The fingerprint or serial number of the certificate, in hex.
+    pub value : String,
+}
+
+/// Describes an entry on the revocation list.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RevocationInfo {
+    /// How the certificate is recognized.
+    pub kind    : RevocationKind,
+    /// The (normalized) fingerprint or serial number of the certificate, in hex.
+    pub value   : String,
+    /// Why the certificate was revoked.
+    pub reason  : String,
+    /// The moment the certificate was revoked.
+    pub revoked : DateTime<Utc>,
+}
+
+
+
+/// Asks the server for the certificates that (almost) expire.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CertStatusRequest {
+    /// Only the certificates that expire within this many days (or have expired already) are listed. Uses the server's `cert_warn_days` if omitted.
+    pub within : Option<u64>,
+    /// If true, lists all certificates regardless of `within`.
+    pub all    : bool,
+}
+
+/// Describes a certificate that the server uses or trusts and when it expires.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CertificateStatus {
+    /// What the server uses the certificate for.
+    pub role      : CertificateRole,
+    /// The name of the user the certificate belongs to, if it is a user certificate.
+    pub username  : Option<String>,
+    /// The file the certificate was read from, if any. Certificates issued by the certificate authority are not stored in a file.
+    pub path      : Option<PathBuf>,
+    /// The subject of the certificate.
+    pub subject   : String,
+    /// The (normalized) serial number of the certificate, in hex.
+    pub serial    : String,
+    /// The moment after which the certificate is no longer valid.
+    pub not_after : DateTime<Utc>,
+}
+
+
+
+/// Asks the server to lift bans.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClearBansRequest {
+    /// The address whose ban to lift. Lifts all bans if omitted.
+    pub address : Option<IpAddr>,
+}
+
+/// Describes an address that the server has banned.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BanInfo {
+    /// The banned address.
+    pub address : IpAddr,
+    /// Why the address was banned.
+    pub reason  : String,
+    /// The moment the address was banned.
+    pub since   : DateTime<Utc>,
+    /// The moment the ban ends by itself.
+    pub until   : DateTime<Utc>,
+}
+
+
+
+/// Asks the server for the entries of its audit log that match the given filters.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditRequest {
+    /// Only the entries of the user with this name.
+    pub username  : Option<String>,
+    /// Only the entries of this operation.
+    pub operation : Option<AuditOperation>,
+    /// Only the entries whose target contains this text.
+    pub target    : Option<String>,
+    /// Only the entries of this moment or later.
+    pub since     : Option<DateTime<Utc>>,
+    /// Only this many of the most recent matching entries.
+    pub limit     : Option<usize>,
+}
+
+/// Sent by the server with the result of checking its audit log.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditReport {
+    /// The number of entries in the log.
+    pub entries  : u64,
+    /// The hash of the last entry, which the next entry will refer to. Keep it somewhere safe to detect that entries were removed from the end later.
+    pub head     : String,
+    /// Everything that is wrong with the log. It is intact if this is empty.
+    pub problems : Vec<AuditProblem>,
+}
+
+/// Describes something that is wrong with the audit log.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AuditProblem {
+    /// The (1-based) line of the log that is wrong.
+    pub line    : u64,
+    /// What is wrong with it.
+    pub problem : String,
+}
+
+
+
+/// Selects the virtual host that the next request is about.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HostRequest {
+    /// The name of the virtual host, as it appears in the `vhosts` of the config.
+    pub name : String,
+}
+
+
+
+/// Sent by the server with what its rebuilt metadata index contains.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IndexReport {
+    /// The number of packages that were indexed.
+    pub packages : usize,
+    /// The number of versions that were indexed.
+    pub versions : usize,
+    /// The number of channels that were indexed.
+    pub channels : usize,
+    /// The number of distinct blobs the versions refer to.
+    pub blobs    : usize,
+}
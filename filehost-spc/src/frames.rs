@@ -0,0 +1,226 @@
+/* FRAMES.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:26:37
+ * Last edited:
+ *   19 Oct 2026, 04:41:58
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Defines the framing used to send messages over the (TLS) streams
+ *   between the client and the server. Every frame consists of a single
+ *   opcode byte, an eight-byte payload length and then the payload
+ *   itself.
+**/
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::io::{ErrorKind, Read, Write};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::ctl_messages::ByteOrder;
+
+
+/***** CONSTANTS *****/
+/// The maximum size of a single frame's payload. Prevents a peer from making us allocate absurd amounts of memory.
+pub const MAX_FRAME_SIZE: u64 = 16 * 1024 * 1024;
+
+
+
+
+
+/***** ERRORS *****/
+/// Defines errors that relate to reading or writing frames.
+#[derive(Debug)]
+pub enum FrameError {
+    /// Could not read the header of a frame.
+    HeaderReadError{ err: std::io::Error },
+    /// The announced payload is larger than we allow.
+    FrameTooLarge{ opcode: u8, size: u64, max: u64 },
+    /// Could not read the payload of a frame.
+    PayloadReadError{ opcode: u8, err: std::io::Error },
+    /// Could not write a frame to the stream.
+    WriteError{ opcode: u8, err: std::io::Error },
+    /// Could not flush the stream after writing a frame.
+    FlushError{ err: std::io::Error },
+
+    /// Could not serialize a payload to JSON.
+    SerializeError{ opcode: u8, err: serde_json::Error },
+    /// Could not deserialize a payload from JSON.
+    DeserializeError{ opcode: u8, err: serde_json::Error },
+}
+
+impl Display for FrameError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use FrameError::*;
+        match self {
+            HeaderReadError{ err }             => write!(f, "Could not read frame header: {}", err),
+            FrameTooLarge{ opcode, size, max } => write!(f, "Frame with opcode {} announces a payload of {} bytes, but the maximum is {} bytes", opcode, size, max),
+            PayloadReadError{ opcode, err }    => write!(f, "Could not read payload of frame with opcode {}: {}", opcode, err),
+            WriteError{ opcode, err }          => write!(f, "Could not write frame with opcode {}: {}", opcode, err),
+            FlushError{ err }                  => write!(f, "Could not flush stream: {}", err),
+
+            SerializeError{ opcode, err }   => write!(f, "Could not serialize payload for frame with opcode {}: {}", opcode, err),
+            DeserializeError{ opcode, err } => write!(f, "Could not deserialize payload of frame with opcode {}: {}", opcode, err),
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Defines a single frame as read from a stream.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// The raw opcode of the frame. Its meaning depends on the protocol that is spoken.
+    pub opcode  : u8,
+    /// The raw payload of the frame.
+    pub payload : Vec<u8>,
+}
+
+impl Frame {
+    /// Parses the payload of this frame as JSON.
+    ///
+    /// # General arguments
+    /// - `T`: The type to deserialize the payload as.
+    ///
+    /// # Returns
+    /// A new instance of `T`, parsed from the payload.
+    ///
+    /// # Errors
+    /// This function errors if the payload is not valid JSON for the given type.
+    #[inline]
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, FrameError> {
+        match serde_json::from_slice(&self.payload) {
+            Ok(res)  => Ok(res),
+            Err(err) => Err(FrameError::DeserializeError{ opcode: self.opcode, err }),
+        }
+    }
+}
+
+
+
+/// Writes a single frame to the given writer.
+///
+/// Note that this does not flush the writer; use `flush()` for that when a message is complete.
+///
+/// # Arguments
+/// - `writer`: The Write-capable stream to write the frame to.
+/// - `opcode`: The opcode of the frame to write.
+/// - `payload`: The raw payload of the frame.
+///
+/// # Errors
+/// This function errors if we failed to write to the given writer.
+pub fn write_frame<W: Write, O: Into<u8>>(writer: &mut W, opcode: O, payload: &[u8]) -> Result<(), FrameError> {
+    let opcode: u8 = opcode.into();
+
+    // Write the header, then the payload
+    if let Err(err) = writer.write_u8(opcode) { return Err(FrameError::WriteError{ opcode, err }); }
+    if let Err(err) = writer.write_u64::<ByteOrder>(payload.len() as u64) { return Err(FrameError::WriteError{ opcode, err }); }
+    if let Err(err) = writer.write_all(payload) { return Err(FrameError::WriteError{ opcode, err }); }
+    Ok(())
+}
+
+/// Writes a single frame to the given writer, serializing the payload as JSON. Also flushes the writer afterwards.
+///
+/// # Arguments
+/// - `writer`: The Write-capable stream to write the frame to.
+/// - `opcode`: The opcode of the frame to write.
+/// - `payload`: The serializable payload to send along.
+///
+/// # Errors
+/// This function errors if we failed to serialize the payload or to write to the given writer.
+pub fn write_json<W: Write, O: Into<u8>, T: Serialize>(writer: &mut W, opcode: O, payload: &T) -> Result<(), FrameError> {
+    let opcode: u8 = opcode.into();
+
+    // Serialize the payload
+    let payload: Vec<u8> = match serde_json::to_vec(payload) {
+        Ok(payload) => payload,
+        Err(err)    => { return Err(FrameError::SerializeError{ opcode, err }); }
+    };
+
+    // Write it and flush
+    write_frame(writer, opcode, &payload)?;
+    flush(writer)
+}
+
+/// Flushes the given writer, wrapping the error in a FrameError.
+///
+/// # Arguments
+/// - `writer`: The Write-capable stream to flush.
+///
+/// # Errors
+/// This function errors if the writer could not be flushed.
+#[inline]
+pub fn flush<W: Write>(writer: &mut W) -> Result<(), FrameError> {
+    match writer.flush() {
+        Ok(_)    => Ok(()),
+        Err(err) => Err(FrameError::FlushError{ err }),
+    }
+}
+
+/// Reads a single frame from the given reader.
+///
+/// # Arguments
+/// - `reader`: The Read-capable stream to read the frame from.
+///
+/// # Returns
+/// The read Frame, or `None` if the stream was closed cleanly before a new frame started.
+///
+/// # Errors
+/// This function errors if we failed to read from the stream or if the frame is too large.
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Frame>, FrameError> {
+    // Read the opcode, catching a clean close
+    let opcode: u8 = match reader.read_u8() {
+        Ok(opcode) => opcode,
+        Err(err)   => {
+            if err.kind() == ErrorKind::UnexpectedEof { return Ok(None); }
+            return Err(FrameError::HeaderReadError{ err });
+        },
+    };
+
+    // Read the rest
+    read_frame_body(reader, opcode).map(Some)
+}
+
+/// Reads the rest of a frame of which the opcode has already been read from the given reader.
+///
+/// This is useful for streams where the first byte decides how the rest is interpreted.
+///
+/// # Arguments
+/// - `reader`: The Read-capable stream to read the frame from.
+/// - `opcode`: The opcode of the frame, which has already been read.
+///
+/// # Returns
+/// The read Frame.
+///
+/// # Errors
+/// This function errors if we failed to read from the stream or if the frame is too large.
+pub fn read_frame_body<R: Read>(reader: &mut R, opcode: u8) -> Result<Frame, FrameError> {
+    // Read the length, and assert it's in bounds
+    let size: u64 = match reader.read_u64::<ByteOrder>() {
+        Ok(size) => size,
+        Err(err) => { return Err(FrameError::HeaderReadError{ err }); }
+    };
+    if size > MAX_FRAME_SIZE { return Err(FrameError::FrameTooLarge{ opcode, size, max: MAX_FRAME_SIZE }); }
+
+    // Read the payload
+    let mut payload: Vec<u8> = vec![ 0; size as usize ];
+    if let Err(err) = reader.read_exact(&mut payload) { return Err(FrameError::PayloadReadError{ opcode, err }); }
+
+    // Done
+    Ok(Frame {
+        opcode,
+        payload,
+    })
+}
@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:36:09
  * Last edited:
- *   11 Jun 2022, 11:29:08
+ *   19 Oct 2026, 06:41:27
  * Auto updated?
  *   Yes
  *
@@ -19,3 +19,17 @@ pub mod config;
 pub mod login;
 /// Module that contains messages between the CTL and the daemon.
 pub mod ctl_messages;
+/// Module that contains the framing of messages sent over streams.
+pub mod frames;
+/// Module that contains the package version manifests.
+pub mod manifest;
+/// Module that contains the content-defined chunking of files.
+pub mod chunking;
+/// Module that contains the channels that point to package versions.
+pub mod channel;
+/// Module that contains the entries of the audit log.
+pub mod audit;
+/// Module that contains the storage quotas of users and packages.
+pub mod quota;
+/// Module that contains messages between the client and the daemon.
+pub mod client_messages;
@@ -4,7 +4,7 @@
  * Created:
  *   11 Jun 2022, 11:28:06
  * Last edited:
- *   11 Jun 2022, 12:34:55
+ *   19 Oct 2026, 05:46:12
  * Auto updated?
  *   Yes
  *
@@ -12,9 +12,12 @@
  *   Contains specification enums & structs for logging in.
 **/
 
+use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FResult};
-use std::ops::{BitOr, BitOrAssign};
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign};
+use std::str::FromStr;
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 
@@ -25,6 +28,35 @@ pub const ROOT_ID : UserId = 0;
 /// The constant ID of the guest user.
 pub const GUEST_ID : UserId = 1;
 
+/// The prefix of every API token, which makes them easy to recognize (e.g., by secret scanners).
+pub const TOKEN_PREFIX  : &str = "fht_";
+/// The prefix of every invite code.
+pub const INVITE_PREFIX : &str = "fhi_";
+
+
+
+
+
+/***** ERRORS *****/
+/// Defines errors that relate to parsing Permissions.
+#[derive(Debug)]
+pub enum PermissionsParseError {
+    /// The given flag is not a permission we know.
+    UnknownFlag{ raw: String },
+}
+
+impl Display for PermissionsParseError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use PermissionsParseError::*;
+        match self {
+            UnknownFlag{ raw } => write!(f, "Unknown permission '{}' (expected 'none', 'all' or a comma-separated list of 'download', 'upload', 'channel' and 'delete')", raw),
+        }
+    }
+}
+
+impl Error for PermissionsParseError {}
+
 
 
 
@@ -48,6 +80,15 @@ impl Permissions {
     /// Shortcut for all permissions.
     pub const ALL  : Self = Self(0xFF);
 
+    /// Allows the user to download package manifests and blobs.
+    pub const DOWNLOAD : Self = Self(0x01);
+    /// Allows the user to publish new package versions.
+    pub const UPLOAD   : Self = Self(0x02);
+    /// Allows the user to move channels to other versions.
+    pub const CHANNEL  : Self = Self(0x04);
+    /// Allows the user to delete package versions.
+    pub const DELETE   : Self = Self(0x08);
+
 
     /// Returns whether this user has (at least) the given set of permissions.
     #[inline]
@@ -70,6 +111,22 @@ impl BitOrAssign for Permissions {
     }
 }
 
+impl BitAnd for Permissions {
+    type Output = Self;
+
+    #[inline]
+    fn bitand(self, rhs: Self) -> Self::Output {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl BitAndAssign for Permissions {
+    #[inline]
+    fn bitand_assign(&mut self, rhs: Self) {
+        self.0 &= rhs.0;
+    }
+}
+
 impl BitOr<u8> for Permissions {
     type Output = Self;
 
@@ -103,6 +160,70 @@ impl From<Permissions> for u8 {
 impl Display for Permissions {
     #[inline]
     fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        // Catch the shortcuts first
+        if *self == Self::NONE { return write!(f, "none"); }
+        if *self == Self::ALL { return write!(f, "all"); }
+
+        // Otherwise, list the flags by name
+        let mut first: bool = true;
+        for (flag, name) in [
+            (Self::DOWNLOAD, "download"),
+            (Self::UPLOAD, "upload"),
+            (Self::CHANNEL, "channel"),
+            (Self::DELETE, "delete"),
+        ] {
+            if self.has(flag) {
+                if !first { write!(f, ",")?; }
+                write!(f, "{}", name)?;
+                first = false;
+            }
+        }
         Ok(())
     }
 }
+
+impl FromStr for Permissions {
+    type Err = PermissionsParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Catch the shortcuts first
+        if s == "none" { return Ok(Self::NONE); }
+        if s == "all" { return Ok(Self::ALL); }
+
+        // Otherwise, parse the flags by name
+        let mut res: Self = Self::NONE;
+        for flag in s.split(',') {
+            match flag.trim() {
+                "download" => { res |= Self::DOWNLOAD; },
+                "upload"   => { res |= Self::UPLOAD; },
+                "channel"  => { res |= Self::CHANNEL; },
+                "delete"   => { res |= Self::DELETE; },
+                raw        => { return Err(PermissionsParseError::UnknownFlag{ raw: raw.into() }); },
+            }
+        }
+        Ok(res)
+    }
+}
+
+
+
+
+
+
+/***** MESSAGES *****/
+/// Describes an API token, without the secret part that is needed to log in with it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenInfo {
+    /// The public identifier of the token.
+    pub id          : String,
+    /// The name of the user that owns the token.
+    pub username    : String,
+    /// The permissions that a session logged in with the token is limited to.
+    pub permissions : Permissions,
+    /// What the token is used for, as given by its owner.
+    pub description : String,
+    /// The moment the token was created.
+    pub created     : DateTime<Utc>,
+    /// The moment after which the token can no longer be used.
+    pub expires     : DateTime<Utc>,
+}
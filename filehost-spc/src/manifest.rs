@@ -0,0 +1,625 @@
+/* MANIFEST.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:26:37
+ * Last edited:
+ *   19 Oct 2026, 06:46:43
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Defines the manifest of a package version, which lists the files in
+ *   the version together with the hash of the blob that stores them.
+**/
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt::{Debug, Display, Formatter, Result as FResult, Write};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::login::UserId;
+
+
+/***** CONSTANTS *****/
+/// The size of the buffer we use when hashing files.
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The first line of what a publisher signs, which keeps the signature from being valid for anything but a manifest (of this encoding).
+const SIGNATURE_CONTEXT: &str = "filehost-manifest-v1";
+
+
+
+
+
+/***** ERRORS *****/
+/// Defines errors that relate to hashes.
+#[derive(Debug)]
+pub enum HashError {
+    /// The given string does not have the correct length.
+    IllegalLength{ raw: String, got: usize, expected: usize },
+    /// The given string is not valid hexadecimal.
+    IllegalHex{ raw: String, err: hex::FromHexError },
+
+    /// Could not open the file to hash.
+    FileOpenError{ path: std::path::PathBuf, err: std::io::Error },
+    /// Could not read the file to hash.
+    FileReadError{ path: std::path::PathBuf, err: std::io::Error },
+}
+
+impl Display for HashError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use HashError::*;
+        match self {
+            IllegalLength{ raw, got, expected } => write!(f, "Hash '{}' has an illegal length (got {} characters, expected {})", raw, got, expected),
+            IllegalHex{ raw, err }              => write!(f, "Hash '{}' is not valid hexadecimal: {}", raw, err),
+
+            FileOpenError{ path, err } => write!(f, "Could not open file '{}' to hash it: {}", path.display(), err),
+            FileReadError{ path, err } => write!(f, "Could not read file '{}' to hash it: {}", path.display(), err),
+        }
+    }
+}
+
+impl error::Error for HashError {}
+
+
+
+/// Defines errors that relate to the signatures of manifests.
+#[derive(Debug)]
+pub enum SignatureError {
+    /// The given public key does not have the correct length.
+    IllegalKeyLength{ raw: String, got: usize, expected: usize },
+    /// The given public key is not valid hexadecimal.
+    IllegalKeyHex{ raw: String, err: hex::FromHexError },
+    /// The signature is not valid hexadecimal.
+    IllegalSignatureHex{ err: hex::FromHexError },
+    /// The signature was not made over the manifest with the key it names.
+    Mismatch{ key: PublicKey },
+}
+
+impl Display for SignatureError {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use SignatureError::*;
+        match self {
+            IllegalKeyLength{ raw, got, expected } => write!(f, "Public key '{}' has an illegal length (got {} characters, expected {})", raw, got, expected),
+            IllegalKeyHex{ raw, err }              => write!(f, "Public key '{}' is not valid hexadecimal: {}", raw, err),
+            IllegalSignatureHex{ err }             => write!(f, "Signature is not valid hexadecimal: {}", err),
+            Mismatch{ key }                        => write!(f, "Signature of key '{}' does not match the manifest", key),
+        }
+    }
+}
+
+impl error::Error for SignatureError {}
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Checks whether the given string is a valid package or version name.
+///
+/// Names may only consist of ASCII alphanumerics, dashes, underscores and dots, and may not start with a dot. This makes them safe to use as a path component.
+///
+/// # Arguments
+/// - `name`: The name to check.
+///
+/// # Returns
+/// Whether the name is valid or not.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 128 && !name.starts_with('.') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+/// Checks whether the given string is a valid path for a manifest entry.
+///
+/// Entry paths must be relative and may not escape the directory they are installed in (i.e., no `..` or root components).
+///
+/// # Arguments
+/// - `path`: The path to check.
+///
+/// # Returns
+/// Whether the path is valid or not.
+pub fn is_valid_entry_path(path: &str) -> bool {
+    if path.is_empty() { return false; }
+    Path::new(path).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Defines the SHA-256 hash that identifies a blob.
+#[derive(Clone, Copy, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct BlobHash(pub [u8; 32]);
+
+impl BlobHash {
+    /// Computes the hash of the given bytes.
+    ///
+    /// # Arguments
+    /// - `data`: The bytes to hash.
+    ///
+    /// # Returns
+    /// A new BlobHash with the hash of the data.
+    #[inline]
+    pub fn of_bytes<B: AsRef<[u8]>>(data: B) -> Self {
+        Self(Sha256::digest(data.as_ref()).into())
+    }
+
+    /// Computes the hash of everything in the given reader.
+    ///
+    /// # Arguments
+    /// - `reader`: The Read-capable reader to hash until it's empty.
+    ///
+    /// # Returns
+    /// A new BlobHash with the hash of the reader's contents.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read from the reader.
+    pub fn of_reader<R: Read>(mut reader: R) -> Result<Self, std::io::Error> {
+        let mut hasher = Sha256::new();
+        let mut buffer: Vec<u8> = vec![ 0; HASH_BUFFER_SIZE ];
+        loop {
+            let n_bytes: usize = reader.read(&mut buffer)?;
+            if n_bytes == 0 { break; }
+            hasher.update(&buffer[..n_bytes]);
+        }
+        Ok(Self(hasher.finalize().into()))
+    }
+
+    /// Computes the hash of the given file.
+    ///
+    /// # Arguments
+    /// - `path`: The Path(-like) of the file to hash.
+    ///
+    /// # Returns
+    /// A new BlobHash with the hash of the file's contents.
+    ///
+    /// # Errors
+    /// This function errors if we failed to open or read the file.
+    pub fn of_file<P: AsRef<Path>>(path: P) -> Result<Self, HashError> {
+        let path: &Path = path.as_ref();
+
+        // Open the file
+        let handle = match File::open(path) {
+            Ok(handle) => handle,
+            Err(err)   => { return Err(HashError::FileOpenError{ path: path.into(), err }); }
+        };
+
+        // Hash it
+        match Self::of_reader(handle) {
+            Ok(hash) => Ok(hash),
+            Err(err) => Err(HashError::FileReadError{ path: path.into(), err }),
+        }
+    }
+}
+
+impl Debug for BlobHash {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(f, "BlobHash({})", self)
+    }
+}
+
+impl Display for BlobHash {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for BlobHash {
+    type Err = HashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Assert the length first
+        if s.len() != 64 { return Err(HashError::IllegalLength{ raw: s.into(), got: s.len(), expected: 64 }); }
+
+        // Decode the hex
+        let mut res: [u8; 32] = [ 0; 32 ];
+        match hex::decode_to_slice(s, &mut res) {
+            Ok(_)    => Ok(Self(res)),
+            Err(err) => Err(HashError::IllegalHex{ raw: s.into(), err }),
+        }
+    }
+}
+
+impl Serialize for BlobHash {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for BlobHash {
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw: String = String::deserialize(deserializer)?;
+        Self::from_str(&raw).map_err(serde::de::Error::custom)
+    }
+}
+
+
+
+/// Defines the Ed25519 public key with which a publisher signs manifests.
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub struct PublicKey(pub [u8; 32]);
+
+impl Debug for PublicKey {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(f, "PublicKey({})", self)
+    }
+}
+
+impl Display for PublicKey {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Assert the length first
+        if s.len() != 64 { return Err(SignatureError::IllegalKeyLength{ raw: s.into(), got: s.len(), expected: 64 }); }
+
+        // Decode the hex
+        let mut res: [u8; 32] = [ 0; 32 ];
+        match hex::decode_to_slice(s, &mut res) {
+            Ok(_)    => Ok(Self(res)),
+            Err(err) => Err(SignatureError::IllegalKeyHex{ raw: s.into(), err }),
+        }
+    }
+}
+
+impl Serialize for PublicKey {
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw: String = String::deserialize(deserializer)?;
+        Self::from_str(&raw).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Defines the signature of a publisher over a manifest.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ManifestSignature {
+    /// The public key of the publisher that signed the manifest.
+    pub key       : PublicKey,
+    /// The Ed25519 signature over `Manifest::signed_bytes()`, in hex.
+    pub signature : String,
+}
+
+
+
+/// Defines the manifest of a single package version.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    /// The name of the package this manifest belongs to.
+    pub package   : String,
+    /// The version of the package this manifest describes.
+    pub version   : String,
+    /// The files in this version.
+    pub entries   : Vec<ManifestEntry>,
+    /// The user that published this version. Set by the server; whatever a client sends is ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publisher : Option<UserId>,
+    /// The signature of the publisher over the manifest, if it signed it. The server stores it as-is, so clients can check that the manifest was not changed since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature : Option<ManifestSignature>,
+}
+
+impl Manifest {
+    /// Returns the total number of bytes of all files in this manifest.
+    #[inline]
+    pub fn total_size(&self) -> u64 { self.entries.iter().map(|e| e.size).sum() }
+
+    /// Returns what a publisher signs: the package, version and entries of the manifest, in a fixed encoding.
+    ///
+    /// Who published the version is left out, since the server fills that in. The signature itself is left out as well, of course.
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        // Strings are JSON-encoded, so no path can pass for another line
+        let quote = |s: &str| serde_json::to_string(s).unwrap_or_else(|err| panic!("Could not encode string '{}': {}", s, err));
+        let mut res: String = format!("{}\npackage {}\nversion {}\n", SIGNATURE_CONTEXT, quote(&self.package), quote(&self.version));
+        for entry in &self.entries {
+            // Writing to a String never fails
+            let _ = writeln!(res, "entry {} {} {}", quote(&entry.path), entry.hash, entry.size);
+            for chunk in &entry.chunks {
+                let _ = writeln!(res, "chunk {} {}", chunk.hash, chunk.size);
+            }
+        }
+        res.into_bytes()
+    }
+
+    /// Signs the manifest with the given key pair of the publisher, replacing any earlier signature.
+    ///
+    /// # Arguments
+    /// - `key`: The Ed25519 key pair to sign with.
+    pub fn sign(&mut self, key: &Ed25519KeyPair) {
+        let mut public: [u8; 32] = [ 0; 32 ];
+        public.copy_from_slice(key.public_key().as_ref());
+        let signature: String = hex::encode(key.sign(&self.signed_bytes()).as_ref());
+        self.signature = Some(ManifestSignature{ key: PublicKey(public), signature });
+    }
+
+    /// Checks the signature of the manifest, if it has one.
+    ///
+    /// This only tells whether the manifest was signed with the key that the signature names; whether that key is trusted is up to the caller.
+    ///
+    /// # Returns
+    /// The key that signed the manifest, or `None` if it is not signed.
+    ///
+    /// # Errors
+    /// This function errors if the signature is malformed or does not match the manifest.
+    pub fn verify_signature(&self) -> Result<Option<PublicKey>, SignatureError> {
+        let signature: &ManifestSignature = match &self.signature {
+            Some(signature) => signature,
+            None            => { return Ok(None); }
+        };
+        let raw: Vec<u8> = hex::decode(&signature.signature).map_err(|err| SignatureError::IllegalSignatureHex{ err })?;
+        match UnparsedPublicKey::new(&ED25519, &signature.key.0).verify(&self.signed_bytes(), &raw) {
+            Ok(_)  => Ok(Some(signature.key)),
+            Err(_) => Err(SignatureError::Mismatch{ key: signature.key }),
+        }
+    }
+
+    /// Computes which entries differ between this manifest and a newer one.
+    ///
+    /// Entries are matched by path, and compared by content hash.
+    ///
+    /// # Arguments
+    /// - `to`: The manifest to compare to.
+    ///
+    /// # Returns
+    /// A ManifestDiff that describes how to get from this manifest to `to`.
+    pub fn diff(&self, to: &Manifest) -> ManifestDiff {
+        let old: HashMap<&str, &ManifestEntry> = self.entries.iter().map(|e| (e.path.as_str(), e)).collect();
+        let new: HashMap<&str, &ManifestEntry> = to.entries.iter().map(|e| (e.path.as_str(), e)).collect();
+
+        // Walk the new entries to find what's added or changed
+        let mut diff: ManifestDiff = ManifestDiff::default();
+        for entry in &to.entries {
+            match old.get(entry.path.as_str()) {
+                Some(prev) => if prev.hash != entry.hash { diff.changed.push(ChangedEntry{ from: (*prev).clone(), to: entry.clone() }); },
+                None       => { diff.added.push(entry.clone()); },
+            }
+        }
+
+        // Walk the old entries to find what's removed
+        for entry in &self.entries {
+            if !new.contains_key(entry.path.as_str()) { diff.removed.push(entry.clone()); }
+        }
+        diff
+    }
+}
+
+
+
+/// Defines a single file in a package version.
+///
+/// A file is either stored as a single blob named after its hash, or (if it has chunks) as the concatenation of its chunks. In the latter case, the hash of the file still identifies its complete contents, but no blob by that name exists.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ManifestEntry {
+    /// The path of the file, relative to the root of the package.
+    pub path   : String,
+    /// The hash of the file's contents.
+    pub hash   : BlobHash,
+    /// The size of the file, in bytes.
+    pub size   : u64,
+    /// The chunks that make up the file, in order. If empty, the file is stored as a single blob.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks : Vec<ManifestChunk>,
+}
+
+impl ManifestEntry {
+    /// Returns whether this file is stored as chunks.
+    #[inline]
+    pub fn is_chunked(&self) -> bool { !self.chunks.is_empty() }
+
+    /// Returns the blobs that make up this file, in order.
+    ///
+    /// # Returns
+    /// An iterator over the hash and size of every blob. For files that are not chunked, this is just the file itself.
+    pub fn blobs(&self) -> impl Iterator<Item = (BlobHash, u64)> + '_ {
+        let whole: Option<(BlobHash, u64)> = if self.chunks.is_empty() { Some((self.hash, self.size)) } else { None };
+        whole.into_iter().chain(self.chunks.iter().map(|c| (c.hash, c.size)))
+    }
+}
+
+/// Defines a single chunk of a chunked file.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct ManifestChunk {
+    /// The hash of the blob that contains the chunk.
+    pub hash : BlobHash,
+    /// The size of the chunk, in bytes.
+    pub size : u64,
+}
+
+
+
+/// Defines the differences between two manifests.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ManifestDiff {
+    /// The entries that only exist in the newer manifest.
+    pub added   : Vec<ManifestEntry>,
+    /// The entries that only exist in the older manifest.
+    pub removed : Vec<ManifestEntry>,
+    /// The entries that exist in both manifests, but with different contents.
+    pub changed : Vec<ChangedEntry>,
+}
+
+impl ManifestDiff {
+    /// Returns whether the two manifests have the same contents.
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() }
+}
+
+/// Defines a single entry that changed between two manifests.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChangedEntry {
+    /// The entry in the older manifest.
+    pub from : ManifestEntry,
+    /// The entry in the newer manifest. Has the same path as `from`.
+    pub to   : ManifestEntry,
+}
+
+
+
+
+
+/***** TESTS *****/
+#[cfg(test)]
+mod tests {
+    use ring::rand::SystemRandom;
+
+    use super::*;
+
+    /// Builds the manifest entry of a file with the given contents.
+    fn entry(path: &str, data: &[u8]) -> ManifestEntry {
+        ManifestEntry{ path: path.into(), hash: BlobHash::of_bytes(data), size: data.len() as u64, chunks: vec![] }
+    }
+
+    /// Builds the manifest of the given version of the package `game`.
+    fn manifest(version: &str, entries: Vec<ManifestEntry>) -> Manifest {
+        Manifest{ package: "game".into(), version: version.into(), entries, publisher: None, signature: None }
+    }
+
+    /// Returns the paths of the given entries, sorted.
+    fn paths<'e>(entries: impl Iterator<Item = &'e ManifestEntry>) -> Vec<&'e str> {
+        let mut paths: Vec<&str> = entries.map(|e| e.path.as_str()).collect();
+        paths.sort_unstable();
+        paths
+    }
+
+    /// Builds an unsigned manifest with a plain and a chunked file.
+    fn signable() -> Manifest {
+        let mut big: ManifestEntry = entry("data/big.bin", b"headtail");
+        big.chunks = vec![
+            ManifestChunk{ hash: BlobHash::of_bytes(b"head"), size: 4 },
+            ManifestChunk{ hash: BlobHash::of_bytes(b"tail"), size: 4 },
+        ];
+        manifest("1.0.0", vec![ entry("README.md", b"readme"), big ])
+    }
+
+    /// Generates a fresh key pair to sign with.
+    fn key() -> Ed25519KeyPair {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn test_diff() {
+        let from: Manifest = manifest("1.0.0", vec![
+            entry("same.txt", b"same"),
+            entry("changed.txt", b"old"),
+            entry("removed.txt", b"gone"),
+            entry("moved/a.txt", b"moved"),
+        ]);
+        let to: Manifest = manifest("1.1.0", vec![
+            entry("added.txt", b"new"),
+            entry("changed.txt", b"new"),
+            entry("moved/b.txt", b"moved"),
+            entry("same.txt", b"same"),
+        ]);
+
+        // Entries are matched by path, so a moved file is removed in one place and added in another
+        let diff: ManifestDiff = from.diff(&to);
+        assert_eq!(paths(diff.added.iter()), [ "added.txt", "moved/b.txt" ]);
+        assert_eq!(paths(diff.removed.iter()), [ "moved/a.txt", "removed.txt" ]);
+        assert_eq!(paths(diff.changed.iter().map(|c| &c.to)), [ "changed.txt" ]);
+        assert_eq!(diff.changed[0].from.hash, BlobHash::of_bytes(b"old"));
+        assert_eq!(diff.changed[0].to.hash, BlobHash::of_bytes(b"new"));
+
+        // The other way around, added and removed swap places
+        let back: ManifestDiff = to.diff(&from);
+        assert_eq!(paths(back.added.iter()), paths(diff.removed.iter()));
+        assert_eq!(paths(back.removed.iter()), paths(diff.added.iter()));
+        assert_eq!(back.changed[0].to.hash, BlobHash::of_bytes(b"old"));
+    }
+
+    #[test]
+    fn test_diff_same() {
+        let from: Manifest = manifest("1.0.0", vec![ entry("a.txt", b"a"), entry("b.txt", b"b") ]);
+        let to: Manifest = manifest("1.0.1", vec![ entry("b.txt", b"b"), entry("a.txt", b"a") ]);
+        assert!(from.diff(&to).is_empty());
+        assert!(from.diff(&from).is_empty());
+
+        // Everything is new compared to nothing
+        let diff: ManifestDiff = manifest("0.0.0", vec![]).diff(&to);
+        assert_eq!(paths(diff.added.iter()), [ "a.txt", "b.txt" ]);
+        assert!(diff.removed.is_empty() && diff.changed.is_empty());
+    }
+
+    #[test]
+    fn test_unsigned() {
+        assert!(matches!(signable().verify_signature(), Ok(None)));
+        assert_eq!(signable().signed_bytes(), signable().signed_bytes());
+    }
+
+    #[test]
+    fn test_signed() {
+        let key: Ed25519KeyPair = key();
+        let mut manifest: Manifest = signable();
+        manifest.sign(&key);
+        let signer: PublicKey = manifest.verify_signature().unwrap().unwrap();
+        assert_eq!(signer.0.as_slice(), key.public_key().as_ref());
+
+        // The publisher is filled in by the server, so it is not signed
+        manifest.publisher = Some(1);
+        assert!(manifest.verify_signature().is_ok());
+    }
+
+    #[test]
+    fn test_tampered() {
+        let mut signed: Manifest = signable();
+        signed.sign(&key());
+
+        let mut manifest: Manifest = signed.clone();
+        manifest.entries[0].hash = BlobHash([ 5; 32 ]);
+        assert!(matches!(manifest.verify_signature(), Err(SignatureError::Mismatch{ .. })));
+
+        let mut manifest: Manifest = signed.clone();
+        manifest.entries[1].chunks[1].size = 101;
+        assert!(matches!(manifest.verify_signature(), Err(SignatureError::Mismatch{ .. })));
+
+        let mut manifest: Manifest = signed.clone();
+        manifest.entries.remove(0);
+        assert!(matches!(manifest.verify_signature(), Err(SignatureError::Mismatch{ .. })));
+
+        let mut manifest: Manifest = signed.clone();
+        manifest.entries.swap(0, 1);
+        assert!(matches!(manifest.verify_signature(), Err(SignatureError::Mismatch{ .. })));
+
+        let mut manifest: Manifest = signed.clone();
+        manifest.version = "1.0.1".into();
+        assert!(matches!(manifest.verify_signature(), Err(SignatureError::Mismatch{ .. })));
+
+        // A signature by another key does not pass for the original one
+        let mut manifest: Manifest = signed.clone();
+        let mut other: Manifest = signed.clone();
+        other.sign(&key());
+        manifest.signature.as_mut().unwrap().signature = other.signature.unwrap().signature;
+        assert!(matches!(manifest.verify_signature(), Err(SignatureError::Mismatch{ .. })));
+
+        let mut manifest: Manifest = signed;
+        manifest.signature.as_mut().unwrap().signature = "not hex".into();
+        assert!(matches!(manifest.verify_signature(), Err(SignatureError::IllegalSignatureHex{ .. })));
+    }
+}
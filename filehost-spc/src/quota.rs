@@ -0,0 +1,102 @@
+/* QUOTA.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 05:13:01
+ * Last edited:
+ *   19 Oct 2026, 05:13:01
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Defines the storage quotas of users and packages, and how much of
+ *   them is in use.
+**/
+
+use std::error::Error;
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::ops::AddAssign;
+
+use serde::{Deserialize, Serialize};
+
+
+/***** ERRORS *****/
+/// Describes how a quota would be exceeded.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum QuotaError {
+    /// Storing the new version would use more bytes than allowed.
+    Bytes{ used: u64, requested: u64, limit: u64 },
+    /// Storing the new version would mean more versions than allowed.
+    Versions{ used: usize, limit: usize },
+}
+
+impl Display for QuotaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use QuotaError::*;
+        match self {
+            Bytes{ used, requested, limit } => write!(f, "{} more bytes would exceed the limit of {} bytes ({} bytes in use)", requested, limit, used),
+            Versions{ used, limit }         => write!(f, "another version would exceed the limit of {} versions ({} in use)", limit, used),
+        }
+    }
+}
+
+impl Error for QuotaError {}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Defines the limits of a user or package. Omitted limits are unlimited.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct Quota {
+    /// The maximum number of bytes of all versions together.
+    #[serde(default)]
+    pub max_bytes    : Option<u64>,
+    /// The maximum number of versions.
+    #[serde(default)]
+    pub max_versions : Option<usize>,
+}
+
+impl Quota {
+    /// Returns whether this quota limits anything at all.
+    #[inline]
+    pub fn is_limited(&self) -> bool { self.max_bytes.is_some() || self.max_versions.is_some() }
+
+    /// Checks whether a new version fits in this quota.
+    ///
+    /// # Arguments
+    /// - `usage`: The current usage, including versions that are still being published.
+    /// - `size`: The size of the new version, in bytes.
+    ///
+    /// # Errors
+    /// This function errors if the new version would exceed any of the limits.
+    pub fn check(&self, usage: &Usage, size: u64) -> Result<(), QuotaError> {
+        if let Some(limit) = self.max_versions {
+            if usage.versions >= limit { return Err(QuotaError::Versions{ used: usage.versions, limit }); }
+        }
+        if let Some(limit) = self.max_bytes {
+            if usage.bytes.saturating_add(size) > limit { return Err(QuotaError::Bytes{ used: usage.bytes, requested: size, limit }); }
+        }
+        Ok(())
+    }
+}
+
+
+
+/// Defines how much of a quota is in use.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct Usage {
+    /// The number of bytes of all versions together.
+    pub bytes    : u64,
+    /// The number of versions.
+    pub versions : usize,
+}
+
+impl AddAssign for Usage {
+    #[inline]
+    fn add_assign(&mut self, rhs: Self) {
+        self.bytes += rhs.bytes;
+        self.versions += rhs.versions;
+    }
+}
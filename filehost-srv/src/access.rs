@@ -0,0 +1,175 @@
+/* ACCESS.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 05:30:25
+ * Last edited:
+ *   19 Oct 2026, 05:39:27
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Decides what a user may do with a package, based on the access
+ *   control list of the package or, if it has none, on the permissions
+ *   of the user.
+**/
+
+use filehost_spc::config::{Acl, Config};
+use filehost_spc::login::{GUEST_ID, ROOT_ID, Permissions};
+
+pub use crate::errors::UserError as Error;
+use crate::users::{User, Users};
+
+
+/***** LIBRARY *****/
+/// Decides whether the given user may do something with the given package.
+///
+/// If the package has an access control list, only that list counts: the root user may do anything, the guest user may only read (and only if the list allows it), and everyone else gets what the list grants to them and to their groups. Packages without a list fall back to the permissions of the user. Either way, the user never gets more than the scope of its session allows.
+///
+/// # Arguments
+/// - `config`: The config with the access control lists.
+/// - `users`: The users database, used to find the groups of the user.
+/// - `user`: The user that wants access. Its permissions must already include those of its groups (see `Users::effective_permissions()`).
+/// - `package`: The package the user wants to access.
+/// - `access`: What the user wants to do with it.
+///
+/// # Returns
+/// Whether the user may go ahead.
+///
+/// # Errors
+/// This function errors if we failed to read the groups of the user.
+pub fn allowed(config: &Config, users: &dyn Users, user: &User, package: &str, access: Permissions) -> Result<bool, Error> {
+    if !user.scope.has(access) { return Ok(false); }
+    let acl: &Acl = match config.acls.get(package) {
+        Some(acl) => acl,
+        None      => { return Ok(user.permissions.has(access)); },
+    };
+    if user.id == ROOT_ID { return Ok(true); }
+    if user.id == GUEST_ID { return Ok(acl.guest_read && Permissions::DOWNLOAD.has(access)); }
+
+    // Collect what the list grants to the user and its groups
+    let mut granted: Permissions = acl.users.get(&user.username).copied().unwrap_or(Permissions::NONE);
+    for group in users.groups_of(user.id)? {
+        if let Some(grant) = acl.groups.get(&group.name) { granted |= *grant; }
+    }
+    Ok(granted.has(access))
+}
+
+
+
+
+
+/***** TESTS *****/
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use serde_json::json;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+    use crate::users::JsonUsers;
+
+    /// Loads a users database with, next to root and guest, `alice` (download and upload), `bob` (nothing) and `carol` (nothing, but a member of `devs`).
+    fn users() -> JsonUsers {
+        // Temporary files are only accessible by us, as the users file should be
+        let mut file: NamedTempFile = NamedTempFile::new().unwrap();
+        write!(file, "{}", json!({
+            "users": {
+                "0": { "username": "root", "certs": "/dev/null", "permissions": 255 },
+                "1": { "username": "guest", "certs": "/dev/null", "permissions": 0 },
+                "2": { "username": "alice", "certs": "/dev/null", "permissions": 3 },
+                "3": { "username": "bob", "certs": "/dev/null", "permissions": 0 },
+                "4": { "username": "carol", "certs": "/dev/null", "permissions": 0 },
+            },
+            "groups": {
+                "devs": { "permissions": 0, "members": [ 4 ] },
+            },
+        })).unwrap();
+        JsonUsers::from_file(file.path()).unwrap()
+    }
+
+    /// Builds a config with an access control list for `private` and `public`.
+    fn config() -> Config {
+        serde_json::from_value(json!({
+            "log_level": "info",
+            "user_db": "/dev/null",
+            "server_cert": "/dev/null",
+            "server_key": "/dev/null",
+            "socket_path": "/dev/null",
+            "listen_addr": "127.0.0.1:0",
+            "data_dir": "/dev/null",
+            "acls": {
+                "private": { "users": { "bob": 1 }, "groups": { "devs": 3 } },
+                "public": { "guest_read": true },
+            },
+        })).unwrap()
+    }
+
+    /// Looks up a user by name.
+    fn user(users: &JsonUsers, username: &str) -> User { users.find(username).unwrap().unwrap() }
+
+    #[test]
+    fn test_root() {
+        let (config, users) = (config(), users());
+        let root: User = user(&users, "root");
+        for package in [ "private", "public", "other" ] {
+            for access in [ Permissions::DOWNLOAD, Permissions::UPLOAD, Permissions::DELETE ] {
+                assert!(allowed(&config, &users, &root, package, access).unwrap());
+            }
+        }
+
+        // Not even root gets more than its session allows
+        let mut root: User = root;
+        root.scope = Permissions::DOWNLOAD;
+        assert!(allowed(&config, &users, &root, "private", Permissions::DOWNLOAD).unwrap());
+        assert!(!allowed(&config, &users, &root, "private", Permissions::UPLOAD).unwrap());
+    }
+
+    #[test]
+    fn test_guest() {
+        let (config, users) = (config(), users());
+        let guest: User = user(&users, "guest");
+        assert!(allowed(&config, &users, &guest, "public", Permissions::DOWNLOAD).unwrap());
+        assert!(!allowed(&config, &users, &guest, "public", Permissions::UPLOAD).unwrap());
+        assert!(!allowed(&config, &users, &guest, "private", Permissions::DOWNLOAD).unwrap());
+        assert!(!allowed(&config, &users, &guest, "other", Permissions::DOWNLOAD).unwrap());
+    }
+
+    #[test]
+    fn test_grants() {
+        let (config, users) = (config(), users());
+
+        // Grants to the user itself
+        let bob: User = user(&users, "bob");
+        assert!(allowed(&config, &users, &bob, "private", Permissions::DOWNLOAD).unwrap());
+        assert!(!allowed(&config, &users, &bob, "private", Permissions::UPLOAD).unwrap());
+
+        // Grants to its groups
+        let carol: User = user(&users, "carol");
+        assert!(allowed(&config, &users, &carol, "private", Permissions::DOWNLOAD).unwrap());
+        assert!(allowed(&config, &users, &carol, "private", Permissions::UPLOAD).unwrap());
+        assert!(!allowed(&config, &users, &carol, "private", Permissions::DELETE).unwrap());
+
+        // No grant means no access, whatever the user may do elsewhere
+        let alice: User = user(&users, "alice");
+        assert!(!allowed(&config, &users, &alice, "private", Permissions::DOWNLOAD).unwrap());
+        assert!(!allowed(&config, &users, &alice, "public", Permissions::DOWNLOAD).unwrap());
+
+        // The scope of the session still applies
+        let mut carol: User = carol;
+        carol.scope = Permissions::DOWNLOAD;
+        assert!(!allowed(&config, &users, &carol, "private", Permissions::UPLOAD).unwrap());
+    }
+
+    #[test]
+    fn test_no_acl() {
+        let (config, users) = (config(), users());
+        let alice: User = user(&users, "alice");
+        assert!(allowed(&config, &users, &alice, "other", Permissions::DOWNLOAD).unwrap());
+        assert!(allowed(&config, &users, &alice, "other", Permissions::UPLOAD).unwrap());
+        assert!(!allowed(&config, &users, &alice, "other", Permissions::DELETE).unwrap());
+        let bob: User = user(&users, "bob");
+        assert!(!allowed(&config, &users, &bob, "other", Permissions::DOWNLOAD).unwrap());
+    }
+}
@@ -0,0 +1,326 @@
+/* AUDIT.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 06:41:27
+ * Last edited:
+ *   19 Oct 2026, 07:08:31
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the append-only audit log of a host, in which every
+ *   operation that changes something is recorded together with who did
+ *   it, from where, and how it went.
+**/
+
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::Utc;
+use log::{error, warn};
+
+use filehost_spc::audit::{hash_line, AuditEntry, AuditOperation, AuditOutcome, GENESIS_HASH};
+use filehost_spc::client_messages::ErrorReply;
+use filehost_spc::ctl_messages::{AuditProblem, AuditReport};
+
+pub use crate::errors::AuditError as Error;
+
+
+/***** CONSTANTS *****/
+/// The name under which the daemon records the operations that it does by itself (e.g., applying retention policies).
+pub const SYSTEM_USER: &str = "filehostd";
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Reads the audit log at the given path. A log that does not exist yet is empty.
+///
+/// # Errors
+/// This function errors if the log exists but could not be read.
+fn read_log(path: &Path) -> Result<String, Error> {
+    match fs::read_to_string(path) {
+        Ok(log)                                       => Ok(log),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(err)                                      => Err(Error::ReadError{ path: path.into(), err }),
+    }
+}
+
+/// Checks that the entries in the given audit log form an unbroken chain.
+fn check(log: &str) -> AuditReport {
+    let mut prev: String = GENESIS_HASH.into();
+    let mut problems: Vec<AuditProblem> = vec![];
+    let mut entries: u64 = 0;
+    for (i, line) in log.lines().enumerate() {
+        entries += 1;
+        let number: u64 = i as u64 + 1;
+        match serde_json::from_str::<AuditEntry>(line) {
+            Ok(entry) => {
+                if entry.seq != i as u64 { problems.push(AuditProblem{ line: number, problem: format!("Entry has sequence number {} instead of {}", entry.seq, i) }); }
+                if entry.prev != prev { problems.push(AuditProblem{ line: number, problem: format!("Entry refers to previous entry '{}', but that one hashes to '{}'", entry.prev, prev) }); }
+            },
+            Err(err) => { problems.push(AuditProblem{ line: number, problem: format!("Not a valid entry: {}", err) }); },
+        }
+        prev = hash_line(line);
+    }
+    AuditReport{ entries, head: prev, problems }
+}
+
+
+
+
+
+/***** HELPER TYPES *****/
+/// The end of the audit log, where new entries are appended.
+#[derive(Debug)]
+struct Head {
+    /// The log, opened for appending.
+    file : File,
+    /// The sequence number of the next entry.
+    seq  : u64,
+    /// The hash of the last entry.
+    prev : String,
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Wraps the stream to a client or the CTL, remembering the last error reply sent over it. That way, the outcome of a request can be audited after its handler ran.
+#[derive(Debug)]
+pub struct Tracked<S> {
+    /// The wrapped stream.
+    pub inner : S,
+    /// The last error reply sent, if any.
+    error     : Option<ErrorReply>,
+}
+
+impl<S> Tracked<S> {
+    /// Constructor for the Tracked stream.
+    ///
+    /// # Arguments
+    /// - `inner`: The stream to wrap.
+    #[inline]
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            error : None,
+        }
+    }
+
+    /// Remembers that the given error reply is sent.
+    #[inline]
+    pub fn fail(&mut self, reply: &ErrorReply) { self.error = Some(reply.clone()); }
+
+    /// Forgets the last error reply, e.g., before the next request is handled.
+    #[inline]
+    pub fn reset(&mut self) { self.error = None; }
+
+    /// Returns the outcome of the request that was just handled, and forgets the last error reply.
+    ///
+    /// # Arguments
+    /// - `res`: What the handler of the request returned.
+    pub fn outcome<E: Display>(&mut self, res: &Result<(), E>) -> AuditOutcome {
+        match (self.error.take(), res) {
+            (Some(reply), _) => AuditOutcome::Failure{ code: reply.code, message: reply.message },
+            (None, Err(err)) => AuditOutcome::Interrupted{ message: err.to_string() },
+            (None, Ok(()))   => AuditOutcome::Success,
+        }
+    }
+}
+
+impl<S: Read> Read for Tracked<S> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { self.inner.read(buf) }
+}
+
+impl<S: Write> Write for Tracked<S> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.inner.write(buf) }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> { self.inner.flush() }
+}
+
+
+
+/// The audit log of a host.
+#[derive(Debug)]
+pub struct AuditLog {
+    /// The path of the log.
+    path : PathBuf,
+    /// The end of the log.
+    head : Mutex<Head>,
+}
+
+impl AuditLog {
+    /// Opens the audit log at the given path, creating it if it does not exist yet. Problems with the entries that are already in it are logged as warnings.
+    ///
+    /// # Errors
+    /// This function errors if the log could not be read or opened for appending.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path: PathBuf = path.into();
+
+        // Check what's already there
+        let log: String = read_log(&path)?;
+        let report: AuditReport = check(&log);
+        for problem in &report.problems {
+            warn!("Audit log '{}' is damaged or was tampered with at line {}: {}", path.display(), problem.line, problem.problem);
+        }
+
+        // Continue where it left off, after finishing any line that was only partly written
+        let mut file: File = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => file,
+            Err(err) => { return Err(Error::OpenError{ path, err }); },
+        };
+        if !log.is_empty() && !log.ends_with('\n') {
+            if let Err(err) = file.write_all(b"\n") { return Err(Error::WriteError{ path, err }); }
+        }
+        Ok(Self {
+            path,
+            head : Mutex::new(Head{ file, seq: report.entries, prev: report.head }),
+        })
+    }
+
+    /// Locks the end of the log, which also keeps others from appending while we read it.
+    #[inline]
+    fn lock(&self) -> MutexGuard<'_, Head> { self.head.lock().unwrap_or_else(|err| err.into_inner()) }
+
+
+
+    /// Appends an entry to the log. Since the operation has already been done, failing to record it is logged instead of returned.
+    ///
+    /// # Arguments
+    /// - `username`: The name of the user that did the operation.
+    /// - `address`: The address the user connected from, or `None` if it used the CTL socket.
+    /// - `operation`: What the user did.
+    /// - `target`: What the user did it to.
+    /// - `outcome`: How it went.
+    pub fn record(&self, username: &str, address: Option<IpAddr>, operation: AuditOperation, target: String, outcome: AuditOutcome) {
+        let mut head: MutexGuard<Head> = self.lock();
+        let entry = AuditEntry {
+            seq      : head.seq,
+            time     : Utc::now(),
+            username : username.into(),
+            address,
+            operation,
+            target,
+            outcome,
+            prev     : head.prev.clone(),
+        };
+        let line: String = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(err) => { error!("{}", Error::SerializeError{ err }); return; },
+        };
+        if let Err(err) = head.file.write_all(format!("{}\n", line).as_bytes()).and_then(|_| head.file.sync_data()) {
+            error!("{} (lost entry: {})", Error::WriteError{ path: self.path.clone(), err }, line);
+            return;
+        }
+        head.seq += 1;
+        head.prev = hash_line(&line);
+    }
+
+
+
+    /// Returns the entries in the log, oldest first. Lines that are not valid entries are skipped.
+    ///
+    /// # Errors
+    /// This function errors if the log could not be read.
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, Error> {
+        let _head: MutexGuard<Head> = self.lock();
+        Ok(read_log(&self.path)?.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+
+    /// Checks that the entries in the log form an unbroken chain.
+    ///
+    /// # Errors
+    /// This function errors if the log could not be read.
+    pub fn verify(&self) -> Result<AuditReport, Error> {
+        let _head: MutexGuard<Head> = self.lock();
+        Ok(check(&read_log(&self.path)?))
+    }
+}
+
+
+
+
+
+/***** TESTS *****/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a log of the given number of entries that form an unbroken chain, one line per entry.
+    fn chain(n: u64) -> Vec<String> {
+        let mut prev: String = GENESIS_HASH.into();
+        (0..n).map(|seq| {
+            let entry = AuditEntry{ seq, time: Utc::now(), username: "alice".into(), address: None, operation: AuditOperation::Publish, target: format!("test@{}", seq), outcome: AuditOutcome::Success, prev: prev.clone() };
+            let line: String = serde_json::to_string(&entry).unwrap();
+            prev = hash_line(&line);
+            line
+        }).collect()
+    }
+
+    /// Joins the lines of a log like the daemon writes them.
+    fn join(lines: &[String]) -> String { lines.iter().map(|line| format!("{}\n", line)).collect() }
+
+    #[test]
+    fn test_intact() {
+        let lines: Vec<String> = chain(4);
+        let report: AuditReport = check(&join(&lines));
+        assert_eq!(report.entries, 4);
+        assert!(report.problems.is_empty());
+        assert_eq!(report.head, hash_line(&lines[3]));
+
+        // An empty log is intact too
+        let report: AuditReport = check("");
+        assert_eq!(report.entries, 0);
+        assert!(report.problems.is_empty());
+        assert_eq!(report.head, GENESIS_HASH);
+    }
+
+    #[test]
+    fn test_tampered() {
+        let mut lines: Vec<String> = chain(4);
+        lines[1] = lines[1].replace("\"alice\"", "\"mallory\"");
+        let report: AuditReport = check(&join(&lines));
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].line, 3);
+    }
+
+    #[test]
+    fn test_reordered() {
+        let mut lines: Vec<String> = chain(4);
+        lines.swap(1, 2);
+        let report: AuditReport = check(&join(&lines));
+        assert!(report.problems.iter().any(|p| p.line == 2));
+        assert!(report.problems.iter().any(|p| p.line == 3));
+        assert!(report.problems.iter().all(|p| p.line == 2 || p.line == 3 || p.line == 4));
+    }
+
+    #[test]
+    fn test_truncated_middle() {
+        let mut lines: Vec<String> = chain(4);
+        lines.remove(1);
+        let report: AuditReport = check(&join(&lines));
+        assert_eq!(report.entries, 3);
+        assert!(!report.problems.is_empty());
+        assert!(report.problems.iter().all(|p| p.line >= 2));
+        assert!(report.problems.iter().any(|p| p.line == 2));
+    }
+
+    #[test]
+    fn test_garbage() {
+        let mut lines: Vec<String> = chain(2);
+        lines.insert(1, "not an entry".into());
+        let report: AuditReport = check(&join(&lines));
+        assert!(report.problems.iter().any(|p| p.line == 2 && p.problem.starts_with("Not a valid entry")));
+    }
+}
@@ -0,0 +1,264 @@
+/* CA.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 05:54:06
+ * Last edited:
+ *   19 Oct 2026, 07:03:36
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the certificate authority with which the daemon issues
+ *   client certificates. Issued certificates carry the ID of their user
+ *   in their subject alternative names, so they don't have to be trusted
+ *   one-by-one.
+**/
+
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, warn};
+use rcgen::{CertificateParams, CertificateSigningRequest, DistinguishedName, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SanType};
+use rustls::Certificate;
+use time::OffsetDateTime;
+use x509_parser::extensions::GeneralName;
+
+use filehost_spc::config::Config;
+use filehost_spc::login::{GUEST_ID, UserId};
+
+pub use crate::errors::CAError as Error;
+use crate::ssl;
+use crate::users::{IssuedCert, User, Users};
+
+
+/***** CONSTANTS *****/
+/// The prefix of the URI in the subject alternative names of an issued certificate, which is followed by the ID of its user.
+pub const USER_URI_PREFIX: &str = "filehost:user:";
+
+/// How long before they are issued that certificates become valid, so clients with a slightly wrong clock can use them right away.
+const CLOCK_SKEW: i64 = 5 * 60;
+
+/// The signature algorithms we accept on certificates that claim to be issued by us.
+static SIGNATURE_ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+];
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Converts a chrono timestamp to the one rcgen wants.
+#[inline]
+fn to_offset_date_time(time: DateTime<Utc>) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp(time.timestamp()).expect("Timestamp out of range for OffsetDateTime; this should never happen!")
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Returns whether the given PEM data contains a certificate signing request (as opposed to, e.g., certificates).
+#[inline]
+pub fn is_csr(pem: &str) -> bool {
+    pem.contains("CERTIFICATE REQUEST-----")
+}
+
+/// Parses a PEM-encoded certificate signing request, checking that it is signed by the key it wants a certificate for.
+///
+/// # Errors
+/// This function errors if the request could not be parsed or if its signature is wrong.
+#[inline]
+pub fn parse_csr(pem: &str) -> Result<CertificateSigningRequest, Error> {
+    CertificateSigningRequest::from_pem(pem).map_err(|err| Error::CsrParseError{ err })
+}
+
+/// Remembers that the certificate authority issued the given certificate to the given user, so that the daemon can warn about it before it expires.
+///
+/// Problems are logged instead of returned, since the certificate has been issued either way.
+///
+/// # Arguments
+/// - `users`: The users database to remember the certificate in.
+/// - `user`: The user the certificate was issued to.
+/// - `pem`: The PEM-encoded certificate.
+/// - `expires`: The moment the certificate expires.
+pub fn record(users: &dyn Users, user: &User, pem: &str, expires: DateTime<Utc>) {
+    let der: Certificate = match rustls_pemfile::certs(&mut pem.as_bytes()).ok().and_then(|certs| certs.into_iter().next()) {
+        Some(der) => Certificate(der),
+        None      => { warn!("Could not parse the certificate issued to user '{}'; the daemon won't warn before it expires", user.username); return; },
+    };
+    let (serial, subject): (String, String) = match (ssl::serial(&der), x509_parser::parse_x509_certificate(&der.0)) {
+        (Some(serial), Ok((_, parsed))) => (serial, parsed.subject().to_string()),
+        _                               => { warn!("Could not parse the certificate issued to user '{}'; the daemon won't warn before it expires", user.username); return; },
+    };
+    let cert = IssuedCert{ serial, user: user.id, subject, issued: Utc::now(), expires };
+    if let Err(err) = users.insert_issued_cert(&cert) { warn!("Could not remember certificate {} issued to user '{}'; the daemon won't warn before it expires: {}", cert.serial, user.username, err); }
+}
+
+
+
+/// The certificate authority that issues the client certificates of users.
+pub struct CertificateAuthority {
+    /// The (DER-encoded) certificate of the certificate authority. Clients that present a certificate issued by it are trusted.
+    pub cert : Certificate,
+    /// The certificate authority as rcgen needs it to sign certificates.
+    signer   : rcgen::Certificate,
+    /// The number of seconds that issued certificates remain valid.
+    validity : u64,
+}
+
+impl CertificateAuthority {
+    /// Constructor for the CertificateAuthority.
+    ///
+    /// # Arguments
+    /// - `cert_path`: The path to the certificate of the certificate authority.
+    /// - `key_path`: The path to the (PKCS#8) private key of the certificate authority.
+    /// - `validity`: The number of seconds that issued certificates remain valid.
+    ///
+    /// # Errors
+    /// This function errors if the certificate or key could not be read, or if they cannot be used to sign certificates.
+    pub fn new(cert_path: &Path, key_path: &Path, validity: u64) -> Result<Self, Error> {
+        // Load the certificate
+        let mut handle = match File::open(cert_path) {
+            Ok(handle) => BufReader::new(handle),
+            Err(err)   => { return Err(Error::CertOpenError{ path: cert_path.into(), err }); }
+        };
+        let cert: Certificate = match rustls_pemfile::certs(&mut handle) {
+            Ok(certs) => match certs.into_iter().next() {
+                Some(cert) => Certificate(cert),
+                None       => { return Err(Error::NoCertsFound{ path: cert_path.into() }); },
+            },
+            Err(err)  => { return Err(Error::CertParseError{ path: cert_path.into(), err }); }
+        };
+
+        // Load the key
+        let key: String = match fs::read_to_string(key_path) {
+            Ok(key)  => key,
+            Err(err) => { return Err(Error::KeyOpenError{ path: key_path.into(), err }); }
+        };
+        let key: KeyPair = KeyPair::from_pem(&key).map_err(|err| Error::KeyParseError{ path: key_path.into(), err })?;
+
+        // Combine them in something that can sign
+        let signer: rcgen::Certificate = CertificateParams::from_ca_cert_der(&cert.0, key)
+            .and_then(rcgen::Certificate::from_params)
+            .map_err(|err| Error::SignerError{ path: cert_path.into(), err })?;
+        Ok(Self {
+            cert,
+            signer,
+            validity,
+        })
+    }
+
+    /// Loads the certificate authority from the paths in the given Config, if it has any.
+    ///
+    /// # Returns
+    /// The CertificateAuthority, or `None` if the Config does not configure one.
+    ///
+    /// # Errors
+    /// This function errors if only half of the certificate authority is configured, or if it could not be loaded.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, Error> {
+        match (&config.ca_cert, &config.ca_key) {
+            (Some(cert), Some(key)) => Ok(Some(Self::new(cert, key, config.cert_validity)?)),
+            (None, None)            => Ok(None),
+            _                       => Err(Error::IncompleteConfig),
+        }
+    }
+
+
+
+    /// Prepares the parameters of a certificate for the given user.
+    ///
+    /// # Returns
+    /// The parameters, together with the moment the certificate expires.
+    fn params(&self, user: &User) -> Result<(CertificateParams, DateTime<Utc>), Error> {
+        if user.id == GUEST_ID { return Err(Error::GuestUser); }
+
+        let now: DateTime<Utc>     = Utc::now();
+        let expires: DateTime<Utc> = now + Duration::seconds(self.validity as i64);
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, user.username.as_str());
+
+        let mut params = CertificateParams::default();
+        params.not_before          = to_offset_date_time(now - Duration::seconds(CLOCK_SKEW));
+        params.not_after           = to_offset_date_time(expires);
+        params.serial_number       = Some(rand::random());
+        params.distinguished_name  = name;
+        params.subject_alt_names   = vec![ SanType::URI(format!("{}{}", USER_URI_PREFIX, user.id)) ];
+        params.is_ca               = IsCa::ExplicitNoCa;
+        params.key_usages          = vec![ KeyUsagePurpose::DigitalSignature ];
+        params.extended_key_usages = vec![ ExtendedKeyUsagePurpose::ClientAuth ];
+        params.use_authority_key_identifier_extension = true;
+        Ok((params, expires))
+    }
+
+    /// Issues a client certificate with a newly generated key pair to the given user.
+    ///
+    /// # Returns
+    /// The PEM-encoded certificate, its PEM-encoded (PKCS#8) private key and the moment it expires. The key is not stored anywhere.
+    ///
+    /// # Errors
+    /// This function errors if the user is the guest user, or if we failed to generate or sign the certificate.
+    pub fn issue(&self, user: &User) -> Result<(String, String, DateTime<Utc>), Error> {
+        let (params, expires): (CertificateParams, DateTime<Utc>) = self.params(user)?;
+        let cert: rcgen::Certificate = rcgen::Certificate::from_params(params).map_err(|err| Error::KeyGenerateError{ err })?;
+        let pem: String = cert.serialize_pem_with_signer(&self.signer).map_err(|err| Error::SignError{ err })?;
+        debug!("Issued certificate to user '{}' ({}), valid until {}", user.username, user.id, expires);
+        Ok((pem, cert.serialize_private_key_pem(), expires))
+    }
+
+    /// Signs a certificate signing request (see `parse_csr()`) for the given user. The subject and alternative names in the request are replaced by those of the user.
+    ///
+    /// # Returns
+    /// The PEM-encoded certificate and the moment it expires.
+    ///
+    /// # Errors
+    /// This function errors if the user is the guest user or if we failed to sign the certificate.
+    pub fn sign(&self, user: &User, mut csr: CertificateSigningRequest) -> Result<(String, DateTime<Utc>), Error> {
+        let (params, expires): (CertificateParams, DateTime<Utc>) = self.params(user)?;
+        csr.params = params;
+        let pem: String = csr.serialize_pem_with_signer(&self.signer).map_err(|err| Error::SignError{ err })?;
+        debug!("Signed certificate signing request of user '{}' ({}), valid until {}", user.username, user.id, expires);
+        Ok((pem, expires))
+    }
+
+
+
+    /// Determines the user that a certificate was issued to, if it was issued by us.
+    ///
+    /// # Returns
+    /// The ID of the user, or `None` if the certificate was not issued by us, is not valid (anymore) or does not name a user.
+    pub fn identify(&self, cert: &Certificate) -> Option<UserId> {
+        // Only trust the names in certificates that we signed ourselves
+        let anchor = webpki::TrustAnchor::try_from_cert_der(&self.cert.0).ok()?;
+        let leaf   = webpki::EndEntityCert::try_from(cert.0.as_slice()).ok()?;
+        let now    = webpki::Time::try_from(SystemTime::now()).ok()?;
+        if let Err(err) = leaf.verify_is_valid_tls_client_cert(SIGNATURE_ALGORITHMS, &webpki::TlsClientTrustAnchors(&[ anchor ]), &[], now) {
+            debug!("Certificate was not issued by our certificate authority: {}", err);
+            return None;
+        }
+
+        // Find the user in its alternative names
+        let (_, cert) = x509_parser::parse_x509_certificate(&cert.0).ok()?;
+        let names = cert.subject_alternative_name().ok()??;
+        names.value.general_names.iter().find_map(|name| match name {
+            GeneralName::URI(uri) => uri.strip_prefix(USER_URI_PREFIX).and_then(|id| id.parse().ok()),
+            _                     => None,
+        })
+    }
+}
@@ -0,0 +1,1118 @@
+/* CLIENT.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:26:37
+ * Last edited:
+ *   19 Oct 2026, 07:29:14
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the handling of a single client connection, which speaks
+ *   the framed protocol from `filehost_spc::client_messages` over TLS.
+**/
+
+use std::io::{ErrorKind, Read};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use rustls::{ServerConnection, StreamOwned};
+use rustls::server::{Accepted, Acceptor};
+
+use filehost_spc::audit::AuditOperation;
+use filehost_spc::channel::Channel;
+use filehost_spc::client_messages::{
+    DATA_CHUNK_SIZE,
+    AuthenticateRequest, BeginPublish, ChannelRequest, Committed, CreateTokenRequest, DeleteRequest, Diff, DiffRequest, DownloadHeader, DownloadRequest, ErrorCode, ErrorReply,
+    ManifestRequest, NewToken, Opcode, PublishState, Redeemed, RedeemRequest, Resolved, ResolveRequest, SetChannelRequest, SignedCertificate, SignRequest, TokenRequest, TransactionRequest,
+    UploadComplete, UploadReady, UploadRequest,
+};
+use filehost_spc::frames::{flush, read_frame, write_frame, write_json, Frame, FrameError};
+use filehost_spc::login::{GUEST_ID, ROOT_ID, Permissions, TokenInfo, UserId};
+use filehost_spc::manifest::Manifest;
+use filehost_spc::quota::Quota;
+
+pub use crate::errors::ClientError as Error;
+use crate::{access, ca, invites, tokens};
+use crate::audit::Tracked;
+use crate::limits::Throttle;
+use crate::errors::{CAError, InviteError, PackageError, PublishError, StorageError, UserError};
+use crate::publish::{Transaction, Upload};
+use crate::state::{Hosts, ServerState};
+use crate::storage::BlobReader;
+use crate::users::{Token, User};
+
+
+/***** CONSTANTS *****/
+/// The time a client may remain silent before we drop its connection.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(300);
+
+
+
+
+
+/***** HELPER TYPES *****/
+/// Shortcut for the TLS stream we talk to the client over, which remembers the errors we send for the audit log.
+type TlsStream = Tracked<StreamOwned<ServerConnection, TcpStream>>;
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Sends an error reply to the client.
+///
+/// # Arguments
+/// - `stream`: The stream to send the reply on.
+/// - `code`: The kind of error to send.
+/// - `message`: A human-readable explanation of the error.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn reply_error(stream: &mut TlsStream, code: ErrorCode, message: impl Into<String>) -> Result<(), Error> {
+    let reply = ErrorReply{ code, message: message.into() };
+    debug!("Replying with error '{}'", reply);
+    stream.fail(&reply);
+    write_json(stream, Opcode::Error, &reply).map_err(|err| Error::FrameError{ err })
+}
+
+/// Maps a PackageError to the error code that we send to the client.
+#[inline]
+fn package_error_code(err: &PackageError) -> ErrorCode {
+    match err {
+        PackageError::IllegalName{ .. }    => ErrorCode::InvalidRequest,
+        PackageError::UnknownPackage{ .. } |
+        PackageError::UnknownVersion{ .. } |
+        PackageError::UnknownChannel{ .. } => ErrorCode::NotFound,
+        PackageError::VersionExists{ .. }  |
+        PackageError::NameTaken{ .. }      |
+        PackageError::VersionInUse{ .. }   => ErrorCode::Conflict,
+        _                                  => ErrorCode::Internal,
+    }
+}
+
+/// Sends a PackageError to the client, logging it if it's our own fault.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+#[inline]
+fn reply_package_error(stream: &mut TlsStream, err: PackageError) -> Result<(), Error> {
+    let code: ErrorCode = package_error_code(&err);
+    if code == ErrorCode::Internal { warn!("{}", err); }
+    reply_error(stream, code, err.to_string())
+}
+
+/// Maps a PublishError to the error code that we send to the client.
+#[inline]
+fn publish_error_code(err: &PublishError) -> ErrorCode {
+    match err {
+        PublishError::IllegalName{ .. }          |
+        PublishError::IllegalEntryPath{ .. }     |
+        PublishError::DuplicateEntryPath{ .. }   |
+        PublishError::InconsistentBlobSize{ .. } |
+        PublishError::ChunkSizeMismatch{ .. }    |
+        PublishError::InvalidSignature{ .. }     |
+        PublishError::UnknownBlob{ .. }          |
+        PublishError::MissingBlob{ .. }          => ErrorCode::InvalidRequest,
+        PublishError::VersionExists{ .. }        |
+        PublishError::TransactionExists{ .. }    |
+        PublishError::UploadInProgress{ .. }     => ErrorCode::Conflict,
+        PublishError::UnknownTransaction{ .. }   => ErrorCode::NotFound,
+        PublishError::UserQuotaExceeded{ .. }    |
+        PublishError::PackageQuotaExceeded{ .. } => ErrorCode::QuotaExceeded,
+        PublishError::IllegalOffset{ .. }        => ErrorCode::InvalidRange,
+        PublishError::HashMismatch{ .. }         |
+        PublishError::SizeMismatch{ .. }         => ErrorCode::HashMismatch,
+        PublishError::PackageError{ err }        => package_error_code(err),
+        _                                        => ErrorCode::Internal,
+    }
+}
+
+/// Sends a PublishError to the client, logging it if it's our own fault.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+#[inline]
+fn reply_publish_error(stream: &mut TlsStream, err: PublishError) -> Result<(), Error> {
+    let code: ErrorCode = publish_error_code(&err);
+    if code == ErrorCode::Internal { warn!("{}", err); }
+    reply_error(stream, code, err.to_string())
+}
+
+/// Maps a StorageError to the error code that we send to the client.
+#[inline]
+fn storage_error_code(err: &StorageError) -> ErrorCode {
+    match err {
+        StorageError::BlobNotFound{ .. } => ErrorCode::NotFound,
+        _                                => ErrorCode::Internal,
+    }
+}
+
+/// Sends a UserError to the client, logging it if it's our own fault.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn reply_user_error(stream: &mut TlsStream, err: UserError) -> Result<(), Error> {
+    let code: ErrorCode = match err {
+        UserError::UnknownToken{ .. } => ErrorCode::NotFound,
+        _                             => { warn!("{}", err); ErrorCode::Internal },
+    };
+    reply_error(stream, code, err.to_string())
+}
+
+/// Checks whether the user may manage its API tokens, replying with an error if not.
+///
+/// Only users that logged in with a client certificate may, so a leaked token cannot be used to create new ones.
+///
+/// # Returns
+/// Whether the user may go ahead. If not, an error has already been sent to the client.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn check_token_owner(stream: &mut TlsStream, user: &User) -> Result<bool, Error> {
+    if user.id == GUEST_ID || user.scope != Permissions::ALL {
+        reply_error(stream, ErrorCode::PermissionDenied, "API tokens can only be managed by users that logged in with a client certificate")?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Checks whether the user may do something with a package, replying with an error if not.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `package`: The package the user wants to access.
+/// - `access`: What the user wants to do with it.
+/// - `what`: Describes what the user wants to do, to complete "You are not allowed to ... package 'X'".
+///
+/// # Returns
+/// Whether the user may go ahead. If not, an error has already been sent to the client.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn check_access(stream: &mut TlsStream, state: &ServerState, user: &User, package: &str, access: Permissions, what: &str) -> Result<bool, Error> {
+    match access::allowed(&state.config, &*state.users, user, package, access) {
+        Ok(true)  => Ok(true),
+        Ok(false) => { reply_error(stream, ErrorCode::PermissionDenied, format!("You are not allowed to {} package '{}'", what, package))?; Ok(false) },
+        Err(err)  => { warn!("{}", err); reply_error(stream, ErrorCode::Internal, err.to_string())?; Ok(false) },
+    }
+}
+
+
+
+/// Handles a request for the manifest of a package version.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_get_manifest(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: ManifestRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' requests manifest of {}@{}", user.username, req.package, req.version);
+
+    // Check the permissions
+    if !check_access(stream, state, user, &req.package, Permissions::DOWNLOAD, "download")? { return Ok(()); }
+
+    // Fetch the manifest
+    let manifest: Manifest = match state.packages.manifest(&req.package, &req.version) {
+        Ok(manifest) => manifest,
+        Err(err)     => { return reply_package_error(stream, err); }
+    };
+
+    // Send it
+    write_json(stream, Opcode::Manifest, &manifest).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request for a range of a blob.
+///
+/// The blob is streamed from the storage backend in chunks of `DATA_CHUNK_SIZE`, so it is never loaded in memory entirely.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `throttle`: Applies the limits of the daemon to the client, which may slow down the download.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client or read the blob. Errors with the request itself are sent to the client instead.
+fn handle_download(stream: &mut TlsStream, state: &ServerState, throttle: &Throttle, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: DownloadRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' requests blob '{}' (offset {}, length {:?})", user.username, req.hash, req.offset, req.length);
+
+    // Check the permissions; the blob must belong to a package the user may read (blobs that no version refers to anymore are only for root, since they may be left over from a package the user could never read)
+    let packages: Vec<String> = match state.packages.packages_with_blob(&req.hash) {
+        Ok(packages) => packages,
+        Err(err)     => { return reply_package_error(stream, err); }
+    };
+    let mut readable: bool = packages.is_empty() && user.id == ROOT_ID && user.scope.has(Permissions::DOWNLOAD);
+    for package in &packages {
+        match access::allowed(&state.config, &*state.users, user, package, Permissions::DOWNLOAD) {
+            Ok(true)  => { readable = true; break; },
+            Ok(false) => {},
+            Err(err)  => { warn!("{}", err); return reply_error(stream, ErrorCode::Internal, err.to_string()); },
+        }
+    }
+    if !readable { return reply_error(stream, ErrorCode::PermissionDenied, format!("You are not allowed to download blob '{}'", req.hash)); }
+
+    // Check the range before opening anything
+    let size: u64 = match state.storage.size(&req.hash) {
+        Ok(size) => size,
+        Err(err) => {
+            if storage_error_code(&err) == ErrorCode::Internal { warn!("{}", err); }
+            return reply_error(stream, storage_error_code(&err), err.to_string());
+        },
+    };
+    if req.offset > size || req.length.map(|l| l > size - req.offset).unwrap_or(false) {
+        return reply_error(stream, ErrorCode::InvalidRange, format!("Range (offset {}, length {:?}) is out of bounds for blob of {} bytes", req.offset, req.length, size));
+    }
+
+    // Open the blob
+    let mut blob: BlobReader = match state.storage.open_range(&req.hash, req.offset, req.length) {
+        Ok(blob) => blob,
+        Err(err) => {
+            if storage_error_code(&err) == ErrorCode::Internal { warn!("{}", err); }
+            return reply_error(stream, storage_error_code(&err), err.to_string());
+        },
+    };
+
+    // Announce what we'll send
+    let header = DownloadHeader{ hash: req.hash, size: blob.size, offset: blob.offset, length: blob.length };
+    if let Err(err) = write_json(stream, Opcode::DownloadHeader, &header) { return Err(Error::FrameError{ err }); }
+
+    // Stream the data itself
+    let mut buffer: Vec<u8> = vec![ 0; DATA_CHUNK_SIZE ];
+    let mut remaining: u64 = blob.length;
+    while remaining > 0 {
+        let n_bytes: usize = match blob.reader.read(&mut buffer) {
+            Ok(0)       => { return Err(Error::BlobReadError{ hash: req.hash, err: std::io::ErrorKind::UnexpectedEof.into() }); },
+            Ok(n_bytes) => n_bytes,
+            Err(err)    => { return Err(Error::BlobReadError{ hash: req.hash, err }); },
+        };
+        throttle.transfer(n_bytes);
+        if let Err(err) = write_frame(stream, Opcode::Data, &buffer[..n_bytes]) { return Err(Error::FrameError{ err }); }
+        remaining -= n_bytes as u64;
+    }
+    flush(stream).map_err(|err| Error::FrameError{ err })
+}
+
+
+
+/// Sends the state of the given transaction to the client.
+///
+/// # Errors
+/// This function errors if we could not write to the stream. Errors with inspecting the transaction are sent to the client instead.
+fn reply_publish_state(stream: &mut TlsStream, state: &ServerState, transaction: &Transaction) -> Result<(), Error> {
+    let needed = match transaction.needed(&state.storage) {
+        Ok(needed) => needed,
+        Err(err)   => { return reply_publish_error(stream, err); }
+    };
+    let reply = PublishState {
+        transaction : transaction.id.clone(),
+        package     : transaction.manifest.package.clone(),
+        version     : transaction.manifest.version.clone(),
+        needed,
+    };
+    write_json(stream, Opcode::PublishState, &reply).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to open a new publish transaction.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_begin_publish(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: BeginPublish = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' wants to publish {}@{} ({} files)", user.username, req.manifest.package, req.manifest.version, req.manifest.entries.len());
+
+    // Check the permissions
+    if !check_access(stream, state, user, &req.manifest.package, Permissions::UPLOAD, "publish to")? { return Ok(()); }
+
+    // Open the transaction
+    let package_quota: Quota = state.config.quotas.get(&req.manifest.package).copied().unwrap_or_default();
+    let transaction: Arc<Mutex<Transaction>> = match state.transactions.begin(user.id, req.manifest, &state.packages, &user.quota, &package_quota) {
+        Ok(transaction) => transaction,
+        Err(err)        => { return reply_publish_error(stream, err); }
+    };
+    let transaction: MutexGuard<Transaction> = match state.transactions.lock(&transaction, user.id) {
+        Ok(transaction) => transaction,
+        Err(err)        => { return reply_publish_error(stream, err); }
+    };
+
+    // Tell the client what we need
+    reply_publish_state(stream, state, &transaction)
+}
+
+/// Handles a request for the state of an open publish transaction.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_publish_status(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: TransactionRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' requests the state of transaction '{}'", user.username, req.transaction);
+
+    // Find the transaction
+    let transaction: Arc<Mutex<Transaction>> = match state.transactions.get(&req.transaction, user.id) {
+        Ok(transaction) => transaction,
+        Err(err)        => { return reply_publish_error(stream, err); }
+    };
+    let transaction: MutexGuard<Transaction> = match state.transactions.lock(&transaction, user.id) {
+        Ok(transaction) => transaction,
+        Err(err)        => { return reply_publish_error(stream, err); }
+    };
+
+    // Check the permissions
+    if !check_access(stream, state, user, &transaction.manifest.package, Permissions::UPLOAD, "publish to")? { return Ok(()); }
+
+    // Tell the client what we need
+    reply_publish_state(stream, state, &transaction)
+}
+
+/// Handles the upload of (the rest of) a blob in a publish transaction.
+///
+/// The blob is received as `Data` frames and appended to its staged file as they come in, so an interrupted upload can be resumed later. Once complete, the blob is verified against its hash.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `throttle`: Applies the limits of the daemon to the client, which may slow down the upload.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client or store the data it sends. Errors with the request itself are sent to the client instead.
+fn handle_upload(stream: &mut TlsStream, state: &ServerState, throttle: &Throttle, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: UploadRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' uploads blob '{}' (offset {}) in transaction '{}'", user.username, req.hash, req.offset, req.transaction);
+
+    // Find the transaction
+    let transaction: Arc<Mutex<Transaction>> = match state.transactions.get(&req.transaction, user.id) {
+        Ok(transaction) => transaction,
+        Err(err)        => { return reply_publish_error(stream, err); }
+    };
+    let size: u64 = {
+        let transaction: MutexGuard<Transaction> = match state.transactions.lock(&transaction, user.id) {
+            Ok(transaction) => transaction,
+            Err(err)        => { return reply_publish_error(stream, err); }
+        };
+
+        // Check the permissions
+        if !check_access(stream, state, user, &transaction.manifest.package, Permissions::UPLOAD, "publish to")? { return Ok(()); }
+
+        // Find out how much we still need
+        let size: u64 = match transaction.blob_size(&req.hash) {
+            Some(size) => size,
+            None       => { return reply_publish_error(stream, PublishError::UnknownBlob{ hash: req.hash }); }
+        };
+        let received: u64 = match transaction.received(&state.storage, &req.hash) {
+            Ok(received) => received,
+            Err(err)     => { return reply_publish_error(stream, err); }
+        };
+        if req.offset != received { return reply_publish_error(stream, PublishError::IllegalOffset{ hash: req.hash, got: req.offset, expected: received }); }
+        if transaction.is_complete(&state.storage, &req.hash) {
+            // We already have all of it
+            if let Err(err) = write_json(stream, Opcode::UploadReady, &UploadReady{ hash: req.hash, offset: size, length: 0 }) { return Err(Error::FrameError{ err }); }
+            return write_json(stream, Opcode::UploadComplete, &UploadComplete{ hash: req.hash }).map_err(|err| Error::FrameError{ err });
+        }
+        size
+    };
+
+    // Don't keep the transaction locked while we wait for the client
+    let mut upload: Upload = match state.transactions.upload(&transaction, user.id, &req.hash, req.offset) {
+        Ok(upload) => upload,
+        Err(err)   => { return reply_publish_error(stream, err); }
+    };
+
+    // Tell the client to go ahead
+    let length: u64 = size - req.offset;
+    if let Err(err) = write_json(stream, Opcode::UploadReady, &UploadReady{ hash: req.hash, offset: req.offset, length }) { return Err(Error::FrameError{ err }); }
+
+    // Receive the data
+    let mut remaining: u64 = length;
+    while remaining > 0 {
+        let frame: Frame = match read_frame(stream) {
+            Ok(Some(frame)) => frame,
+            Ok(None)        => { return Err(Error::UploadInterrupted{ hash: req.hash }); },
+            Err(err)        => { return Err(Error::FrameError{ err }); }
+        };
+        if frame.opcode != u8::from(Opcode::Data) { return Err(Error::UnexpectedFrame{ opcode: frame.opcode }); }
+        if frame.payload.is_empty() { return Err(Error::EmptyData{ hash: req.hash }); }
+        if frame.payload.len() as u64 > remaining { return Err(Error::UploadOverflow{ hash: req.hash, expected: length }); }
+        if let Err(err) = upload.write(&frame.payload) { return Err(Error::UploadError{ err }); }
+        remaining -= frame.payload.len() as u64;
+        throttle.transfer(frame.payload.len());
+    }
+
+    // Verify it
+    if let Err(err) = upload.finish() { return reply_publish_error(stream, err); }
+    debug!("Received blob '{}' in transaction '{}'", req.hash, req.transaction);
+    write_json(stream, Opcode::UploadComplete, &UploadComplete{ hash: req.hash }).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to commit a publish transaction.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_commit(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: TransactionRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' commits transaction '{}'", user.username, req.transaction);
+
+    // Check the permissions on the package of the transaction, since they may have changed since it was opened
+    let package: String = match state.transactions.get(&req.transaction, user.id).and_then(|t| state.transactions.lock(&t, user.id).map(|t| t.manifest.package.clone())) {
+        Ok(package) => package,
+        Err(err)    => { return reply_publish_error(stream, err); }
+    };
+    if !check_access(stream, state, user, &package, Permissions::UPLOAD, "publish to")? { return Ok(()); }
+
+    // Commit it
+    let manifest: Manifest = match state.transactions.commit(&req.transaction, user.id, &state.storage, &state.packages) {
+        Ok(manifest) => manifest,
+        Err(err)     => { return reply_publish_error(stream, err); }
+    };
+    write_json(stream, Opcode::Committed, &Committed{ package: manifest.package, version: manifest.version }).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to abort a publish transaction.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_abort(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: TransactionRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' aborts transaction '{}'", user.username, req.transaction);
+
+    // Abort it (no need to check the permissions, since only the user that opened it may abort it)
+    if let Err(err) = state.transactions.abort(&req.transaction, user.id) { return reply_publish_error(stream, err); }
+    write_json(stream, Opcode::Aborted, &req).map_err(|err| Error::FrameError{ err })
+}
+
+
+
+/// Handles a request to resolve a version or channel name to a version.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_resolve(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: ResolveRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' wants to resolve {}@{}", user.username, req.package, req.name);
+
+    // Check the permissions
+    if !check_access(stream, state, user, &req.package, Permissions::DOWNLOAD, "download")? { return Ok(()); }
+
+    // Resolve the name
+    let (version, channel): (String, Option<String>) = match state.packages.resolve(&req.package, &req.name) {
+        Ok(res)  => res,
+        Err(err) => { return reply_package_error(stream, err); }
+    };
+    write_json(stream, Opcode::Resolved, &Resolved{ package: req.package, version, channel }).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request for a channel and its history.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_get_channel(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: ChannelRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' requests channel '{}' of package '{}'", user.username, req.channel, req.package);
+
+    // Check the permissions
+    if !check_access(stream, state, user, &req.package, Permissions::DOWNLOAD, "download")? { return Ok(()); }
+
+    // Fetch the channel
+    let channel: Channel = match state.packages.channel(&req.package, &req.channel) {
+        Ok(channel) => channel,
+        Err(err)    => { return reply_package_error(stream, err); }
+    };
+    write_json(stream, Opcode::Channel, &channel).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to move a channel to another version.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_set_channel(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: SetChannelRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' wants to point channel '{}' of package '{}' to version '{}'", user.username, req.channel, req.package, req.version);
+
+    // Check the permissions
+    if !check_access(stream, state, user, &req.package, Permissions::CHANNEL, "move the channels of")? { return Ok(()); }
+
+    // Move the channel
+    let channel: Channel = match state.packages.set_channel(&req.package, &req.channel, &req.version, &user.username) {
+        Ok(channel) => channel,
+        Err(err)    => { return reply_package_error(stream, err); }
+    };
+    info!("User '{}' pointed channel '{}' of package '{}' to version '{}'", user.username, channel.name, channel.package, channel.version);
+    write_json(stream, Opcode::Channel, &channel).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request for the differences between two versions of a package.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_get_diff(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: DiffRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' requests the diff of package '{}' from '{}' to '{}'", user.username, req.package, req.from, req.to);
+
+    // Check the permissions
+    if !check_access(stream, state, user, &req.package, Permissions::DOWNLOAD, "download")? { return Ok(()); }
+
+    // Resolve both sides and read their manifests
+    let mut manifests: Vec<Manifest> = Vec::with_capacity(2);
+    for name in [ &req.from, &req.to ] {
+        let manifest: Manifest = match state.packages.resolve(&req.package, name).and_then(|(version, _)| state.packages.manifest(&req.package, &version)) {
+            Ok(manifest) => manifest,
+            Err(err)     => { return reply_package_error(stream, err); }
+        };
+        manifests.push(manifest);
+    }
+
+    // Compare them
+    let diff: Diff = Diff{ package: req.package, from: manifests[0].version.clone(), to: manifests[1].version.clone(), diff: manifests[0].diff(&manifests[1]) };
+    write_json(stream, Opcode::Diff, &diff).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to delete a package version.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_delete(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: DeleteRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' wants to delete {}@{}", user.username, req.package, req.version);
+
+    // Check the permissions
+    if !check_access(stream, state, user, &req.package, Permissions::DELETE, "delete versions of")? { return Ok(()); }
+
+    // Remove it; its blobs are left for the garbage collector
+    {
+        let _commits: MutexGuard<()> = state.transactions.pause_commits();
+        if let Err(err) = state.packages.remove(&req.package, &req.version) { return reply_package_error(stream, err); }
+    }
+    info!("User '{}' deleted version '{}' of package '{}'", user.username, req.version, req.package);
+    write_json(stream, Opcode::Deleted, &req).map_err(|err| Error::FrameError{ err })
+}
+
+
+
+
+
+/// Handles a login with an API token, which turns the guest session into a session of the token's owner.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user of the session, which is replaced by the owner of the token if the login succeeds.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_authenticate(stream: &mut TlsStream, state: &ServerState, user: &mut User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: AuthenticateRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    if user.id != GUEST_ID || user.scope != Permissions::ALL { return reply_error(stream, ErrorCode::InvalidRequest, format!("Already logged in as user '{}'", user.username)); }
+
+    // Find the token
+    let (mut owner, token): (User, Token) = match tokens::authenticate(&*state.users, &req.token) {
+        Ok(Some(res)) => res,
+        Ok(None)      => { return reply_error(stream, ErrorCode::PermissionDenied, "Invalid or expired API token"); },
+        Err(err)      => { return reply_user_error(stream, err); },
+    };
+    if owner.is_suspended() { return reply_error(stream, ErrorCode::PermissionDenied, format!("User '{}' is suspended", owner.username)); }
+
+    // The session gets the permissions of the owner, but only as far as the token allows
+    owner.permissions = match state.users.effective_permissions(&owner) {
+        Ok(permissions) => permissions & token.permissions,
+        Err(err)        => { return reply_user_error(stream, err); },
+    };
+    owner.scope = token.permissions;
+    info!("Session of user '{}' is now logged in as user '{}' ({}) with API token '{}' (permissions {})", user.username, owner.username, owner.id, token.id, owner.permissions);
+
+    let info: TokenInfo = token.info(owner.username.clone());
+    *user = owner;
+    write_json(stream, Opcode::Authenticated, &info).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to create an API token.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_create_token(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: CreateTokenRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' wants to create an API token with permissions {} for {} seconds", user.username, req.permissions, req.valid_for);
+    if !check_token_owner(stream, user)? { return Ok(()); }
+    if req.valid_for == 0 || req.valid_for > tokens::MAX_VALID_FOR { return reply_error(stream, ErrorCode::InvalidRequest, format!("API tokens must be valid for at least 1 and at most {} seconds", tokens::MAX_VALID_FOR)); }
+
+    // Create it
+    let (token, raw): (Token, String) = match tokens::create(&*state.users, user, req.permissions, req.valid_for, req.description) {
+        Ok(res)  => res,
+        Err(err) => { return reply_user_error(stream, err); }
+    };
+    info!("User '{}' created API token '{}' with permissions {}, valid until {}", user.username, token.id, token.permissions, token.expires);
+    write_json(stream, Opcode::TokenCreated, &NewToken{ info: token.info(user.username.clone()), token: raw }).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request for the API tokens of the user.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client.
+fn handle_get_tokens(stream: &mut TlsStream, state: &ServerState, user: &User) -> Result<(), Error> {
+    debug!("User '{}' requests its API tokens", user.username);
+    if !check_token_owner(stream, user)? { return Ok(()); }
+
+    match state.users.tokens_of(user.id) {
+        Ok(tokens) => write_json(stream, Opcode::Tokens, &tokens.iter().map(|t| t.info(user.username.clone())).collect::<Vec<TokenInfo>>()).map_err(|err| Error::FrameError{ err }),
+        Err(err)   => reply_user_error(stream, err),
+    }
+}
+
+/// Handles a request to revoke one of the API tokens of the user.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_revoke_token(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: TokenRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' wants to revoke API token '{}'", user.username, req.id);
+    if !check_token_owner(stream, user)? { return Ok(()); }
+
+    // Users may only revoke their own tokens; those of others might as well not exist
+    match state.users.token(&req.id) {
+        Ok(Some(token)) if token.user == user.id => {},
+        Ok(_)    => { return reply_user_error(stream, UserError::UnknownToken{ id: req.id }); },
+        Err(err) => { return reply_user_error(stream, err); },
+    }
+    let token: Token = match state.users.remove_token(&req.id) {
+        Ok(token) => token,
+        Err(err)  => { return reply_user_error(stream, err); }
+    };
+    info!("User '{}' revoked API token '{}'", user.username, token.id);
+    write_json(stream, Opcode::TokenRevoked, &token.info(user.username.clone())).map_err(|err| Error::FrameError{ err })
+}
+
+
+
+/// Handles a request to have the certificate authority sign a certificate signing request of the user.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `user`: The user that sent the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_sign_certificate(stream: &mut TlsStream, state: &ServerState, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: SignRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' wants a certificate signing request signed", user.username);
+    if user.id == GUEST_ID || user.scope != Permissions::ALL { return reply_error(stream, ErrorCode::PermissionDenied, "Certificates can only be requested by users that logged in with a client certificate"); }
+    let authority = match &state.ca {
+        Some(ca) => ca,
+        None     => { return reply_error(stream, ErrorCode::InvalidRequest, "Server has no certificate authority"); }
+    };
+
+    // Sign it
+    let csr = match ca::parse_csr(&req.csr) {
+        Ok(csr)  => csr,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    let (certificate, expires): (String, DateTime<Utc>) = match authority.sign(user, csr) {
+        Ok(res)  => res,
+        Err(err) => { warn!("{}", err); return reply_error(stream, ErrorCode::Internal, err.to_string()); }
+    };
+    info!("Issued certificate to user '{}', valid until {}", user.username, expires);
+    ca::record(&*state.users, user, &certificate, expires);
+    write_json(stream, Opcode::CertificateSigned, &SignedCertificate{ certificate, expires }).map_err(|err| Error::FrameError{ err })
+}
+
+
+
+
+
+/// Handles a request to create a new user with an invite code.
+///
+/// # Arguments
+/// - `stream`: The stream to reply on.
+/// - `state`: The state of the server.
+/// - `throttle`: Applies the limits of the daemon to the client, which gets a strike for an invalid code.
+/// - `user`: The user that sent the request, which must be the guest user.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the client. Errors with the request itself are sent to the client instead.
+fn handle_redeem(stream: &mut TlsStream, state: &ServerState, throttle: &Throttle, user: &User, frame: &Frame) -> Result<(), Error> {
+    // Parse the request
+    let req: RedeemRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("User '{}' wants to redeem an invite code as user '{}'", user.username, req.username);
+    if user.id != GUEST_ID || user.scope != Permissions::ALL { return reply_error(stream, ErrorCode::InvalidRequest, format!("Already logged in as user '{}'", user.username)); }
+
+    // Create the user
+    let (new, groups, certificate): (User, Vec<String>, Option<String>) = match invites::redeem(state, &req.code, &req.username, &req.certificate) {
+        Ok(res)  => res,
+        Err(err) => {
+            let code: ErrorCode = match &err {
+                InviteError::InvalidCode                                     => { throttle.strike("tried to redeem an invalid invite code"); ErrorCode::PermissionDenied },
+                InviteError::IllegalUsername{ .. }                           |
+                InviteError::CertParseError{ .. }                            |
+                InviteError::NotACertificate                                 |
+                InviteError::NoCertsFound                                    |
+                InviteError::IllegalCert{ .. }                               |
+                InviteError::NoAuthority                                     |
+                InviteError::CAError{ err: CAError::CsrParseError{ .. } }    => ErrorCode::InvalidRequest,
+                InviteError::CertInUse{ .. }                                 |
+                InviteError::UserError{ err: UserError::UsernameTaken{ .. } } => ErrorCode::Conflict,
+                _                                                            => { warn!("{}", err); ErrorCode::Internal },
+            };
+            return reply_error(stream, code, err.to_string());
+        },
+    };
+    info!("Created user '{}' ({}) with permissions {} from an invite code", new.username, new.id, new.permissions);
+    write_json(stream, Opcode::Redeemed, &Redeemed{ username: new.username, permissions: new.permissions, groups, certificate }).map_err(|err| Error::FrameError{ err })
+}
+
+/// Determines whether the given request changes something, and if so, what.
+///
+/// # Returns
+/// The operation to audit and what it changes, or `None` if the request changes nothing. Requests that cannot be parsed change nothing either.
+fn audit_target(state: &ServerState, user: &User, frame: &Frame) -> Option<(AuditOperation, String)> {
+    match Opcode::try_from(frame.opcode).ok()? {
+        opcode @ (Opcode::Commit | Opcode::Abort) => {
+            let req: TransactionRequest = frame.parse().ok()?;
+            let operation: AuditOperation = if opcode == Opcode::Commit { AuditOperation::Publish } else { AuditOperation::Abort };
+            // The transaction knows which version it publishes, as long as it exists
+            let target: Option<String> = state.transactions.get(&req.transaction, user.id).ok().and_then(|transaction| {
+                state.transactions.lock(&transaction, user.id).ok().map(|transaction| format!("{}@{}", transaction.manifest.package, transaction.manifest.version))
+            });
+            Some((operation, target.unwrap_or_else(|| format!("transaction '{}'", req.transaction))))
+        },
+        Opcode::Delete => {
+            let req: DeleteRequest = frame.parse().ok()?;
+            Some((AuditOperation::Delete, format!("{}@{}", req.package, req.version)))
+        },
+        Opcode::SetChannel => {
+            let req: SetChannelRequest = frame.parse().ok()?;
+            Some((AuditOperation::SetChannel, format!("{}:{} -> {}", req.package, req.channel, req.version)))
+        },
+        Opcode::CreateToken => {
+            let req: CreateTokenRequest = frame.parse().ok()?;
+            Some((AuditOperation::CreateToken, format!("API token with permissions {}", req.permissions)))
+        },
+        Opcode::RevokeToken => {
+            let req: TokenRequest = frame.parse().ok()?;
+            Some((AuditOperation::RevokeToken, format!("API token '{}'", req.id)))
+        },
+        Opcode::Redeem => {
+            let req: RedeemRequest = frame.parse().ok()?;
+            Some((AuditOperation::Redeem, format!("user '{}'", req.username)))
+        },
+        Opcode::SignCertificate => Some((AuditOperation::SignCertificate, format!("certificate of user '{}'", user.username))),
+        _                       => None,
+    }
+}
+
+/// Returns whether the given error means that the client misbehaved (instead of, e.g., losing its connection), which counts towards a ban.
+fn is_offence(err: &Error) -> bool {
+    match err {
+        Error::ClientHelloError{ .. }                             |
+        Error::UnknownCertificate                                 |
+        Error::FrameError{ err: FrameError::FrameTooLarge{ .. } } |
+        Error::UnexpectedFrame{ .. }                              |
+        Error::UploadOverflow{ .. }                               => true,
+        // Rustls reports TLS errors (e.g., an untrusted client certificate) as invalid data
+        Error::HandshakeError{ err }                              => err.kind() == ErrorKind::InvalidData,
+        _                                                         => false,
+    }
+}
+
+/// Serves a single client connection until the client closes it.
+///
+/// # Arguments
+/// - `stream`: The (still unencrypted) stream to the client.
+/// - `address`: The address of the client.
+/// - `hosts`: The hosts of the server, of which the client picks one with the server name it sends.
+/// - `throttle`: Applies the limits of the daemon to the client.
+///
+/// # Errors
+/// This function errors if the TLS session could not be established or if we could not communicate with the client.
+fn serve(mut stream: TcpStream, address: SocketAddr, hosts: &Hosts, throttle: &mut Throttle) -> Result<(), Error> {
+    // Make sure the client can't keep us hostage
+    if let Err(err) = stream.set_read_timeout(Some(CLIENT_TIMEOUT)) { return Err(Error::TimeoutSetError{ err }); }
+    if let Err(err) = stream.set_write_timeout(Some(CLIENT_TIMEOUT)) { return Err(Error::TimeoutSetError{ err }); }
+
+    // Read the ClientHello first, since the server name in it decides which host (and thus certificate) the client gets
+    let mut acceptor: Acceptor = Acceptor::default();
+    let accepted: Accepted = loop {
+        match acceptor.read_tls(&mut stream) {
+            Ok(0)    => { return Err(Error::HandshakeError{ err: ErrorKind::UnexpectedEof.into() }); },
+            Ok(_)    => {},
+            Err(err) => { return Err(Error::HandshakeError{ err }); },
+        }
+        match acceptor.accept() {
+            Ok(Some(accepted)) => { break accepted; },
+            Ok(None)           => { continue; },
+            Err(err)           => { return Err(Error::ClientHelloError{ err }); },
+        }
+    };
+    let state: Arc<ServerState> = hosts.by_server_name(accepted.client_hello().server_name()).clone();
+    if let Some(vhost) = &state.vhost { debug!("Client '{}' asked for virtual host '{}'", address, vhost); }
+
+    // Wrap the stream in a TLS session and complete the handshake
+    let session: ServerConnection = match accepted.into_connection(state.ssl.config.clone()) {
+        Ok(session) => session,
+        Err(err)    => { return Err(Error::SessionCreateError{ err }); }
+    };
+    let mut stream: TlsStream = Tracked::new(StreamOwned::new(session, stream));
+    while stream.inner.conn.is_handshaking() {
+        if let Err(err) = stream.inner.conn.complete_io(&mut stream.inner.sock) { return Err(Error::HandshakeError{ err }); }
+    }
+
+    // Find out who we're talking to
+    let user_id: UserId = match state.ssl.identify(stream.inner.conn.peer_certificates()) {
+        Some(user_id) => user_id,
+        None          => { return Err(Error::UnknownCertificate); }
+    };
+    let mut user: User = match state.users.get(user_id) {
+        Ok(Some(user)) => user,
+        Ok(None)       => { return Err(Error::UnknownCertificate); },
+        Err(err)       => { return Err(Error::UserError{ err }); },
+    };
+    info!("Client '{}' authenticated as user '{}' ({})", address, user.username, user.id);
+
+    // For the rest of the session, the user has the permissions of its groups too
+    user.permissions = match state.users.effective_permissions(&user) {
+        Ok(permissions) => permissions,
+        Err(err)        => { return Err(Error::UserError{ err }); },
+    };
+    debug!("User '{}' has effective permissions {}", user.username, user.permissions);
+    throttle.login(&state, &user);
+
+    // Handle requests until the client is done
+    loop {
+        // Read the next request
+        let frame: Frame = match read_frame(&mut stream) {
+            Ok(Some(frame)) => frame,
+            Ok(None)        => { break; },
+            Err(err)        => { return Err(Error::FrameError{ err }); }
+        };
+
+        // Make sure the client stays within its limits
+        if throttle.is_banned() {
+            info!("Closing the connection of client '{}', which is banned", address);
+            break;
+        }
+        if let Some(wait) = throttle.request() {
+            reply_error(&mut stream, ErrorCode::RateLimited, format!("Too many requests; try again in {} ms", wait.as_millis().max(1)))?;
+            continue;
+        }
+
+        // Note what the request changes, if anything, before handling it changes the state
+        let audited: Option<(AuditOperation, String)> = audit_target(&state, &user, &frame);
+        stream.reset();
+
+        // Switch on its opcode
+        let res: Result<(), Error> = match Opcode::try_from(frame.opcode) {
+            Ok(Opcode::GetManifest)     => handle_get_manifest(&mut stream, &state, &user, &frame),
+            Ok(Opcode::Download)        => handle_download(&mut stream, &state, throttle, &user, &frame),
+            Ok(Opcode::BeginPublish)    => handle_begin_publish(&mut stream, &state, &user, &frame),
+            Ok(Opcode::PublishStatus)   => handle_publish_status(&mut stream, &state, &user, &frame),
+            Ok(Opcode::Upload)          => handle_upload(&mut stream, &state, throttle, &user, &frame),
+            Ok(Opcode::Commit)          => handle_commit(&mut stream, &state, &user, &frame),
+            Ok(Opcode::Abort)           => handle_abort(&mut stream, &state, &user, &frame),
+            Ok(Opcode::Resolve)         => handle_resolve(&mut stream, &state, &user, &frame),
+            Ok(Opcode::GetChannel)      => handle_get_channel(&mut stream, &state, &user, &frame),
+            Ok(Opcode::SetChannel)      => handle_set_channel(&mut stream, &state, &user, &frame),
+            Ok(Opcode::GetDiff)         => handle_get_diff(&mut stream, &state, &user, &frame),
+            Ok(Opcode::Delete)          => handle_delete(&mut stream, &state, &user, &frame),
+            Ok(Opcode::Authenticate)    => handle_authenticate(&mut stream, &state, &mut user, &frame).map(|()| {
+                if user.id != GUEST_ID { throttle.login(&state, &user); } else { throttle.strike("failed to log in with an API token"); }
+            }),
+            Ok(Opcode::CreateToken)     => handle_create_token(&mut stream, &state, &user, &frame),
+            Ok(Opcode::GetTokens)       => handle_get_tokens(&mut stream, &state, &user),
+            Ok(Opcode::RevokeToken)     => handle_revoke_token(&mut stream, &state, &user, &frame),
+            Ok(Opcode::Redeem)          => handle_redeem(&mut stream, &state, throttle, &user, &frame),
+            Ok(Opcode::SignCertificate) => handle_sign_certificate(&mut stream, &state, &user, &frame),
+
+            Ok(opcode) => {
+                throttle.strike(format!("sent unexpected opcode '{}'", opcode));
+                reply_error(&mut stream, ErrorCode::InvalidRequest, format!("Unexpected opcode '{}'", opcode))
+            },
+            Err(err) => {
+                throttle.strike(&err);
+                reply_error(&mut stream, ErrorCode::InvalidRequest, err.to_string())
+            },
+        };
+
+        // Record what the client changed, whether it went well or not
+        if let Some((operation, target)) = audited {
+            state.audit.record(&user.username, Some(address.ip()), operation, target, stream.outcome(&res));
+        }
+        res?;
+    }
+
+    // Done
+    debug!("Client '{}' closed the connection", address);
+    Ok(())
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Handles a single client connection until the client closes it.
+///
+/// # Arguments
+/// - `stream`: The (still unencrypted) stream to the client.
+/// - `address`: The address of the client.
+/// - `hosts`: The hosts of the server, of which the client picks one with the server name it sends.
+///
+/// # Errors
+/// This function errors if the TLS session could not be established or if we could not communicate with the client.
+pub fn handle(stream: TcpStream, address: SocketAddr, hosts: Arc<Hosts>) -> Result<(), Error> {
+    let mut throttle: Throttle = Throttle::new(hosts.limiter.clone(), address.ip());
+    match serve(stream, address, &hosts, &mut throttle) {
+        Ok(())   => Ok(()),
+        Err(err) => {
+            if is_offence(&err) { throttle.strike(&err); }
+            Err(err)
+        },
+    }
+}
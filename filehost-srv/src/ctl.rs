@@ -0,0 +1,947 @@
+/* CTL.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:41:58
+ * Last edited:
+ *   19 Oct 2026, 07:06:48
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the handling of the framed requests sent over the CTL
+ *   socket (i.e., everything except the health check).
+**/
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::MutexGuard;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use rustls::Certificate;
+
+use filehost_spc::audit::{AuditEntry, AuditOperation};
+use filehost_spc::channel::Channel;
+use filehost_spc::client_messages::{ChannelRequest, ErrorCode, ErrorReply, ResolveRequest, Resolved, TokenRequest};
+use filehost_spc::ctl_messages::{
+    AuditReport, AuditRequest, BanInfo, CertificateStatus, CertStatusRequest, ClearBansRequest, CreateInviteRequest, FsckReport, FsckRequest, GcReport, GcRequest, GroupInfo, GroupRequest, HEALTH_REPLY, HostRequest, IndexReport,
+    InviteInfo, InviteRequest, IssuedCertificate, IssueRequest, MemberRequest, NewInvite, Opcode, RevocationInfo, RevocationRequest, RevokeRequest, SetGroupRequest, SuspendRequest, UserInfo, UserRequest,
+};
+use filehost_spc::login::{GUEST_ID, Permissions, TokenInfo};
+use filehost_spc::quota::Usage;
+use filehost_spc::frames::{read_frame, read_frame_body, write_json, Frame};
+
+pub use crate::errors::CtlError as Error;
+use crate::audit::Tracked;
+use crate::errors::{CAError, PackageError, UserError};
+use crate::{access, ca, expiry, fsck, gc, invites, ssl};
+use crate::limits::Limiter;
+use crate::peers::Peer;
+use crate::state::{Hosts, ServerState};
+use crate::users::{Group, Invite, IssuedCert, Revocation, Token, User};
+
+
+/***** CONSTANTS *****/
+/// The time the CTL may remain silent before we drop its connection.
+const CTL_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+
+
+
+/***** HELPER TYPES *****/
+/// Shortcut for the stream we talk to the CTL over, which remembers the errors we send for the audit log.
+type CtlStream = Tracked<UnixStream>;
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Checks whether an ordinary user may make the given request.
+///
+/// Ordinary users may only look at the packages they may download, and at their own account and API tokens.
+///
+/// # Returns
+/// Why the user may not make the request, if it may not. Requests that cannot be parsed are let through, so that their handler reports that instead.
+///
+/// # Errors
+/// This function errors if we failed to read the users database.
+fn refusal(state: &ServerState, username: &str, opcode: Opcode, frame: &Frame) -> Result<Option<String>, UserError> {
+    let mut user: User = match state.users.find(username)? {
+        Some(user) => user,
+        None       => { return Ok(Some(format!("You act as user '{}', which does not exist", username))); },
+    };
+    if user.is_suspended() { return Ok(Some(format!("User '{}' is suspended", username))); }
+    user.permissions = state.users.effective_permissions(&user)?;
+
+    // Only a few requests are open to ordinary users
+    let package: String = match opcode {
+        Opcode::Resolve                     => match frame.parse::<ResolveRequest>() { Ok(req) => req.package, Err(_) => { return Ok(None); } },
+        Opcode::GetChannel                  => match frame.parse::<ChannelRequest>() { Ok(req) => req.package, Err(_) => { return Ok(None); } },
+        Opcode::GetUser | Opcode::GetTokens => {
+            return Ok(frame.parse::<UserRequest>().ok().filter(|req| req.username != user.username).map(|req| format!("You may not look at user '{}'", req.username)));
+        },
+        Opcode::RevokeToken => {
+            let tokens: Vec<Token> = state.users.tokens_of(user.id)?;
+            return Ok(frame.parse::<TokenRequest>().ok().filter(|req| !tokens.iter().any(|t| t.id == req.id)).map(|req| format!("You may not revoke API token '{}'", req.id)));
+        },
+        _ => { return Ok(Some("Only the root user may do this".into())); },
+    };
+    if access::allowed(&state.config, state.users.as_ref(), &user, &package, Permissions::DOWNLOAD)? { Ok(None) } else { Ok(Some(format!("You are not allowed to download package '{}'", package))) }
+}
+
+
+
+/// Determines whether the given request changes something, and if so, what.
+///
+/// # Returns
+/// The operation to audit and what it changes, or `None` if the request changes nothing. Requests that cannot be parsed change nothing either.
+fn audit_target(opcode: Opcode, frame: &Frame) -> Option<(AuditOperation, String)> {
+    match opcode {
+        Opcode::CollectGarbage => {
+            let req: GcRequest = frame.parse().ok()?;
+            if req.dry_run { None } else { Some((AuditOperation::CollectGarbage, "storage".into())) }
+        },
+        Opcode::Fsck => {
+            let req: FsckRequest = frame.parse().ok()?;
+            if req.quarantine { Some((AuditOperation::Quarantine, "storage".into())) } else { None }
+        },
+        Opcode::RebuildIndex => Some((AuditOperation::RebuildIndex, "index".into())),
+        Opcode::SetSuspended => {
+            let req: SuspendRequest = frame.parse().ok()?;
+            Some((AuditOperation::SetSuspended, format!("user '{}' ({})", req.username, if req.suspended { "suspended" } else { "unsuspended" })))
+        },
+        Opcode::SetGroup => {
+            let req: SetGroupRequest = frame.parse().ok()?;
+            Some((AuditOperation::SetGroup, format!("group '{}' ({})", req.name, req.permissions)))
+        },
+        Opcode::RemoveGroup => {
+            let req: GroupRequest = frame.parse().ok()?;
+            Some((AuditOperation::RemoveGroup, format!("group '{}'", req.name)))
+        },
+        opcode @ (Opcode::AddMember | Opcode::RemoveMember) => {
+            let req: MemberRequest = frame.parse().ok()?;
+            let operation: AuditOperation = if opcode == Opcode::AddMember { AuditOperation::AddMember } else { AuditOperation::RemoveMember };
+            Some((operation, format!("user '{}' in group '{}'", req.username, req.group)))
+        },
+        Opcode::RevokeToken => {
+            let req: TokenRequest = frame.parse().ok()?;
+            Some((AuditOperation::RevokeToken, format!("API token '{}'", req.id)))
+        },
+        Opcode::CreateInvite => {
+            let req: CreateInviteRequest = frame.parse().ok()?;
+            Some((AuditOperation::CreateInvite, format!("invite code with permissions {}", req.permissions)))
+        },
+        Opcode::RevokeInvite => {
+            let req: InviteRequest = frame.parse().ok()?;
+            Some((AuditOperation::RevokeInvite, format!("invite code '{}'", req.id)))
+        },
+        Opcode::IssueCertificate => {
+            let req: IssueRequest = frame.parse().ok()?;
+            Some((AuditOperation::IssueCertificate, format!("certificate of user '{}'", req.username)))
+        },
+        Opcode::RevokeCertificate => {
+            let req: RevokeRequest = frame.parse().ok()?;
+            Some((AuditOperation::RevokeCertificate, format!("certificate with {} {}", req.kind, req.value)))
+        },
+        Opcode::UnrevokeCertificate => {
+            let req: RevocationRequest = frame.parse().ok()?;
+            Some((AuditOperation::UnrevokeCertificate, format!("certificate with {} {}", req.kind, req.value)))
+        },
+        Opcode::ClearBans => {
+            let req: ClearBansRequest = frame.parse().ok()?;
+            Some((AuditOperation::ClearBans, req.address.map(|address| format!("address {}", address)).unwrap_or_else(|| "all addresses".into())))
+        },
+        _ => None,
+    }
+}
+
+/// Sends an error reply to the CTL.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn reply_error(stream: &mut CtlStream, code: ErrorCode, message: impl Into<String>) -> Result<(), Error> {
+    let reply = ErrorReply{ code, message: message.into() };
+    debug!("Replying to CTL with error '{}'", reply);
+    stream.fail(&reply);
+    write_json(stream, Opcode::Error, &reply).map_err(|err| Error::FrameError{ err })
+}
+
+/// Sends a PackageError to the CTL, logging it if it's our own fault.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn reply_package_error(stream: &mut CtlStream, err: PackageError) -> Result<(), Error> {
+    let code: ErrorCode = match err {
+        PackageError::IllegalName{ .. }    => ErrorCode::InvalidRequest,
+        PackageError::UnknownPackage{ .. } |
+        PackageError::UnknownVersion{ .. } |
+        PackageError::UnknownChannel{ .. } => ErrorCode::NotFound,
+        _                                  => { warn!("{}", err); ErrorCode::Internal },
+    };
+    reply_error(stream, code, err.to_string())
+}
+
+/// Sends a UserError to the CTL, logging it if it's our own fault.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn reply_user_error(stream: &mut CtlStream, err: UserError) -> Result<(), Error> {
+    let code: ErrorCode = match err {
+        UserError::IllegalGroupName{ .. }  => ErrorCode::InvalidRequest,
+        UserError::UnknownUser{ .. }       |
+        UserError::UnknownGroup{ .. }      |
+        UserError::UnknownToken{ .. }      |
+        UserError::UnknownInvite{ .. }     |
+        UserError::UnknownRevocation{ .. } => ErrorCode::NotFound,
+        UserError::UsernameTaken{ .. }     => ErrorCode::Conflict,
+        _                                  => { warn!("{}", err); ErrorCode::Internal },
+    };
+    reply_error(stream, code, err.to_string())
+}
+
+/// Looks up a user by name, sending an error to the CTL if that fails.
+///
+/// # Returns
+/// The user, or None if an error was sent instead.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn find_user(stream: &mut CtlStream, state: &ServerState, username: &str) -> Result<Option<User>, Error> {
+    match state.users.find(username) {
+        Ok(Some(user)) => Ok(Some(user)),
+        Ok(None)       => { reply_error(stream, ErrorCode::NotFound, format!("Unknown user '{}'", username))?; Ok(None) },
+        Err(err)       => { reply_user_error(stream, err)?; Ok(None) },
+    }
+}
+
+/// Describes a group to the CTL, naming its members instead of giving their IDs.
+///
+/// # Errors
+/// This function errors if we failed to read the users database.
+fn group_info(state: &ServerState, group: Group) -> Result<GroupInfo, UserError> {
+    let mut members: Vec<String> = Vec::with_capacity(group.members.len());
+    for id in group.members {
+        match state.users.get(id)? {
+            Some(user) => members.push(user.username),
+            None       => members.push(format!("<unknown user {}>", id)),
+        }
+    }
+    Ok(GroupInfo{ name: group.name, permissions: group.permissions, members })
+}
+
+/// Sends a group to the CTL.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn reply_group(stream: &mut CtlStream, state: &ServerState, group: Group) -> Result<(), Error> {
+    match group_info(state, group) {
+        Ok(info) => write_json(stream, Opcode::Group, &info).map_err(|err| Error::FrameError{ err }),
+        Err(err) => reply_user_error(stream, err),
+    }
+}
+
+/// Sends a user, its groups, its quota and its usage to the CTL.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn reply_user(stream: &mut CtlStream, state: &ServerState, user: User) -> Result<(), Error> {
+    let (groups, effective): (Vec<Group>, Permissions) = match state.users.groups_of(user.id).and_then(|groups| Ok((groups, state.users.effective_permissions(&user)?))) {
+        Ok(res)  => res,
+        Err(err) => { return reply_user_error(stream, err); }
+    };
+    let usage: Usage = match state.packages.usage(None, Some(user.id)) {
+        Ok(usage) => usage,
+        Err(err)  => { return reply_package_error(stream, err); }
+    };
+    let info = UserInfo{
+        id              : user.id,
+        username        : user.username.clone(),
+        permissions     : user.permissions,
+        groups          : groups.into_iter().map(|g| g.name).collect(),
+        effective,
+        quota           : user.quota,
+        usage,
+        pending         : state.transactions.pending_usage(user.id),
+        suspended       : user.is_suspended(),
+        suspended_until : user.suspended_until.filter(|_| user.is_suspended()),
+    };
+    write_json(stream, Opcode::User, &info).map_err(|err| Error::FrameError{ err })
+}
+
+/// Rebuilds the SSL configuration after the trusted certificates changed, sending an error to the CTL if that fails.
+///
+/// # Returns
+/// Whether the change is in effect. If not, an error was sent instead.
+///
+/// # Errors
+/// This function errors if we could not write to the stream.
+fn reload_ssl(stream: &mut CtlStream, state: &ServerState) -> Result<bool, Error> {
+    match state.reload_ssl() {
+        Ok(_)    => Ok(true),
+        Err(err) => {
+            warn!("{}", err);
+            reply_error(stream, ErrorCode::Internal, format!("Change was stored, but only takes effect once the daemon restarts: {}", err))?;
+            Ok(false)
+        },
+    }
+}
+
+
+
+/// Handles a request to resolve a version or channel name to a version.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_resolve(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: ResolveRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to resolve {}@{}", req.package, req.name);
+
+    let (version, channel): (String, Option<String>) = match state.packages.resolve(&req.package, &req.name) {
+        Ok(res)  => res,
+        Err(err) => { return reply_package_error(stream, err); }
+    };
+    write_json(stream, Opcode::Resolved, &Resolved{ package: req.package, version, channel }).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request for a channel and its history.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_get_channel(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: ChannelRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL requests channel '{}' of package '{}'", req.channel, req.package);
+
+    let channel: Channel = match state.packages.channel(&req.package, &req.channel) {
+        Ok(channel) => channel,
+        Err(err)    => { return reply_package_error(stream, err); }
+    };
+    write_json(stream, Opcode::Channel, &channel).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to collect garbage in the storage.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_collect_garbage(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: GcRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    let grace_period: Duration = Duration::from_secs(req.grace_period.unwrap_or(state.config.gc_grace_period));
+    debug!("CTL wants to collect garbage (dry run: {}, grace period: {}s)", req.dry_run, grace_period.as_secs());
+
+    let report: GcReport = match gc::collect(&state.storage, &state.packages, &state.transactions, grace_period, req.dry_run) {
+        Ok(report) => report,
+        Err(err)   => { warn!("{}", err); return reply_error(stream, ErrorCode::Internal, err.to_string()); }
+    };
+    write_json(stream, Opcode::GarbageCollected, &report).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request of the CTL to check the integrity of the storage.
+///
+/// # Arguments
+/// - `stream`: The stream to the CTL.
+/// - `state`: The state of the server.
+/// - `frame`: The frame with the FsckRequest.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL.
+fn handle_fsck(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: FsckRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to check the storage (quarantine: {})", req.quarantine);
+
+    let quarantine: Option<PathBuf> = if req.quarantine { Some(fsck::quarantine_dir(&state.config.data_dir)) } else { None };
+    let report: FsckReport = match fsck::scrub(&state.storage, &state.packages, &state.transactions, quarantine.as_deref()) {
+        Ok(report) => report,
+        Err(err)   => { warn!("{}", err); return reply_error(stream, ErrorCode::Internal, err.to_string()); }
+    };
+    write_json(stream, Opcode::FsckReport, &report).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request for a user, its quota and its usage.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_get_user(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: UserRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL requests user '{}'", req.username);
+
+    match find_user(stream, state, &req.username)? {
+        Some(user) => reply_user(stream, state, user),
+        None       => Ok(()),
+    }
+}
+
+/// Handles a request to suspend a user or to lift its suspension.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_set_suspended(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: SuspendRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to {} user '{}'", if req.suspended { "suspend" } else { "lift the suspension of" }, req.username);
+    if req.duration == Some(0) { return reply_error(stream, ErrorCode::InvalidRequest, "Suspensions must last at least 1 second"); }
+
+    let mut user: User = match find_user(stream, state, &req.username)? {
+        Some(user) => user,
+        None       => { return Ok(()); },
+    };
+    if user.id == GUEST_ID { return reply_error(stream, ErrorCode::InvalidRequest, "The guest user cannot be suspended"); }
+    user.suspended       = req.suspended;
+    // A suspension too long to represent simply never ends
+    user.suspended_until = if req.suspended { req.duration.and_then(|d| Utc::now().checked_add_signed(chrono::Duration::seconds(d.min(i64::MAX as u64 / 1000) as i64))) } else { None };
+    if let Err(err) = state.users.update(&user) { return reply_user_error(stream, err); }
+    if !reload_ssl(stream, state)? { return Ok(()); }
+
+    match (user.suspended, user.suspended_until) {
+        (true, Some(until)) => info!("Suspended user '{}' until {}", user.username, until),
+        (true, None)        => info!("Suspended user '{}'", user.username),
+        (false, _)          => info!("Lifted suspension of user '{}'", user.username),
+    }
+    reply_user(stream, state, user)
+}
+
+/// Handles a request for all groups.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL.
+fn handle_get_groups(stream: &mut CtlStream, state: &ServerState) -> Result<(), Error> {
+    debug!("CTL requests all groups");
+
+    let infos: Result<Vec<GroupInfo>, UserError> = state.users.groups().and_then(|groups| groups.into_iter().map(|g| group_info(state, g)).collect());
+    match infos {
+        Ok(infos) => write_json(stream, Opcode::Groups, &infos).map_err(|err| Error::FrameError{ err }),
+        Err(err)  => reply_user_error(stream, err),
+    }
+}
+
+/// Handles a request to create a group or change its permissions.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_set_group(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: SetGroupRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to give group '{}' permissions {}", req.name, req.permissions);
+
+    let group: Group = match state.users.set_group(&req.name, req.permissions) {
+        Ok(group) => group,
+        Err(err)  => { return reply_user_error(stream, err); }
+    };
+    info!("Group '{}' now has permissions {}", group.name, group.permissions);
+    reply_group(stream, state, group)
+}
+
+/// Handles a request to remove a group.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_remove_group(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: GroupRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to remove group '{}'", req.name);
+
+    let group: Group = match state.users.remove_group(&req.name) {
+        Ok(group) => group,
+        Err(err)  => { return reply_user_error(stream, err); }
+    };
+    info!("Removed group '{}'", group.name);
+    reply_group(stream, state, group)
+}
+
+/// Handles a request to add a user to or remove a user from a group.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_member(stream: &mut CtlStream, state: &ServerState, frame: &Frame, add: bool) -> Result<(), Error> {
+    let req: MemberRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to {} user '{}' {} group '{}'", if add { "add" } else { "remove" }, req.username, if add { "to" } else { "from" }, req.group);
+
+    let user: User = match find_user(stream, state, &req.username)? {
+        Some(user) => user,
+        None       => { return Ok(()); },
+    };
+    let res: Result<Group, UserError> = if add { state.users.add_member(&req.group, user.id) } else { state.users.remove_member(&req.group, user.id) };
+    let group: Group = match res {
+        Ok(group) => group,
+        Err(err)  => { return reply_user_error(stream, err); }
+    };
+    info!("{} user '{}' {} group '{}'", if add { "Added" } else { "Removed" }, user.username, if add { "to" } else { "from" }, group.name);
+    reply_group(stream, state, group)
+}
+
+/// Handles a request for the API tokens of a user.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_get_tokens(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: UserRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL requests the API tokens of user '{}'", req.username);
+
+    let user: User = match find_user(stream, state, &req.username)? {
+        Some(user) => user,
+        None       => { return Ok(()); },
+    };
+    match state.users.tokens_of(user.id) {
+        Ok(tokens) => write_json(stream, Opcode::Tokens, &tokens.iter().map(|t| t.info(user.username.clone())).collect::<Vec<TokenInfo>>()).map_err(|err| Error::FrameError{ err }),
+        Err(err)   => reply_user_error(stream, err),
+    }
+}
+
+/// Handles a request to revoke an API token.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_revoke_token(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: TokenRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to revoke API token '{}'", req.id);
+
+    let token: Token = match state.users.remove_token(&req.id) {
+        Ok(token) => token,
+        Err(err)  => { return reply_user_error(stream, err); }
+    };
+    let username: String = match state.users.get(token.user) {
+        Ok(user) => user.map(|u| u.username).unwrap_or_else(|| format!("<unknown user {}>", token.user)),
+        Err(err) => { return reply_user_error(stream, err); }
+    };
+    info!("Revoked API token '{}' of user '{}'", token.id, username);
+    write_json(stream, Opcode::TokenRevoked, &token.info(username)).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request for all invite codes that have not been redeemed yet.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL.
+fn handle_get_invites(stream: &mut CtlStream, state: &ServerState) -> Result<(), Error> {
+    debug!("CTL requests all invite codes");
+
+    match state.users.invites() {
+        Ok(invites) => write_json(stream, Opcode::Invites, &invites.iter().map(Invite::info).collect::<Vec<InviteInfo>>()).map_err(|err| Error::FrameError{ err }),
+        Err(err)    => reply_user_error(stream, err),
+    }
+}
+
+/// Handles a request to create an invite code.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_create_invite(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: CreateInviteRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to create an invite code with permissions {} and groups {:?} for {} seconds", req.permissions, req.groups, req.valid_for);
+    if req.valid_for == 0 || req.valid_for > invites::MAX_VALID_FOR { return reply_error(stream, ErrorCode::InvalidRequest, format!("Invite codes must be valid for at least 1 and at most {} seconds", invites::MAX_VALID_FOR)); }
+
+    let (invite, code): (Invite, String) = match invites::create(&*state.users, req.permissions, req.groups, req.valid_for, req.description) {
+        Ok(res)  => res,
+        Err(err) => { return reply_user_error(stream, err); }
+    };
+    info!("Created invite code '{}' with permissions {}, valid until {}", invite.id, invite.permissions, invite.expires);
+    write_json(stream, Opcode::InviteCreated, &NewInvite{ info: invite.info(), code }).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to revoke an invite code.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_revoke_invite(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: InviteRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to revoke invite code '{}'", req.id);
+
+    let invite: Invite = match state.users.remove_invite(&req.id) {
+        Ok(invite) => invite,
+        Err(err)   => { return reply_user_error(stream, err); }
+    };
+    info!("Revoked invite code '{}'", invite.id);
+    write_json(stream, Opcode::InviteRevoked, &invite.info()).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to have the certificate authority issue a client certificate to a user.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_issue_certificate(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: IssueRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to issue a certificate to user '{}'{}", req.username, if req.csr.is_some() { " from a certificate signing request" } else { "" });
+    let authority = match &state.ca {
+        Some(ca) => ca,
+        None     => { return reply_error(stream, ErrorCode::InvalidRequest, "Server has no certificate authority (set 'ca_cert' and 'ca_key' in its config)"); }
+    };
+
+    let user: User = match find_user(stream, state, &req.username)? {
+        Some(user) => user,
+        None       => { return Ok(()); },
+    };
+    let res = match req.csr {
+        Some(csr) => ca::parse_csr(&csr).and_then(|csr| authority.sign(&user, csr)).map(|(cert, expires)| (cert, None, expires)),
+        None      => authority.issue(&user).map(|(cert, key, expires)| (cert, Some(key), expires)),
+    };
+    match res {
+        Ok((certificate, key, expires)) => {
+            // Tell the serial number and fingerprint too, so the certificate can be revoked later
+            let der: Certificate = match rustls_pemfile::certs(&mut certificate.as_bytes()).ok().and_then(|certs| certs.into_iter().next()) {
+                Some(der) => Certificate(der),
+                None      => { return reply_error(stream, ErrorCode::Internal, "Could not parse issued certificate"); }
+            };
+            let (serial, fingerprint): (String, String) = (ssl::serial(&der).unwrap_or_default(), ssl::fingerprint(&der));
+            info!("Issued certificate with serial number {} to user '{}', valid until {}", serial, user.username, expires);
+            ca::record(&*state.users, &user, &certificate, expires);
+            write_json(stream, Opcode::CertificateIssued, &IssuedCertificate{ username: user.username, certificate, key, expires, serial, fingerprint }).map_err(|err| Error::FrameError{ err })
+        },
+        Err(err @ CAError::GuestUser)          |
+        Err(err @ CAError::CsrParseError{ .. }) => reply_error(stream, ErrorCode::InvalidRequest, err.to_string()),
+        Err(err)                                => { warn!("{}", err); reply_error(stream, ErrorCode::Internal, err.to_string()) },
+    }
+}
+
+/// Handles a request for the revocation list.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL.
+fn handle_get_revocations(stream: &mut CtlStream, state: &ServerState) -> Result<(), Error> {
+    debug!("CTL requests the revocation list");
+
+    match state.users.revocations() {
+        Ok(revocations) => write_json(stream, Opcode::Revocations, &revocations.iter().map(Revocation::info).collect::<Vec<RevocationInfo>>()).map_err(|err| Error::FrameError{ err }),
+        Err(err)        => reply_user_error(stream, err),
+    }
+}
+
+/// Handles a request to revoke a client certificate.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_revoke_certificate(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: RevokeRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to revoke the certificate with {} '{}'", req.kind, req.value);
+
+    let value: String = match req.kind.normalize(&req.value) {
+        Some(value) => value,
+        None        => { return reply_error(stream, ErrorCode::InvalidRequest, format!("'{}' is not a hexadecimal {}", req.value, req.kind)); }
+    };
+    let revocation = Revocation{ value, kind: req.kind, reason: req.reason, revoked: Utc::now() };
+    if let Err(err) = state.users.insert_revocation(&revocation) { return reply_user_error(stream, err); }
+    if !reload_ssl(stream, state)? { return Ok(()); }
+    info!("Revoked certificate with {} {}", revocation.kind, revocation.value);
+    write_json(stream, Opcode::CertificateRevoked, &revocation.info()).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to take a certificate off the revocation list.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_unrevoke_certificate(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: RevocationRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL wants to take the certificate with {} '{}' off the revocation list", req.kind, req.value);
+
+    let value: String = match req.kind.normalize(&req.value) {
+        Some(value) => value,
+        None        => { return reply_error(stream, ErrorCode::InvalidRequest, format!("'{}' is not a hexadecimal {}", req.value, req.kind)); }
+    };
+    let revocation: Revocation = match state.users.remove_revocation(&value) {
+        Ok(revocation) => revocation,
+        Err(err)       => { return reply_user_error(stream, err); }
+    };
+    if !reload_ssl(stream, state)? { return Ok(()); }
+    info!("Took certificate with {} {} off the revocation list", revocation.kind, revocation.value);
+    write_json(stream, Opcode::CertificateUnrevoked, &revocation.info()).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request for the certificates that expire soon.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_get_certificates(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: CertStatusRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    let within: u64 = req.within.unwrap_or(state.config.cert_warn_days);
+    debug!("CTL wants the certificates that expire within {} day(s){}", within, if req.all { " (or all)" } else { "" });
+
+    let users: Vec<User> = match state.users.list() {
+        Ok(users) => users,
+        Err(err)  => { return reply_user_error(stream, err); }
+    };
+    let issued: Vec<IssuedCert> = match state.users.issued_certs() {
+        Ok(issued) => issued,
+        Err(err)   => { return reply_user_error(stream, err); }
+    };
+    let revocations: Vec<Revocation> = match state.users.revocations() {
+        Ok(revocations) => revocations,
+        Err(err)        => { return reply_user_error(stream, err); }
+    };
+    let mut statuses: Vec<CertificateStatus> = expiry::host_certs(&state.config, state.ca.is_some());
+    statuses.extend(expiry::user_certs(&users, &issued, &revocations));
+
+    // Only keep the ones that matter, the most urgent first
+    let now: DateTime<Utc> = Utc::now();
+    statuses.retain(|status| req.all || (status.not_after - now).num_days() < i64::try_from(within).unwrap_or(i64::MAX));
+    statuses.sort_by_key(|status| status.not_after);
+    write_json(stream, Opcode::Certificates, &statuses).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request for the banned addresses.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL.
+fn handle_get_bans(stream: &mut CtlStream, limiter: &Limiter) -> Result<(), Error> {
+    debug!("CTL requests the banned addresses");
+    write_json(stream, Opcode::Bans, &limiter.bans()).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to lift bans.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_clear_bans(stream: &mut CtlStream, limiter: &Limiter, frame: &Frame) -> Result<(), Error> {
+    let req: ClearBansRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    match req.address {
+        Some(address) => debug!("CTL wants to lift the ban of '{}'", address),
+        None          => debug!("CTL wants to lift all bans"),
+    }
+
+    let lifted: Vec<BanInfo> = limiter.clear(req.address);
+    if let (Some(address), true) = (req.address, lifted.is_empty()) { return reply_error(stream, ErrorCode::NotFound, format!("Address '{}' is not banned", address)); }
+    write_json(stream, Opcode::BansCleared, &lifted).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request for entries of the audit log.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL. Errors with the request itself are sent to the CTL instead.
+fn handle_get_audit(stream: &mut CtlStream, state: &ServerState, frame: &Frame) -> Result<(), Error> {
+    let req: AuditRequest = match frame.parse() {
+        Ok(req)  => req,
+        Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+    };
+    debug!("CTL requests entries of the audit log");
+
+    let mut entries: Vec<AuditEntry> = match state.audit.entries() {
+        Ok(entries) => entries,
+        Err(err)    => { warn!("{}", err); return reply_error(stream, ErrorCode::Internal, err.to_string()); }
+    };
+    entries.retain(|entry| {
+        req.username.as_ref().map(|username| &entry.username == username).unwrap_or(true)
+            && req.operation.map(|operation| entry.operation == operation).unwrap_or(true)
+            && req.target.as_ref().map(|target| entry.target.contains(target.as_str())).unwrap_or(true)
+            && req.since.map(|since| entry.time >= since).unwrap_or(true)
+    });
+    if let Some(limit) = req.limit { entries.drain(..entries.len().saturating_sub(limit)); }
+    write_json(stream, Opcode::Audit, &entries).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to check the hash chain of the audit log.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL.
+fn handle_verify_audit(stream: &mut CtlStream, state: &ServerState) -> Result<(), Error> {
+    debug!("CTL wants to verify the audit log");
+
+    let report: AuditReport = match state.audit.verify() {
+        Ok(report) => report,
+        Err(err)   => { warn!("{}", err); return reply_error(stream, ErrorCode::Internal, err.to_string()); }
+    };
+    if !report.problems.is_empty() { warn!("Audit log has {} problem(s)", report.problems.len()); }
+    write_json(stream, Opcode::AuditVerified, &report).map_err(|err| Error::FrameError{ err })
+}
+
+/// Handles a request to rebuild the metadata index from the package database.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL.
+fn handle_rebuild_index(stream: &mut CtlStream, state: &ServerState) -> Result<(), Error> {
+    debug!("CTL wants to rebuild the metadata index");
+
+    // Make sure no version is added or removed while we rebuild
+    let _commits: MutexGuard<()> = state.transactions.pause_commits();
+    let report: IndexReport = match state.packages.rebuild_index() {
+        Ok(report) => report,
+        Err(err)   => { return reply_package_error(stream, err); }
+    };
+    info!("Rebuilt metadata index of {} package(s), {} version(s) and {} channel(s)", report.packages, report.versions, report.channels);
+    write_json(stream, Opcode::IndexRebuilt, &report).map_err(|err| Error::FrameError{ err })
+}
+
+/// Hands a request on the CTL socket to the function that handles it.
+///
+/// # Arguments
+/// - `stream`: The stream to the CTL.
+/// - `hosts`: The hosts of the server.
+/// - `state`: The state of the host that the request is about.
+/// - `opcode`: The opcode of the request.
+/// - `frame`: The frame with the request.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL.
+fn dispatch(stream: &mut CtlStream, hosts: &Hosts, state: &ServerState, opcode: Opcode, frame: &Frame) -> Result<(), Error> {
+    match opcode {
+        Opcode::Resolve             => handle_resolve(stream, state, frame),
+        Opcode::GetChannel          => handle_get_channel(stream, state, frame),
+        Opcode::CollectGarbage      => handle_collect_garbage(stream, state, frame),
+        Opcode::Fsck                => handle_fsck(stream, state, frame),
+        Opcode::GetUser             => handle_get_user(stream, state, frame),
+        Opcode::SetSuspended        => handle_set_suspended(stream, state, frame),
+        Opcode::RebuildIndex        => handle_rebuild_index(stream, state),
+        Opcode::GetGroups           => handle_get_groups(stream, state),
+        Opcode::SetGroup            => handle_set_group(stream, state, frame),
+        Opcode::RemoveGroup         => handle_remove_group(stream, state, frame),
+        Opcode::AddMember           => handle_member(stream, state, frame, true),
+        Opcode::RemoveMember        => handle_member(stream, state, frame, false),
+        Opcode::GetTokens           => handle_get_tokens(stream, state, frame),
+        Opcode::RevokeToken         => handle_revoke_token(stream, state, frame),
+        Opcode::GetInvites          => handle_get_invites(stream, state),
+        Opcode::CreateInvite        => handle_create_invite(stream, state, frame),
+        Opcode::RevokeInvite        => handle_revoke_invite(stream, state, frame),
+        Opcode::IssueCertificate    => handle_issue_certificate(stream, state, frame),
+        Opcode::GetRevocations      => handle_get_revocations(stream, state),
+        Opcode::RevokeCertificate   => handle_revoke_certificate(stream, state, frame),
+        Opcode::UnrevokeCertificate => handle_unrevoke_certificate(stream, state, frame),
+        Opcode::GetCertificates     => handle_get_certificates(stream, state, frame),
+        Opcode::GetBans             => handle_get_bans(stream, &hosts.limiter),
+        Opcode::ClearBans           => handle_clear_bans(stream, &hosts.limiter, frame),
+        Opcode::GetAudit            => handle_get_audit(stream, state, frame),
+        Opcode::VerifyAudit         => handle_verify_audit(stream, state),
+
+        opcode => reply_error(stream, ErrorCode::InvalidRequest, format!("Unexpected opcode '{}'", opcode)),
+    }
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Handles a single request on the CTL socket, from reading its opcode to sending the reply.
+///
+/// This is meant to run in its own thread, so that a peer that is slow to send its request (or never sends one) cannot hold up the main loop.
+///
+/// # Arguments
+/// - `stream`: The stream to the CTL.
+/// - `hosts`: The hosts of the server. The request is about the main host, unless it selects a virtual host first.
+/// - `peer`: As whom the local user on the other side may use the CTL socket.
+///
+/// # Errors
+/// This function errors if we could not communicate with the CTL.
+pub fn handle(stream: UnixStream, hosts: &Hosts, peer: &Peer) -> Result<(), Error> {
+    // Make sure the CTL can't keep us waiting forever
+    if let Err(err) = stream.set_read_timeout(Some(CTL_TIMEOUT)) { return Err(Error::TimeoutSetError{ err }); }
+    if let Err(err) = stream.set_write_timeout(Some(CTL_TIMEOUT)) { return Err(Error::TimeoutSetError{ err }); }
+    let stream: &mut CtlStream = &mut Tracked::new(stream);
+
+    // Read the first opcode
+    let mut opcode: [u8; 1] = [ 0 ];
+    match stream.read(&mut opcode) {
+        Ok(0)    => { return Err(Error::EmptyStream); },
+        Ok(_)    => {},
+        Err(err) => { return Err(Error::OpcodeReadError{ err }); },
+    }
+    let opcode: Opcode = Opcode::try_from(opcode[0]).map_err(|err| Error::OpcodeError{ err })?;
+
+    // Health is the only request that is not framed; send the agreed upon constant back, unless the peer may not even know that
+    if opcode == Opcode::Health {
+        if let Peer::Refused(_) = peer { return reply_error(stream, ErrorCode::PermissionDenied, "You may not use the CTL socket"); }
+        if let Err(err) = stream.write_all(&HEALTH_REPLY) { return Err(Error::HealthWriteError{ err }); }
+        debug!("Handled Health status update");
+        return Ok(());
+    }
+
+    // Read the rest of the request
+    let frame: Frame = match read_frame_body(stream, opcode.into()) {
+        Ok(frame) => frame,
+        Err(err)  => { return Err(Error::FrameError{ err }); }
+    };
+
+    // If the CTL selects a virtual host, the actual request follows
+    let (state, opcode, frame): (&ServerState, Opcode, Frame) = if opcode == Opcode::SelectHost {
+        let req: HostRequest = match frame.parse() {
+            Ok(req)  => req,
+            Err(err) => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+        };
+        let state: &ServerState = match hosts.vhosts.get(&req.name) {
+            Some(state) => state,
+            None        => { return reply_error(stream, ErrorCode::NotFound, format!("Unknown virtual host '{}'", req.name)); },
+        };
+        write_json(stream, Opcode::HostSelected, &()).map_err(|err| Error::FrameError{ err })?;
+        let frame: Frame = match read_frame(stream) {
+            Ok(Some(frame)) => frame,
+            Ok(None)        => { return Ok(()); },
+            Err(err)        => { return Err(Error::FrameError{ err }); }
+        };
+        let opcode: Opcode = match Opcode::try_from(frame.opcode) {
+            Ok(opcode) => opcode,
+            Err(err)   => { return reply_error(stream, ErrorCode::InvalidRequest, err.to_string()); }
+        };
+        debug!("CTL request '{}' is about virtual host '{}'", opcode, req.name);
+        (state, opcode, frame)
+    } else {
+        (&hosts.main, opcode, frame)
+    };
+
+    // Note what the request changes, if anything, before handling it changes the state
+    let audited: Option<(AuditOperation, String)> = audit_target(opcode, &frame);
+
+    // Peers that may not use the socket get nothing, and ordinary users may only do a few things
+    let res: Result<(), Error> = match peer {
+        Peer::Root           => dispatch(stream, hosts, state, opcode, &frame),
+        Peer::User(username) => match refusal(state, username, opcode, &frame) {
+            Ok(Some(reason)) => reply_error(stream, ErrorCode::PermissionDenied, reason),
+            Ok(None)         => dispatch(stream, hosts, state, opcode, &frame),
+            Err(err)         => reply_user_error(stream, err),
+        },
+        Peer::Refused(_) => reply_error(stream, ErrorCode::PermissionDenied, "You may not use the CTL socket"),
+    };
+
+    // Record what the CTL changed or tried to change, whether it went well or not
+    if let Some((operation, target)) = audited { state.audit.record(&peer.audit_name(), None, operation, target, stream.outcome(&res)); }
+    if res.is_ok() { debug!("Handled CTL request '{}'", opcode); }
+    res
+}
@@ -0,0 +1,177 @@
+/* DATABASE.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 05:23:59
+ * Last edited:
+ *   19 Oct 2026, 07:03:36
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Opens the SQLite metadata database that backs the package index and,
+ *   optionally, the user database, and migrates it to the newest schema.
+**/
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, info};
+use rusqlite::{Connection, Transaction};
+
+pub use crate::errors::DatabaseError as Error;
+
+
+/***** CONSTANTS *****/
+/// The migrations that bring the schema of the metadata database up to date, in order. The schema version of a database (its `user_version`) is the number of migrations applied to it.
+///
+/// Never change a migration once it has been released; add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: The initial schema
+    "CREATE TABLE packages (
+        name TEXT PRIMARY KEY
+    );
+    CREATE TABLE versions (
+        package   TEXT NOT NULL REFERENCES packages(name) ON DELETE CASCADE,
+        version   TEXT NOT NULL,
+        publisher INTEGER,
+        size      INTEGER NOT NULL,
+        published INTEGER NOT NULL,
+        PRIMARY KEY (package, version)
+    );
+    CREATE INDEX versions_publisher ON versions(publisher);
+    CREATE TABLE channels (
+        package TEXT NOT NULL REFERENCES packages(name) ON DELETE CASCADE,
+        name    TEXT NOT NULL,
+        version TEXT NOT NULL,
+        PRIMARY KEY (package, name)
+    );
+    CREATE TABLE entries (
+        package TEXT NOT NULL,
+        version TEXT NOT NULL,
+        path    TEXT NOT NULL,
+        hash    TEXT NOT NULL,
+        size    INTEGER NOT NULL,
+        chunks  INTEGER NOT NULL,
+        PRIMARY KEY (package, version, path),
+        FOREIGN KEY (package, version) REFERENCES versions(package, version) ON DELETE CASCADE
+    );
+    CREATE TABLE version_blobs (
+        package TEXT NOT NULL,
+        version TEXT NOT NULL,
+        hash    TEXT NOT NULL,
+        PRIMARY KEY (package, version, hash),
+        FOREIGN KEY (package, version) REFERENCES versions(package, version) ON DELETE CASCADE
+    );
+    CREATE TABLE blobs (
+        hash TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        refs INTEGER NOT NULL
+    );",
+    // 2: Bookkeeping and the user database
+    "CREATE TABLE meta (
+        key   TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE users (
+        id           INTEGER PRIMARY KEY,
+        username     TEXT NOT NULL UNIQUE,
+        certs        TEXT NOT NULL,
+        permissions  INTEGER NOT NULL,
+        max_bytes    INTEGER,
+        max_versions INTEGER
+    );
+    INSERT INTO meta (key, value) SELECT 'index_built', '1' WHERE EXISTS (SELECT 1 FROM packages);",
+    // 3: Groups of users
+    "CREATE TABLE groups (
+        name        TEXT PRIMARY KEY,
+        permissions INTEGER NOT NULL
+    );
+    CREATE TABLE group_members (
+        name TEXT NOT NULL REFERENCES groups(name) ON DELETE CASCADE,
+        user INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        PRIMARY KEY (name, user)
+    );",
+    // 4: API tokens
+    "CREATE TABLE tokens (
+        id          TEXT PRIMARY KEY,
+        user        INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        hash        TEXT NOT NULL,
+        permissions INTEGER NOT NULL,
+        description TEXT NOT NULL,
+        created     INTEGER NOT NULL,
+        expires     INTEGER NOT NULL
+    );
+    CREATE INDEX tokens_user ON tokens(user);",
+    // 5: Invite codes
+    "CREATE TABLE invites (
+        id          TEXT PRIMARY KEY,
+        hash        TEXT NOT NULL,
+        permissions INTEGER NOT NULL,
+        groups      TEXT NOT NULL,
+        description TEXT NOT NULL,
+        created     INTEGER NOT NULL,
+        expires     INTEGER NOT NULL
+    );",
+    // 6: Certificate revocation & user suspension
+    "ALTER TABLE users ADD COLUMN suspended INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE users ADD COLUMN suspended_until INTEGER;
+    CREATE TABLE revocations (
+        value   TEXT PRIMARY KEY,
+        kind    TEXT NOT NULL,
+        reason  TEXT NOT NULL,
+        revoked INTEGER NOT NULL
+    );",
+    // 7: Certificates issued by the certificate authority
+    "CREATE TABLE issued_certs (
+        serial  TEXT PRIMARY KEY,
+        user    INTEGER NOT NULL REFERENCES users(id),
+        subject TEXT NOT NULL,
+        issued  INTEGER NOT NULL,
+        expires INTEGER NOT NULL
+    );
+    CREATE INDEX issued_certs_user ON issued_certs(user);",
+];
+
+
+
+
+
+/***** LIBRARY *****/
+/// Opens the metadata database, creating it and migrating it to the newest schema if needed.
+///
+/// Every component that uses the database opens its own connection; SQLite serializes the writers for us.
+///
+/// # Arguments
+/// - `path`: The path of the database file.
+///
+/// # Returns
+/// A new Connection to the database.
+///
+/// # Errors
+/// This function errors if the database could not be opened or migrated, or if it has a schema we don't know.
+pub fn open(path: &Path) -> Result<Connection, Error> {
+    let mut conn: Connection = Connection::open(path).map_err(|err| Error::OpenError{ path: path.into(), err })?;
+    conn.pragma_update(None, "foreign_keys", true).map_err(|err| Error::OpenError{ path: path.into(), err })?;
+    conn.busy_timeout(Duration::from_secs(5)).map_err(|err| Error::OpenError{ path: path.into(), err })?;
+
+    // Find out where we are; take the write lock first so two connections don't migrate at the same time
+    let tx: Transaction = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate).map_err(|err| Error::OpenError{ path: path.into(), err })?;
+    let version: usize = tx.pragma_query_value(None, "user_version", |row| row.get::<_, i64>(0)).map_err(|err| Error::OpenError{ path: path.into(), err })? as usize;
+    if version > MIGRATIONS.len() { return Err(Error::UnknownSchema{ path: path.into(), version }); }
+
+    // Apply the missing migrations in one go
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        debug!("Migrating metadata database '{}' to schema version {}...", path.display(), i + 1);
+        tx.execute_batch(migration).map_err(|err| Error::MigrationError{ path: path.into(), version: i + 1, err })?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len() as i64).map_err(|err| Error::MigrationError{ path: path.into(), version: MIGRATIONS.len(), err })?;
+    tx.commit().map_err(|err| Error::MigrationError{ path: path.into(), version: MIGRATIONS.len(), err })?;
+    if version < MIGRATIONS.len() { info!("Migrated metadata database '{}' from schema version {} to {}", path.display(), version, MIGRATIONS.len()); }
+
+    Ok(conn)
+}
+
+/// Returns the path of the metadata database in the given data directory.
+#[inline]
+pub fn database_path(data_dir: &Path) -> PathBuf { data_dir.join("index.sqlite") }
@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:34:34
  * Last edited:
- *   11 Jun 2022, 15:24:08
+ *   19 Oct 2026, 07:28:06
  * Auto updated?
  *   Yes
  *
@@ -17,7 +17,11 @@ use std::fmt::{Debug, Display, Formatter, Result as FResult, Write};
 use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 
+use filehost_spc::ctl_messages::OpcodeError;
+use filehost_spc::frames::FrameError;
 use filehost_spc::login::UserId;
+use filehost_spc::manifest::{BlobHash, SignatureError};
+use filehost_spc::quota::QuotaError;
 
 
 /***** ERRORS *****/
@@ -30,9 +34,25 @@ pub enum ServerError {
     UsersParseError{ path: PathBuf, err: UserError },
     /// Could not prepare the SSL config
     SSLConfigError{ err: SSLError },
+    /// Could not load the certificate authority
+    CAInitError{ err: CAError },
+    /// Could not prepare the storage backend
+    StorageInitError{ err: StorageError },
+    /// Could not prepare the package database
+    PackagesInitError{ err: PackageError },
+    /// Could not prepare the publish transactions
+    TransactionsInitError{ err: PublishError },
+    /// Could not open the audit log
+    AuditInitError{ err: AuditError },
+    /// A virtual host uses the data directory of another host.
+    DataDirInUse{ path: PathBuf },
+    /// Could not load a virtual host.
+    VhostInitError{ name: String, err: Box<Self> },
 
     /// Could not get the list of file descriptors from systemd.
     ListenFdsFailure{ err: systemd::Error },
+    /// Could not bind the network listener.
+    ListenerBindError{ addr: String, err: std::io::Error },
 
     /// Could not wait for any socket to become available.
     SelectError{ err: nix::Error },
@@ -43,12 +63,18 @@ pub enum ServerError {
 
     /// Could not accept a new connection.
     StreamAcceptError{ what: &'static str, err: std::io::Error },
-    /// The given stream was notified but empty.
-    EmptyStream{ what: &'static str },
-    /// Could not read from the given stream.
-    StreamReadError{ what: &'static str, err: std::io::Error },
-    /// Could not write to the given stream.
-    StreamWriteError{ what: &'static str, err: std::io::Error },
+    /// Could not spawn a thread to handle a client connection.
+    ThreadSpawnError{ err: std::io::Error },
+    /// Could not spawn the thread that reaps stale transactions.
+    ReaperSpawnError{ err: std::io::Error },
+    /// Could not spawn a thread to handle a CTL request.
+    CtlSpawnError{ err: std::io::Error },
+    /// Could not spawn the thread that scrubs the storage.
+    ScrubberSpawnError{ err: std::io::Error },
+    /// Could not spawn the thread that applies the retention policies.
+    RetentionSpawnError{ err: std::io::Error },
+    /// Could not spawn the thread that checks when certificates expire.
+    ExpirySpawnError{ err: std::io::Error },
 }
 
 impl Display for ServerError {
@@ -56,19 +82,30 @@ impl Display for ServerError {
         use ServerError::*;
         match self {
             ConfigParseError{ path, err } => write!(f, "Could not parse configuration file '{}': {}", path.display(), err),
-            UsersParseError{ path, err }  => write!(f, "Could not parse users database '{}': {}", path.display(), err),
+            UsersParseError{ path, err }  => write!(f, "Could not load users database '{}': {}", path.display(), err),
             SSLConfigError{ err }         => write!(f, "Could not initialize SSL config: {}", err),
+            CAInitError{ err }            => write!(f, "Could not load certificate authority: {}", err),
+            StorageInitError{ err }       => write!(f, "Could not initialize storage backend: {}", err),
+            PackagesInitError{ err }      => write!(f, "Could not initialize package database: {}", err),
+            TransactionsInitError{ err }  => write!(f, "Could not initialize publish transactions: {}", err),
+            AuditInitError{ err }         => write!(f, "Could not initialize audit log: {}", err),
+            DataDirInUse{ path }          => write!(f, "Data directory '{}' is already used by another host", path.display()),
+            VhostInitError{ name, err }   => write!(f, "Could not load virtual host '{}': {}", name, err),
 
-            ListenFdsFailure{ err } => write!(f, "Could not get list of file descriptors: {}", err),
+            ListenFdsFailure{ err }        => write!(f, "Could not get list of file descriptors: {}", err),
+            ListenerBindError{ addr, err } => write!(f, "Could not bind network listener to '{}': {}", addr, err),
 
             SelectError{ err }            => write!(f, "Could not select on sockets: {}", err),
             CtlSocketError{ fd, err }     => write!(f, "An error has occurred on the CTL socket ({}): {}", fd, err),
             FdError{ what, fd }           => write!(f, "{} file descriptor ({}) has become invalid", what, fd),
 
             StreamAcceptError{ what, err } => write!(f, "Could not accept new connection on {} stream: {}", what, err),
-            EmptyStream{ what }            => write!(f, "{} stream is woken up but empty", what),
-            StreamReadError{ what, err }   => write!(f, "Could not read from {} stream: {}", what, err),
-            StreamWriteError{ what, err }  => write!(f, "Could not write to {} stream: {}", what, err),
+            ThreadSpawnError{ err }        => write!(f, "Could not spawn client thread: {}", err),
+            ReaperSpawnError{ err }        => write!(f, "Could not spawn transaction reaper thread: {}", err),
+            CtlSpawnError{ err }           => write!(f, "Could not spawn CTL thread: {}", err),
+            ScrubberSpawnError{ err }      => write!(f, "Could not spawn storage scrubber thread: {}", err),
+            RetentionSpawnError{ err }     => write!(f, "Could not spawn retention thread: {}", err),
+            ExpirySpawnError{ err }        => write!(f, "Could not spawn certificate expiry thread: {}", err),
         }
     }
 }
@@ -90,23 +127,50 @@ pub enum SSLError {
     NoKeysFound{ path: PathBuf },
     /// Failed to parse the given key file.
     KeyParseError{ path: PathBuf, err: std::io::Error },
+    /// The given key file is encrypted, but the config does not say where its passphrase is.
+    NoPassphrase{ path: PathBuf },
+    /// Could not read the file with the passphrase of the server key.
+    PassphraseReadError{ path: PathBuf, err: std::io::Error },
+    /// Could not read the environment variable with the passphrase of the server key.
+    PassphraseEnvError{ name: String, err: std::env::VarError },
+    /// The passphrase of the server key is a systemd credential, but systemd did not pass us any.
+    NoCredentialsDirectory{ name: String },
+    /// Failed to parse the given encrypted key file.
+    EncryptedKeyParseError{ path: PathBuf, err: pkcs8::Error },
+    /// Failed to decrypt the given key file.
+    KeyDecryptError{ path: PathBuf, err: pkcs8::Error },
     /// Could not add a new client key to the global list
     CertAddError{ err: webpki::Error },
+    /// The TLS policy names a cipher suite that we don't know.
+    UnknownCipherSuite{ name: String },
+    /// The cipher suites and protocol versions in the TLS policy don't go together.
+    PolicyError{ err: rustls::Error },
     /// Failed to build the server config.
     ConfigError{ err: rustls::Error },
+    /// Could not read the users whose certificates to trust.
+    UsersError{ err: UserError },
 }
 
 impl Display for SSLError {
     fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
         use SSLError::*;
         match self {
-            CertOpenError{ path, err }  => write!(f, "Could not open certificate(s) file '{}': {}", path.display(), err),
-            CertParseError{ path, err } => write!(f, "Could not parse certificate(s) file '{}': {}", path, err),
-            KeyOpenError{ path, err }   => write!(f, "Could not open server key file '{}': {}", path.display(), err),
-            NoKeysFound{ path }         => write!(f, "Key file '{}' does not contain any keys", path.display()),
-            KeyParseError{ path, err }  => write!(f, "Could not parse key file '{}': {}", path.display(), err),
-            CertAddError{ err }         => write!(f, "Failed to add user key to root store: {}", err),
-            ConfigError{ err }          => write!(f, "Failed to create server SSL/TLS config: {}", err),
+            CertOpenError{ path, err }          => write!(f, "Could not open certificate(s) file '{}': {}", path.display(), err),
+            CertParseError{ path, err }         => write!(f, "Could not parse certificate(s) file '{}': {}", path, err),
+            KeyOpenError{ path, err }           => write!(f, "Could not open server key file '{}': {}", path.display(), err),
+            NoKeysFound{ path }                 => write!(f, "Key file '{}' does not contain any keys", path.display()),
+            KeyParseError{ path, err }          => write!(f, "Could not parse key file '{}': {}", path.display(), err),
+            NoPassphrase{ path }                => write!(f, "Key file '{}' is encrypted, but no 'server_key_passphrase' is configured", path.display()),
+            PassphraseReadError{ path, err }    => write!(f, "Could not read passphrase file '{}': {}", path.display(), err),
+            PassphraseEnvError{ name, err }     => write!(f, "Could not read passphrase from environment variable '{}': {}", name, err),
+            NoCredentialsDirectory{ name }      => write!(f, "Could not read passphrase from credential '{}': $CREDENTIALS_DIRECTORY is not set (is the daemon started by systemd with 'LoadCredential={}:...'?)", name, name),
+            EncryptedKeyParseError{ path, err } => write!(f, "Could not parse encrypted key file '{}': {}", path.display(), err),
+            KeyDecryptError{ path, err }        => write!(f, "Could not decrypt key file '{}' (is the passphrase right?): {}", path.display(), err),
+            CertAddError{ err }                 => write!(f, "Failed to add user key to root store: {}", err),
+            UnknownCipherSuite{ name }          => write!(f, "Unknown cipher suite '{}' in TLS policy", name),
+            PolicyError{ err }                  => write!(f, "Invalid TLS policy: {}", err),
+            ConfigError{ err }                  => write!(f, "Failed to create server SSL/TLS config: {}", err),
+            UsersError{ err }                   => write!(f, "Could not read users: {}", err),
         }
     }
 }
@@ -115,6 +179,58 @@ impl Error for SSLError {}
 
 
 
+/// Errors that relate to the certificate authority that issues client certificates.
+#[derive(Debug)]
+pub enum CAError {
+    /// Only one of the certificate and the key of the certificate authority is configured.
+    IncompleteConfig,
+    /// Could not open the certificate file of the certificate authority.
+    CertOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not parse the certificate file of the certificate authority.
+    CertParseError{ path: PathBuf, err: std::io::Error },
+    /// The certificate file of the certificate authority is empty.
+    NoCertsFound{ path: PathBuf },
+    /// Could not read the key file of the certificate authority.
+    KeyOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not parse the key file of the certificate authority.
+    KeyParseError{ path: PathBuf, err: rcgen::RcgenError },
+    /// Could not sign with the certificate and key of the certificate authority.
+    SignerError{ path: PathBuf, err: rcgen::RcgenError },
+
+    /// Certificates cannot be issued to the guest user.
+    GuestUser,
+    /// Could not parse (or verify) a certificate signing request.
+    CsrParseError{ err: rcgen::RcgenError },
+    /// Could not generate a key pair for a new certificate.
+    KeyGenerateError{ err: rcgen::RcgenError },
+    /// Could not sign a new certificate.
+    SignError{ err: rcgen::RcgenError },
+}
+
+impl Display for CAError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use CAError::*;
+        match self {
+            IncompleteConfig            => write!(f, "Both 'ca_cert' and 'ca_key' must be given to enable the certificate authority"),
+            CertOpenError{ path, err }  => write!(f, "Could not open certificate file '{}': {}", path.display(), err),
+            CertParseError{ path, err } => write!(f, "Could not parse certificate file '{}': {}", path.display(), err),
+            NoCertsFound{ path }        => write!(f, "Certificate file '{}' does not contain any certificates", path.display()),
+            KeyOpenError{ path, err }   => write!(f, "Could not read key file '{}': {}", path.display(), err),
+            KeyParseError{ path, err }  => write!(f, "Could not parse key file '{}' (is it a PKCS#8 key?): {}", path.display(), err),
+            SignerError{ path, err }    => write!(f, "Could not sign with certificate '{}': {}", path.display(), err),
+
+            GuestUser               => write!(f, "Cannot issue certificates to the guest user"),
+            CsrParseError{ err }    => write!(f, "Could not parse certificate signing request: {}", err),
+            KeyGenerateError{ err } => write!(f, "Could not generate key pair: {}", err),
+            SignError{ err }        => write!(f, "Could not sign certificate: {}", err),
+        }
+    }
+}
+
+impl Error for CAError {}
+
+
+
 /// Errors that relate to interaction with the User database / logging in.
 #[derive(Debug)]
 pub enum UserError {
@@ -128,6 +244,36 @@ pub enum UserError {
     FileParseError{ path: PathBuf, err: serde_json::Error },
     /// Missing one of the reserved users.
     ReservedUserError{ path: PathBuf, id: UserId },
+    /// A group in the users database file has a member that does not exist.
+    UnknownMember{ path: PathBuf, group: String, id: UserId },
+    /// An API token in the users database file belongs to a user that does not exist.
+    UnknownOwner{ path: PathBuf, token: String, id: UserId },
+    /// Could not serialize the users database.
+    SerializeError{ err: serde_json::Error },
+    /// Could not write the users database file.
+    FileWriteError{ path: PathBuf, err: std::io::Error },
+
+    /// Could not open the database the users live in.
+    DatabaseError{ err: DatabaseError },
+    /// Could not query or update the users in the database.
+    QueryError{ err: rusqlite::Error },
+    /// Could not import the users from the JSON file into the database.
+    ImportError{ path: PathBuf, err: Box<Self> },
+
+    /// Another user already has the given username.
+    UsernameTaken{ username: String },
+    /// The given user does not exist.
+    UnknownUser{ id: UserId },
+    /// The given group name is not a valid name.
+    IllegalGroupName{ name: String },
+    /// The given group does not exist.
+    UnknownGroup{ name: String },
+    /// The given API token does not exist.
+    UnknownToken{ id: String },
+    /// The given invite code does not exist.
+    UnknownInvite{ id: String },
+    /// The given certificate is not on the revocation list.
+    UnknownRevocation{ value: String },
 }
 
 impl UserError {
@@ -175,8 +321,562 @@ impl Display for UserError {
             IncorrectPermissions{ path, got, expected } => write!(f, "File '{}' has insecure permissions set (got {} ({:?}), expected {} ({:?}))", path.display(), Self::octet_display(got)?, Self::octet_debug(got)?, Self::octet_display(expected)?, Self::octet_debug(expected)?),
             FileParseError{ path, err }                 => write!(f, "Could not parse file '{}': {}", path.display(), err),
             ReservedUserError{ path, id }               => write!(f, "File '{}' is missing reserved user with ID {}", path.display(), id),
+            UnknownMember{ path, group, id }            => write!(f, "File '{}' lists unknown user with ID {} as member of group '{}'", path.display(), id, group),
+            UnknownOwner{ path, token, id }             => write!(f, "File '{}' lists unknown user with ID {} as owner of API token '{}'", path.display(), id, token),
+            SerializeError{ err }                       => write!(f, "Could not serialize users database: {}", err),
+            FileWriteError{ path, err }                 => write!(f, "Could not write file '{}': {}", path.display(), err),
+
+            DatabaseError{ err }                        => write!(f, "{}", err),
+            QueryError{ err }                           => write!(f, "Could not query users in metadata database: {}", err),
+            ImportError{ path, err }                    => write!(f, "Could not import users from '{}': {}", path.display(), err),
+
+            UsernameTaken{ username }                   => write!(f, "Username '{}' is already taken", username),
+            UnknownUser{ id }                           => write!(f, "Unknown user with ID {}", id),
+            IllegalGroupName{ name }                    => write!(f, "Illegal group name '{}'", name),
+            UnknownGroup{ name }                        => write!(f, "Unknown group '{}'", name),
+            UnknownToken{ id }                          => write!(f, "Unknown API token '{}'", id),
+            UnknownInvite{ id }                         => write!(f, "Unknown invite code '{}'", id),
+            UnknownRevocation{ value }                  => write!(f, "Certificate '{}' is not revoked", value),
         }
     }
 }
 
 impl Error for UserError {}
+
+
+
+/// Errors that relate to redeeming invite codes.
+#[derive(Debug)]
+pub enum InviteError {
+    /// The given code is malformed, unknown, wrong, expired or already redeemed.
+    InvalidCode,
+    /// The given username is not a valid name.
+    IllegalUsername{ username: String },
+    /// Could not parse the given certificate(s).
+    CertParseError{ err: std::io::Error },
+    /// The given PEM data contains something other than certificates (e.g., a private key).
+    NotACertificate,
+    /// The given PEM data did not contain any certificates.
+    NoCertsFound,
+    /// The given certificate cannot be used to log in with.
+    IllegalCert{ err: webpki::Error },
+    /// The given certificate already belongs to another user.
+    CertInUse{ id: UserId },
+    /// Could not create the directory with the certificates of the users.
+    DirCreateError{ path: PathBuf, err: std::io::Error },
+    /// Could not write the certificate file of the new user.
+    CertWriteError{ path: PathBuf, err: std::io::Error },
+    /// Could not read or update the users database.
+    UserError{ err: UserError },
+    /// Could not update the SSL config with the new user.
+    SSLError{ err: SSLError },
+    /// The new user sent a certificate signing request, but the server has no certificate authority to sign it.
+    NoAuthority,
+    /// The certificate authority could not sign the certificate signing request of the new user.
+    CAError{ err: CAError },
+}
+
+impl Display for InviteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use InviteError::*;
+        match self {
+            InvalidCode                 => write!(f, "Invalid, expired or already redeemed invite code"),
+            IllegalUsername{ username } => write!(f, "Illegal username '{}'", username),
+            CertParseError{ err }       => write!(f, "Could not parse certificate(s): {}", err),
+            NotACertificate             => write!(f, "Given PEM data contains something other than certificates (never send your private key!)"),
+            NoCertsFound                => write!(f, "Given PEM data does not contain any certificates"),
+            IllegalCert{ err }          => write!(f, "Given certificate cannot be used to log in: {}", err),
+            CertInUse{ id }             => write!(f, "Given certificate already belongs to user with ID {}", id),
+            DirCreateError{ path, err } => write!(f, "Could not create certificates directory '{}': {}", path.display(), err),
+            CertWriteError{ path, err } => write!(f, "Could not write certificate file '{}': {}", path.display(), err),
+            UserError{ err }            => write!(f, "{}", err),
+            SSLError{ err }             => write!(f, "Could not reload SSL config: {}", err),
+            NoAuthority                 => write!(f, "Server has no certificate authority to sign certificate signing requests; send a certificate instead"),
+            CAError{ err }              => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for InviteError {}
+
+
+
+/// Errors that relate to the storage backend.
+#[derive(Debug)]
+pub enum StorageError {
+    /// Could not create a directory in the storage.
+    DirCreateError{ path: PathBuf, err: std::io::Error },
+    /// The given blob does not exist.
+    BlobNotFound{ hash: BlobHash },
+    /// Could not open a blob file.
+    BlobOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not get the metadata of a blob file.
+    BlobMetadataError{ path: PathBuf, err: std::io::Error },
+    /// Could not seek in a blob file.
+    BlobSeekError{ path: PathBuf, err: std::io::Error },
+    /// Could not move a file into the storage.
+    BlobInsertError{ path: PathBuf, err: std::io::Error },
+    /// Could not update the modification time of a blob file.
+    BlobTouchError{ path: PathBuf, err: std::io::Error },
+    /// Could not remove a blob file.
+    BlobRemoveError{ path: PathBuf, err: std::io::Error },
+    /// Could not read a directory in the storage.
+    DirReadError{ path: PathBuf, err: std::io::Error },
+    /// Could not move a blob file to the quarantine directory.
+    QuarantineError{ path: PathBuf, err: std::io::Error },
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use StorageError::*;
+        match self {
+            DirCreateError{ path, err }    => write!(f, "Could not create storage directory '{}': {}", path.display(), err),
+            BlobNotFound{ hash }           => write!(f, "Blob '{}' does not exist", hash),
+            BlobOpenError{ path, err }     => write!(f, "Could not open blob file '{}': {}", path.display(), err),
+            BlobMetadataError{ path, err } => write!(f, "Could not get metadata of blob file '{}': {}", path.display(), err),
+            BlobSeekError{ path, err }     => write!(f, "Could not seek in blob file '{}': {}", path.display(), err),
+            BlobInsertError{ path, err }   => write!(f, "Could not move file '{}' into the storage: {}", path.display(), err),
+            BlobTouchError{ path, err }    => write!(f, "Could not touch blob file '{}': {}", path.display(), err),
+            BlobRemoveError{ path, err }   => write!(f, "Could not remove blob file '{}': {}", path.display(), err),
+            DirReadError{ path, err }      => write!(f, "Could not read storage directory '{}': {}", path.display(), err),
+            QuarantineError{ path, err }   => write!(f, "Could not move blob file '{}' to quarantine: {}", path.display(), err),
+        }
+    }
+}
+
+impl Error for StorageError {}
+
+
+
+/// Errors that relate to the SQLite metadata database.
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// Could not open the database.
+    OpenError{ path: PathBuf, err: rusqlite::Error },
+    /// The database was created by a newer version of the server.
+    UnknownSchema{ path: PathBuf, version: usize },
+    /// Could not migrate the database to a newer schema.
+    MigrationError{ path: PathBuf, version: usize, err: rusqlite::Error },
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use DatabaseError::*;
+        match self {
+            OpenError{ path, err }               => write!(f, "Could not open metadata database '{}': {}", path.display(), err),
+            UnknownSchema{ path, version }       => write!(f, "Metadata database '{}' has unknown schema version {} (was it created by a newer server?)", path.display(), version),
+            MigrationError{ path, version, err } => write!(f, "Could not migrate metadata database '{}' to schema version {}: {}", path.display(), version, err),
+        }
+    }
+}
+
+impl Error for DatabaseError {}
+
+
+
+/// Errors that relate to the metadata index.
+#[derive(Debug)]
+pub enum IndexError {
+    /// Could not open the database the index lives in.
+    DatabaseError{ err: DatabaseError },
+    /// Could not query or update the index database.
+    QueryError{ err: rusqlite::Error },
+    /// The index database contains a blob hash that is not a hash.
+    IllegalHash{ raw: String },
+}
+
+impl Display for IndexError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use IndexError::*;
+        match self {
+            DatabaseError{ err } => write!(f, "{}", err),
+            QueryError{ err }    => write!(f, "Could not query index database: {}", err),
+            IllegalHash{ raw }   => write!(f, "Index database contains illegal blob hash '{}'", raw),
+        }
+    }
+}
+
+impl Error for IndexError {}
+
+
+
+/// Errors that relate to the package database.
+#[derive(Debug)]
+pub enum PackageError {
+    /// Could not create a directory in the package database.
+    DirCreateError{ path: PathBuf, err: std::io::Error },
+    /// Could not read a directory in the package database.
+    DirReadError{ path: PathBuf, err: std::io::Error },
+    /// The given package or version name is illegal.
+    IllegalName{ name: String },
+    /// The given package does not exist.
+    UnknownPackage{ package: String },
+    /// The given version does not exist.
+    UnknownVersion{ package: String, version: String },
+    /// Could not open a manifest file.
+    ManifestOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not get the metadata of a manifest file.
+    ManifestMetadataError{ path: PathBuf, err: std::io::Error },
+    /// Could not parse a manifest file.
+    ManifestParseError{ path: PathBuf, err: serde_json::Error },
+    /// The given version already exists.
+    VersionExists{ package: String, version: String },
+    /// The given name is already used by a version or channel of the package.
+    NameTaken{ package: String, name: String },
+    /// Could not write a file in the package database.
+    FileWriteError{ path: PathBuf, err: std::io::Error },
+    /// Could not serialize a file in the package database.
+    FileSerializeError{ path: PathBuf, err: serde_json::Error },
+    /// Could not remove a file from the package database.
+    FileRemoveError{ path: PathBuf, err: std::io::Error },
+
+    /// The given channel does not exist.
+    UnknownChannel{ package: String, channel: String },
+    /// Could not open a channel file.
+    ChannelOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not parse a channel file.
+    ChannelParseError{ path: PathBuf, err: serde_json::Error },
+    /// The given version cannot be removed, since a channel points to it.
+    VersionInUse{ version: String, channel: String },
+
+    /// Could not use the metadata index.
+    IndexError{ err: IndexError },
+}
+
+impl Display for PackageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use PackageError::*;
+        match self {
+            DirCreateError{ path, err }        => write!(f, "Could not create package directory '{}': {}", path.display(), err),
+            DirReadError{ path, err }          => write!(f, "Could not read package directory '{}': {}", path.display(), err),
+            IllegalName{ name }                => write!(f, "'{}' is not a legal package, version or channel name", name),
+            UnknownPackage{ package }          => write!(f, "Unknown package '{}'", package),
+            UnknownVersion{ package, version } => write!(f, "Unknown version '{}' of package '{}'", version, package),
+            ManifestOpenError{ path, err }     => write!(f, "Could not open manifest file '{}': {}", path.display(), err),
+            ManifestMetadataError{ path, err } => write!(f, "Could not get metadata of manifest file '{}': {}", path.display(), err),
+            ManifestParseError{ path, err }    => write!(f, "Could not parse manifest file '{}': {}", path.display(), err),
+            VersionExists{ package, version }  => write!(f, "Version '{}' of package '{}' already exists", version, package),
+            NameTaken{ package, name }         => write!(f, "Package '{}' already has a version or channel named '{}'", package, name),
+            FileWriteError{ path, err }        => write!(f, "Could not write file '{}': {}", path.display(), err),
+            FileSerializeError{ path, err }    => write!(f, "Could not serialize file '{}': {}", path.display(), err),
+            FileRemoveError{ path, err }       => write!(f, "Could not remove file '{}': {}", path.display(), err),
+
+            UnknownChannel{ package, channel } => write!(f, "Unknown channel '{}' of package '{}'", channel, package),
+            ChannelOpenError{ path, err }      => write!(f, "Could not open channel file '{}': {}", path.display(), err),
+            ChannelParseError{ path, err }     => write!(f, "Could not parse channel file '{}': {}", path.display(), err),
+            VersionInUse{ version, channel }   => write!(f, "Version '{}' cannot be removed, since channel '{}' points to it", version, channel),
+
+            IndexError{ err } => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for PackageError {}
+
+
+
+/// Errors that relate to publish transactions.
+#[derive(Debug)]
+pub enum PublishError {
+    /// Could not create a directory in the staging area.
+    DirCreateError{ path: PathBuf, err: std::io::Error },
+    /// Could not read a directory in the staging area.
+    DirReadError{ path: PathBuf, err: std::io::Error },
+    /// Could not remove the staging directory of a transaction.
+    DirRemoveError{ path: PathBuf, err: std::io::Error },
+    /// Could not write the file describing a transaction.
+    TransactionWriteError{ path: PathBuf, err: std::io::Error },
+    /// Could not serialize the file describing a transaction.
+    TransactionSerializeError{ path: PathBuf, err: serde_json::Error },
+    /// Could not read the file describing a transaction.
+    TransactionReadError{ path: PathBuf, err: std::io::Error },
+    /// Could not parse the file describing a transaction.
+    TransactionParseError{ path: PathBuf, err: serde_json::Error },
+
+    /// The given package or version name is illegal.
+    IllegalName{ name: String },
+    /// A manifest entry has an illegal path.
+    IllegalEntryPath{ path: String },
+    /// A manifest contains the same path twice.
+    DuplicateEntryPath{ path: String },
+    /// A manifest contains the same blob with different sizes.
+    InconsistentBlobSize{ hash: BlobHash },
+    /// The chunks of a file in the manifest do not add up to its size.
+    ChunkSizeMismatch{ path: String, got: u64, expected: u64 },
+    /// The manifest is signed, but the signature does not match it.
+    InvalidSignature{ err: SignatureError },
+    /// The version to publish already exists.
+    VersionExists{ package: String, version: String },
+    /// Another transaction is already publishing the same version.
+    TransactionExists{ package: String, version: String, id: String },
+    /// The given transaction does not exist (or does not belong to the user).
+    UnknownTransaction{ id: String },
+    /// Publishing the version would exceed the quota of the user.
+    UserQuotaExceeded{ user: UserId, err: QuotaError },
+    /// Publishing the version would exceed the quota of the package.
+    PackageQuotaExceeded{ package: String, err: QuotaError },
+
+    /// The given blob is not part of the transaction.
+    UnknownBlob{ hash: BlobHash },
+    /// An upload did not start at the number of bytes we already have.
+    IllegalOffset{ hash: BlobHash, got: u64, expected: u64 },
+    /// The blob is already being uploaded on another connection.
+    UploadInProgress{ hash: BlobHash },
+    /// Could not open the staged file of a blob.
+    BlobOpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not write to the staged file of a blob.
+    BlobWriteError{ path: PathBuf, err: std::io::Error },
+    /// Could not move the staged file of a blob.
+    BlobRenameError{ path: PathBuf, err: std::io::Error },
+    /// Could not hash a blob.
+    BlobHashError{ err: filehost_spc::manifest::HashError },
+    /// A blob does not match the hash it was announced with.
+    HashMismatch{ hash: BlobHash, got: BlobHash },
+    /// A blob does not have the size it was announced with.
+    SizeMismatch{ hash: BlobHash, got: u64, expected: u64 },
+    /// A blob was neither uploaded nor already present in the storage.
+    MissingBlob{ hash: BlobHash },
+
+    /// Could not interact with the storage backend.
+    StorageError{ err: StorageError },
+    /// Could not interact with the package database.
+    PackageError{ err: PackageError },
+}
+
+impl Display for PublishError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use PublishError::*;
+        match self {
+            DirCreateError{ path, err }            => write!(f, "Could not create staging directory '{}': {}", path.display(), err),
+            DirReadError{ path, err }              => write!(f, "Could not read staging directory '{}': {}", path.display(), err),
+            DirRemoveError{ path, err }            => write!(f, "Could not remove staging directory '{}': {}", path.display(), err),
+            TransactionWriteError{ path, err }     => write!(f, "Could not write transaction file '{}': {}", path.display(), err),
+            TransactionSerializeError{ path, err } => write!(f, "Could not serialize transaction file '{}': {}", path.display(), err),
+            TransactionReadError{ path, err }      => write!(f, "Could not read transaction file '{}': {}", path.display(), err),
+            TransactionParseError{ path, err }     => write!(f, "Could not parse transaction file '{}': {}", path.display(), err),
+
+            IllegalName{ name }                       => write!(f, "'{}' is not a legal package or version name", name),
+            IllegalEntryPath{ path }                  => write!(f, "'{}' is not a legal path for a package file", path),
+            DuplicateEntryPath{ path }                => write!(f, "Path '{}' occurs more than once in the manifest", path),
+            InconsistentBlobSize{ hash }              => write!(f, "Blob '{}' occurs in the manifest with different sizes", hash),
+            ChunkSizeMismatch{ path, got, expected }  => write!(f, "Chunks of '{}' add up to {} bytes instead of {}", path, got, expected),
+            InvalidSignature{ err }                   => write!(f, "Invalid manifest signature: {}", err),
+            VersionExists{ package, version }         => write!(f, "Version '{}' of package '{}' already exists", version, package),
+            TransactionExists{ package, version, id } => write!(f, "Version '{}' of package '{}' is already being published in transaction '{}'", version, package, id),
+            UnknownTransaction{ id }                  => write!(f, "Unknown transaction '{}'", id),
+            UserQuotaExceeded{ user, err }            => write!(f, "Quota of user {} exceeded: {}", user, err),
+            PackageQuotaExceeded{ package, err }      => write!(f, "Quota of package '{}' exceeded: {}", package, err),
+
+            UnknownBlob{ hash }                  => write!(f, "Blob '{}' is not part of the transaction", hash),
+            IllegalOffset{ hash, got, expected } => write!(f, "Upload of blob '{}' starts at offset {}, but the server has {} bytes of it", hash, got, expected),
+            UploadInProgress{ hash }             => write!(f, "Blob '{}' is already being uploaded", hash),
+            BlobOpenError{ path, err }           => write!(f, "Could not open staged blob '{}': {}", path.display(), err),
+            BlobWriteError{ path, err }          => write!(f, "Could not write to staged blob '{}': {}", path.display(), err),
+            BlobRenameError{ path, err }         => write!(f, "Could not move staged blob '{}': {}", path.display(), err),
+            BlobHashError{ err }                 => write!(f, "{}", err),
+            HashMismatch{ hash, got }            => write!(f, "Blob '{}' has hash '{}' instead", hash, got),
+            SizeMismatch{ hash, got, expected }  => write!(f, "Blob '{}' has {} bytes instead of {}", hash, got, expected),
+            MissingBlob{ hash }                  => write!(f, "Blob '{}' was not uploaded", hash),
+
+            StorageError{ err } => write!(f, "{}", err),
+            PackageError{ err } => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for PublishError {}
+
+
+
+/// Errors that relate to collecting garbage in the storage.
+#[derive(Debug)]
+pub enum GcError {
+    /// Could not list or remove the blobs in the storage.
+    StorageError{ err: StorageError },
+    /// Could not read the package versions that refer to blobs.
+    PackageError{ err: PackageError },
+}
+
+impl Display for GcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use GcError::*;
+        match self {
+            StorageError{ err } => write!(f, "{}", err),
+            PackageError{ err } => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for GcError {}
+
+
+
+/// Errors that relate to checking the integrity of the storage.
+#[derive(Debug)]
+pub enum FsckError {
+    /// Could not list the blobs in the storage.
+    StorageError{ err: StorageError },
+    /// Could not read the package versions that refer to blobs.
+    PackageError{ err: PackageError },
+}
+
+impl Display for FsckError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use FsckError::*;
+        match self {
+            StorageError{ err } => write!(f, "{}", err),
+            PackageError{ err } => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for FsckError {}
+
+
+
+/// Errors that relate to handling a single client connection.
+#[derive(Debug)]
+pub enum ClientError {
+    /// Could not set the timeouts on the client socket.
+    TimeoutSetError{ err: std::io::Error },
+    /// Could not create a new TLS session.
+    SessionCreateError{ err: rustls::Error },
+    /// The client sent an invalid TLS ClientHello.
+    ClientHelloError{ err: rustls::Error },
+    /// The TLS handshake failed.
+    HandshakeError{ err: std::io::Error },
+    /// The client presented a certificate that belongs to no user.
+    UnknownCertificate,
+    /// Could not read or write a frame.
+    FrameError{ err: FrameError },
+    /// Could not read the blob to send.
+    BlobReadError{ hash: BlobHash, err: std::io::Error },
+    /// The client closed the connection in the middle of an upload.
+    UploadInterrupted{ hash: BlobHash },
+    /// The client sent a frame other than a Data frame during an upload.
+    UnexpectedFrame{ opcode: u8 },
+    /// The client sent more data than it announced during an upload.
+    UploadOverflow{ hash: BlobHash, expected: u64 },
+    /// The client sent a Data frame without any data during an upload.
+    EmptyData{ hash: BlobHash },
+    /// Could not store the data of an upload.
+    UploadError{ err: PublishError },
+    /// Could not look up the user of the client.
+    UserError{ err: UserError },
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use ClientError::*;
+        match self {
+            TimeoutSetError{ err }           => write!(f, "Could not set client socket timeouts: {}", err),
+            SessionCreateError{ err }        => write!(f, "Could not create TLS session: {}", err),
+            ClientHelloError{ err }          => write!(f, "Could not read TLS ClientHello: {}", err),
+            HandshakeError{ err }            => write!(f, "TLS handshake failed: {}", err),
+            UnknownCertificate               => write!(f, "Client presented a certificate that does not belong to any user"),
+            FrameError{ err }                => write!(f, "{}", err),
+            BlobReadError{ hash, err }       => write!(f, "Could not read blob '{}': {}", hash, err),
+            UploadInterrupted{ hash }        => write!(f, "Client closed the connection during the upload of blob '{}'", hash),
+            UnexpectedFrame{ opcode }        => write!(f, "Client sent unexpected frame with opcode {} during upload", opcode),
+            UploadOverflow{ hash, expected } => write!(f, "Client sent more than the expected {} bytes of blob '{}'", expected, hash),
+            EmptyData{ hash }                => write!(f, "Client sent an empty Data frame during the upload of blob '{}'", hash),
+            UploadError{ err }               => write!(f, "{}", err),
+            UserError{ err }                 => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ClientError {}
+
+
+
+/// Errors that relate to handling requests on the CTL socket.
+#[derive(Debug)]
+pub enum CtlError {
+    /// Could not set the timeouts on the CTL socket.
+    TimeoutSetError{ err: std::io::Error },
+    /// The CTL closed the connection without sending anything.
+    EmptyStream,
+    /// Could not read the opcode of the request.
+    OpcodeReadError{ err: std::io::Error },
+    /// The CTL sent an opcode we don't know.
+    OpcodeError{ err: OpcodeError },
+    /// Could not answer a health check.
+    HealthWriteError{ err: std::io::Error },
+    /// Could not read or write a frame.
+    FrameError{ err: FrameError },
+}
+
+impl Display for CtlError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use CtlError::*;
+        match self {
+            TimeoutSetError{ err }  => write!(f, "Could not set CTL socket timeouts: {}", err),
+            EmptyStream             => write!(f, "CTL closed the connection without sending a request"),
+            OpcodeReadError{ err }  => write!(f, "Could not read opcode from CTL socket: {}", err),
+            OpcodeError{ err }      => write!(f, "{}", err),
+            HealthWriteError{ err } => write!(f, "Could not answer health check on CTL socket: {}", err),
+            FrameError{ err }       => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for CtlError {}
+
+
+
+/// Errors that relate to the audit log.
+#[derive(Debug)]
+pub enum AuditError {
+    /// Could not open the audit log.
+    OpenError{ path: PathBuf, err: std::io::Error },
+    /// Could not read the audit log.
+    ReadError{ path: PathBuf, err: std::io::Error },
+    /// Could not append to the audit log.
+    WriteError{ path: PathBuf, err: std::io::Error },
+    /// Could not serialize an entry.
+    SerializeError{ err: serde_json::Error },
+}
+
+impl Display for AuditError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use AuditError::*;
+        match self {
+            OpenError{ path, err }  => write!(f, "Could not open audit log '{}': {}", path.display(), err),
+            ReadError{ path, err }  => write!(f, "Could not read audit log '{}': {}", path.display(), err),
+            WriteError{ path, err } => write!(f, "Could not append to audit log '{}': {}", path.display(), err),
+            SerializeError{ err }   => write!(f, "Could not serialize audit log entry: {}", err),
+        }
+    }
+}
+
+impl Error for AuditError {}
+
+
+
+/// Errors that relate to identifying the local users on the other side of the CTL socket.
+#[derive(Debug)]
+pub enum PeerError {
+    /// Could not get the credentials of the peer.
+    CredentialsError{ err: nix::Error },
+    /// Could not look up the local user with the given ID.
+    UserLookupError{ uid: u32, err: nix::Error },
+    /// Could not look up the groups of the local user with the given name.
+    GroupsLookupError{ name: String, err: nix::Error },
+    /// Could not look up the local group with the given name.
+    GroupLookupError{ name: String, err: nix::Error },
+}
+
+impl Display for PeerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        use PeerError::*;
+        match self {
+            CredentialsError{ err }        => write!(f, "Could not get the credentials of the CTL peer: {}", err),
+            UserLookupError{ uid, err }    => write!(f, "Could not look up local user {}: {}", uid, err),
+            GroupsLookupError{ name, err } => write!(f, "Could not look up the groups of local user '{}': {}", name, err),
+            GroupLookupError{ name, err }  => write!(f, "Could not look up local group '{}': {}", name, err),
+        }
+    }
+}
+
+impl Error for PeerError {}
@@ -0,0 +1,158 @@
+/* EXPIRY.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 06:21:28
+ * Last edited:
+ *   19 Oct 2026, 07:03:36
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Keeps an eye on when the certificates of the server, its certificate
+ *   authority and its users expire, so that they don't only show up as
+ *   failing TLS handshakes.
+**/
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use log::{error, warn};
+use rustls::Certificate;
+
+use filehost_spc::config::Config;
+use filehost_spc::ctl_messages::{CertificateRole, CertificateStatus, RevocationKind};
+use filehost_spc::login::{GUEST_ID, UserId};
+
+use crate::errors::SSLError;
+use crate::ssl;
+use crate::users::{IssuedCert, Revocation, User};
+
+
+/***** HELPER FUNCTIONS *****/
+/// Reads the certificates in the given PEM file.
+///
+/// # Errors
+/// This function errors if the file could not be read or parsed.
+fn read_certs(path: &Path) -> Result<Vec<Certificate>, SSLError> {
+    let mut handle = match File::open(path) {
+        Ok(handle) => BufReader::new(handle),
+        Err(err)   => { return Err(SSLError::CertOpenError{ path: path.into(), err }); }
+    };
+    match rustls_pemfile::certs(&mut handle) {
+        Ok(certs) => Ok(certs.into_iter().map(Certificate).collect()),
+        Err(err)  => Err(SSLError::CertParseError{ path: path.display().to_string(), err }),
+    }
+}
+
+/// Describes the certificates in the given PEM file.
+///
+/// Problems with the file are logged instead of returned, since they should not stop us from checking the other certificates.
+fn statuses(role: CertificateRole, username: Option<&str>, path: &Path) -> Vec<CertificateStatus> {
+    let certs: Vec<Certificate> = match read_certs(path) {
+        Ok(certs) => certs,
+        Err(err)  => { warn!("Could not check when certificate(s) expire: {}", err); return vec![]; },
+    };
+    certs.iter().filter_map(|cert| {
+        let (_, parsed) = match x509_parser::parse_x509_certificate(&cert.0) {
+            Ok(parsed) => parsed,
+            Err(err)   => { warn!("Could not check when certificate in '{}' expires: {}", path.display(), err); return None; },
+        };
+        Some(CertificateStatus {
+            role,
+            username  : username.map(String::from),
+            path      : Some(path.into()),
+            subject   : parsed.subject().to_string(),
+            serial    : ssl::serial(cert).unwrap_or_default(),
+            not_after : Utc.timestamp_opt(parsed.validity().not_after.timestamp(), 0).single()?,
+        })
+    }).collect()
+}
+
+/// Describes a certificate for in the logs.
+fn describe(status: &CertificateStatus) -> String {
+    let origin: String = match &status.path {
+        Some(path) => format!("in '{}'", path.display()),
+        None       => format!("serial {}", status.serial),
+    };
+    match status.role {
+        CertificateRole::Server    => format!("Server certificate '{}' ({})", status.subject, origin),
+        CertificateRole::Authority => format!("Certificate '{}' of the certificate authority ({})", status.subject, origin),
+        CertificateRole::User      => format!("Certificate '{}' of user '{}' ({})", status.subject, status.username.as_deref().unwrap_or("?"), origin),
+    }
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Describes the certificate that a host presents to clients and, if asked, that of its certificate authority.
+///
+/// # Arguments
+/// - `config`: The configuration of the host.
+/// - `ca`: Whether to include the certificate of the certificate authority, if the host has one.
+pub fn host_certs(config: &Config, ca: bool) -> Vec<CertificateStatus> {
+    let mut res: Vec<CertificateStatus> = statuses(CertificateRole::Server, None, &config.server_cert);
+    if ca {
+        if let Some(path) = &config.ca_cert { res.extend(statuses(CertificateRole::Authority, None, path)); }
+    }
+    res
+}
+
+/// Describes the certificates that the given users registered, and those that the certificate authority issued to them.
+///
+/// Of the issued certificates, only the one of every user that expires last counts (so that renewing a certificate silences the warnings about the old one), and only if it has not been revoked.
+///
+/// # Arguments
+/// - `users`: The users with their registered certificates.
+/// - `issued`: The certificates that the certificate authority issued.
+/// - `revocations`: The revoked certificates.
+pub fn user_certs(users: &[User], issued: &[IssuedCert], revocations: &[Revocation]) -> Vec<CertificateStatus> {
+    let mut res: Vec<CertificateStatus> = users.iter().filter(|user| user.id != GUEST_ID).filter_map(|user| user.certs.as_ref().map(|path| statuses(CertificateRole::User, Some(&user.username), path))).flatten().collect();
+
+    // Find the latest certificate of every user
+    let mut latest: HashMap<UserId, &IssuedCert> = HashMap::new();
+    for cert in issued {
+        if revocations.iter().any(|r| r.kind == RevocationKind::Serial && r.value == cert.serial) { continue; }
+        let entry: &mut &IssuedCert = latest.entry(cert.user).or_insert(cert);
+        if cert.expires > entry.expires { *entry = cert; }
+    }
+    for user in users {
+        if let Some(cert) = latest.get(&user.id) {
+            res.push(CertificateStatus {
+                role      : CertificateRole::User,
+                username  : Some(user.username.clone()),
+                path      : None,
+                subject   : cert.subject.clone(),
+                serial    : cert.serial.clone(),
+                not_after : cert.expires,
+            });
+        }
+    }
+    res
+}
+
+
+
+/// Logs the certificates that have expired or expire soon.
+///
+/// # Arguments
+/// - `config`: The configuration that determines what "soon" means.
+/// - `statuses`: The certificates to check.
+/// - `now`: The current time.
+pub fn check(config: &Config, statuses: &[CertificateStatus], now: DateTime<Utc>) {
+    for status in statuses {
+        let days: i64 = (status.not_after - now).num_days();
+        if status.not_after <= now {
+            error!("{} has expired on {}", describe(status), status.not_after);
+        } else if days < config.cert_critical_days as i64 {
+            error!("{} expires in {} day(s), on {}", describe(status), days, status.not_after);
+        } else if days < config.cert_warn_days as i64 {
+            warn!("{} expires in {} day(s), on {}", describe(status), days, status.not_after);
+        }
+    }
+}
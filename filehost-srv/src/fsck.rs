@@ -0,0 +1,143 @@
+/* FSCK.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 05:08:13
+ * Last edited:
+ *   19 Oct 2026, 05:08:13
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the integrity scrub of the storage, which re-hashes every
+ *   blob and checks that every blob a package version refers to exists.
+**/
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::MutexGuard;
+
+use log::{debug, error, info, warn};
+
+use filehost_spc::ctl_messages::{CorruptBlob, FsckReport, MissingBlob};
+use filehost_spc::manifest::{BlobHash, Manifest};
+
+pub use crate::errors::FsckError as Error;
+use crate::errors::StorageError;
+use crate::packages::Packages;
+use crate::publish::Transactions;
+use crate::storage::{BlobInfo, Storage};
+
+
+/***** HELPER FUNCTIONS *****/
+/// Re-hashes a single blob in the storage.
+///
+/// # Arguments
+/// - `storage`: The storage that contains the blob.
+/// - `hash`: The hash of the blob to check.
+///
+/// # Returns
+/// None if the blob was removed in the meantime, or else the actual hash of its contents (which is None if it could not be read).
+fn rehash(storage: &Storage, hash: &BlobHash) -> Option<Option<BlobHash>> {
+    let handle: File = match storage.open(hash) {
+        Ok((handle, _))                       => handle,
+        Err(StorageError::BlobNotFound{ .. }) => { return None; },
+        Err(err)                              => { error!("{}", err); return Some(None); },
+    };
+    match BlobHash::of_reader(handle) {
+        Ok(got)  => Some(Some(got)),
+        Err(err) => { error!("Could not read blob file '{}': {}", storage.path_of(hash).display(), err); Some(None) },
+    }
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Checks the integrity of the storage.
+///
+/// Every blob is re-hashed and compared to its name, and every blob that a package version refers to must exist. Blobs that nothing refers to are reported as orphaned, but are left for the garbage collector. Commits are only paused while we take a snapshot of what is referenced; the blobs themselves are hashed afterwards.
+///
+/// # Arguments
+/// - `storage`: The storage to check.
+/// - `packages`: The package database with the versions that refer to blobs.
+/// - `transactions`: The open transactions that refer to blobs.
+/// - `quarantine`: If given, corrupt blobs are moved to this directory.
+///
+/// # Returns
+/// A FsckReport that describes what was found.
+///
+/// # Errors
+/// This function errors if we failed to read the package versions or to list the blobs. Problems with individual blobs are reported instead.
+pub fn scrub(storage: &Storage, packages: &Packages, transactions: &Transactions, quarantine: Option<&Path>) -> Result<FsckReport, Error> {
+    let mut report: FsckReport = FsckReport::default();
+
+    // Take a snapshot of the storage and what refers to it
+    let (blobs, referenced): (Vec<BlobInfo>, HashSet<BlobHash>) = {
+        let _commits: MutexGuard<()> = transactions.pause_commits();
+        let blobs: Vec<BlobInfo> = storage.list().map_err(|err| Error::StorageError{ err })?;
+        let present: HashSet<BlobHash> = blobs.iter().map(|b| b.hash).collect();
+
+        let mut referenced: HashSet<BlobHash> = transactions.referenced_blobs();
+        for package in packages.packages().map_err(|err| Error::PackageError{ err })? {
+            for version in packages.versions(&package).map_err(|err| Error::PackageError{ err })? {
+                let manifest: Manifest = packages.manifest(&package, &version).map_err(|err| Error::PackageError{ err })?;
+                for entry in &manifest.entries {
+                    for (hash, _) in entry.blobs() {
+                        if !present.contains(&hash) {
+                            report.missing.push(MissingBlob{ package: package.clone(), version: version.clone(), path: entry.path.clone(), hash });
+                        }
+                        referenced.insert(hash);
+                    }
+                }
+            }
+        }
+        (blobs, referenced)
+    };
+    debug!("Checking {} blob(s) against {} reference(s)", blobs.len(), referenced.len());
+
+    // Re-hash every blob
+    for blob in blobs {
+        let got: Option<BlobHash> = match rehash(storage, &blob.hash) {
+            Some(got) => got,
+            None      => { debug!("Blob '{}' was removed while checking; skipping", blob.hash); continue; },
+        };
+        report.blobs += 1;
+        report.bytes += blob.size;
+        if got == Some(blob.hash) {
+            if !referenced.contains(&blob.hash) { report.orphaned.push(blob.hash); }
+            continue;
+        }
+
+        // It's corrupt; move it out of the way if told to
+        warn!("Blob '{}' is corrupt (contents hash to {})", blob.hash, got.map(|h| h.to_string()).unwrap_or_else(|| "nothing".into()));
+        let mut quarantined: bool = false;
+        if let Some(dir) = quarantine {
+            match storage.quarantine(&blob.hash, dir) {
+                Ok(path) => { info!("Moved corrupt blob '{}' to '{}'", blob.hash, path.display()); quarantined = true; },
+                Err(err) => { error!("{}", err); },
+            }
+        }
+        report.corrupt.push(CorruptBlob{ hash: blob.hash, got, quarantined });
+    }
+
+    // Done
+    if report.is_healthy() {
+        info!("Storage check found no problems in {} blob(s) ({} bytes); {} orphaned blob(s)", report.blobs, report.bytes, report.orphaned.len());
+    } else {
+        warn!("Storage check found {} corrupt and {} missing blob(s) in {} blob(s) ({} bytes); {} orphaned blob(s)", report.corrupt.len(), report.missing.len(), report.blobs, report.bytes, report.orphaned.len());
+    }
+    Ok(report)
+}
+
+/// Returns the directory where corrupt blobs are quarantined.
+///
+/// # Arguments
+/// - `data_dir`: The data directory of the server.
+///
+/// # Returns
+/// The path of the quarantine directory. It is kept out of the storage itself, so the garbage collector and the scrub never see quarantined blobs again.
+#[inline]
+pub fn quarantine_dir(data_dir: &Path) -> PathBuf { data_dir.join("quarantine") }
@@ -0,0 +1,95 @@
+/* GC.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 05:02:50
+ * Last edited:
+ *   19 Oct 2026, 07:00:39
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements a mark-and-sweep garbage collector that removes the blobs
+ *   no package version or publish transaction refers to anymore.
+**/
+
+use std::collections::HashSet;
+use std::sync::MutexGuard;
+use std::time::{Duration, SystemTime};
+
+use log::{debug, info};
+
+use filehost_spc::ctl_messages::GcReport;
+use filehost_spc::manifest::BlobHash;
+
+pub use crate::errors::GcError as Error;
+use crate::packages::Packages;
+use crate::publish::Transactions;
+use crate::storage::{BlobInfo, Storage};
+
+
+/***** HELPER FUNCTIONS *****/
+/// Collects the blobs that any package version or open transaction refers to.
+///
+/// # Errors
+/// This function errors if we failed to query the metadata index.
+fn mark(packages: &Packages, transactions: &Transactions) -> Result<HashSet<BlobHash>, Error> {
+    let mut marked: HashSet<BlobHash> = transactions.referenced_blobs();
+    marked.extend(packages.referenced_blobs().map_err(|err| Error::PackageError{ err })?);
+    Ok(marked)
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Removes all blobs from the storage that no package version or open transaction refers to.
+///
+/// Blobs that were touched within the grace period are kept anyway. Commits and the opening of new transactions are paused while we collect, so no transaction can start relying on a blob after we decided to remove it.
+///
+/// # Arguments
+/// - `storage`: The storage to collect garbage in.
+/// - `packages`: The package database with the versions that refer to blobs.
+/// - `transactions`: The open transactions that refer to blobs.
+/// - `grace_period`: The time an unreferenced blob is kept after it was last touched.
+/// - `dry_run`: If true, only reports what would be removed.
+///
+/// # Returns
+/// A GcReport that describes what was (or would be) removed.
+///
+/// # Errors
+/// This function errors if we failed to read the package versions, or to list or remove the blobs. Nothing is removed if we could not find out what is referenced.
+pub fn collect(storage: &Storage, packages: &Packages, transactions: &Transactions, grace_period: Duration, dry_run: bool) -> Result<GcReport, Error> {
+    let _commits: MutexGuard<()> = transactions.pause_commits();
+
+    // Mark what's in use
+    let marked: HashSet<BlobHash> = mark(packages, transactions)?;
+    debug!("Marked {} referenced blob(s)", marked.len());
+
+    // Sweep the rest
+    let now: SystemTime = SystemTime::now();
+    let mut report = GcReport{ dry_run, blobs: 0, kept: 0, young: 0, removed: 0, freed: 0 };
+    let blobs: Vec<BlobInfo> = storage.list().map_err(|err| Error::StorageError{ err })?;
+    for blob in blobs {
+        report.blobs += 1;
+        if marked.contains(&blob.hash) { report.kept += 1; continue; }
+        if now.duration_since(blob.modified).unwrap_or_default() < grace_period {
+            debug!("Keeping unreferenced blob '{}', since it was touched within the grace period", blob.hash);
+            report.young += 1;
+            continue;
+        }
+
+        // Remove it
+        if !dry_run {
+            debug!("Removing unreferenced blob '{}' ({} bytes)", blob.hash, blob.size);
+            storage.remove(&blob.hash).map_err(|err| Error::StorageError{ err })?;
+        }
+        report.removed += 1;
+        report.freed += blob.size;
+    }
+
+    // Done
+    info!("Garbage collection {} {} blob(s) ({} bytes); kept {} referenced and {} recent blob(s)", if dry_run { "would remove" } else { "removed" }, report.removed, report.freed, report.kept, report.young);
+    Ok(report)
+}
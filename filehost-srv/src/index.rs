@@ -0,0 +1,301 @@
+/* INDEX.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 05:17:08
+ * Last edited:
+ *   19 Oct 2026, 05:30:25
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the metadata index, a SQLite database that indexes the
+ *   packages, versions, channels, manifest entries and blob reference
+ *   counts, so we don't have to scan every manifest to answer questions
+ *   about them.
+**/
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use filehost_spc::login::UserId;
+use filehost_spc::manifest::{BlobHash, Manifest};
+use filehost_spc::quota::Usage;
+
+pub use crate::errors::IndexError as Error;
+use crate::database;
+
+
+/***** CONSTANTS *****/
+/// The key in the `meta` table that marks the index as built.
+const BUILT_KEY: &str = "index_built";
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Converts a moment in time to the number of seconds since the Unix epoch, as stored in the index.
+#[inline]
+fn to_secs(time: SystemTime) -> i64 { time.duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0) }
+
+/// Converts a number of seconds since the Unix epoch, as stored in the index, to a moment in time.
+#[inline]
+fn from_secs(secs: i64) -> SystemTime { UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64) }
+
+/// Adds the given package version to the index, as part of the given transaction.
+///
+/// # Errors
+/// This function errors if we failed to update the index.
+fn insert_version(tx: &Transaction, manifest: &Manifest, published: SystemTime) -> Result<(), rusqlite::Error> {
+    tx.execute("INSERT OR IGNORE INTO packages (name) VALUES (?1)", params![ manifest.package ])?;
+    tx.execute(
+        "INSERT INTO versions (package, version, publisher, size, published) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![ manifest.package, manifest.version, manifest.publisher.map(|p| p as i64), manifest.total_size() as i64, to_secs(published) ],
+    )?;
+
+    // Add the entries
+    let mut blobs: HashMap<BlobHash, u64> = HashMap::new();
+    for entry in &manifest.entries {
+        tx.execute(
+            "INSERT INTO entries (package, version, path, hash, size, chunks) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![ manifest.package, manifest.version, entry.path, entry.hash.to_string(), entry.size as i64, entry.chunks.len() as i64 ],
+        )?;
+        blobs.extend(entry.blobs());
+    }
+
+    // Count the references to every blob once per version
+    for (hash, size) in blobs {
+        tx.execute("INSERT INTO version_blobs (package, version, hash) VALUES (?1, ?2, ?3)", params![ manifest.package, manifest.version, hash.to_string() ])?;
+        tx.execute(
+            "INSERT INTO blobs (hash, size, refs) VALUES (?1, ?2, 1) ON CONFLICT (hash) DO UPDATE SET refs = refs + 1",
+            params![ hash.to_string(), size as i64 ],
+        )?;
+    }
+    Ok(())
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// The metadata index, which mirrors what's in the package database in a SQLite database.
+///
+/// The index lives in the metadata database (see `database.rs`). The files in the package database remain the source of truth; the index can always be rebuilt from them. To keep the garbage collector on the safe side, the index is updated such that it never refers to fewer blobs than the package database does.
+#[derive(Debug)]
+pub struct Index {
+    /// The connection to the database.
+    conn : Mutex<Connection>,
+}
+
+impl Index {
+    /// Opens the index in the given metadata database, creating and migrating the database if needed.
+    ///
+    /// # Errors
+    /// This function errors if the database could not be opened or migrated.
+    #[inline]
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let conn: Connection = database::open(path).map_err(|err| Error::DatabaseError{ err })?;
+        Ok(Self{ conn: Mutex::new(conn) })
+    }
+
+    /// Locks the connection to the database, ignoring whether another thread panicked while holding it.
+    #[inline]
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(err) => err.into_inner(),
+        }
+    }
+
+
+
+    /// Adds a new package version to the index.
+    ///
+    /// # Arguments
+    /// - `manifest`: The manifest of the new version.
+    /// - `published`: The moment the version was published.
+    ///
+    /// # Errors
+    /// This function errors if the version is already in the index or if we failed to update it.
+    pub fn insert_version(&self, manifest: &Manifest, published: SystemTime) -> Result<(), Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        insert_version(&tx, manifest, published).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })
+    }
+
+    /// Removes a package version from the index, dropping its references to blobs.
+    ///
+    /// # Errors
+    /// This function errors if we failed to update the index.
+    pub fn remove_version(&self, package: &str, version: &str) -> Result<(), Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        tx.execute(
+            "UPDATE blobs SET refs = refs - 1 WHERE hash IN (SELECT hash FROM version_blobs WHERE package = ?1 AND version = ?2)",
+            params![ package, version ],
+        ).map_err(|err| Error::QueryError{ err })?;
+        tx.execute("DELETE FROM blobs WHERE refs <= 0", []).map_err(|err| Error::QueryError{ err })?;
+        tx.execute("DELETE FROM versions WHERE package = ?1 AND version = ?2", params![ package, version ]).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })
+    }
+
+    /// Points the given channel to the given version in the index, adding it if it's new.
+    ///
+    /// # Errors
+    /// This function errors if we failed to update the index.
+    pub fn set_channel(&self, package: &str, channel: &str, version: &str) -> Result<(), Error> {
+        self.conn().execute(
+            "INSERT INTO channels (package, name, version) VALUES (?1, ?2, ?3) ON CONFLICT (package, name) DO UPDATE SET version = excluded.version",
+            params![ package, channel, version ],
+        ).map_err(|err| Error::QueryError{ err })?;
+        Ok(())
+    }
+
+    /// Replaces everything in the index with the given package versions and channels.
+    ///
+    /// # Arguments
+    /// - `packages`: The names of all packages.
+    /// - `versions`: The manifests of all versions, together with when they were published.
+    /// - `channels`: All channels, as (package, channel, version).
+    ///
+    /// # Errors
+    /// This function errors if we failed to update the index. In that case, it is left as it was.
+    pub fn replace(&self, packages: &[String], versions: &[(Manifest, SystemTime)], channels: &[(String, String, String)]) -> Result<(), Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        tx.execute_batch("DELETE FROM blobs; DELETE FROM packages;").map_err(|err| Error::QueryError{ err })?;
+        for package in packages {
+            tx.execute("INSERT OR IGNORE INTO packages (name) VALUES (?1)", params![ package ]).map_err(|err| Error::QueryError{ err })?;
+        }
+        for (manifest, published) in versions {
+            insert_version(&tx, manifest, *published).map_err(|err| Error::QueryError{ err })?;
+        }
+        for (package, channel, version) in channels {
+            tx.execute("INSERT INTO channels (package, name, version) VALUES (?1, ?2, ?3)", params![ package, channel, version ]).map_err(|err| Error::QueryError{ err })?;
+        }
+        tx.execute("INSERT OR REPLACE INTO meta (key, value) VALUES (?1, '1')", params![ BUILT_KEY ]).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })
+    }
+
+
+
+    /// Returns whether the index has ever been filled, i.e., whether it reflects the package database.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    pub fn is_built(&self) -> Result<bool, Error> {
+        self.conn().query_row("SELECT 1 FROM meta WHERE key = ?1", params![ BUILT_KEY ], |_| Ok(()))
+            .optional()
+            .map(|res| res.is_some())
+            .map_err(|err| Error::QueryError{ err })
+    }
+
+    /// Returns whether the index knows the given package.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    pub fn has_package(&self, package: &str) -> Result<bool, Error> {
+        self.conn().query_row("SELECT 1 FROM packages WHERE name = ?1", params![ package ], |_| Ok(()))
+            .optional()
+            .map(|res| res.is_some())
+            .map_err(|err| Error::QueryError{ err })
+    }
+
+    /// Returns the names of all packages, sorted.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    pub fn packages(&self) -> Result<Vec<String>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare("SELECT name FROM packages ORDER BY name").map_err(|err| Error::QueryError{ err })?;
+        let names = stmt.query_map([], |row| row.get(0)).map_err(|err| Error::QueryError{ err })?;
+        names.collect::<Result<Vec<String>, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+    /// Returns the names of all versions of the given package, sorted.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    pub fn versions(&self, package: &str) -> Result<Vec<String>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare("SELECT version FROM versions WHERE package = ?1 ORDER BY version").map_err(|err| Error::QueryError{ err })?;
+        let names = stmt.query_map(params![ package ], |row| row.get(0)).map_err(|err| Error::QueryError{ err })?;
+        names.collect::<Result<Vec<String>, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+    /// Returns the names of all channels of the given package, sorted.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    pub fn channels(&self, package: &str) -> Result<Vec<String>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare("SELECT name FROM channels WHERE package = ?1 ORDER BY name").map_err(|err| Error::QueryError{ err })?;
+        let names = stmt.query_map(params![ package ], |row| row.get(0)).map_err(|err| Error::QueryError{ err })?;
+        names.collect::<Result<Vec<String>, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+    /// Returns when the given package version was published, if the index knows it.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    pub fn published(&self, package: &str, version: &str) -> Result<Option<SystemTime>, Error> {
+        self.conn().query_row("SELECT published FROM versions WHERE package = ?1 AND version = ?2", params![ package, version ], |row| row.get::<_, i64>(0))
+            .optional()
+            .map(|secs| secs.map(from_secs))
+            .map_err(|err| Error::QueryError{ err })
+    }
+
+    /// Computes how much the versions of a package or a user take up.
+    ///
+    /// # Arguments
+    /// - `package`: If given, only counts the versions of this package.
+    /// - `publisher`: If given, only counts the versions that this user published.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    pub fn usage(&self, package: Option<&str>, publisher: Option<UserId>) -> Result<Usage, Error> {
+        self.conn().query_row(
+            "SELECT COALESCE(SUM(size), 0), COUNT(*) FROM versions WHERE (?1 IS NULL OR package = ?1) AND (?2 IS NULL OR publisher = ?2)",
+            params![ package, publisher.map(|p| p as i64) ],
+            |row| Ok(Usage{ bytes: row.get::<_, i64>(0)? as u64, versions: row.get::<_, i64>(1)? as usize }),
+        ).map_err(|err| Error::QueryError{ err })
+    }
+
+    /// Returns the names of the packages that have a version that refers to the given blob, sorted.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    pub fn packages_with_blob(&self, hash: &BlobHash) -> Result<Vec<String>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare("SELECT DISTINCT package FROM version_blobs WHERE hash = ?1 ORDER BY package").map_err(|err| Error::QueryError{ err })?;
+        let names = stmt.query_map(params![ hash.to_string() ], |row| row.get(0)).map_err(|err| Error::QueryError{ err })?;
+        names.collect::<Result<Vec<String>, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+    /// Returns the blobs that any package version refers to.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    pub fn referenced_blobs(&self) -> Result<HashSet<BlobHash>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare("SELECT hash FROM blobs WHERE refs > 0").map_err(|err| Error::QueryError{ err })?;
+        let hashes = stmt.query_map([], |row| row.get::<_, String>(0)).map_err(|err| Error::QueryError{ err })?;
+        let mut res: HashSet<BlobHash> = HashSet::new();
+        for raw in hashes {
+            let raw: String = raw.map_err(|err| Error::QueryError{ err })?;
+            match BlobHash::from_str(&raw) {
+                Ok(hash) => { res.insert(hash); },
+                Err(_)   => { return Err(Error::IllegalHash{ raw }); },
+            }
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,203 @@
+/* INVITES.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 05:46:12
+ * Last edited:
+ *   19 Oct 2026, 07:03:36
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the one-time invite codes with which new users create their
+ *   own account. A code looks like `fhi_<id>.<secret>`; only the hash of
+ *   the secret is stored in the users database.
+**/
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use chrono::{Duration, Utc};
+use log::{debug, warn};
+use rcgen::CertificateSigningRequest;
+use rustls::{Certificate, RootCertStore};
+
+use filehost_spc::login::{INVITE_PREFIX, Permissions};
+use filehost_spc::manifest::is_valid_name;
+use filehost_spc::quota::Quota;
+
+pub use crate::errors::InviteError as Error;
+use crate::ca;
+use crate::errors::UserError;
+use crate::state::ServerState;
+use crate::tokens::{generate_secret, hash_secret, split_secret};
+use crate::users::{Invite, User, Users};
+
+
+/***** CONSTANTS *****/
+/// The longest time (in seconds) that an invite code may remain valid.
+pub const MAX_VALID_FOR: u64 = 90 * 24 * 60 * 60;
+
+/// The directory (in the data directory) where the certificates of users that joined with an invite code are stored.
+pub const CERTS_DIR: &str = "certs";
+
+/// Makes sure only one code is redeemed at a time, so two new users can't claim the same username (and certificate file) at once.
+static REDEEMING: Mutex<()> = Mutex::new(());
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Parses the certificates that a new user wants to log in with.
+///
+/// # Errors
+/// This function errors if the PEM data could not be parsed, contains anything but certificates, or contains certificates that we could not trust.
+fn parse_certificates(pem: &str) -> Result<Vec<Certificate>, Error> {
+    let items: Vec<rustls_pemfile::Item> = rustls_pemfile::read_all(&mut pem.as_bytes()).map_err(|err| Error::CertParseError{ err })?;
+    let mut certs: Vec<Certificate> = Vec::with_capacity(items.len());
+    for item in items {
+        match item {
+            rustls_pemfile::Item::X509Certificate(cert) => { certs.push(Certificate(cert)); },
+            _                                           => { return Err(Error::NotACertificate); },
+        }
+    }
+    if certs.is_empty() { return Err(Error::NoCertsFound); }
+
+    // Make sure the SSL config will accept them later on
+    let mut roots: RootCertStore = RootCertStore::empty();
+    for cert in &certs { roots.add(cert).map_err(|err| Error::IllegalCert{ err })?; }
+    Ok(certs)
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Creates a new invite code and stores it in the users database.
+///
+/// # Arguments
+/// - `users`: The users database to store the code in.
+/// - `permissions`: The permissions the new user gets.
+/// - `groups`: The groups the new user is added to. They must exist.
+/// - `valid_for`: The number of seconds the code remains valid. Must not exceed `MAX_VALID_FOR`.
+/// - `description`: Who or what the code is for.
+///
+/// # Returns
+/// The stored invite, together with the full code to give to the new user. The latter cannot be retrieved again.
+///
+/// # Errors
+/// This function errors if one of the groups does not exist or if we failed to update the users database.
+pub fn create(users: &dyn Users, permissions: Permissions, groups: Vec<String>, valid_for: u64, description: impl Into<String>) -> Result<(Invite, String), UserError> {
+    for group in &groups {
+        if users.group(group)?.is_none() { return Err(UserError::UnknownGroup{ name: group.clone() }); }
+    }
+
+    let (id, secret): (String, String) = generate_secret();
+    let created = Utc::now();
+    let invite = Invite {
+        id          : id.clone(),
+        hash        : hash_secret(&secret),
+        permissions,
+        groups,
+        description : description.into(),
+        created,
+        expires     : created + Duration::seconds(valid_for.min(MAX_VALID_FOR) as i64),
+    };
+    users.insert_invite(&invite)?;
+    Ok((invite, format!("{}{}.{}", INVITE_PREFIX, id, secret)))
+}
+
+/// Redeems an invite code, creating a new user that logs in with the given certificate(s).
+///
+/// The certificates are stored in the `certs` directory in the data directory and trusted right away; the code can't be used again. If a certificate signing request is given instead, the certificate authority of the server signs it.
+///
+/// # Arguments
+/// - `state`: The state of the server, whose users database and SSL config are updated.
+/// - `code`: The full invite code.
+/// - `username`: The username of the new user.
+/// - `certificate`: The PEM-encoded certificate(s) the new user will log in with, or a PEM-encoded certificate signing request.
+///
+/// # Returns
+/// The new user, the names of the groups it was added to and the PEM-encoded certificate that was issued to it, if any.
+///
+/// # Errors
+/// This function errors if the code is invalid, the username or certificate cannot be used, or if we failed to update the users database or the SSL config.
+pub fn redeem(state: &ServerState, code: &str, username: &str, certificate: &str) -> Result<(User, Vec<String>, Option<String>), Error> {
+    let _lock: MutexGuard<()> = REDEEMING.lock().unwrap_or_else(|err| err.into_inner());
+
+    // Check the code
+    let (id, secret): (&str, &str) = split_secret(INVITE_PREFIX, code).ok_or(Error::InvalidCode)?;
+    let invite: Invite = match state.users.invite(id).map_err(|err| Error::UserError{ err })? {
+        Some(invite) => invite,
+        None         => { debug!("Unknown invite code '{}'", id); return Err(Error::InvalidCode); },
+    };
+    if hash_secret(secret) != invite.hash { debug!("Wrong secret for invite code '{}'", id); return Err(Error::InvalidCode); }
+    if invite.is_expired() { debug!("Invite code '{}' expired at {}", id, invite.expires); return Err(Error::InvalidCode); }
+
+    // Check what the new user wants to be
+    if !is_valid_name(username) { return Err(Error::IllegalUsername{ username: username.into() }); }
+    if state.users.find(username).map_err(|err| Error::UserError{ err })?.is_some() { return Err(Error::UserError{ err: UserError::UsernameTaken{ username: username.into() } }); }
+    let csr: Option<CertificateSigningRequest> = if ca::is_csr(certificate) {
+        if state.ca.is_none() { return Err(Error::NoAuthority); }
+        Some(ca::parse_csr(certificate).map_err(|err| Error::CAError{ err })?)
+    } else {
+        for cert in parse_certificates(certificate)? {
+            if let Some(id) = state.ssl.owner(&cert) { return Err(Error::CertInUse{ id }); }
+        }
+        None
+    };
+
+    // Use up the code
+    state.users.remove_invite(&invite.id).map_err(|err| Error::UserError{ err })?;
+
+    // Store the certificate (unless the CA will issue one) and create the user
+    let certs: Option<PathBuf> = if csr.is_none() {
+        let dir: PathBuf = state.config.data_dir.join(CERTS_DIR);
+        if let Err(err) = fs::create_dir_all(&dir) { return Err(Error::DirCreateError{ path: dir, err }); }
+        let path: PathBuf = dir.join(format!("{}.crt", username));
+        if let Err(err) = fs::write(&path, certificate) { return Err(Error::CertWriteError{ path, err }); }
+        Some(path)
+    } else {
+        None
+    };
+    let mut user = User{ id: 0, username: username.into(), certs: certs.clone(), permissions: invite.permissions, quota: Quota::default(), suspended: false, suspended_until: None, scope: Permissions::ALL };
+    user.id = match state.users.insert(user.clone()) {
+        Ok(id)   => id,
+        Err(err) => {
+            // Give the code back, since it didn't get anyone in
+            if let Err(err) = state.users.insert_invite(&invite) { warn!("Could not restore invite code '{}': {}", invite.id, err); }
+            if let Some(path) = certs {
+                if let Err(err) = fs::remove_file(&path) { warn!("Could not remove certificate file '{}': {}", path.display(), err); }
+            }
+            return Err(Error::UserError{ err });
+        },
+    };
+
+    // Add it to its groups; the user exists by now, so we only complain if that fails
+    let mut groups: Vec<String> = Vec::with_capacity(invite.groups.len());
+    for group in invite.groups {
+        match state.users.add_member(&group, user.id) {
+            Ok(_)    => { groups.push(group); },
+            Err(err) => { warn!("Could not add new user '{}' to group '{}': {}", user.username, group, err); },
+        }
+    }
+
+    // Either issue it a certificate, or trust its own from now on
+    match (csr, &state.ca) {
+        (Some(csr), Some(ca)) => {
+            let (cert, expires): (String, _) = ca.sign(&user, csr).map_err(|err| {
+                warn!("New user '{}' exists, but has no certificate to log in with; issue one with the CTL", user.username);
+                Error::CAError{ err }
+            })?;
+            ca::record(&*state.users, &user, &cert, expires);
+            Ok((user, groups, Some(cert)))
+        },
+        _ => {
+            state.reload_ssl().map_err(|err| Error::SSLError{ err })?;
+            Ok((user, groups, None))
+        },
+    }
+}
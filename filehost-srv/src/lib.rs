@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 19:32:50
  * Last edited:
- *   11 Jun 2022, 13:30:37
+ *   19 Oct 2026, 06:41:27
  * Auto updated?
  *   Yes
  *
@@ -16,5 +16,43 @@
 pub mod errors;
 /// Modules that does the complicated SSL junk.
 pub mod ssl;
+/// Module that implements the certificate authority that issues client certificates.
+pub mod ca;
+/// Module that checks when the certificates of the server and its users expire.
+pub mod expiry;
 /// Modules that interacts with some user database.
 pub mod users;
+/// Module that implements the content-addressed blob storage.
+pub mod storage;
+/// Module that implements opening and migrating the SQLite metadata database.
+pub mod database;
+/// Module that implements the metadata index of the package database.
+pub mod index;
+/// Module that implements the API tokens users may log in with.
+pub mod tokens;
+/// Module that implements the invite codes with which new users create their own account.
+pub mod invites;
+/// Module that decides what users may do with packages.
+pub mod access;
+/// Module that implements the package database.
+pub mod packages;
+/// Module that implements publish transactions.
+pub mod publish;
+/// Module that implements the audit log of operations that change something.
+pub mod audit;
+/// Module that implements garbage collection of unreferenced blobs.
+pub mod gc;
+/// Module that implements the integrity check of the storage.
+pub mod fsck;
+/// Module that implements the retention policies that prune old package versions.
+pub mod retention;
+/// Module that limits how much clients may ask of the daemon, and bans those that misbehave.
+pub mod limits;
+/// Module that defines the state shared between the connections.
+pub mod state;
+/// Module that handles client connections.
+pub mod client;
+/// Module that decides which local users may use the CTL socket.
+pub mod peers;
+/// Module that handles requests on the CTL socket.
+pub mod ctl;
@@ -0,0 +1,383 @@
+/* LIMITS.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 06:32:31
+ * Last edited:
+ *   19 Oct 2026, 06:32:31
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Limits how much clients may ask of the daemon, per source address and
+ *   per user, and temporarily bans the addresses of clients that keep
+ *   failing to authenticate or sending invalid messages.
+**/
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+
+use filehost_spc::config::{Limits, RateLimit};
+use filehost_spc::ctl_messages::BanInfo;
+use filehost_spc::login::{GUEST_ID, UserId};
+
+use crate::state::ServerState;
+use crate::users::User;
+
+
+/***** CONSTANTS *****/
+/// How often, at most, we forget the addresses and users that have been idle for a while.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Locks the given mutex, ignoring that another thread panicked while holding it (the maps it guards remain usable).
+#[inline]
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> { mutex.lock().unwrap_or_else(|err| err.into_inner()) }
+
+/// Returns the given rate as a float, or `None` if it is unlimited.
+#[inline]
+fn rate<T: Into<f64>>(rate: Option<T>) -> Option<f64> { rate.map(Into::into).filter(|rate| *rate > 0.0) }
+
+
+
+
+
+/***** HELPER TYPES *****/
+/// Identifies a user across hosts: hosts that share their users database share its users, while hosts with their own may reuse the same IDs.
+type UserKey = (usize, UserId);
+
+
+
+/// A token bucket, which refills at a fixed rate and holds at most a second's worth of tokens.
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    /// The number of tokens in the bucket. Negative if it is in debt.
+    tokens : f64,
+    /// The last time we refilled the bucket.
+    filled : Instant,
+}
+
+impl Bucket {
+    /// Refills the bucket for the time that passed since the last time.
+    #[inline]
+    fn refill(&mut self, rate: f64, now: Instant) {
+        self.tokens = (self.tokens + rate * now.saturating_duration_since(self.filled).as_secs_f64()).min(rate.max(1.0));
+        self.filled = now;
+    }
+
+    /// Takes a single token, if there is one.
+    ///
+    /// # Returns
+    /// `None` if we took it, or else how long it takes before there is one.
+    fn take(&mut self, rate: f64, now: Instant) -> Option<Duration> {
+        self.refill(rate, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    /// Takes the given number of tokens, going into debt if there aren't enough.
+    ///
+    /// # Returns
+    /// How long it takes before the debt is paid off.
+    fn spend(&mut self, rate: f64, amount: f64, now: Instant) -> Duration {
+        self.refill(rate, now);
+        self.tokens -= amount;
+        if self.tokens < 0.0 { Duration::from_secs_f64(-self.tokens / rate) } else { Duration::ZERO }
+    }
+}
+
+
+
+/// The buckets of a single address or user.
+#[derive(Debug, Default)]
+struct Account {
+    /// The bucket for requests, if they are limited.
+    requests : Option<Bucket>,
+    /// The bucket for transferred bytes, if they are limited.
+    bytes    : Option<Bucket>,
+}
+
+impl Account {
+    /// Takes a request from the account.
+    ///
+    /// # Returns
+    /// `None` if the request is allowed, or else how long to wait before it is.
+    fn request(&mut self, limit: &RateLimit, now: Instant) -> Option<Duration> {
+        let rate: f64 = rate(limit.requests_per_sec)?;
+        self.requests.get_or_insert(Bucket{ tokens: rate.max(1.0), filled: now }).take(rate, now)
+    }
+
+    /// Takes transferred bytes from the account.
+    ///
+    /// # Returns
+    /// How long to wait before transferring more.
+    fn transfer(&mut self, limit: &RateLimit, bytes: usize, now: Instant) -> Duration {
+        let rate: f64 = match rate(limit.bytes_per_sec.map(|rate| rate as f64)) {
+            Some(rate) => rate,
+            None       => { return Duration::ZERO; },
+        };
+        self.bytes.get_or_insert(Bucket{ tokens: rate, filled: now }).spend(rate, bytes as f64, now)
+    }
+
+    /// Returns whether the account's buckets have refilled completely, in which case forgetting it changes nothing.
+    fn is_idle(&self, now: Instant) -> bool {
+        [ self.requests, self.bytes ].iter().flatten().all(|bucket| bucket.tokens >= 0.0 && now.saturating_duration_since(bucket.filled) >= Duration::from_secs(1))
+    }
+}
+
+
+
+/// A banned address.
+#[derive(Clone, Debug)]
+struct Ban {
+    /// Why the address was banned.
+    reason : String,
+    /// The moment the address was banned.
+    since  : DateTime<Utc>,
+    /// The moment the ban ends by itself.
+    until  : DateTime<Utc>,
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Keeps track of how much every client asks of the daemon, and of the addresses it has banned. Shared by all hosts.
+#[derive(Debug)]
+pub struct Limiter {
+    /// The limits to enforce.
+    limits    : Limits,
+    /// The buckets of every source address.
+    addresses : Mutex<HashMap<IpAddr, Account>>,
+    /// The buckets of every authenticated user.
+    users     : Mutex<HashMap<UserKey, Account>>,
+    /// The recent failed authentications and invalid messages of every source address.
+    strikes   : Mutex<HashMap<IpAddr, Vec<Instant>>>,
+    /// The banned addresses.
+    bans      : Mutex<HashMap<IpAddr, Ban>>,
+    /// The last time we forgot idle addresses and users.
+    pruned    : Mutex<Instant>,
+}
+
+impl Limiter {
+    /// Constructor for the Limiter.
+    ///
+    /// # Arguments
+    /// - `limits`: The limits to enforce.
+    #[inline]
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            addresses : Mutex::new(HashMap::new()),
+            users     : Mutex::new(HashMap::new()),
+            strikes   : Mutex::new(HashMap::new()),
+            bans      : Mutex::new(HashMap::new()),
+            pruned    : Mutex::new(Instant::now()),
+        }
+    }
+
+
+
+    /// Forgets the accounts that are idle, the strikes that are too old to count and the bans that have ended.
+    fn prune(&self, now: Instant) {
+        {
+            let mut pruned: MutexGuard<Instant> = lock(&self.pruned);
+            if now.saturating_duration_since(*pruned) < PRUNE_INTERVAL { return; }
+            *pruned = now;
+        }
+        lock(&self.addresses).retain(|_, account| !account.is_idle(now));
+        lock(&self.users).retain(|_, account| !account.is_idle(now));
+        let window: Duration = Duration::from_secs(self.limits.ban_window);
+        lock(&self.strikes).retain(|_, strikes| strikes.iter().any(|strike| now.saturating_duration_since(*strike) < window));
+        let utc_now: DateTime<Utc> = Utc::now();
+        lock(&self.bans).retain(|_, ban| ban.until > utc_now);
+    }
+
+    /// Takes a request from the account with the given key, creating it if needed.
+    fn request<K: Eq + Hash>(accounts: &Mutex<HashMap<K, Account>>, key: K, limit: &RateLimit, now: Instant) -> Option<Duration> {
+        // Unlimited requests need no account
+        limit.requests_per_sec.and_then(|_| lock(accounts).entry(key).or_default().request(limit, now))
+    }
+
+    /// Takes transferred bytes from the account with the given key, creating it if needed.
+    fn transfer<K: Eq + Hash>(accounts: &Mutex<HashMap<K, Account>>, key: K, limit: &RateLimit, bytes: usize, now: Instant) -> Duration {
+        if limit.bytes_per_sec.is_none() { return Duration::ZERO; }
+        lock(accounts).entry(key).or_default().transfer(limit, bytes, now)
+    }
+
+
+
+    /// Decides whether to accept a new connection from the given address. It must not be banned, and the connection counts as a request.
+    ///
+    /// # Returns
+    /// Whether to accept the connection.
+    pub fn admit(&self, address: IpAddr) -> bool {
+        let now: Instant = Instant::now();
+        self.prune(now);
+        if let Some(until) = self.banned_until(address) {
+            debug!("Refusing connection from '{}', which is banned until {}", address, until);
+            return false;
+        }
+        if let Some(wait) = Self::request(&self.addresses, address, &self.limits.per_address, now) {
+            debug!("Refusing connection from '{}', which exceeds its rate limit (for another {} ms)", address, wait.as_millis());
+            return false;
+        }
+        true
+    }
+
+    /// Returns until when the given address is banned, if it is.
+    pub fn banned_until(&self, address: IpAddr) -> Option<DateTime<Utc>> {
+        lock(&self.bans).get(&address).map(|ban| ban.until).filter(|until| *until > Utc::now())
+    }
+
+    /// Records that the client at the given address failed to authenticate or sent an invalid message. Bans it if it did so too often.
+    ///
+    /// # Arguments
+    /// - `address`: The address of the client.
+    /// - `what`: What the client did, for in the logs and the reason of the ban.
+    pub fn strike(&self, address: IpAddr, what: impl Display) {
+        debug!("Strike for '{}': {}", address, what);
+        if self.limits.ban_threshold == 0 { return; }
+
+        // Count the recent strikes, including this one
+        let now: Instant = Instant::now();
+        let window: Duration = Duration::from_secs(self.limits.ban_window);
+        let count: usize = {
+            let mut strikes = lock(&self.strikes);
+            let strikes: &mut Vec<Instant> = strikes.entry(address).or_default();
+            strikes.retain(|strike| now.saturating_duration_since(*strike) < window);
+            strikes.push(now);
+            if strikes.len() < self.limits.ban_threshold as usize { return; }
+            let count: usize = strikes.len();
+            strikes.clear();
+            count
+        };
+
+        // Too many; ban the address
+        let since: DateTime<Utc> = Utc::now();
+        let until: DateTime<Utc> = since + chrono::Duration::seconds(self.limits.ban_duration.min(i64::MAX as u64) as i64);
+        let reason: String = format!("{} failed authentication(s) or invalid message(s) within {} seconds; last: {}", count, self.limits.ban_window, what);
+        warn!("Banning '{}' until {}: {}", address, until, reason);
+        lock(&self.bans).insert(address, Ban{ reason, since, until });
+    }
+
+
+
+    /// Returns the addresses that are currently banned, the one banned first first.
+    pub fn bans(&self) -> Vec<BanInfo> {
+        let now: DateTime<Utc> = Utc::now();
+        let mut res: Vec<BanInfo> = lock(&self.bans).iter()
+            .filter(|(_, ban)| ban.until > now)
+            .map(|(address, ban)| BanInfo{ address: *address, reason: ban.reason.clone(), since: ban.since, until: ban.until })
+            .collect();
+        res.sort_by_key(|info| info.since);
+        res
+    }
+
+    /// Lifts the ban of the given address, or of all addresses.
+    ///
+    /// # Returns
+    /// The bans that were lifted.
+    pub fn clear(&self, address: Option<IpAddr>) -> Vec<BanInfo> {
+        let now: DateTime<Utc> = Utc::now();
+        let mut bans = lock(&self.bans);
+        let lifted: Vec<(IpAddr, Ban)> = match address {
+            Some(address) => bans.remove(&address).map(|ban| (address, ban)).into_iter().collect(),
+            None          => bans.drain().collect(),
+        };
+        let mut res: Vec<BanInfo> = lifted.into_iter()
+            .filter(|(_, ban)| ban.until > now)
+            .map(|(address, ban)| BanInfo{ address, reason: ban.reason, since: ban.since, until: ban.until })
+            .collect();
+        res.sort_by_key(|info| info.since);
+        for info in &res { info!("Lifted the ban of '{}'", info.address); }
+        res
+    }
+}
+
+
+
+/// Applies the limits of the daemon to a single client connection.
+#[derive(Debug)]
+pub struct Throttle {
+    /// The limiter of the daemon.
+    limiter : Arc<Limiter>,
+    /// The address of the client.
+    address : IpAddr,
+    /// The user the client is logged in as, unless it is the guest user.
+    user    : Option<UserKey>,
+}
+
+impl Throttle {
+    /// Constructor for the Throttle.
+    ///
+    /// # Arguments
+    /// - `limiter`: The limiter of the daemon.
+    /// - `address`: The address of the client.
+    #[inline]
+    pub fn new(limiter: Arc<Limiter>, address: IpAddr) -> Self {
+        Self {
+            limiter,
+            address,
+            user : None,
+        }
+    }
+
+    /// Tells the throttle which user the client is (now) logged in as, so that the limits of that user apply too.
+    ///
+    /// # Arguments
+    /// - `state`: The host that the client connected to.
+    /// - `user`: The user that the client is logged in as.
+    pub fn login(&mut self, state: &ServerState, user: &User) {
+        self.user = if user.id != GUEST_ID { Some((Arc::as_ptr(&state.users) as *const () as usize, user.id)) } else { None };
+    }
+
+
+
+    /// Takes a request from the address and user of the client.
+    ///
+    /// # Returns
+    /// `None` if the client may make the request, or else how long it should wait before it may.
+    pub fn request(&self) -> Option<Duration> {
+        let now: Instant = Instant::now();
+        if let Some(user) = self.user {
+            if let Some(wait) = Limiter::request(&self.limiter.users, user, &self.limiter.limits.per_user, now) { return Some(wait); }
+        }
+        Limiter::request(&self.limiter.addresses, self.address, &self.limiter.limits.per_address, now)
+    }
+
+    /// Takes the given number of transferred bytes from the address and user of the client, sleeping if they go faster than they may.
+    pub fn transfer(&self, bytes: usize) {
+        let now: Instant = Instant::now();
+        let mut wait: Duration = Limiter::transfer(&self.limiter.addresses, self.address, &self.limiter.limits.per_address, bytes, now);
+        if let Some(user) = self.user {
+            wait = wait.max(Limiter::transfer(&self.limiter.users, user, &self.limiter.limits.per_user, bytes, now));
+        }
+        if !wait.is_zero() { thread::sleep(wait); }
+    }
+
+    /// Records that the client failed to authenticate or sent an invalid message. See `Limiter::strike()`.
+    #[inline]
+    pub fn strike(&self, what: impl Display) { self.limiter.strike(self.address, what) }
+
+    /// Returns whether the address of the client is banned (by now).
+    #[inline]
+    pub fn is_banned(&self) -> bool { self.limiter.banned_until(self.address).is_some() }
+}
@@ -4,7 +4,7 @@
  * Created:
  *   30 Mar 2022, 20:56:29
  * Last edited:
- *   11 Jun 2022, 15:32:17
+ *   19 Oct 2026, 07:08:31
  * Auto updated?
  *   Yes
  *
@@ -12,12 +12,18 @@
  *   Entrypoint to the FileHost server/
 **/
 
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufReader, Read, Write};
-use std::os::unix::io::{FromRawFd, RawFd};
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::UnixListener;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
+use chrono::Utc;
 use clap::Parser;
 use log::{debug, error, info, warn};
 use nix::sys::select::{FdSet, select};
@@ -26,13 +32,184 @@ use simplelog::{ColorChoice, TermLogger, TerminalMode};
 use systemd::daemon;
 use systemd_journal_logger::{connected_to_journal, init_with_extra_fields};
 
-use filehost_spc::config::Config;
-use filehost_spc::ctl_messages::{ByteOrder, HEALTH_REPLY, Opcode};
-use filehost_spc::login::ROOT_ID;
+use filehost_spc::config::{Config, UserBackend};
+use filehost_spc::audit::{AuditOperation, AuditOutcome};
+use filehost_spc::client_messages::ErrorCode;
+use filehost_spc::ctl_messages::{CertificateStatus, FsckReport};
 
 pub use filehost_srv::errors::ServerError as Error;
-use filehost_srv::users::{User, Users};
-use filehost_srv::ssl::SSLConfig;
+use filehost_srv::{client, ctl, database, expiry, fsck, peers, retention, users};
+use filehost_srv::audit::{AuditLog, SYSTEM_USER};
+use filehost_srv::limits::Limiter;
+use filehost_srv::peers::Peer;
+use filehost_srv::packages::Packages;
+use filehost_srv::publish::Transactions;
+use filehost_srv::state::{Hosts, ServerState};
+use filehost_srv::storage::Storage;
+use filehost_srv::users::{IssuedCert, Revocation, User, Users};
+use filehost_srv::ca::CertificateAuthority;
+use filehost_srv::ssl::{ClientVerifier, SSLConfig};
+
+
+/***** CONSTANTS *****/
+/// The interval at which we check for publish transactions that have timed out.
+const REAPER_INTERVAL: Duration = Duration::from_secs(60);
+/// The interval at which we check whether certificates expire soon, apart from when they are loaded.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Loads the users, SSL configuration, storage and packages of a host.
+///
+/// # Arguments
+/// - `config`: The configuration of the host.
+/// - `vhost`: The name of the host if it is a virtual host, or `None` for the main host.
+/// - `shared`: The host whose users (and thus certificate authority and client certificate verifier) this host shares, if any.
+///
+/// # Errors
+/// This function errors if any part of the host could not be loaded.
+fn load_host(config: Config, vhost: Option<String>, shared: Option<&ServerState>) -> Result<ServerState, Error> {
+    // Read the database file, unless we share that of another host
+    let (users, ca, verifier): (Arc<dyn Users>, Option<Arc<CertificateAuthority>>, Arc<ClientVerifier>) = match shared {
+        Some(shared) => {
+            debug!("Sharing the users of the main host");
+            (shared.users.clone(), shared.ca.clone(), shared.ssl.verifier.clone())
+        },
+        None => {
+            info!("Loading users...");
+            let user_db: PathBuf = match config.user_backend {
+                UserBackend::Json   => config.user_db.clone(),
+                UserBackend::Sqlite => database::database_path(&config.data_dir),
+            };
+            debug!("User database: '{}'", user_db.display());
+            let users: Arc<dyn Users> = users::open(&config).map(Arc::from).map_err(|err| Error::UsersParseError{ path: user_db.clone(), err })?;
+            let user_list: Vec<User> = users.list().map_err(|err| Error::UsersParseError{ path: user_db.clone(), err })?;
+            let revocations: Vec<Revocation> = users.revocations().map_err(|err| Error::UsersParseError{ path: user_db.clone(), err })?;
+            let issued: Vec<IssuedCert> = users.issued_certs().map_err(|err| Error::UsersParseError{ path: user_db, err })?;
+            expiry::check(&config, &expiry::user_certs(&user_list, &issued, &revocations), Utc::now());
+
+            // Load the certificate authority, if any
+            let ca: Option<Arc<CertificateAuthority>> = CertificateAuthority::from_config(&config).map_err(|err| Error::CAInitError{ err })?.map(Arc::new);
+            if ca.is_some() { info!("Certificate authority enabled"); }
+
+            // Trust the certificates of the users
+            let verifier: ClientVerifier = ClientVerifier::new(&user_list, &revocations, ca.clone(), config.tls.client_auth).map_err(|err| Error::SSLConfigError{ err })?;
+            (users, ca, Arc::new(verifier))
+        },
+    };
+
+    // Prepare the SSL Config
+    info!("Initializing SSL...");
+    let ssl: SSLConfig = SSLConfig::new(&config.server_cert, &config.server_key, config.server_key_passphrase.as_ref(), &config.tls, verifier).map_err(|err| Error::SSLConfigError{ err })?;
+    expiry::check(&config, &expiry::host_certs(&config, shared.is_none() && ca.is_some()), Utc::now());
+
+    // Prepare the storage & package database
+    info!("Initializing storage...");
+    debug!("Data directory: '{}'", config.data_dir.display());
+    let storage: Storage = Storage::new(config.data_dir.join("blobs")).map_err(|err| Error::StorageInitError{ err })?;
+    let packages: Packages = Packages::new(config.data_dir.join("packages"), &database::database_path(&config.data_dir)).map_err(|err| Error::PackagesInitError{ err })?;
+    let transactions: Transactions = Transactions::new(config.data_dir.join("staging")).map_err(|err| Error::TransactionsInitError{ err })?;
+    let audit: AuditLog = AuditLog::open(config.data_dir.join("audit.log")).map_err(|err| Error::AuditInitError{ err })?;
+
+    // Collect everything in the state of the host
+    Ok(ServerState {
+        vhost,
+        config,
+        users,
+        ssl,
+        ca,
+        storage,
+        packages,
+        transactions,
+        audit,
+    })
+}
+
+/// Spawns the threads that do the background work of a host.
+///
+/// # Arguments
+/// - `state`: The state of the host.
+/// - `own_users`: Whether the host has its own users (and certificate authority), or shares those of the main host.
+///
+/// # Errors
+/// This function errors if any of the threads could not be spawned.
+fn spawn_workers(state: &Arc<ServerState>, own_users: bool) -> Result<(), Error> {
+    // Threads of virtual hosts are recognizable by their name
+    let suffix: String = state.vhost.as_ref().map(|name| format!("-{}", name)).unwrap_or_default();
+
+    // Discard publish transactions that have been abandoned
+    let reaper_state: Arc<ServerState> = state.clone();
+    thread::Builder::new().name(format!("reaper{}", suffix)).spawn(move || {
+        let timeout: Duration = Duration::from_secs(reaper_state.config.publish_timeout);
+        loop {
+            thread::sleep(REAPER_INTERVAL);
+            let n_reaped: usize = reaper_state.transactions.reap(timeout);
+            if n_reaped > 0 { debug!("Discarded {} timed out transaction(s)", n_reaped); }
+        }
+    }).map_err(|err| Error::ReaperSpawnError{ err })?;
+
+    // Check the integrity of the storage periodically, if told to
+    if let Some(interval) = state.config.fsck_interval {
+        let scrubber_state: Arc<ServerState> = state.clone();
+        thread::Builder::new().name(format!("scrubber{}", suffix)).spawn(move || {
+            let interval: Duration = Duration::from_secs(interval);
+            let quarantine: Option<PathBuf> = if scrubber_state.config.fsck_quarantine { Some(fsck::quarantine_dir(&scrubber_state.config.data_dir)) } else { None };
+            loop {
+                thread::sleep(interval);
+                debug!("Running scheduled storage check...");
+                let report: FsckReport = match fsck::scrub(&scrubber_state.storage, &scrubber_state.packages, &scrubber_state.transactions, quarantine.as_deref()) {
+                    Ok(report) => report,
+                    Err(err)   => { error!("{}", err); continue; },
+                };
+
+                // Record what we moved out of the storage
+                if quarantine.is_none() { continue; }
+                for blob in report.corrupt {
+                    let outcome: AuditOutcome = if blob.quarantined { AuditOutcome::Success } else { AuditOutcome::Failure{ code: ErrorCode::Internal, message: "Could not move corrupt blob to quarantine".into() } };
+                    scrubber_state.audit.record(SYSTEM_USER, None, AuditOperation::Quarantine, format!("blob {}", blob.hash), outcome);
+                }
+            }
+        }).map_err(|err| Error::ScrubberSpawnError{ err })?;
+    }
+
+    // Prune old package versions periodically, if any package has a retention policy
+    if state.config.retention.values().any(|p| p.prunes()) {
+        let retention_state: Arc<ServerState> = state.clone();
+        thread::Builder::new().name(format!("retention{}", suffix)).spawn(move || {
+            let interval: Duration = Duration::from_secs(retention_state.config.retention_interval);
+            loop {
+                thread::sleep(interval);
+                let n_removed: usize = retention::apply(&retention_state.packages, &retention_state.transactions, &retention_state.audit, &retention_state.config.retention);
+                if n_removed > 0 { info!("Retention policies removed {} version(s)", n_removed); }
+            }
+        }).map_err(|err| Error::RetentionSpawnError{ err })?;
+    }
+
+    // Keep warning about certificates that expire soon
+    let expiry_state: Arc<ServerState> = state.clone();
+    thread::Builder::new().name(format!("expiry{}", suffix)).spawn(move || {
+        loop {
+            thread::sleep(EXPIRY_INTERVAL);
+            let mut statuses: Vec<CertificateStatus> = expiry::host_certs(&expiry_state.config, own_users && expiry_state.ca.is_some());
+            if own_users {
+                match (expiry_state.users.list(), expiry_state.users.issued_certs(), expiry_state.users.revocations()) {
+                    (Ok(users), Ok(issued), Ok(revocations)) => statuses.extend(expiry::user_certs(&users, &issued, &revocations)),
+                    (Err(err), _, _) | (_, Err(err), _) | (_, _, Err(err)) => { error!("{}", err); },
+                }
+            }
+            expiry::check(&expiry_state.config, &statuses, Utc::now());
+        }
+    }).map_err(|err| Error::ExpirySpawnError{ err })?;
+
+    // Done
+    Ok(())
+}
+
+
+
 
 
 /***** CLI *****/
@@ -81,20 +258,26 @@ fn main() {
 
 
 
-    // Read the database file
-    info!("Loading users...");
-    debug!("User database: '{}'", config.user_db.display());
-    let users: Users = match Users::from_file(&config.user_db) {
-        Ok(users) => users,
-        Err(err)  => { error!("{}", Error::UsersParseError{ path: config.user_db, err }); std::process::exit(1); }
-    };
-
-    // Prepare the SSL Config
-    info!("Initializing SSL...");
-    let ssl_conf: SSLConfig = match SSLConfig::new(&config.server_cert, &config.server_key, &users) {
-        Ok(users) => users,
-        Err(err)  => { error!("{}", Error::SSLConfigError{ err }); std::process::exit(1); }  
+    // Load the main host, then the virtual hosts
+    let main: Arc<ServerState> = match load_host(config.clone(), None, None) {
+        Ok(state) => Arc::new(state),
+        Err(err)  => { error!("{}", err); std::process::exit(1); }
     };
+    let mut vhosts: HashMap<String, Arc<ServerState>> = HashMap::with_capacity(config.vhosts.len());
+    for (name, vhost) in &config.vhosts {
+        info!("Loading virtual host '{}'...", name);
+        if vhost.data_dir == config.data_dir || config.vhosts.iter().any(|(other, v)| other != name && v.data_dir == vhost.data_dir) {
+            error!("{}", Error::VhostInitError{ name: name.clone(), err: Box::new(Error::DataDirInUse{ path: vhost.data_dir.clone() }) });
+            std::process::exit(1);
+        }
+        let shared: Option<&ServerState> = if vhost.has_own_users() { None } else { Some(&main) };
+        match load_host(config.for_vhost(vhost), Some(name.clone()), shared) {
+            Ok(state) => { vhosts.insert(name.clone(), Arc::new(state)); },
+            Err(err)  => { error!("{}", Error::VhostInitError{ name: name.clone(), err: Box::new(err) }); std::process::exit(1); }
+        }
+    }
+    let limiter: Arc<Limiter> = Arc::new(Limiter::new(main.config.limits.clone()));
+    let hosts: Arc<Hosts> = Arc::new(Hosts{ main, vhosts, limiter });
 
 
 
@@ -124,7 +307,18 @@ fn main() {
     let ctl_socket: UnixListener = unsafe { UnixListener::from_raw_fd(ctl_fd) };
 
     // Next, open a stream around the network socket
-    /* TBD */
+    debug!("Binding network listener to '{}'...", config.listen_addr);
+    let tcp_listener: TcpListener = match TcpListener::bind(&config.listen_addr) {
+        Ok(listener) => listener,
+        Err(err)     => { error!("{}", Error::ListenerBindError{ addr: config.listen_addr, err }); std::process::exit(1); }
+    };
+    let tcp_fd: RawFd = tcp_listener.as_raw_fd();
+
+    // Start the background work of every host
+    for state in hosts.iter() {
+        let own_users: bool = state.vhost.as_ref().map(|name| config.vhosts[name].has_own_users()).unwrap_or(true);
+        if let Err(err) = spawn_workers(state, own_users) { error!("{}", err); std::process::exit(1); }
+    }
 
 
 
@@ -134,9 +328,10 @@ fn main() {
         // Collect the file descriptors in a set
         let mut readfds = FdSet::new();
         readfds.insert(ctl_fd);
+        readfds.insert(tcp_fd);
 
         // Create an error set for that set
-        let mut errorfds = readfds.clone();
+        let mut errorfds = readfds;
 
         // Switch on the first one to become available
         if let Err(err) = select(None, &mut readfds, None, &mut errorfds, None) {
@@ -158,6 +353,15 @@ fn main() {
                 error!("{}", Error::CtlSocketError{ fd, err: err.unwrap_or_else(|| panic!("No error found, but the file descriptor did awake on an error")) });
                 std::process::exit(1);
 
+            } else if fd == tcp_fd {
+                // Get the underlying error
+                match tcp_listener.take_error() {
+                    Ok(Some(err)) => { error!("{}", Error::StreamAcceptError{ what: "network", err }); },
+                    Ok(None)      => { error!("{}", Error::FdError{ what: "Network", fd }); },
+                    Err(err)      => { error!("Could not get network socket error: {}", err); },
+                }
+                std::process::exit(1);
+
             } else {
                 warn!("Unknown file descriptor '{}' reports an error; ignoring", fd);
             }
@@ -165,49 +369,51 @@ fn main() {
 
         // Iterate through the triggeted fds which got new data available
         for fd in readfds.fds(None) {
-            // Determine the user for this session
-            let user: &User = if fd == ctl_fd {
-                // The user is the root user
-                users.users.get(&ROOT_ID).expect("No Root user in users database; this should never happen!")
-            } else {
+            // Client connections are handled in their own thread
+            if fd == tcp_fd {
+                // Accept the connection
+                debug!("Accepting new client connection...");
+                let (stream, address) = match tcp_listener.accept() {
+                    Ok(res)  => res,
+                    Err(err) => { error!("{}", Error::StreamAcceptError{ what: "network", err }); continue; }
+                };
+                if !hosts.limiter.admit(address.ip()) { continue; }
+
+                // Hand it to a new thread
+                let hosts: Arc<Hosts> = hosts.clone();
+                if let Err(err) = thread::Builder::new().name(format!("client-{}", address)).spawn(move || {
+                    if let Err(err) = client::handle(stream, address, hosts) { error!("Client '{}': {}", address, err); }
+                }) {
+                    error!("{}", Error::ThreadSpawnError{ err });
+                }
+                continue;
+            }
+
+            if fd != ctl_fd {
                 warn!("Unknown file descriptor '{}' is ready for reading; ignoring", fd);
                 continue;
-            };
+            }
 
             // Accept the connection
             debug!("Accepting new connection...");
-            let (mut stream, address) = match ctl_socket.accept() {
+            let (stream, address) = match ctl_socket.accept() {
                 Ok(res)  => res,
                 Err(err) => { error!("{}", Error::StreamAcceptError{ what: "CTL", err }); continue; }
             };
 
-            // Wrap in an SSL tunnel
-            /* TBD */
-            debug!("Established connection with '{:?}'", address);
-
-            // Read the first opcode
-            let mut opcode: [u8; 1] = [ 0 ];
-            let msg_len: usize = match stream.read(&mut opcode) {
-                Ok(msg_len) => msg_len,
-                Err(err)    => { error!("{}", Error::StreamReadError{ what: "CTL", err }); continue; },
-            };
-            if msg_len == 0 { error!("{}", Error::EmptyStream{ what: "CTL" }); continue; }
-
-            // Cast the opcode
-            let opcode = match Opcode::try_from(opcode[0]) {
-                Ok(opcode) => opcode,
-                Err(err)   => { error!("{}", err); continue; }  
+            // Determine who is on the other side. Peers that may not use the socket still get to send their request, so that they can read why it is refused.
+            let peer: Peer = match peers::identify(&stream, &hosts.main.config.ctl_access) {
+                Ok(peer) => peer,
+                Err(err) => { error!("{}", err); continue; },
             };
-
-            // Switch on the opcode
-            match opcode {
-                Opcode::Health => {
-                    // Send the agreed upon constant back
-                    if let Err(err) = stream.write(&HEALTH_REPLY) { error!("{}", Error::StreamWriteError{ what: "CTL", err }); continue; }
-
-                    // That's it for health
-                    debug!("Handled Health status update");
-                },
+            debug!("Established connection with '{:?}' as {}", address, peer);
+
+            // Hand it to a new thread, so that a peer that is slow to send its request (or never sends one) does not block us; some requests (e.g., garbage collection) take a while too
+            let hosts: Arc<Hosts> = hosts.clone();
+            if let Err(err) = thread::Builder::new().name("ctl".into()).spawn(move || {
+                if let Err(err) = ctl::handle(stream, &hosts, &peer) { error!("{}", err); }
+            }) {
+                error!("{}", Error::CtlSpawnError{ err });
             }
 
             // Done
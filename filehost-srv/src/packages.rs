@@ -0,0 +1,528 @@
+/* PACKAGES.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:26:37
+ * Last edited:
+ *   19 Oct 2026, 07:29:38
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the package database, which keeps track of the packages,
+ *   their versions and the manifests of those versions, and the channels
+ *   that point to them.
+**/
+
+use std::collections::HashSet;
+use std::fs::{self, File, Permissions};
+use std::io::{BufReader, BufWriter, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use chrono::{SubsecRound, Utc};
+use log::{debug, info, warn};
+use serde::Serialize;
+
+use filehost_spc::channel::{Channel, ChannelMove};
+use filehost_spc::ctl_messages::IndexReport;
+use filehost_spc::login::UserId;
+use filehost_spc::manifest::{is_valid_name, BlobHash, Manifest};
+use filehost_spc::quota::Usage;
+
+pub use crate::errors::PackageError as Error;
+use crate::index::Index;
+
+
+/***** CONSTANTS *****/
+/// The file mode of committed manifests, which are never changed again.
+const MANIFEST_MODE: u32 = 0o444;
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Writes the given value as JSON to a temporary file next to the given path, and then moves it in place.
+///
+/// This way, readers never see a half-written file.
+///
+/// # Arguments
+/// - `path`: The path to write to.
+/// - `value`: The value to write.
+/// - `mode`: If given, the file mode to give the file before it's moved in place.
+///
+/// # Errors
+/// This function errors if we could not write, serialize or move the file.
+fn write_atomic<T: Serialize>(path: &Path, value: &T, mode: Option<u32>) -> Result<(), Error> {
+    let dir: &Path = path.parent().expect("Database path has no parent; this should never happen!");
+    if let Err(err) = fs::create_dir_all(dir) { return Err(Error::DirCreateError{ path: dir.into(), err }); }
+
+    // Write it to a temporary file
+    let temp_path: PathBuf = dir.join(format!(".{}.tmp", path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()));
+    let mut handle = match File::create(&temp_path) {
+        Ok(handle) => BufWriter::new(handle),
+        Err(err)   => { return Err(Error::FileWriteError{ path: temp_path, err }); }
+    };
+    if let Err(err) = serde_json::to_writer_pretty(&mut handle, value) { return Err(Error::FileSerializeError{ path: temp_path, err }); }
+    if let Err(err) = handle.flush() { return Err(Error::FileWriteError{ path: temp_path, err }); }
+    drop(handle);
+    if let Some(mode) = mode {
+        if let Err(err) = fs::set_permissions(&temp_path, Permissions::from_mode(mode)) { return Err(Error::FileWriteError{ path: temp_path, err }); }
+    }
+
+    // Move it in place
+    if let Err(err) = fs::rename(&temp_path, path) { return Err(Error::FileWriteError{ path: path.into(), err }); }
+    Ok(())
+}
+
+/// Returns the names of the entries in the given directory that are valid package, version or channel names.
+///
+/// # Arguments
+/// - `dir`: The directory to read.
+/// - `extension`: If given, only considers files with this extension, and strips it from the names.
+///
+/// # Errors
+/// This function errors if we failed to read the directory.
+fn read_names(dir: &Path, extension: Option<&str>) -> Result<Vec<String>, Error> {
+    let iter = match fs::read_dir(dir) {
+        Ok(iter) => iter,
+        Err(err) => { return Err(Error::DirReadError{ path: dir.into(), err }); }
+    };
+    let mut names: Vec<String> = Vec::new();
+    for entry in iter {
+        let path: PathBuf = match entry {
+            Ok(entry) => entry.path(),
+            Err(err)  => { return Err(Error::DirReadError{ path: dir.into(), err }); }
+        };
+        let name: Option<&str> = match extension {
+            Some(extension) => if path.extension().map(|e| e == extension).unwrap_or(false) { path.file_stem().and_then(|n| n.to_str()) } else { None },
+            None            => path.file_name().and_then(|n| n.to_str()),
+        };
+        if let Some(name) = name {
+            if is_valid_name(name) { names.push(name.into()); }
+        }
+    }
+    Ok(names)
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// The package database, which stores the manifest of every version of every package on disk, together with the channels of every package.
+///
+/// The layout is `<root>/<package>/versions/<version>.json` and `<root>/<package>/channels/<channel>.json`. Versions and channels of the same package share a namespace, so a name always resolves unambiguously.
+///
+/// Versions are immutable once they are added; only channels may change. Versions may be removed as a whole, as long as no channel points to them.
+///
+/// Everything is mirrored in a metadata index, which answers the questions that would otherwise require reading every manifest.
+#[derive(Debug)]
+pub struct Packages {
+    /// The root directory of the package database.
+    root         : PathBuf,
+    /// The metadata index of the package database.
+    index        : Index,
+    /// Serializes changes to channels and the versions they may point to, so no move gets lost and no name is used by both.
+    channel_lock : Mutex<()>,
+}
+
+impl Packages {
+    /// Constructor for the Packages, which makes sure its root directory exists and opens its metadata index.
+    ///
+    /// If the index was never built, it is built from the package database.
+    ///
+    /// # Arguments
+    /// - `root`: The directory to store the package manifests in.
+    /// - `database`: The path of the metadata database that holds the index.
+    ///
+    /// # Errors
+    /// This function errors if the given directory did not exist and could not be created, or if the index could not be opened or built.
+    pub fn new<P: Into<PathBuf>>(root: P, database: &Path) -> Result<Self, Error> {
+        let root: PathBuf = root.into();
+        if let Err(err) = fs::create_dir_all(&root) { return Err(Error::DirCreateError{ path: root, err }); }
+        let index: Index = Index::open(database).map_err(|err| Error::IndexError{ err })?;
+
+        let res = Self {
+            root,
+            index,
+            channel_lock : Mutex::new(()),
+        };
+        if !res.index.is_built().map_err(|err| Error::IndexError{ err })? {
+            let report: IndexReport = res.rebuild_index()?;
+            info!("Built metadata index of {} package(s), {} version(s) and {} channel(s)", report.packages, report.versions, report.channels);
+        }
+        Ok(res)
+    }
+
+
+
+    /// Returns the directory of the given package.
+    ///
+    /// # Errors
+    /// This function errors if the package name is illegal.
+    pub fn package_dir(&self, package: &str) -> Result<PathBuf, Error> {
+        if !is_valid_name(package) { return Err(Error::IllegalName{ name: package.into() }); }
+        Ok(self.root.join(package))
+    }
+
+    /// Returns the path of the manifest file of the given package version.
+    ///
+    /// # Errors
+    /// This function errors if the package or version name is illegal.
+    pub fn manifest_path(&self, package: &str, version: &str) -> Result<PathBuf, Error> {
+        if !is_valid_name(version) { return Err(Error::IllegalName{ name: version.into() }); }
+        Ok(self.package_dir(package)?.join("versions").join(format!("{}.json", version)))
+    }
+
+    /// Returns the path of the file of the given channel.
+    ///
+    /// # Errors
+    /// This function errors if the package or channel name is illegal.
+    pub fn channel_path(&self, package: &str, channel: &str) -> Result<PathBuf, Error> {
+        if !is_valid_name(channel) { return Err(Error::IllegalName{ name: channel.into() }); }
+        Ok(self.package_dir(package)?.join("channels").join(format!("{}.json", channel)))
+    }
+
+
+
+    /// Reads the manifest of the given package version.
+    ///
+    /// # Arguments
+    /// - `package`: The name of the package.
+    /// - `version`: The version of the package.
+    ///
+    /// # Returns
+    /// The parsed Manifest of that version.
+    ///
+    /// # Errors
+    /// This function errors if the package or version does not exist, or if the manifest could not be read.
+    pub fn manifest(&self, package: &str, version: &str) -> Result<Manifest, Error> {
+        // Make sure the package exists
+        let package_dir: PathBuf = self.package_dir(package)?;
+        if !package_dir.is_dir() { return Err(Error::UnknownPackage{ package: package.into() }); }
+
+        // Open the manifest
+        let path: PathBuf = self.manifest_path(package, version)?;
+        let handle = match File::open(&path) {
+            Ok(handle) => BufReader::new(handle),
+            Err(err)   => {
+                if err.kind() == std::io::ErrorKind::NotFound { return Err(Error::UnknownVersion{ package: package.into(), version: version.into() }); }
+                return Err(Error::ManifestOpenError{ path, err });
+            },
+        };
+
+        // Parse it
+        match serde_json::from_reader(handle) {
+            Ok(manifest) => Ok(manifest),
+            Err(err)     => Err(Error::ManifestParseError{ path, err }),
+        }
+    }
+
+    /// Returns whether the given package version exists.
+    ///
+    /// # Errors
+    /// This function errors if the package or version name is illegal.
+    #[inline]
+    pub fn exists(&self, package: &str, version: &str) -> Result<bool, Error> {
+        Ok(self.manifest_path(package, version)?.is_file())
+    }
+
+    /// Returns when the given package version was published.
+    ///
+    /// # Errors
+    /// This function errors if the version does not exist or we failed to query the index.
+    pub fn published(&self, package: &str, version: &str) -> Result<SystemTime, Error> {
+        match self.index.published(package, version) {
+            Ok(Some(time)) => Ok(time),
+            Ok(None)       => Err(Error::UnknownVersion{ package: package.into(), version: version.into() }),
+            Err(err)       => Err(Error::IndexError{ err }),
+        }
+    }
+
+
+
+    /// Returns whether the given name is a channel of the given package.
+    ///
+    /// # Errors
+    /// This function errors if the package or channel name is illegal.
+    #[inline]
+    pub fn is_channel(&self, package: &str, name: &str) -> Result<bool, Error> {
+        Ok(self.channel_path(package, name)?.is_file())
+    }
+
+
+
+    /// Returns the names of all packages, sorted.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    #[inline]
+    pub fn packages(&self) -> Result<Vec<String>, Error> {
+        self.index.packages().map_err(|err| Error::IndexError{ err })
+    }
+
+    /// Returns the names of all versions of the given package, sorted.
+    ///
+    /// # Errors
+    /// This function errors if the package does not exist or we failed to query the index.
+    pub fn versions(&self, package: &str) -> Result<Vec<String>, Error> {
+        if !self.index.has_package(package).map_err(|err| Error::IndexError{ err })? { return Err(Error::UnknownPackage{ package: package.into() }); }
+        self.index.versions(package).map_err(|err| Error::IndexError{ err })
+    }
+
+    /// Computes how much the versions of a package or a user take up.
+    ///
+    /// # Arguments
+    /// - `package`: If given, only counts the versions of this package.
+    /// - `publisher`: If given, only counts the versions that this user published.
+    ///
+    /// # Returns
+    /// The number of matching versions and the total size of their files. Unknown packages take up nothing.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    #[inline]
+    pub fn usage(&self, package: Option<&str>, publisher: Option<UserId>) -> Result<Usage, Error> {
+        self.index.usage(package, publisher).map_err(|err| Error::IndexError{ err })
+    }
+
+    /// Returns the names of all channels of the given package, sorted.
+    ///
+    /// # Errors
+    /// This function errors if the package does not exist or we failed to query the index.
+    pub fn channels(&self, package: &str) -> Result<Vec<String>, Error> {
+        if !self.index.has_package(package).map_err(|err| Error::IndexError{ err })? { return Err(Error::UnknownPackage{ package: package.into() }); }
+        self.index.channels(package).map_err(|err| Error::IndexError{ err })
+    }
+
+    /// Returns the packages that have a version that refers to the given blob.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    #[inline]
+    pub fn packages_with_blob(&self, hash: &BlobHash) -> Result<Vec<String>, Error> {
+        self.index.packages_with_blob(hash).map_err(|err| Error::IndexError{ err })
+    }
+
+    /// Returns the blobs that any package version refers to.
+    ///
+    /// # Errors
+    /// This function errors if we failed to query the index.
+    #[inline]
+    pub fn referenced_blobs(&self) -> Result<HashSet<BlobHash>, Error> {
+        self.index.referenced_blobs().map_err(|err| Error::IndexError{ err })
+    }
+
+
+
+    /// Adds a new package version by writing its manifest, which makes the version visible.
+    ///
+    /// The manifest is written read-only and is never touched again, which makes the version immutable.
+    ///
+    /// # Arguments
+    /// - `manifest`: The Manifest of the new version.
+    ///
+    /// # Errors
+    /// This function errors if the version already exists, if its name is taken by a channel or if we failed to write the manifest.
+    pub fn insert(&self, manifest: &Manifest) -> Result<(), Error> {
+        let _lock = match self.channel_lock.lock() {
+            Ok(lock) => lock,
+            Err(err) => err.into_inner(),
+        };
+
+        // Refuse to overwrite anything
+        let path: PathBuf = self.manifest_path(&manifest.package, &manifest.version)?;
+        if path.exists() { return Err(Error::VersionExists{ package: manifest.package.clone(), version: manifest.version.clone() }); }
+        if self.is_channel(&manifest.package, &manifest.version)? { return Err(Error::NameTaken{ package: manifest.package.clone(), name: manifest.version.clone() }); }
+
+        // Index it first, so the index never misses a version (and the blobs it refers to)
+        let published: SystemTime = SystemTime::now();
+        if let Err(err) = self.index.remove_version(&manifest.package, &manifest.version) { return Err(Error::IndexError{ err }); }
+        if let Err(err) = self.index.insert_version(manifest, published) { return Err(Error::IndexError{ err }); }
+
+        // Write it
+        if let Err(err) = write_atomic(&path, manifest, Some(MANIFEST_MODE)) {
+            if let Err(err) = self.index.remove_version(&manifest.package, &manifest.version) { warn!("{}", err); }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Removes a package version by removing its manifest.
+    ///
+    /// The blobs of the version are left alone; the garbage collector removes them once nothing refers to them anymore.
+    ///
+    /// # Arguments
+    /// - `package`: The name of the package.
+    /// - `version`: The version to remove.
+    ///
+    /// # Errors
+    /// This function errors if the version does not exist, if a channel points to it or if we failed to remove its manifest.
+    pub fn remove(&self, package: &str, version: &str) -> Result<(), Error> {
+        let _lock = match self.channel_lock.lock() {
+            Ok(lock) => lock,
+            Err(err) => err.into_inner(),
+        };
+
+        // Refuse to leave a channel dangling
+        for channel in self.channels(package)? {
+            if self.channel(package, &channel)?.version == version { return Err(Error::VersionInUse{ version: version.into(), channel }); }
+        }
+
+        // Remove it, and only then from the index, so the index never misses a version (and the blobs it refers to)
+        let path: PathBuf = self.manifest_path(package, version)?;
+        if let Err(err) = fs::remove_file(&path) {
+            if err.kind() == std::io::ErrorKind::NotFound { return Err(Error::UnknownVersion{ package: package.into(), version: version.into() }); }
+            return Err(Error::FileRemoveError{ path, err });
+        }
+        self.index.remove_version(package, version).map_err(|err| Error::IndexError{ err })
+    }
+
+
+
+    /// Reads the given channel.
+    ///
+    /// # Arguments
+    /// - `package`: The name of the package.
+    /// - `channel`: The name of the channel.
+    ///
+    /// # Returns
+    /// The Channel, including its history.
+    ///
+    /// # Errors
+    /// This function errors if the channel does not exist or could not be read.
+    pub fn channel(&self, package: &str, channel: &str) -> Result<Channel, Error> {
+        // Open the file
+        let path: PathBuf = self.channel_path(package, channel)?;
+        let handle = match File::open(&path) {
+            Ok(handle) => BufReader::new(handle),
+            Err(err)   => {
+                if err.kind() == std::io::ErrorKind::NotFound { return Err(Error::UnknownChannel{ package: package.into(), channel: channel.into() }); }
+                return Err(Error::ChannelOpenError{ path, err });
+            },
+        };
+
+        // Parse it
+        match serde_json::from_reader(handle) {
+            Ok(channel) => Ok(channel),
+            Err(err)    => Err(Error::ChannelParseError{ path, err }),
+        }
+    }
+
+    /// Points the given channel to the given version, creating the channel if it does not exist yet.
+    ///
+    /// # Arguments
+    /// - `package`: The name of the package.
+    /// - `channel`: The name of the channel.
+    /// - `version`: The version to point the channel to.
+    /// - `by`: The name of the user that moves the channel, which is recorded in its history.
+    ///
+    /// # Returns
+    /// The Channel after the move.
+    ///
+    /// # Errors
+    /// This function errors if the version does not exist, if the channel name is taken by a version or if we failed to read or write the channel.
+    pub fn set_channel(&self, package: &str, channel: &str, version: &str, by: &str) -> Result<Channel, Error> {
+        let _lock = match self.channel_lock.lock() {
+            Ok(lock) => lock,
+            Err(err) => err.into_inner(),
+        };
+
+        // Check the names
+        if !self.exists(package, version)? { return Err(Error::UnknownVersion{ package: package.into(), version: version.into() }); }
+        if self.exists(package, channel)? { return Err(Error::NameTaken{ package: package.into(), name: channel.into() }); }
+
+        // Get the current state of the channel, if any
+        let mut state: Channel = match self.channel(package, channel) {
+            Ok(state)                        => state,
+            Err(Error::UnknownChannel{ .. }) => Channel{ package: package.into(), name: channel.into(), version: version.into(), history: vec![] },
+            Err(err)                         => { return Err(err); }
+        };
+
+        // Move it
+        let from: Option<String> = if state.history.is_empty() { None } else { Some(state.version.clone()) };
+        state.version = version.into();
+        state.history.push(ChannelMove{ from, to: version.into(), by: by.into(), time: Utc::now().trunc_subsecs(0) });
+        if let Err(err) = self.index.set_channel(package, channel, version) { return Err(Error::IndexError{ err }); }
+        write_atomic(&self.channel_path(package, channel)?, &state, None)?;
+        Ok(state)
+    }
+
+    /// Resolves the given name to a version of the given package.
+    ///
+    /// # Arguments
+    /// - `package`: The name of the package.
+    /// - `name`: The name of either a version or a channel.
+    ///
+    /// # Returns
+    /// The version the name refers to, and the name of the channel it was resolved through (if any).
+    ///
+    /// # Errors
+    /// This function errors if the name is neither a version nor a channel, or if the channel could not be read.
+    pub fn resolve(&self, package: &str, name: &str) -> Result<(String, Option<String>), Error> {
+        // Make sure the package exists
+        let package_dir: PathBuf = self.package_dir(package)?;
+        if !package_dir.is_dir() { return Err(Error::UnknownPackage{ package: package.into() }); }
+
+        // Try versions first, then channels
+        if self.exists(package, name)? { return Ok((name.into(), None)); }
+        match self.channel(package, name) {
+            Ok(channel)                      => Ok((channel.version, Some(channel.name))),
+            Err(Error::UnknownChannel{ .. }) => Err(Error::UnknownVersion{ package: package.into(), version: name.into() }),
+            Err(err)                         => Err(err),
+        }
+    }
+
+
+    /// Rebuilds the metadata index from the package database, replacing whatever was in it.
+    ///
+    /// Channels are not moved while we rebuild. The caller should make sure that no versions are added or removed either.
+    ///
+    /// # Returns
+    /// An IndexReport that describes what was indexed.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the package database or to update the index. In that case, the index is left as it was.
+    pub fn rebuild_index(&self) -> Result<IndexReport, Error> {
+        let _lock = match self.channel_lock.lock() {
+            Ok(lock) => lock,
+            Err(err) => err.into_inner(),
+        };
+
+        // Read everything from disk
+        let packages: Vec<String> = read_names(&self.root, None)?;
+        let mut versions: Vec<(Manifest, SystemTime)> = Vec::new();
+        let mut channels: Vec<(String, String, String)> = Vec::new();
+        for package in &packages {
+            let package_dir: PathBuf = self.package_dir(package)?;
+            let versions_dir: PathBuf = package_dir.join("versions");
+            if versions_dir.is_dir() {
+                for version in read_names(&versions_dir, Some("json"))? {
+                    let path: PathBuf = self.manifest_path(package, &version)?;
+                    let published: SystemTime = match fs::metadata(&path).and_then(|m| m.modified()) {
+                        Ok(published) => published,
+                        Err(err)      => { return Err(Error::ManifestMetadataError{ path, err }); }
+                    };
+                    versions.push((self.manifest(package, &version)?, published));
+                }
+            }
+            let channels_dir: PathBuf = package_dir.join("channels");
+            if channels_dir.is_dir() {
+                for channel in read_names(&channels_dir, Some("json"))? {
+                    let version: String = self.channel(package, &channel)?.version;
+                    channels.push((package.clone(), channel, version));
+                }
+            }
+        }
+
+        // Replace the index with it
+        if let Err(err) = self.index.replace(&packages, &versions, &channels) { return Err(Error::IndexError{ err }); }
+        let blobs: usize = self.referenced_blobs()?.len();
+        debug!("Rebuilt metadata index from '{}'", self.root.display());
+        Ok(IndexReport{ packages: packages.len(), versions: versions.len(), channels: channels.len(), blobs })
+    }
+}
@@ -0,0 +1,128 @@
+/* PEERS.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 06:26:20
+ * Last edited:
+ *   19 Oct 2026, 07:06:48
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Decides which local users may use the CTL socket, and as whom, based
+ *   on the credentials of their connection (`SO_PEERCRED`).
+**/
+
+use std::ffi::CString;
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+use log::{debug, warn};
+use nix::sys::socket::{getsockopt, sockopt, UnixCredentials};
+use nix::unistd::{self, Gid, Group, Uid, User};
+
+use filehost_spc::config::CtlAccess;
+
+pub use crate::errors::PeerError as Error;
+
+
+/***** HELPER FUNCTIONS *****/
+/// Checks whether the given local user is a member of any of the given groups.
+///
+/// # Arguments
+/// - `user`: The local user.
+/// - `gid`: The group the user is connected with, which need not be one of its own.
+/// - `groups`: The names of the groups to look for.
+///
+/// # Errors
+/// This function errors if we failed to look up the groups.
+fn in_any_group(user: &User, gid: Gid, groups: &[String]) -> Result<bool, Error> {
+    if groups.is_empty() { return Ok(false); }
+
+    // Collect the groups the user is in
+    let name: CString = CString::new(user.name.as_str()).map_err(|_| Error::GroupsLookupError{ name: user.name.clone(), err: nix::Error::EINVAL })?;
+    let mut gids: Vec<Gid> = unistd::getgrouplist(&name, user.gid).map_err(|err| Error::GroupsLookupError{ name: user.name.clone(), err })?;
+    gids.push(gid);
+
+    // Compare them to the ones we look for
+    for group in groups {
+        match Group::from_name(group) {
+            Ok(Some(group)) => { if gids.contains(&group.gid) { return Ok(true); } },
+            Ok(None)        => { warn!("Unknown local group '{}' in CTL access configuration", group); },
+            Err(err)        => { return Err(Error::GroupLookupError{ name: group.clone(), err }); },
+        }
+    }
+    Ok(false)
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Defines as whom a local user uses the CTL socket.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Peer {
+    /// The local user manages the daemon as the root user.
+    Root,
+    /// The local user acts as the user with the given name.
+    User(String),
+    /// The local user with the given name (or UID, if it has none) may not use the socket at all.
+    Refused(String),
+}
+
+impl Peer {
+    /// Returns the name under which the requests of the peer are recorded in the audit log.
+    pub fn audit_name(&self) -> String {
+        match self {
+            Peer::Root           => "root".into(),
+            Peer::User(username) => username.clone(),
+            Peer::Refused(local) => format!("local:{}", local),
+        }
+    }
+}
+
+impl Display for Peer {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        match self {
+            Peer::Root           => write!(f, "the root user"),
+            Peer::User(username) => write!(f, "user '{}'", username),
+            Peer::Refused(local) => write!(f, "local user '{}', who may not use the CTL socket", local),
+        }
+    }
+}
+
+
+
+/// Determines as whom the local user on the other side of the CTL socket may use it.
+///
+/// # Arguments
+/// - `stream`: The (freshly accepted) stream to the peer.
+/// - `access`: Which local users may use the CTL socket, and as whom.
+///
+/// # Returns
+/// As whom the peer may use the socket, which is `Peer::Refused` if it may not use it at all.
+///
+/// # Errors
+/// This function errors if we failed to get the credentials of the peer or to look up its user or groups.
+pub fn identify(stream: &UnixStream, access: &CtlAccess) -> Result<Peer, Error> {
+    let creds: UnixCredentials = getsockopt(stream.as_raw_fd(), sockopt::PeerCredentials).map_err(|err| Error::CredentialsError{ err })?;
+    let user: User = match User::from_uid(Uid::from_raw(creds.uid())) {
+        Ok(Some(user)) => user,
+        Ok(None)       => { warn!("Unknown local user {} (PID {}) may not use the CTL socket", creds.uid(), creds.pid()); return Ok(Peer::Refused(creds.uid().to_string())); },
+        Err(err)       => { return Err(Error::UserLookupError{ uid: creds.uid(), err }); },
+    };
+    debug!("CTL peer (PID {}) is local user '{}' ({})", creds.pid(), user.name, creds.uid());
+
+    // Root users come first, then users that map to an ordinary user
+    if access.root_users.contains(&user.name) || in_any_group(&user, Gid::from_raw(creds.gid()), &access.root_groups)? { return Ok(Peer::Root); }
+    match access.users.get(&user.name) {
+        Some(username) => Ok(Peer::User(username.clone())),
+        None           => {
+            warn!("Local user '{}' ({}) may not use the CTL socket", user.name, creds.uid());
+            Ok(Peer::Refused(user.name))
+        },
+    }
+}
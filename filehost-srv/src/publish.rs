@@ -0,0 +1,917 @@
+/* PUBLISH.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:37:44
+ * Last edited:
+ *   19 Oct 2026, 07:29:14
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements publish transactions, which stage the blobs of a new
+ *   package version until it is committed (and only then made visible)
+ *   or aborted.
+**/
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use filehost_spc::client_messages::NeededBlob;
+use filehost_spc::login::UserId;
+use filehost_spc::manifest::{is_valid_entry_path, is_valid_name, BlobHash, HashError, Manifest};
+use filehost_spc::quota::{Quota, Usage};
+
+pub use crate::errors::PublishError as Error;
+use crate::errors::PackageError;
+use crate::packages::Packages;
+use crate::storage::Storage;
+
+
+/***** CONSTANTS *****/
+/// The name of the file in a staging directory that describes the transaction.
+const TRANSACTION_FILE: &str = "transaction.json";
+
+/// The extension of staged blobs that are still being uploaded.
+const PARTIAL_EXTENSION: &str = "part";
+
+
+
+
+
+/***** HELPER STRUCTS *****/
+/// The part of a transaction that is persisted in its staging directory, so it survives a restart of the server.
+#[derive(Deserialize, Serialize)]
+struct TransactionFile {
+    /// The identifier of the transaction.
+    id       : String,
+    /// The user that opened the transaction.
+    user     : UserId,
+    /// The manifest of the version that is being published.
+    manifest : Manifest,
+}
+
+/// An entry in the list of open transactions, which caches what we need to know about a transaction without locking it.
+#[derive(Debug)]
+struct OpenTransaction {
+    /// The user that opened the transaction.
+    user        : UserId,
+    /// The package that is being published.
+    package     : String,
+    /// The version that is being published.
+    version     : String,
+    /// The total size of the files in the version that is being published.
+    size        : u64,
+    /// The blobs that the transaction refers to.
+    blobs       : HashSet<BlobHash>,
+    /// The transaction itself.
+    transaction : Arc<Mutex<Transaction>>,
+}
+
+impl OpenTransaction {
+    /// Constructor for the OpenTransaction.
+    #[inline]
+    fn new(transaction: Transaction) -> Self {
+        Self {
+            user        : transaction.user,
+            package     : transaction.manifest.package.clone(),
+            version     : transaction.manifest.version.clone(),
+            size        : transaction.manifest.total_size(),
+            blobs       : transaction.manifest.entries.iter().flat_map(|e| e.blobs()).map(|(hash, _)| hash).collect(),
+            transaction : Arc::new(Mutex::new(transaction)),
+        }
+    }
+}
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Locks the given mutex, ignoring whether another thread panicked while holding it.
+#[inline]
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(err)  => err.into_inner(),
+    }
+}
+
+/// Locks the given transaction, making sure it is still open and belongs to the given user, and marks it as being used.
+///
+/// # Errors
+/// This function errors if the transaction is closed or belongs to another user.
+fn lock_open(transaction: &Mutex<Transaction>, user: UserId) -> Result<MutexGuard<'_, Transaction>, Error> {
+    let mut guard: MutexGuard<Transaction> = lock(transaction);
+    if guard.closed || guard.user != user { return Err(Error::UnknownTransaction{ id: guard.id.clone() }); }
+    guard.touch();
+    Ok(guard)
+}
+
+/// Computes how much the open transactions that match the given filter would take up once committed.
+#[inline]
+fn pending(open: &HashMap<String, OpenTransaction>, filter: impl Fn(&OpenTransaction) -> bool) -> Usage {
+    let mut usage: Usage = Usage::default();
+    for transaction in open.values().filter(|t| filter(t)) { usage += Usage{ bytes: transaction.size, versions: 1 }; }
+    usage
+}
+
+/// Generates a new, random transaction identifier.
+#[inline]
+fn generate_id() -> String {
+    let mut id: [u8; 16] = [ 0; 16 ];
+    rand::thread_rng().fill_bytes(&mut id);
+    hex::encode(id)
+}
+
+/// Returns the size of the given file, or 0 if it does not exist.
+///
+/// # Errors
+/// This function errors if the file exists but its metadata could not be read.
+fn file_size(path: &Path) -> Result<u64, Error> {
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(err)     => if err.kind() == std::io::ErrorKind::NotFound {
+            Ok(0)
+        } else {
+            Err(Error::BlobOpenError{ path: path.into(), err })
+        },
+    }
+}
+
+/// Checks that the given manifest may be published.
+///
+/// # Errors
+/// This function errors if any name or path in the manifest is illegal, a path occurs twice, the chunks of a file do not add up to its size, the same blob occurs with different sizes or the manifest has a signature that does not match it.
+fn validate_manifest(manifest: &Manifest) -> Result<(), Error> {
+    if !is_valid_name(&manifest.package) { return Err(Error::IllegalName{ name: manifest.package.clone() }); }
+    if !is_valid_name(&manifest.version) { return Err(Error::IllegalName{ name: manifest.version.clone() }); }
+
+    let mut paths: HashSet<&str> = HashSet::with_capacity(manifest.entries.len());
+    let mut sizes: HashMap<BlobHash, u64> = HashMap::with_capacity(manifest.entries.len());
+    for entry in &manifest.entries {
+        if !is_valid_entry_path(&entry.path) { return Err(Error::IllegalEntryPath{ path: entry.path.clone() }); }
+        if !paths.insert(&entry.path) { return Err(Error::DuplicateEntryPath{ path: entry.path.clone() }); }
+        if entry.is_chunked() {
+            let total: u64 = entry.chunks.iter().map(|c| c.size).sum();
+            if total != entry.size { return Err(Error::ChunkSizeMismatch{ path: entry.path.clone(), got: total, expected: entry.size }); }
+        }
+        for (hash, size) in entry.blobs() {
+            if *sizes.entry(hash).or_insert(size) != size { return Err(Error::InconsistentBlobSize{ hash }); }
+        }
+    }
+
+    // We don't decide whom to trust, but we won't store signatures that are bogus anyway
+    if let Err(err) = manifest.verify_signature() { return Err(Error::InvalidSignature{ err }); }
+    Ok(())
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// A single publish transaction, which stages the blobs of a new package version.
+///
+/// The blobs of a transaction are those of every file in its manifest, which for chunked files are their chunks. Blobs are uploaded to `<dir>/blobs/<hash>.part` and moved to `<dir>/blobs/<hash>` once they are complete and verified.
+#[derive(Debug)]
+pub struct Transaction {
+    /// The identifier of the transaction.
+    pub id       : String,
+    /// The user that opened the transaction. Only they may use it.
+    pub user     : UserId,
+    /// The manifest of the version that is being published.
+    pub manifest : Manifest,
+
+    /// The staging directory of the transaction.
+    dir         : PathBuf,
+    /// The last time the transaction was used.
+    last_active : Instant,
+    /// Whether the transaction was committed or aborted already.
+    closed      : bool,
+    /// The blobs that are being uploaded right now.
+    uploading   : HashSet<BlobHash>,
+}
+
+impl Transaction {
+    /// Returns the path of the staged file of the given blob once it is complete.
+    #[inline]
+    pub fn blob_path(&self, hash: &BlobHash) -> PathBuf { self.dir.join("blobs").join(hash.to_string()) }
+
+    /// Returns the path of the staged file of the given blob while it is being uploaded.
+    #[inline]
+    pub fn part_path(&self, hash: &BlobHash) -> PathBuf { self.dir.join("blobs").join(format!("{}.{}", hash, PARTIAL_EXTENSION)) }
+
+    /// Returns the size of the given blob according to the manifest, or None if it is not part of this transaction.
+    #[inline]
+    pub fn blob_size(&self, hash: &BlobHash) -> Option<u64> {
+        self.manifest.entries.iter().flat_map(|e| e.blobs()).find(|(h, _)| h == hash).map(|(_, size)| size)
+    }
+
+    /// Marks the transaction as being used right now, which keeps it from timing out.
+    #[inline]
+    pub fn touch(&mut self) { self.last_active = Instant::now(); }
+
+
+
+    /// Returns whether the given blob needs no further uploading, because it is already in the storage or completely staged.
+    #[inline]
+    pub fn is_complete(&self, storage: &Storage, hash: &BlobHash) -> bool {
+        storage.exists(hash) || self.blob_path(hash).is_file()
+    }
+
+    /// Returns the number of bytes of the given blob that the server already has.
+    ///
+    /// Blobs that are complete count as received entirely.
+    ///
+    /// # Errors
+    /// This function errors if the blob is not part of the transaction or if we could not inspect the staged file.
+    pub fn received(&self, storage: &Storage, hash: &BlobHash) -> Result<u64, Error> {
+        let size: u64 = match self.blob_size(hash) {
+            Some(size) => size,
+            None       => { return Err(Error::UnknownBlob{ hash: *hash }); }
+        };
+        if self.is_complete(storage, hash) { return Ok(size); }
+        file_size(&self.part_path(hash))
+    }
+
+    /// Returns the blobs that still have to be uploaded before the transaction can be committed.
+    ///
+    /// # Errors
+    /// This function errors if we could not inspect the staged files.
+    pub fn needed(&self, storage: &Storage) -> Result<Vec<NeededBlob>, Error> {
+        let mut seen: HashSet<BlobHash> = HashSet::with_capacity(self.manifest.entries.len());
+        let mut needed: Vec<NeededBlob> = Vec::new();
+        for (hash, size) in self.manifest.entries.iter().flat_map(|e| e.blobs()) {
+            if !seen.insert(hash) || self.is_complete(storage, &hash) { continue; }
+            let offset: u64 = self.received(storage, &hash)?;
+            needed.push(NeededBlob{ hash, size, offset });
+        }
+        Ok(needed)
+    }
+
+
+
+    /// Opens the partial file of the given blob for appending, making sure it ends at the given offset.
+    ///
+    /// # Arguments
+    /// - `hash`: The hash of the blob to append to.
+    /// - `offset`: The number of bytes that are expected to be in the partial file already.
+    ///
+    /// # Errors
+    /// This function errors if the offset is not the number of bytes we have, or if the file could not be opened.
+    pub fn open_part(&self, hash: &BlobHash, offset: u64) -> Result<File, Error> {
+        let path: PathBuf = self.part_path(hash);
+        let have: u64 = file_size(&path)?;
+        if offset != have { return Err(Error::IllegalOffset{ hash: *hash, got: offset, expected: have }); }
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(handle) => Ok(handle),
+            Err(err)   => Err(Error::BlobOpenError{ path, err }),
+        }
+    }
+
+    /// Verifies the partial file of the given blob and, if it is correct, marks it as complete.
+    ///
+    /// If the partial file does not match its hash, it is removed so that the blob may be uploaded again.
+    ///
+    /// # Errors
+    /// This function errors if the partial file does not match the blob's hash or size, or if we failed to hash or move it.
+    pub fn finish_part(&self, hash: &BlobHash) -> Result<(), Error> {
+        let path: PathBuf = self.part_path(hash);
+        let expected: u64 = match self.blob_size(hash) {
+            Some(size) => size,
+            None       => { return Err(Error::UnknownBlob{ hash: *hash }); }
+        };
+
+        // Check the size & hash
+        let size: u64 = file_size(&path)?;
+        if size != expected {
+            if let Err(err) = fs::remove_file(&path) { warn!("Could not remove partial blob '{}': {}", path.display(), err); }
+            return Err(Error::SizeMismatch{ hash: *hash, got: size, expected });
+        }
+        let got: BlobHash = match BlobHash::of_file(&path) {
+            Ok(got)  => got,
+            Err(err) => { return Err(Error::BlobHashError{ err }); }
+        };
+        if &got != hash {
+            if let Err(err) = fs::remove_file(&path) { warn!("Could not remove partial blob '{}': {}", path.display(), err); }
+            return Err(Error::HashMismatch{ hash: *hash, got });
+        }
+
+        // Mark it as complete
+        if let Err(err) = fs::rename(&path, self.blob_path(hash)) { return Err(Error::BlobRenameError{ path, err }); }
+        Ok(())
+    }
+}
+
+
+
+/// A blob that is being uploaded in a publish transaction.
+///
+/// Only one upload of a blob may be in progress at a time. Dropping the Upload ends it, after which the blob may be uploaded again.
+#[derive(Debug)]
+pub struct Upload {
+    /// The transaction the blob belongs to.
+    transaction : Arc<Mutex<Transaction>>,
+    /// The user that uploads the blob.
+    user        : UserId,
+    /// The hash of the blob.
+    hash        : BlobHash,
+    /// The path of the partial file of the blob.
+    path        : PathBuf,
+    /// The partial file of the blob, opened for appending.
+    handle      : File,
+}
+
+impl Upload {
+    /// Appends the given data to the partial file of the blob.
+    ///
+    /// # Errors
+    /// This function errors if the transaction was closed in the meantime or if we failed to write the data.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        // Keeps the transaction from timing out while the client is still sending
+        drop(lock_open(&self.transaction, self.user)?);
+        match self.handle.write_all(data) {
+            Ok(_)    => Ok(()),
+            Err(err) => Err(Error::BlobWriteError{ path: self.path.clone(), err }),
+        }
+    }
+
+    /// Verifies the uploaded blob and, if it is correct, marks it as complete (see `Transaction::finish_part()`).
+    ///
+    /// # Errors
+    /// This function errors if we failed to write the partial file to disk, if the transaction was closed in the meantime or if the blob is not correct.
+    pub fn finish(self) -> Result<(), Error> {
+        if let Err(err) = self.handle.sync_all() { return Err(Error::BlobWriteError{ path: self.path.clone(), err }); }
+        let transaction: MutexGuard<Transaction> = lock_open(&self.transaction, self.user)?;
+        let res: Result<(), Error> = transaction.finish_part(&self.hash);
+        drop(transaction);
+        res
+    }
+}
+
+impl Drop for Upload {
+    fn drop(&mut self) { lock(&self.transaction).uploading.remove(&self.hash); }
+}
+
+
+
+/// Keeps track of all open publish transactions.
+///
+/// Every transaction has its own staging directory under the root, named after its identifier. Open transactions are reloaded from there when the server starts.
+#[derive(Debug)]
+pub struct Transactions {
+    /// The root directory of the staging area.
+    root        : PathBuf,
+    /// The open transactions, by identifier.
+    open        : Mutex<HashMap<String, OpenTransaction>>,
+    /// Serializes commits and the registration of new transactions, so two transactions can never make the same version visible and the garbage collector never sweeps a blob that a new transaction is about to use.
+    commit_lock : Mutex<()>,
+}
+
+impl Transactions {
+    /// Constructor for the Transactions, which reloads any transactions that were open when the server stopped.
+    ///
+    /// # Arguments
+    /// - `root`: The directory to stage the transactions in.
+    ///
+    /// # Errors
+    /// This function errors if the given directory did not exist and could not be created, or could not be read.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Result<Self, Error> {
+        let root: PathBuf = root.into();
+        if let Err(err) = fs::create_dir_all(&root) { return Err(Error::DirCreateError{ path: root, err }); }
+
+        // Reload the transactions in it
+        let entries = match fs::read_dir(&root) {
+            Ok(entries) => entries,
+            Err(err)    => { return Err(Error::DirReadError{ path: root, err }); }
+        };
+        let mut open: HashMap<String, OpenTransaction> = HashMap::new();
+        for entry in entries {
+            let dir: PathBuf = match entry {
+                Ok(entry) => entry.path(),
+                Err(err)  => { return Err(Error::DirReadError{ path: root, err }); }
+            };
+            match Self::load(&dir) {
+                Ok(transaction) => {
+                    debug!("Reloaded transaction '{}' for {}@{}", transaction.id, transaction.manifest.package, transaction.manifest.version);
+                    open.insert(transaction.id.clone(), OpenTransaction::new(transaction));
+                },
+                Err(err) => {
+                    warn!("Discarding staging directory '{}': {}", dir.display(), err);
+                    if let Err(err) = fs::remove_dir_all(&dir) { warn!("{}", Error::DirRemoveError{ path: dir, err }); }
+                },
+            }
+        }
+
+        Ok(Self {
+            root,
+            open        : Mutex::new(open),
+            commit_lock : Mutex::new(()),
+        })
+    }
+
+    /// Loads a transaction from its staging directory.
+    ///
+    /// # Errors
+    /// This function errors if the transaction file could not be read or parsed.
+    fn load(dir: &Path) -> Result<Transaction, Error> {
+        let path: PathBuf = dir.join(TRANSACTION_FILE);
+        let handle = match File::open(&path) {
+            Ok(handle) => BufReader::new(handle),
+            Err(err)   => { return Err(Error::TransactionReadError{ path, err }); }
+        };
+        let file: TransactionFile = match serde_json::from_reader(handle) {
+            Ok(file) => file,
+            Err(err) => { return Err(Error::TransactionParseError{ path, err }); }
+        };
+        if dir.file_name().map(|n| n != file.id.as_str()).unwrap_or(true) { return Err(Error::UnknownTransaction{ id: file.id }); }
+        let mut manifest: Manifest = file.manifest;
+        manifest.publisher = Some(file.user);
+
+        Ok(Transaction {
+            id          : file.id,
+            user        : file.user,
+            manifest,
+            dir         : dir.into(),
+            last_active : Instant::now(),
+            closed      : false,
+            uploading   : HashSet::new(),
+        })
+    }
+
+
+
+    /// Opens a new transaction to publish the given package version.
+    ///
+    /// # Arguments
+    /// - `user`: The user that opens the transaction.
+    /// - `manifest`: The manifest of the version to publish.
+    /// - `packages`: The package database to check whether the version already exists.
+    /// - `user_quota`: The quota of the user, which counts the versions the user published or is publishing.
+    /// - `package_quota`: The quota of the package, which counts all its versions, published or being published.
+    ///
+    /// # Returns
+    /// The new Transaction.
+    ///
+    /// # Errors
+    /// This function errors if the manifest is illegal, the version already exists or is already being published, if it would exceed either quota or if we could not create the staging directory.
+    pub fn begin(&self, user: UserId, mut manifest: Manifest, packages: &Packages, user_quota: &Quota, package_quota: &Quota) -> Result<Arc<Mutex<Transaction>>, Error> {
+        manifest.publisher = Some(user);
+        validate_manifest(&manifest)?;
+
+        // Create the staging directory up front, so we don't keep commits waiting on the disk
+        let id: String = generate_id();
+        let dir: PathBuf = self.root.join(&id);
+        let blobs_dir: PathBuf = dir.join("blobs");
+        if let Err(err) = fs::create_dir_all(&blobs_dir) { return Err(Error::DirCreateError{ path: blobs_dir, err }); }
+        let file = TransactionFile{ id: id.clone(), user, manifest };
+        let transaction = Transaction {
+            id          : file.id.clone(),
+            user,
+            manifest    : file.manifest.clone(),
+            dir         : dir.clone(),
+            last_active : Instant::now(),
+            closed      : false,
+            uploading   : HashSet::new(),
+        };
+        let res: Result<Arc<Mutex<Transaction>>, Error> = Self::write_file(&dir, &file).and_then(|_| self.register(transaction, packages, user_quota, package_quota));
+        if res.is_err() {
+            if let Err(err) = fs::remove_dir_all(&dir) { warn!("{}", Error::DirRemoveError{ path: dir, err }); }
+        }
+        res
+    }
+
+    /// Writes the file that describes a transaction to its staging directory.
+    ///
+    /// # Errors
+    /// This function errors if we failed to write the file.
+    fn write_file(dir: &Path, file: &TransactionFile) -> Result<(), Error> {
+        let path: PathBuf = dir.join(TRANSACTION_FILE);
+        let mut handle = match File::create(&path) {
+            Ok(handle) => BufWriter::new(handle),
+            Err(err)   => { return Err(Error::TransactionWriteError{ path, err }); }
+        };
+        if let Err(err) = serde_json::to_writer_pretty(&mut handle, file) { return Err(Error::TransactionSerializeError{ path, err }); }
+        if let Err(err) = handle.flush() { return Err(Error::TransactionWriteError{ path, err }); }
+        Ok(())
+    }
+
+    /// Adds a new transaction to the open ones, if the version it publishes may be published.
+    ///
+    /// This happens while commits are paused, so the garbage collector either ran before and the client sees which blobs are gone, or runs after and sees that the transaction refers to them.
+    ///
+    /// # Errors
+    /// This function errors if the version already exists or is already being published, or if it would exceed either quota.
+    fn register(&self, transaction: Transaction, packages: &Packages, user_quota: &Quota, package_quota: &Quota) -> Result<Arc<Mutex<Transaction>>, Error> {
+        let _commit = lock(&self.commit_lock);
+        let manifest: &Manifest = &transaction.manifest;
+        match packages.exists(&manifest.package, &manifest.version) {
+            Ok(true)  => { return Err(Error::VersionExists{ package: manifest.package.clone(), version: manifest.version.clone() }); },
+            Ok(false) => {},
+            Err(err)  => { return Err(Error::PackageError{ err }); }
+        }
+        match packages.is_channel(&manifest.package, &manifest.version) {
+            Ok(true)  => { return Err(Error::PackageError{ err: PackageError::NameTaken{ package: manifest.package.clone(), name: manifest.version.clone() } }); },
+            Ok(false) => {},
+            Err(err)  => { return Err(Error::PackageError{ err }); }
+        }
+
+        // Make sure nobody else is publishing it already
+        let mut open = lock(&self.open);
+        if let Some((id, _)) = open.iter().find(|(_, t)| t.package == manifest.package && t.version == manifest.version) {
+            return Err(Error::TransactionExists{ package: manifest.package.clone(), version: manifest.version.clone(), id: id.clone() });
+        }
+
+        // Make sure it fits in the quotas, counting what's being published as well
+        let (user, size): (UserId, u64) = (transaction.user, manifest.total_size());
+        if user_quota.is_limited() {
+            let mut usage: Usage = packages.usage(None, Some(user)).map_err(|err| Error::PackageError{ err })?;
+            usage += pending(&open, |t| t.user == user);
+            if let Err(err) = user_quota.check(&usage, size) { return Err(Error::UserQuotaExceeded{ user, err }); }
+        }
+        if package_quota.is_limited() {
+            let mut usage: Usage = packages.usage(Some(&manifest.package), None).map_err(|err| Error::PackageError{ err })?;
+            usage += pending(&open, |t| t.package == manifest.package);
+            if let Err(err) = package_quota.check(&usage, size) { return Err(Error::PackageQuotaExceeded{ package: manifest.package.clone(), err }); }
+        }
+
+        // Register it
+        info!("Opened transaction '{}' to publish {}@{}", transaction.id, manifest.package, manifest.version);
+        let id: String = transaction.id.clone();
+        let transaction = OpenTransaction::new(transaction);
+        let res: Arc<Mutex<Transaction>> = transaction.transaction.clone();
+        open.insert(id, transaction);
+        Ok(res)
+    }
+
+    /// Returns the given transaction.
+    ///
+    /// # Arguments
+    /// - `id`: The identifier of the transaction.
+    /// - `user`: The user that wants to use the transaction. Transactions of other users are reported as unknown.
+    ///
+    /// # Errors
+    /// This function errors if there is no such transaction for the given user.
+    pub fn get(&self, id: &str, user: UserId) -> Result<Arc<Mutex<Transaction>>, Error> {
+        match lock(&self.open).get(id) {
+            Some(transaction) if transaction.user == user => Ok(transaction.transaction.clone()),
+            _                                             => Err(Error::UnknownTransaction{ id: id.into() }),
+        }
+    }
+
+    /// Locks the given transaction, making sure it is still open and belongs to the given user.
+    ///
+    /// # Errors
+    /// This function errors if there is no such (open) transaction for the given user.
+    #[inline]
+    pub fn lock<'t>(&self, transaction: &'t Mutex<Transaction>, user: UserId) -> Result<MutexGuard<'t, Transaction>, Error> { lock_open(transaction, user) }
+
+    /// Starts receiving (the rest of) a blob of the given transaction.
+    ///
+    /// The transaction is not kept locked during the upload, so a slow client cannot keep others from using it (or, while it is being committed, from committing anything else).
+    ///
+    /// # Arguments
+    /// - `transaction`: The transaction to upload to.
+    /// - `user`: The user that uploads the blob.
+    /// - `hash`: The hash of the blob to upload.
+    /// - `offset`: The number of bytes that the client thinks we already have.
+    ///
+    /// # Returns
+    /// An Upload to write the data of the blob to.
+    ///
+    /// # Errors
+    /// This function errors if the transaction is closed, the blob is being uploaded already, the offset is not the number of bytes we have or if we could not open the partial file.
+    pub fn upload(&self, transaction: &Arc<Mutex<Transaction>>, user: UserId, hash: &BlobHash, offset: u64) -> Result<Upload, Error> {
+        let mut guard: MutexGuard<Transaction> = lock_open(transaction, user)?;
+        if guard.uploading.contains(hash) { return Err(Error::UploadInProgress{ hash: *hash }); }
+        let handle: File = guard.open_part(hash, offset)?;
+        guard.uploading.insert(*hash);
+        Ok(Upload {
+            transaction : transaction.clone(),
+            user,
+            hash        : *hash,
+            path        : guard.part_path(hash),
+            handle,
+        })
+    }
+
+
+
+    /// Commits the given transaction, which makes the staged version visible.
+    ///
+    /// Before doing so, every blob in the manifest is checked to be present (either staged or already in the storage) and to have the right size. Their contents are not hashed again, since `Transaction::finish_part()` and the storage already did so when they came in. The chunks of chunked files must add up to the hash of the file, though.
+    ///
+    /// # Arguments
+    /// - `id`: The identifier of the transaction.
+    /// - `user`: The user that commits the transaction.
+    /// - `storage`: The storage to move the staged blobs to.
+    /// - `packages`: The package database to add the version to.
+    ///
+    /// # Returns
+    /// The manifest of the version that was made visible.
+    ///
+    /// # Errors
+    /// This function errors if the transaction does not exist, is incomplete or contains a corrupt blob, or if we failed to move it in place.
+    pub fn commit(&self, id: &str, user: UserId, storage: &Storage, packages: &Packages) -> Result<Manifest, Error> {
+        let transaction: Arc<Mutex<Transaction>> = self.get(id, user)?;
+        let mut transaction: MutexGuard<Transaction> = self.lock(&transaction, user)?;
+        let _commit = lock(&self.commit_lock);
+        match packages.exists(&transaction.manifest.package, &transaction.manifest.version) {
+            Ok(true)  => { return Err(Error::VersionExists{ package: transaction.manifest.package.clone(), version: transaction.manifest.version.clone() }); },
+            Ok(false) => {},
+            Err(err)  => { return Err(Error::PackageError{ err }); }
+        }
+
+        // Find every blob
+        let mut blobs: HashMap<BlobHash, PathBuf> = HashMap::with_capacity(transaction.manifest.entries.len());
+        for (hash, size) in transaction.manifest.entries.iter().flat_map(|e| e.blobs()) {
+            if blobs.contains_key(&hash) { continue; }
+
+            // Find where the blob lives
+            let staged_path: PathBuf = transaction.blob_path(&hash);
+            let path: PathBuf = if staged_path.is_file() {
+                staged_path
+            } else if storage.exists(&hash) {
+                storage.path_of(&hash)
+            } else {
+                return Err(Error::MissingBlob{ hash });
+            };
+
+            // Check it
+            let got_size: u64 = file_size(&path)?;
+            if got_size != size { return Err(Error::SizeMismatch{ hash, got: got_size, expected: size }); }
+            blobs.insert(hash, path);
+        }
+
+        // Verify that the chunked files are what they claim to be
+        for entry in transaction.manifest.entries.iter().filter(|e| e.is_chunked()) {
+            let mut hasher = Sha256::new();
+            for chunk in &entry.chunks {
+                let path: &PathBuf = &blobs[&chunk.hash];
+                match File::open(path).and_then(|handle| std::io::copy(&mut BufReader::new(handle), &mut hasher)) {
+                    Ok(_)    => {},
+                    Err(err) => { return Err(Error::BlobHashError{ err: HashError::FileReadError{ path: path.clone(), err } }); }
+                }
+            }
+            let got: BlobHash = BlobHash(hasher.finalize().into());
+            if got != entry.hash { return Err(Error::HashMismatch{ hash: entry.hash, got }); }
+        }
+        let staged: Vec<(BlobHash, PathBuf)> = blobs.into_iter().filter(|(_, path)| path.starts_with(&transaction.dir)).collect();
+
+        // Move the staged blobs to the storage, and only then make the version visible
+        for (hash, path) in staged {
+            if let Err(err) = storage.insert_file(&hash, &path) { return Err(Error::StorageError{ err }); }
+        }
+        if let Err(err) = packages.insert(&transaction.manifest) { return Err(Error::PackageError{ err }); }
+        info!("Committed transaction '{}'; {}@{} is now available", transaction.id, transaction.manifest.package, transaction.manifest.version);
+
+        // Clean up
+        transaction.closed = true;
+        lock(&self.open).remove(&transaction.id);
+        if let Err(err) = fs::remove_dir_all(&transaction.dir) { warn!("{}", Error::DirRemoveError{ path: transaction.dir.clone(), err }); }
+        Ok(transaction.manifest.clone())
+    }
+
+    /// Aborts the given transaction, discarding everything that was staged in it.
+    ///
+    /// # Arguments
+    /// - `id`: The identifier of the transaction.
+    /// - `user`: The user that aborts the transaction.
+    ///
+    /// # Errors
+    /// This function errors if the transaction does not exist or if we failed to remove its staging directory.
+    pub fn abort(&self, id: &str, user: UserId) -> Result<(), Error> {
+        let transaction: Arc<Mutex<Transaction>> = self.get(id, user)?;
+        let mut transaction: MutexGuard<Transaction> = self.lock(&transaction, user)?;
+        self.discard(&mut transaction)?;
+        info!("Aborted transaction '{}' for {}@{}", transaction.id, transaction.manifest.package, transaction.manifest.version);
+        Ok(())
+    }
+
+    /// Returns what the open transactions of the given user will take up once committed.
+    #[inline]
+    pub fn pending_usage(&self, user: UserId) -> Usage { pending(&lock(&self.open), |t| t.user == user) }
+
+    /// Returns the blobs that any open transaction refers to, whether they have been uploaded yet or not.
+    pub fn referenced_blobs(&self) -> HashSet<BlobHash> {
+        lock(&self.open).values().flat_map(|t| t.blobs.iter().copied()).collect()
+    }
+
+    /// Prevents any transaction from being committed or opened until the returned guard is dropped.
+    ///
+    /// This lets the garbage collector look at the storage, the package database and the open transactions without any of them changing under its feet.
+    #[inline]
+    pub fn pause_commits(&self) -> MutexGuard<'_, ()> { lock(&self.commit_lock) }
+
+    /// Aborts all transactions that have not been used for the given time.
+    ///
+    /// Transactions that are in use at the moment are skipped.
+    ///
+    /// # Arguments
+    /// - `timeout`: The time after which an unused transaction is aborted.
+    ///
+    /// # Returns
+    /// The number of transactions that were aborted.
+    pub fn reap(&self, timeout: Duration) -> usize {
+        let candidates: Vec<Arc<Mutex<Transaction>>> = lock(&self.open).values().map(|t| t.transaction.clone()).collect();
+        let mut n_reaped: usize = 0;
+        for transaction in candidates {
+            let mut transaction = match transaction.try_lock() {
+                Ok(transaction) => transaction,
+                Err(_)          => { continue; },
+            };
+            if transaction.closed || transaction.last_active.elapsed() < timeout { continue; }
+            match self.discard(&mut transaction) {
+                Ok(_)    => {
+                    info!("Transaction '{}' for {}@{} timed out; discarded it", transaction.id, transaction.manifest.package, transaction.manifest.version);
+                    n_reaped += 1;
+                },
+                Err(err) => { warn!("Could not discard timed out transaction '{}': {}", transaction.id, err); },
+            }
+        }
+        n_reaped
+    }
+
+    /// Closes the given transaction and removes its staging directory.
+    ///
+    /// # Errors
+    /// This function errors if we failed to remove the staging directory.
+    fn discard(&self, transaction: &mut Transaction) -> Result<(), Error> {
+        transaction.closed = true;
+        lock(&self.open).remove(&transaction.id);
+        match fs::remove_dir_all(&transaction.dir) {
+            Ok(_)    => Ok(()),
+            Err(err) => Err(Error::DirRemoveError{ path: transaction.dir.clone(), err }),
+        }
+    }
+}
+
+
+
+
+
+/***** TESTS *****/
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use tempfile::TempDir;
+
+    use filehost_spc::manifest::{ManifestChunk, ManifestEntry};
+
+    use super::*;
+
+    /// The user that publishes in the tests.
+    const USER: UserId = 2;
+
+    /// The storage, package database and staging area of a host, in a directory that is removed when it's dropped.
+    struct Host {
+        _dir         : TempDir,
+        storage      : Storage,
+        packages     : Packages,
+        transactions : Transactions,
+    }
+
+    impl Host {
+        /// Creates an empty host.
+        fn new() -> Self {
+            let dir: TempDir = tempfile::tempdir().unwrap();
+            Self {
+                storage      : Storage::new(dir.path().join("blobs")).unwrap(),
+                packages     : Packages::new(dir.path().join("packages"), &dir.path().join("index.db")).unwrap(),
+                transactions : Transactions::new(dir.path().join("staging")).unwrap(),
+                _dir         : dir,
+            }
+        }
+
+        /// Opens a transaction for the given manifest.
+        fn begin(&self, manifest: Manifest) -> Result<Arc<Mutex<Transaction>>, Error> {
+            self.transactions.begin(USER, manifest, &self.packages, &Quota::default(), &Quota::default())
+        }
+
+        /// Uploads the given data in one go as the blob with the given hash.
+        fn upload(&self, transaction: &Mutex<Transaction>, hash: &BlobHash, data: &[u8]) -> Result<(), Error> {
+            let transaction: MutexGuard<Transaction> = self.transactions.lock(transaction, USER).unwrap();
+            transaction.open_part(hash, 0).unwrap().write_all(data).unwrap();
+            transaction.finish_part(hash)
+        }
+    }
+
+    /// Builds the manifest entry of a file with the given contents.
+    fn entry(path: &str, data: &[u8]) -> ManifestEntry {
+        ManifestEntry{ path: path.into(), hash: BlobHash::of_bytes(data), size: data.len() as u64, chunks: vec![] }
+    }
+
+    /// Builds the manifest entry of a file that is stored as the given chunks.
+    fn chunked(path: &str, chunks: &[&[u8]]) -> ManifestEntry {
+        let data: Vec<u8> = chunks.concat();
+        let chunks: Vec<ManifestChunk> = chunks.iter().map(|c| ManifestChunk{ hash: BlobHash::of_bytes(c), size: c.len() as u64 }).collect();
+        ManifestEntry{ path: path.into(), hash: BlobHash::of_bytes(&data), size: data.len() as u64, chunks }
+    }
+
+    /// Builds the manifest of the given version of the package `game`.
+    fn manifest(version: &str, entries: Vec<ManifestEntry>) -> Manifest {
+        Manifest{ package: "game".into(), version: version.into(), entries, publisher: None, signature: None }
+    }
+
+
+
+    #[test]
+    fn test_commit() {
+        let host = Host::new();
+        let transaction = host.begin(manifest("1.0.0", vec![ entry("a.txt", b"hello"), entry("b.txt", b"world"), entry("c.txt", b"hello") ])).unwrap();
+        let id: String = lock(&transaction).id.clone();
+
+        // Nothing is visible until it is committed
+        host.upload(&transaction, &BlobHash::of_bytes(b"hello"), b"hello").unwrap();
+        host.upload(&transaction, &BlobHash::of_bytes(b"world"), b"world").unwrap();
+        assert!(!host.packages.exists("game", "1.0.0").unwrap());
+        assert!(!host.storage.exists(&BlobHash::of_bytes(b"hello")));
+
+        let committed: Manifest = host.transactions.commit(&id, USER, &host.storage, &host.packages).unwrap();
+        assert_eq!(committed.entries.len(), 3);
+        assert!(host.packages.exists("game", "1.0.0").unwrap());
+        assert!(host.storage.exists(&BlobHash::of_bytes(b"hello")));
+        assert!(host.storage.exists(&BlobHash::of_bytes(b"world")));
+        assert!(matches!(host.transactions.get(&id, USER), Err(Error::UnknownTransaction{ .. })));
+
+        // Blobs we already have need not be uploaded again, but the version can't be published twice
+        let transaction = host.begin(manifest("1.0.1", vec![ entry("a.txt", b"hello") ])).unwrap();
+        let id: String = lock(&transaction).id.clone();
+        host.transactions.commit(&id, USER, &host.storage, &host.packages).unwrap();
+        assert!(host.packages.exists("game", "1.0.1").unwrap());
+        assert!(matches!(host.begin(manifest("1.0.1", vec![])), Err(Error::VersionExists{ .. })));
+    }
+
+    #[test]
+    fn test_commit_incomplete() {
+        let host = Host::new();
+        let transaction = host.begin(manifest("1.0.0", vec![ entry("a.txt", b"hello"), entry("b.txt", b"world") ])).unwrap();
+        let id: String = lock(&transaction).id.clone();
+        assert!(matches!(host.begin(manifest("1.0.0", vec![])), Err(Error::TransactionExists{ .. })));
+
+        // A missing blob fails the commit, but leaves the transaction open
+        host.upload(&transaction, &BlobHash::of_bytes(b"hello"), b"hello").unwrap();
+        assert!(matches!(host.transactions.commit(&id, USER, &host.storage, &host.packages), Err(Error::MissingBlob{ .. })));
+        assert!(!host.packages.exists("game", "1.0.0").unwrap());
+
+        host.upload(&transaction, &BlobHash::of_bytes(b"world"), b"world").unwrap();
+        host.transactions.commit(&id, USER, &host.storage, &host.packages).unwrap();
+        assert!(host.packages.exists("game", "1.0.0").unwrap());
+    }
+
+    #[test]
+    fn test_corrupt_upload() {
+        let host = Host::new();
+        let hash: BlobHash = BlobHash::of_bytes(b"hello");
+        let transaction = host.begin(manifest("1.0.0", vec![ entry("a.txt", b"hello") ])).unwrap();
+
+        // Blobs with the wrong contents or size are thrown away, so they can be uploaded again
+        assert!(matches!(host.upload(&transaction, &hash, b"jello"), Err(Error::HashMismatch{ .. })));
+        assert_eq!(host.transactions.lock(&transaction, USER).unwrap().received(&host.storage, &hash).unwrap(), 0);
+        assert!(matches!(host.upload(&transaction, &hash, b"hello!"), Err(Error::SizeMismatch{ .. })));
+        assert_eq!(host.transactions.lock(&transaction, USER).unwrap().received(&host.storage, &hash).unwrap(), 0);
+        host.upload(&transaction, &hash, b"hello").unwrap();
+
+        // Others can't touch the transaction
+        let id: String = lock(&transaction).id.clone();
+        assert!(matches!(host.transactions.commit(&id, USER + 1, &host.storage, &host.packages), Err(Error::UnknownTransaction{ .. })));
+        host.transactions.commit(&id, USER, &host.storage, &host.packages).unwrap();
+    }
+
+    #[test]
+    fn test_commit_chunked() {
+        let host = Host::new();
+        let transaction = host.begin(manifest("1.0.0", vec![ chunked("level.bin", &[ b"head", b"tail" ]) ])).unwrap();
+        let id: String = lock(&transaction).id.clone();
+        assert_eq!(lock(&transaction).needed(&host.storage).unwrap().len(), 2);
+        host.upload(&transaction, &BlobHash::of_bytes(b"head"), b"head").unwrap();
+        host.upload(&transaction, &BlobHash::of_bytes(b"tail"), b"tail").unwrap();
+        host.transactions.commit(&id, USER, &host.storage, &host.packages).unwrap();
+
+        // Chunks that are fine by themselves must still make up the file they claim to
+        let mut entry: ManifestEntry = chunked("level.bin", &[ b"tail", b"head" ]);
+        entry.hash = BlobHash::of_bytes(b"headtail");
+        let transaction = host.begin(manifest("1.0.1", vec![ entry ])).unwrap();
+        let id: String = lock(&transaction).id.clone();
+        assert!(lock(&transaction).needed(&host.storage).unwrap().is_empty());
+        match host.transactions.commit(&id, USER, &host.storage, &host.packages) {
+            Err(Error::HashMismatch{ hash, got }) => {
+                assert_eq!(hash, BlobHash::of_bytes(b"headtail"));
+                assert_eq!(got, BlobHash::of_bytes(b"tailhead"));
+            },
+            res => panic!("Expected a hash mismatch, got {:?}", res.map(|m| m.version)),
+        }
+        assert!(!host.packages.exists("game", "1.0.1").unwrap());
+    }
+}
@@ -0,0 +1,125 @@
+/* RETENTION.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 05:10:05
+ * Last edited:
+ *   19 Oct 2026, 07:08:31
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the retention policies, which prune old versions of
+ *   packages automatically.
+**/
+
+use std::collections::{HashMap, HashSet};
+use std::sync::MutexGuard;
+use std::time::{Duration, SystemTime};
+
+use log::{debug, error, info};
+
+use filehost_spc::audit::{AuditOperation, AuditOutcome};
+use filehost_spc::client_messages::ErrorCode;
+use filehost_spc::config::RetentionPolicy;
+
+use crate::audit::{AuditLog, SYSTEM_USER};
+use crate::errors::PackageError;
+use crate::packages::Packages;
+use crate::publish::Transactions;
+
+
+/***** CONSTANTS *****/
+/// The number of seconds in a day.
+const DAY: u64 = 24 * 60 * 60;
+
+
+
+
+
+/***** HELPER FUNCTIONS *****/
+/// Applies the retention policy of a single package.
+///
+/// # Arguments
+/// - `packages`: The package database to prune.
+/// - `audit`: The audit log to record the removed versions in.
+/// - `package`: The name of the package to prune.
+/// - `policy`: The retention policy of the package.
+/// - `now`: The moment to compute the age of versions against.
+///
+/// # Returns
+/// The versions that were removed.
+///
+/// # Errors
+/// This function errors if we failed to read the versions or channels of the package. Versions that could not be removed are logged and skipped.
+fn prune(packages: &Packages, audit: &AuditLog, package: &str, policy: &RetentionPolicy, now: SystemTime) -> Result<Vec<String>, PackageError> {
+    // Collect the versions that are always kept
+    let mut protected: HashSet<String> = policy.pinned.iter().cloned().collect();
+    for channel in packages.channels(package)? {
+        protected.insert(packages.channel(package, &channel)?.version);
+    }
+
+    // Order the versions by when they were published, newest first
+    let mut versions: Vec<(String, SystemTime)> = Vec::new();
+    for version in packages.versions(package)? {
+        let published: SystemTime = packages.published(package, &version)?;
+        versions.push((version, published));
+    }
+    versions.sort_by(|(n1, t1), (n2, t2)| t2.cmp(t1).then_with(|| n2.cmp(n1)));
+
+    // Remove everything no rule keeps
+    let max_age: Option<Duration> = policy.keep_days.map(|days| Duration::from_secs(days * DAY));
+    let mut removed: Vec<String> = Vec::new();
+    for (i, (version, published)) in versions.into_iter().enumerate() {
+        if protected.contains(&version) { continue; }
+        if policy.keep_last.map(|n| i < n).unwrap_or(false) { continue; }
+        if max_age.map(|age| now.duration_since(published).unwrap_or_default() < age).unwrap_or(false) { continue; }
+
+        let target: String = format!("{}@{}", package, version);
+        match packages.remove(package, &version) {
+            Ok(_)    => {
+                info!("Retention policy removed version '{}' of package '{}'", version, package);
+                audit.record(SYSTEM_USER, None, AuditOperation::Delete, target, AuditOutcome::Success);
+                removed.push(version);
+            },
+            Err(err) => {
+                error!("Could not remove version '{}' of package '{}': {}", version, package, err);
+                audit.record(SYSTEM_USER, None, AuditOperation::Delete, target, AuditOutcome::Failure{ code: ErrorCode::Internal, message: err.to_string() });
+            },
+        }
+    }
+    Ok(removed)
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Applies the given retention policies to the package database.
+///
+/// Commits are paused while we prune, so the garbage collector and the integrity check never see a version disappear halfway. The blobs of removed versions are left to the garbage collector. Every removal is recorded in the audit log, as done by `SYSTEM_USER`.
+///
+/// # Arguments
+/// - `packages`: The package database to prune.
+/// - `transactions`: The publish transactions to pause while pruning.
+/// - `audit`: The audit log to record the removed versions in.
+/// - `policies`: The retention policies, by package name.
+///
+/// # Returns
+/// The number of versions that were removed. Packages that could not be pruned are logged and skipped.
+pub fn apply(packages: &Packages, transactions: &Transactions, audit: &AuditLog, policies: &HashMap<String, RetentionPolicy>) -> usize {
+    let _commits: MutexGuard<()> = transactions.pause_commits();
+
+    let now: SystemTime = SystemTime::now();
+    let mut n_removed: usize = 0;
+    for (package, policy) in policies {
+        if !policy.prunes() { continue; }
+        match prune(packages, audit, package, policy, now) {
+            Ok(removed)                             => { n_removed += removed.len(); },
+            Err(PackageError::UnknownPackage{ .. }) => { debug!("Skipping retention policy of unknown package '{}'", package); },
+            Err(err)                                => { error!("Could not apply retention policy of package '{}': {}", package, err); },
+        }
+    }
+    n_removed
+}
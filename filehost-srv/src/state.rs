@@ -0,0 +1,111 @@
+/* STATE.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:26:37
+ * Last edited:
+ *   19 Oct 2026, 07:03:36
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Defines the state of the server that is shared between the main loop
+ *   and the threads that handle client connections.
+**/
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+use log::debug;
+
+use filehost_spc::config::Config;
+
+use crate::expiry;
+use crate::audit::AuditLog;
+use crate::ca::CertificateAuthority;
+use crate::errors::SSLError;
+use crate::limits::Limiter;
+use crate::packages::Packages;
+use crate::publish::Transactions;
+use crate::ssl::SSLConfig;
+use crate::storage::Storage;
+use crate::users::Users;
+
+
+/***** LIBRARY *****/
+/// Collects everything the server needs to handle requests for one host.
+pub struct ServerState {
+    /// The name of the virtual host this state belongs to, or `None` for the main host.
+    pub vhost        : Option<String>,
+    /// The configuration of the host, which for a virtual host is derived from that of the main host.
+    pub config       : Config,
+    /// The database of users, which virtual hosts may share with the main host.
+    pub users        : Arc<dyn Users>,
+    /// The SSL configuration used for client connections.
+    pub ssl          : SSLConfig,
+    /// The certificate authority that issues client certificates, if the host has one.
+    pub ca           : Option<Arc<CertificateAuthority>>,
+    /// The backend that stores the blobs.
+    pub storage      : Storage,
+    /// The database of packages and their versions.
+    pub packages     : Packages,
+    /// The open publish transactions.
+    pub transactions : Transactions,
+    /// The log of who changed what.
+    pub audit        : AuditLog,
+}
+
+
+impl ServerState {
+    /// Reloads the trusted client certificates from the users database, so that changes to the trusted certificates, the revocation list or the suspended users take effect without a restart. Hosts that share the users see the change too, and connections that are already open are not affected.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the users or to load their certificates. The old ones remain trusted in that case.
+    pub fn reload_ssl(&self) -> Result<(), SSLError> {
+        let users = self.users.list().map_err(|err| SSLError::UsersError{ err })?;
+        let revocations = self.users.revocations().map_err(|err| SSLError::UsersError{ err })?;
+        self.ssl.verifier.reload(&users, &revocations)?;
+        let issued = self.users.issued_certs().map_err(|err| SSLError::UsersError{ err })?;
+        expiry::check(&self.config, &expiry::user_certs(&users, &issued, &revocations), Utc::now());
+        Ok(())
+    }
+}
+
+
+
+/// Collects the main host and the virtual hosts that the daemon serves.
+pub struct Hosts {
+    /// The main host, which also serves the clients that ask for no (or an unknown) virtual host.
+    pub main    : Arc<ServerState>,
+    /// The virtual hosts, by name.
+    pub vhosts  : HashMap<String, Arc<ServerState>>,
+    /// Limits how much clients may ask of the daemon, regardless of the host they connect to.
+    pub limiter : Arc<Limiter>,
+}
+
+impl Hosts {
+    /// Returns the host that serves clients that sent the given server name.
+    ///
+    /// # Arguments
+    /// - `server_name`: The server name that the client sent with SNI, if any.
+    pub fn by_server_name(&self, server_name: Option<&str>) -> &Arc<ServerState> {
+        let server_name: &str = match server_name {
+            Some(server_name) => server_name,
+            None              => { return &self.main; },
+        };
+        match self.main.config.vhosts.iter().find(|(_, vhost)| vhost.serves(server_name)).and_then(|(name, _)| self.vhosts.get(name)) {
+            Some(state) => state,
+            None        => {
+                debug!("No virtual host serves '{}'; using the main host", server_name);
+                &self.main
+            },
+        }
+    }
+
+    /// Iterates over all hosts, starting with the main host.
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<ServerState>> {
+        std::iter::once(&self.main).chain(self.vhosts.values())
+    }
+}
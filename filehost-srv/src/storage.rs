@@ -0,0 +1,312 @@
+/* STORAGE.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 04:26:37
+ * Last edited:
+ *   19 Oct 2026, 05:08:13
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the content-addressed storage backend in which the
+ *   server keeps the blobs of all package versions.
+**/
+
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Take};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use log::debug;
+
+use filehost_spc::manifest::BlobHash;
+
+pub use crate::errors::StorageError as Error;
+
+
+/***** HELPER FUNCTIONS *****/
+/// Returns the paths of everything in the given directory.
+///
+/// # Errors
+/// This function errors if we failed to read the directory.
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let iter = match fs::read_dir(dir) {
+        Ok(iter) => iter,
+        Err(err) => { return Err(Error::DirReadError{ path: dir.into(), err }); }
+    };
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for entry in iter {
+        match entry {
+            Ok(entry) => { paths.push(entry.path()); },
+            Err(err)  => { return Err(Error::DirReadError{ path: dir.into(), err }); }
+        }
+    }
+    Ok(paths)
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// A handle to a (range of a) blob that can be read from without loading it in memory entirely.
+pub struct BlobReader {
+    /// The total size of the blob.
+    pub size   : u64,
+    /// The offset of the first byte that the reader will return.
+    pub offset : u64,
+    /// The number of bytes that the reader will return.
+    pub length : u64,
+    /// The reader itself.
+    pub reader : Take<File>,
+}
+
+/// Describes a single blob in the storage.
+#[derive(Clone, Debug)]
+pub struct BlobInfo {
+    /// The hash of the blob.
+    pub hash     : BlobHash,
+    /// The size of the blob, in bytes.
+    pub size     : u64,
+    /// The last time the blob was touched.
+    pub modified : SystemTime,
+}
+
+
+
+/// The storage backend that stores blobs on the local filesystem, named after their hash.
+#[derive(Clone, Debug)]
+pub struct Storage {
+    /// The root directory of the blobs.
+    root : PathBuf,
+}
+
+impl Storage {
+    /// Constructor for the Storage, which makes sure its root directory exists.
+    ///
+    /// # Arguments
+    /// - `root`: The directory to store the blobs in.
+    ///
+    /// # Errors
+    /// This function errors if the given directory did not exist and could not be created.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Result<Self, Error> {
+        let root: PathBuf = root.into();
+        if let Err(err) = fs::create_dir_all(&root) { return Err(Error::DirCreateError{ path: root, err }); }
+        Ok(Self {
+            root,
+        })
+    }
+
+
+
+    /// Returns the path of the file that (would) store the given blob.
+    ///
+    /// Blobs are fanned out over subdirectories named after the first byte of their hash to keep directories small.
+    #[inline]
+    pub fn path_of(&self, hash: &BlobHash) -> PathBuf {
+        let hash: String = hash.to_string();
+        self.root.join(&hash[..2]).join(hash)
+    }
+
+    /// Returns the root directory of the storage.
+    #[inline]
+    pub fn root(&self) -> &Path { &self.root }
+
+    /// Returns whether the given blob exists.
+    #[inline]
+    pub fn exists(&self, hash: &BlobHash) -> bool { self.path_of(hash).is_file() }
+
+    /// Returns the size of the given blob.
+    ///
+    /// # Errors
+    /// This function errors if the blob does not exist or its metadata could not be read.
+    pub fn size(&self, hash: &BlobHash) -> Result<u64, Error> {
+        let path: PathBuf = self.path_of(hash);
+        match fs::metadata(&path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err)     => if err.kind() == std::io::ErrorKind::NotFound {
+                Err(Error::BlobNotFound{ hash: *hash })
+            } else {
+                Err(Error::BlobMetadataError{ path, err })
+            },
+        }
+    }
+
+    /// Opens the given blob for reading.
+    ///
+    /// # Arguments
+    /// - `hash`: The hash of the blob to open.
+    ///
+    /// # Returns
+    /// A File handle to the blob, and its size.
+    ///
+    /// # Errors
+    /// This function errors if the blob does not exist or could not be opened.
+    pub fn open(&self, hash: &BlobHash) -> Result<(File, u64), Error> {
+        let path: PathBuf = self.path_of(hash);
+
+        // Open the file
+        let handle: File = match File::open(&path) {
+            Ok(handle) => handle,
+            Err(err)   => {
+                if err.kind() == std::io::ErrorKind::NotFound { return Err(Error::BlobNotFound{ hash: *hash }); }
+                return Err(Error::BlobOpenError{ path, err });
+            },
+        };
+
+        // Get its size
+        let size: u64 = match handle.metadata() {
+            Ok(metadata) => metadata.len(),
+            Err(err)     => { return Err(Error::BlobMetadataError{ path, err }); }
+        };
+
+        // Done
+        Ok((handle, size))
+    }
+
+    /// Opens a range of the given blob for reading.
+    ///
+    /// Note that the range is not checked against the size of the blob; it is simply clipped to it. Check the returned BlobReader to see what will be read.
+    ///
+    /// # Arguments
+    /// - `hash`: The hash of the blob to open.
+    /// - `offset`: The offset of the first byte to read.
+    /// - `length`: The maximum number of bytes to read. Reads until the end of the blob if omitted.
+    ///
+    /// # Returns
+    /// A new BlobReader that reads the requested range.
+    ///
+    /// # Errors
+    /// This function errors if the blob does not exist or could not be opened or seeked.
+    pub fn open_range(&self, hash: &BlobHash, offset: u64, length: Option<u64>) -> Result<BlobReader, Error> {
+        // Open the blob
+        let (mut handle, size): (File, u64) = self.open(hash)?;
+
+        // Clip the range
+        let offset: u64 = std::cmp::min(offset, size);
+        let length: u64 = std::cmp::min(length.unwrap_or(u64::MAX), size - offset);
+
+        // Move to the start of the range
+        if let Err(err) = handle.seek(SeekFrom::Start(offset)) { return Err(Error::BlobSeekError{ path: self.path_of(hash), err }); }
+
+        // Done
+        Ok(BlobReader {
+            size,
+            offset,
+            length,
+            reader : handle.take(length),
+        })
+    }
+
+
+
+    /// Moves the given file into the storage as the given blob.
+    ///
+    /// The file must already be verified to have the given hash, and should live on the same filesystem as the storage. If the blob already exists, the file is simply removed instead. Either way, the blob is touched afterwards.
+    ///
+    /// # Arguments
+    /// - `hash`: The hash of the blob.
+    /// - `path`: The file that contains the blob.
+    ///
+    /// # Errors
+    /// This function errors if we failed to move or remove the file.
+    pub fn insert_file(&self, hash: &BlobHash, path: &Path) -> Result<(), Error> {
+        let target: PathBuf = self.path_of(hash);
+
+        // If we already have it, we can get rid of the new one
+        if target.is_file() {
+            if let Err(err) = fs::remove_file(path) { return Err(Error::BlobInsertError{ path: path.into(), err }); }
+            return self.touch(hash);
+        }
+
+        // Otherwise, move it in place
+        if let Some(parent) = target.parent() {
+            if let Err(err) = fs::create_dir_all(parent) { return Err(Error::DirCreateError{ path: parent.into(), err }); }
+        }
+        if let Err(err) = fs::rename(path, &target) { return Err(Error::BlobInsertError{ path: path.into(), err }); }
+        self.touch(hash)
+    }
+
+    /// Marks the given blob as being used right now, which protects it from the garbage collector for a while.
+    ///
+    /// # Errors
+    /// This function errors if the blob does not exist or we failed to update its modification time.
+    pub fn touch(&self, hash: &BlobHash) -> Result<(), Error> {
+        let path: PathBuf = self.path_of(hash);
+        let handle: File = match File::open(&path) {
+            Ok(handle) => handle,
+            Err(err)   => {
+                if err.kind() == std::io::ErrorKind::NotFound { return Err(Error::BlobNotFound{ hash: *hash }); }
+                return Err(Error::BlobOpenError{ path, err });
+            },
+        };
+        if let Err(err) = handle.set_modified(SystemTime::now()) { return Err(Error::BlobTouchError{ path, err }); }
+        Ok(())
+    }
+
+    /// Removes the given blob from the storage.
+    ///
+    /// # Errors
+    /// This function errors if we failed to remove the blob.
+    pub fn remove(&self, hash: &BlobHash) -> Result<(), Error> {
+        let path: PathBuf = self.path_of(hash);
+        if let Err(err) = fs::remove_file(&path) { return Err(Error::BlobRemoveError{ path, err }); }
+
+        // Clean up its directory if it's the last one there
+        if let Some(parent) = path.parent() { if fs::remove_dir(parent).is_ok() { debug!("Removed empty storage directory '{}'", parent.display()); } }
+        Ok(())
+    }
+
+    /// Moves the given blob out of the storage, into the given quarantine directory.
+    ///
+    /// # Arguments
+    /// - `hash`: The hash of the blob to quarantine.
+    /// - `dir`: The quarantine directory to move it to. Should be on the same filesystem as the storage.
+    ///
+    /// # Returns
+    /// The path the blob was moved to.
+    ///
+    /// # Errors
+    /// This function errors if we failed to create the quarantine directory or to move the blob.
+    pub fn quarantine(&self, hash: &BlobHash, dir: &Path) -> Result<PathBuf, Error> {
+        if let Err(err) = fs::create_dir_all(dir) { return Err(Error::DirCreateError{ path: dir.into(), err }); }
+        let path: PathBuf = self.path_of(hash);
+        let target: PathBuf = dir.join(hash.to_string());
+        if let Err(err) = fs::rename(&path, &target) { return Err(Error::QuarantineError{ path, err }); }
+        Ok(target)
+    }
+
+    /// Lists all blobs in the storage.
+    ///
+    /// Files that are not named after a hash are ignored.
+    ///
+    /// # Returns
+    /// A BlobInfo for every blob.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the storage directories or the metadata of a blob.
+    pub fn list(&self) -> Result<Vec<BlobInfo>, Error> {
+        let mut blobs: Vec<BlobInfo> = Vec::new();
+        for dir in read_dir(&self.root)? {
+            if !dir.is_dir() { continue; }
+            for path in read_dir(&dir)? {
+                let hash: BlobHash = match path.file_name().and_then(|n| n.to_str()).map(BlobHash::from_str) {
+                    Some(Ok(hash)) => hash,
+                    _              => { debug!("Ignoring non-blob file '{}' in storage", path.display()); continue; },
+                };
+                let metadata = match fs::metadata(&path) {
+                    Ok(metadata) => metadata,
+                    Err(err)     => { return Err(Error::BlobMetadataError{ path, err }); }
+                };
+                let modified: SystemTime = match metadata.modified() {
+                    Ok(modified) => modified,
+                    Err(err)     => { return Err(Error::BlobMetadataError{ path, err }); }
+                };
+                blobs.push(BlobInfo{ hash, size: metadata.len(), modified });
+            }
+        }
+        Ok(blobs)
+    }
+}
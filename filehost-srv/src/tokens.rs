@@ -0,0 +1,127 @@
+/* TOKENS.rs
+ *   by Lut99
+ *
+ * Created:
+ *   19 Oct 2026, 05:39:27
+ * Last edited:
+ *   19 Oct 2026, 05:46:12
+ * Auto updated?
+ *   Yes
+ *
+ * Description:
+ *   Implements the API tokens with which users may log in without a client
+ *   certificate. A token looks like `fht_<id>.<secret>`; only the hash of
+ *   the secret is stored in the users database.
+**/
+
+use chrono::{Duration, Utc};
+use log::debug;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use filehost_spc::login::{Permissions, TOKEN_PREFIX};
+
+pub use crate::errors::UserError as Error;
+use crate::users::{Token, User, Users};
+
+
+/***** CONSTANTS *****/
+/// The number of random bytes in the public identifier of a token.
+const ID_BYTES: usize = 8;
+/// The number of random bytes in the secret part of a token.
+const SECRET_BYTES: usize = 32;
+
+/// The longest time (in seconds) that a token may remain valid.
+pub const MAX_VALID_FOR: u64 = 2 * 365 * 24 * 60 * 60;
+
+
+
+
+
+/***** LIBRARY *****/
+/// Generates a new random identifier and secret, hex-encoded.
+///
+/// Invite codes use the same `<prefix><id>.<secret>` layout as tokens, so they share this.
+pub fn generate_secret() -> (String, String) {
+    let mut id: [u8; ID_BYTES] = [ 0; ID_BYTES ];
+    let mut secret: [u8; SECRET_BYTES] = [ 0; SECRET_BYTES ];
+    rand::thread_rng().fill_bytes(&mut id);
+    rand::thread_rng().fill_bytes(&mut secret);
+    (hex::encode(id), hex::encode(secret))
+}
+
+/// Splits a token (or invite code) in its identifier and secret.
+///
+/// # Returns
+/// The identifier and the secret, or None if the token does not start with the given prefix or is otherwise malformed.
+#[inline]
+pub fn split_secret<'r>(prefix: &str, raw: &'r str) -> Option<(&'r str, &'r str)> { raw.strip_prefix(prefix).and_then(|rest| rest.split_once('.')) }
+
+/// Hashes the secret part of a token (or invite code) the way it is stored in the users database.
+#[inline]
+pub fn hash_secret(secret: &str) -> String { hex::encode(Sha256::digest(secret.as_bytes())) }
+
+
+
+/// Creates a new API token for the given user and stores it in the users database.
+///
+/// # Arguments
+/// - `users`: The users database to store the token in.
+/// - `user`: The user that will own the token.
+/// - `permissions`: The permissions that sessions logged in with the token are limited to.
+/// - `valid_for`: The number of seconds the token remains valid. Must not exceed `MAX_VALID_FOR`.
+/// - `description`: What the token is used for.
+///
+/// # Returns
+/// The stored token, together with the full token to give to the user. The latter cannot be retrieved again.
+///
+/// # Errors
+/// This function errors if we failed to update the users database.
+pub fn create(users: &dyn Users, user: &User, permissions: Permissions, valid_for: u64, description: impl Into<String>) -> Result<(Token, String), Error> {
+    let (id, secret): (String, String) = generate_secret();
+    let created = Utc::now();
+    let token = Token {
+        id          : id.clone(),
+        user        : user.id,
+        hash        : hash_secret(&secret),
+        permissions,
+        description : description.into(),
+        created,
+        expires     : created + Duration::seconds(valid_for.min(MAX_VALID_FOR) as i64),
+    };
+    users.insert_token(&token)?;
+    Ok((token, format!("{}{}.{}", TOKEN_PREFIX, id, secret)))
+}
+
+/// Finds the user that the given API token logs in as.
+///
+/// # Arguments
+/// - `users`: The users database to find the token in.
+/// - `raw`: The full token, as given by the client.
+///
+/// # Returns
+/// The user that owns the token together with the token itself, or None if the token is malformed, unknown, wrong or expired.
+///
+/// # Errors
+/// This function errors if we failed to read the users database.
+pub fn authenticate(users: &dyn Users, raw: &str) -> Result<Option<(User, Token)>, Error> {
+    // Split the token in its parts
+    let (id, secret): (&str, &str) = match split_secret(TOKEN_PREFIX, raw) {
+        Some(parts) => parts,
+        None        => { debug!("API token is malformed"); return Ok(None); },
+    };
+
+    // Find it and check the secret; comparing hashes does not tell an attacker anything about the secret itself
+    let token: Token = match users.token(id)? {
+        Some(token) => token,
+        None        => { debug!("Unknown API token '{}'", id); return Ok(None); },
+    };
+    if hash_secret(secret) != token.hash { debug!("Wrong secret for API token '{}'", id); return Ok(None); }
+    if token.is_expired() { debug!("API token '{}' expired at {}", id, token.expires); return Ok(None); }
+
+    // Find its owner
+    match users.get(token.user)? {
+        Some(user) => Ok(Some((user, token))),
+        None       => { debug!("Owner of API token '{}' no longer exists", id); Ok(None) },
+    }
+}
@@ -4,7 +4,7 @@
  * Created:
  *   11 Jun 2022, 11:24:04
  * Last edited:
- *   11 Jun 2022, 15:02:01
+ *   19 Oct 2026, 07:03:36
  * Auto updated?
  *   Yes
  *
@@ -13,21 +13,41 @@
 **/
 
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Write};
 use std::path::{Path, PathBuf};
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::sync::{Mutex, MutexGuard};
 
+use chrono::{DateTime, Utc};
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
 use serde::{Deserialize, Serialize};
 
-use filehost_spc::login::{GUEST_ID, ROOT_ID, Permissions, UserId};
+use filehost_spc::config::{Config, UserBackend};
+use filehost_spc::ctl_messages::{InviteInfo, RevocationInfo, RevocationKind};
+use filehost_spc::login::{GUEST_ID, ROOT_ID, Permissions, TokenInfo, UserId};
+use filehost_spc::manifest::is_valid_name;
+use filehost_spc::quota::Quota;
 
 pub use crate::errors::UserError as Error;
+use crate::database;
 
 
 /***** CONSTANTS *****/
 /// The permissions that we require on the users file.
 const USER_PERMISSIONS : [u8; 3] = [ 6, 0, 0 ];
+/// The columns we select to build a User from a row in the database.
+const USER_COLUMNS     : &str = "id, username, certs, permissions, max_bytes, max_versions, suspended, suspended_until";
+/// The columns we select to build a Token from a row in the database.
+const TOKEN_COLUMNS    : &str = "id, user, hash, permissions, description, created, expires";
+/// The columns we select to build an Invite from a row in the database.
+const INVITE_COLUMNS   : &str = "id, hash, permissions, groups, description, created, expires";
+/// The columns we select to build a Revocation from a row in the database.
+const REVOCATION_COLUMNS : &str = "value, kind, reason, revoked";
+/// The columns we select to build an IssuedCert from a row in the database.
+const ISSUED_COLUMNS     : &str = "serial, user, subject, issued, expires";
 
 
 
@@ -55,23 +75,174 @@ impl<T: PermissionsExt> PermissionsOctet for T {}
 
 
 
-/***** LIBRARY *****/
-/// A JSON struct that contains the global user database.
+/***** HELPER FUNCTIONS *****/
+/// Returns the scope of a user that is not limited in any way, for serde.
+#[inline]
+fn default_scope() -> Permissions { Permissions::ALL }
+
+/// Builds a User from a row in the `users` table that was selected with `USER_COLUMNS`.
+fn user_from_row(row: &Row) -> Result<User, rusqlite::Error> {
+    Ok(User {
+        id              : row.get::<_, i64>(0)? as UserId,
+        username        : row.get(1)?,
+        certs           : Some(PathBuf::from(row.get::<_, String>(2)?)).filter(|certs| !certs.as_os_str().is_empty()),
+        permissions     : Permissions::from(row.get::<_, u8>(3)?),
+        quota           : Quota{ max_bytes: row.get::<_, Option<i64>>(4)?.map(|b| b as u64), max_versions: row.get::<_, Option<i64>>(5)?.map(|v| v as usize) },
+        suspended       : row.get(6)?,
+        suspended_until : row.get::<_, Option<i64>>(7)?.and_then(|until| DateTime::from_timestamp(until, 0)),
+        scope           : Permissions::ALL,
+    })
+}
+
+/// Builds a Revocation from a row in the `revocations` table that was selected with `REVOCATION_COLUMNS`.
+fn revocation_from_row(row: &Row) -> Result<Revocation, rusqlite::Error> {
+    let kind: String = row.get(1)?;
+    Ok(Revocation {
+        value   : row.get(0)?,
+        kind    : serde_json::from_value(serde_json::Value::String(kind)).map_err(|err| rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(err)))?,
+        reason  : row.get(2)?,
+        revoked : DateTime::from_timestamp(row.get(3)?, 0).unwrap_or_default(),
+    })
+}
+
+/// Writes a Revocation to the `revocations` table, overwriting any revocation of the same certificate.
+fn write_revocation(tx: &Transaction, revocation: &Revocation) -> Result<(), rusqlite::Error> {
+    tx.execute(
+        "INSERT OR REPLACE INTO revocations (value, kind, reason, revoked) VALUES (?1, ?2, ?3, ?4)",
+        params![ revocation.value, revocation.kind.to_string(), revocation.reason, revocation.revoked.timestamp() ],
+    )?;
+    Ok(())
+}
+
+/// Builds an IssuedCert from a row in the `issued_certs` table that was selected with `ISSUED_COLUMNS`.
+fn issued_from_row(row: &Row) -> Result<IssuedCert, rusqlite::Error> {
+    Ok(IssuedCert {
+        serial  : row.get(0)?,
+        user    : row.get::<_, i64>(1)? as UserId,
+        subject : row.get(2)?,
+        issued  : DateTime::from_timestamp(row.get(3)?, 0).unwrap_or_default(),
+        expires : DateTime::from_timestamp(row.get(4)?, 0).unwrap_or_default(),
+    })
+}
+
+/// Writes an IssuedCert to the `issued_certs` table, overwriting any certificate with the same serial number.
+fn write_issued(tx: &Transaction, cert: &IssuedCert) -> Result<(), rusqlite::Error> {
+    tx.execute(
+        "INSERT OR REPLACE INTO issued_certs (serial, user, subject, issued, expires) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![ cert.serial, cert.user as i64, cert.subject, cert.issued.timestamp(), cert.expires.timestamp() ],
+    )?;
+    Ok(())
+}
+
+/// Builds a Token from a row in the `tokens` table that was selected with `TOKEN_COLUMNS`.
+fn token_from_row(row: &Row) -> Result<Token, rusqlite::Error> {
+    Ok(Token {
+        id          : row.get(0)?,
+        user        : row.get::<_, i64>(1)? as UserId,
+        hash        : row.get(2)?,
+        permissions : Permissions::from(row.get::<_, u8>(3)?),
+        description : row.get(4)?,
+        created     : DateTime::from_timestamp(row.get(5)?, 0).unwrap_or_default(),
+        expires     : DateTime::from_timestamp(row.get(6)?, 0).unwrap_or_default(),
+    })
+}
+
+/// Builds an Invite from a row in the `invites` table that was selected with `INVITE_COLUMNS`.
+fn invite_from_row(row: &Row) -> Result<Invite, rusqlite::Error> {
+    let groups: String = row.get(3)?;
+    Ok(Invite {
+        id          : row.get(0)?,
+        hash        : row.get(1)?,
+        permissions : Permissions::from(row.get::<_, u8>(2)?),
+        groups      : serde_json::from_str(&groups).map_err(|err| rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(err)))?,
+        description : row.get(4)?,
+        created     : DateTime::from_timestamp(row.get(5)?, 0).unwrap_or_default(),
+        expires     : DateTime::from_timestamp(row.get(6)?, 0).unwrap_or_default(),
+    })
+}
+
+/// Writes an Invite to the `invites` table.
+fn write_invite(tx: &Transaction, invite: &Invite) -> Result<(), rusqlite::Error> {
+    let groups: String = serde_json::to_string(&invite.groups).map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+    tx.execute(
+        "INSERT INTO invites (id, hash, permissions, groups, description, created, expires) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![ invite.id, invite.hash, u8::from(invite.permissions), groups, invite.description, invite.created.timestamp(), invite.expires.timestamp() ],
+    )?;
+    Ok(())
+}
+
+/// Writes a Token to the `tokens` table.
+fn write_token(tx: &Transaction, token: &Token) -> Result<(), rusqlite::Error> {
+    tx.execute(
+        "INSERT INTO tokens (id, user, hash, permissions, description, created, expires) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![ token.id, token.user as i64, token.hash, u8::from(token.permissions), token.description, token.created.timestamp(), token.expires.timestamp() ],
+    )?;
+    Ok(())
+}
+
+/// Writes a Group and its members to the database, overwriting any group with the same name.
+fn write_group(tx: &Transaction, group: &Group) -> Result<(), rusqlite::Error> {
+    tx.execute("DELETE FROM groups WHERE name = ?1", params![ group.name ])?;
+    tx.execute("INSERT INTO groups (name, permissions) VALUES (?1, ?2)", params![ group.name, u8::from(group.permissions) ])?;
+    for id in &group.members {
+        tx.execute("INSERT OR IGNORE INTO group_members (name, user) VALUES (?1, ?2)", params![ group.name, *id as i64 ])?;
+    }
+    Ok(())
+}
+
+/// Reads a group and its members from the database.
+fn read_group(conn: &Connection, name: &str) -> Result<Option<Group>, rusqlite::Error> {
+    let permissions: u8 = match conn.query_row("SELECT permissions FROM groups WHERE name = ?1", params![ name ], |row| row.get(0)).optional()? {
+        Some(permissions) => permissions,
+        None              => { return Ok(None); },
+    };
+    let mut stmt = conn.prepare("SELECT user FROM group_members WHERE name = ?1 ORDER BY user")?;
+    let members: Vec<UserId> = stmt.query_map(params![ name ], |row| row.get::<_, i64>(0).map(|id| id as UserId))?.collect::<Result<_, _>>()?;
+    Ok(Some(Group{ name: name.into(), permissions: Permissions::from(permissions), members }))
+}
+
+/// Writes a User to the `users` table, overwriting any user with the same ID.
+fn write_user(tx: &Transaction, user: &User) -> Result<(), rusqlite::Error> {
+    tx.execute(
+        "INSERT OR REPLACE INTO users (id, username, certs, permissions, max_bytes, max_versions, suspended, suspended_until) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![ user.id as i64, user.username, user.certs.as_deref().map(|certs| certs.to_string_lossy()).unwrap_or_default(), u8::from(user.permissions), user.quota.max_bytes.map(|b| b as i64), user.quota.max_versions.map(|v| v as i64), user.suspended, user.suspended_until.map(|until| until.timestamp()) ],
+    )?;
+    Ok(())
+}
+
+
+
+
+
+/***** HELPER TYPES *****/
+/// The contents of the JSON users file.
 #[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct Users {
+struct UsersFile {
     /// The highest UserId used.
     #[serde(skip)]
-    pub max_id : UserId,
+    max_id  : UserId,
     /// The list of Users.
-    pub users  : HashMap<UserId, User>,
+    users   : HashMap<UserId, User>,
+    /// The list of Groups.
+    #[serde(default)]
+    groups  : HashMap<String, Group>,
+    /// The list of API tokens.
+    #[serde(default)]
+    tokens  : HashMap<String, Token>,
+    /// The list of invite codes that have not been redeemed yet.
+    #[serde(default)]
+    invites : HashMap<String, Invite>,
+    /// The list of revoked certificates, by their fingerprint or serial number.
+    #[serde(default)]
+    revocations : HashMap<String, Revocation>,
+    /// The certificates that the certificate authority issued, by their serial number.
+    #[serde(default)]
+    issued      : HashMap<String, IssuedCert>,
 }
 
-impl Users {
-    /// Constructor for the Users database, which loads it from a file.
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        // Turn the Path-like into a Path
-        let path: &Path = path.as_ref();
-
+impl UsersFile {
+    /// Loads the users file, checking its permissions and that the reserved users are present.
+    fn from_file(path: &Path) -> Result<Self, Error> {
         // Try to open the file
         let handle = match File::open(path) {
             Ok(handle) => handle,
@@ -86,7 +257,7 @@ impl Users {
         if perms != USER_PERMISSIONS { return Err(Error::IncorrectPermissions{ path: path.into(), got: perms, expected: USER_PERMISSIONS }) }
 
         // If it _does_ check out, read with Serde
-        let mut res: Users = match serde_json::from_reader(BufReader::new(handle)) {
+        let mut res: UsersFile = match serde_json::from_reader(BufReader::new(handle)) {
             Ok(res)  => res,
             Err(err) => { return Err(Error::FileParseError{ path: path.into(), err }); }
         };
@@ -108,9 +279,747 @@ impl Users {
         if !root_found  { return Err(Error::ReservedUserError{ path: path.into(), id: ROOT_ID }); }
         if !guest_found { return Err(Error::ReservedUserError{ path: path.into(), id: GUEST_ID }); }
 
+        // Check the groups only have members we know
+        for (name, group) in &mut res.groups {
+            group.name = name.clone();
+            if let Some(id) = group.members.iter().find(|id| !res.users.contains_key(id)) { return Err(Error::UnknownMember{ path: path.into(), group: name.clone(), id: *id }); }
+        }
+
+        // Check the tokens only belong to users we know
+        for (id, token) in &mut res.tokens {
+            token.id = id.clone();
+            if !res.users.contains_key(&token.user) { return Err(Error::UnknownOwner{ path: path.into(), token: id.clone(), id: token.user }); }
+        }
+        for (id, invite) in &mut res.invites { invite.id = id.clone(); }
+        for (value, revocation) in &mut res.revocations { revocation.value = value.clone(); }
+        for (serial, cert) in &mut res.issued { cert.serial = serial.clone(); }
+
         // DOne
         Ok(res)
     }
+
+    /// Returns the given group, or an error if it does not exist.
+    fn group_mut(&mut self, name: &str) -> Result<&mut Group, Error> {
+        self.groups.get_mut(name).ok_or_else(|| Error::UnknownGroup{ name: name.into() })
+    }
+
+    /// Writes the users file, replacing the old one in one go so a crash never leaves half a file behind.
+    fn to_file(&self, path: &Path) -> Result<(), Error> {
+        let raw: String = serde_json::to_string_pretty(self).map_err(|err| Error::SerializeError{ err })?;
+
+        // Write it next to the real file first, with the permissions we demand when loading it
+        let tmp: PathBuf = path.with_extension("json.tmp");
+        let mut handle: File = match OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&tmp) {
+            Ok(handle) => handle,
+            Err(err)   => { return Err(Error::FileWriteError{ path: tmp, err }); }
+        };
+        if let Err(err) = handle.write_all(raw.as_bytes()).and_then(|_| handle.sync_all()) { return Err(Error::FileWriteError{ path: tmp, err }); }
+        if let Err(err) = fs::rename(&tmp, path) { return Err(Error::FileWriteError{ path: path.into(), err }); }
+        Ok(())
+    }
+}
+
+
+
+
+
+/***** LIBRARY *****/
+/// Defines the interface to a database of users.
+///
+/// The daemon only ever talks to this trait, so it does not care whether the users live in a JSON file or in the metadata database.
+pub trait Users: Debug + Send + Sync {
+    /// Returns the user with the given ID.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn get(&self, id: UserId) -> Result<Option<User>, Error>;
+
+    /// Returns the user with the given username.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn find(&self, username: &str) -> Result<Option<User>, Error>;
+
+    /// Returns all users in the database.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn list(&self) -> Result<Vec<User>, Error>;
+
+    /// Adds a new user to the database, giving it the next free ID. The ID in the given user is ignored.
+    ///
+    /// # Returns
+    /// The ID of the new user.
+    ///
+    /// # Errors
+    /// This function errors if the username is already taken or if we failed to update the database.
+    fn insert(&self, user: User) -> Result<UserId, Error>;
+
+    /// Replaces the user with the same ID as the given one.
+    ///
+    /// # Errors
+    /// This function errors if the user does not exist, if its new username is already taken or if we failed to update the database.
+    fn update(&self, user: &User) -> Result<(), Error>;
+
+
+
+    /// Returns all groups in the database.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn groups(&self) -> Result<Vec<Group>, Error>;
+
+    /// Returns the group with the given name.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn group(&self, name: &str) -> Result<Option<Group>, Error>;
+
+    /// Sets the permissions of the given group, creating it without members if it does not exist yet.
+    ///
+    /// # Returns
+    /// The group as it is now.
+    ///
+    /// # Errors
+    /// This function errors if the name is illegal or if we failed to update the database.
+    fn set_group(&self, name: &str, permissions: Permissions) -> Result<Group, Error>;
+
+    /// Removes the given group. Its members lose the permissions they got from it.
+    ///
+    /// # Returns
+    /// The group as it was.
+    ///
+    /// # Errors
+    /// This function errors if the group does not exist or if we failed to update the database.
+    fn remove_group(&self, name: &str) -> Result<Group, Error>;
+
+    /// Adds the given user to the given group. Does nothing if it already is a member.
+    ///
+    /// # Returns
+    /// The group as it is now.
+    ///
+    /// # Errors
+    /// This function errors if the group or the user does not exist, or if we failed to update the database.
+    fn add_member(&self, name: &str, id: UserId) -> Result<Group, Error>;
+
+    /// Removes the given user from the given group. Does nothing if it isn't a member.
+    ///
+    /// # Returns
+    /// The group as it is now.
+    ///
+    /// # Errors
+    /// This function errors if the group does not exist or if we failed to update the database.
+    fn remove_member(&self, name: &str, id: UserId) -> Result<Group, Error>;
+
+    /// Returns the groups the given user is a member of.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn groups_of(&self, id: UserId) -> Result<Vec<Group>, Error> {
+        Ok(self.groups()?.into_iter().filter(|g| g.members.contains(&id)).collect())
+    }
+
+    /// Returns the permissions the given user effectively has, i.e., its own together with those of all its groups.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn effective_permissions(&self, user: &User) -> Result<Permissions, Error> {
+        let mut res: Permissions = user.permissions;
+        for group in self.groups_of(user.id)? { res |= group.permissions; }
+        Ok(res)
+    }
+
+
+
+    /// Returns the API token with the given identifier.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn token(&self, id: &str) -> Result<Option<Token>, Error>;
+
+    /// Returns the API tokens of the given user, oldest first.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn tokens_of(&self, user: UserId) -> Result<Vec<Token>, Error>;
+
+    /// Adds a new API token to the database.
+    ///
+    /// # Errors
+    /// This function errors if the user that owns it does not exist or if we failed to update the database.
+    fn insert_token(&self, token: &Token) -> Result<(), Error>;
+
+    /// Removes the given API token, after which it can no longer be used to log in.
+    ///
+    /// # Returns
+    /// The token as it was.
+    ///
+    /// # Errors
+    /// This function errors if the token does not exist or if we failed to update the database.
+    fn remove_token(&self, id: &str) -> Result<Token, Error>;
+
+
+
+    /// Returns all invite codes that have not been redeemed yet, oldest first.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn invites(&self) -> Result<Vec<Invite>, Error>;
+
+    /// Returns the invite code with the given identifier.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn invite(&self, id: &str) -> Result<Option<Invite>, Error>;
+
+    /// Adds a new invite code to the database.
+    ///
+    /// # Errors
+    /// This function errors if we failed to update the database.
+    fn insert_invite(&self, invite: &Invite) -> Result<(), Error>;
+
+    /// Removes the given invite code, e.g., because it was redeemed or revoked.
+    ///
+    /// # Returns
+    /// The invite code as it was.
+    ///
+    /// # Errors
+    /// This function errors if the invite code does not exist or if we failed to update the database. The former also happens if someone else removed it first, so only one caller ever gets it.
+    fn remove_invite(&self, id: &str) -> Result<Invite, Error>;
+
+
+
+    /// Returns the revocation list, oldest first.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn revocations(&self) -> Result<Vec<Revocation>, Error>;
+
+    /// Adds a certificate to the revocation list, replacing the reason if it already is on it.
+    ///
+    /// # Errors
+    /// This function errors if we failed to update the database.
+    fn insert_revocation(&self, revocation: &Revocation) -> Result<(), Error>;
+
+    /// Takes a certificate off the revocation list.
+    ///
+    /// # Returns
+    /// The revocation as it was.
+    ///
+    /// # Errors
+    /// This function errors if the certificate is not on the list or if we failed to update the database.
+    fn remove_revocation(&self, value: &str) -> Result<Revocation, Error>;
+
+
+
+    /// Returns the certificates that the certificate authority issued, oldest first.
+    ///
+    /// # Errors
+    /// This function errors if we failed to read the database.
+    fn issued_certs(&self) -> Result<Vec<IssuedCert>, Error>;
+
+    /// Remembers that the certificate authority issued the given certificate.
+    ///
+    /// # Errors
+    /// This function errors if we failed to update the database.
+    fn insert_issued_cert(&self, cert: &IssuedCert) -> Result<(), Error>;
+}
+
+/// Opens the users database that the given config points to.
+///
+/// # Errors
+/// This function errors if the database could not be opened, or if it is missing one of the reserved users.
+pub fn open(config: &Config) -> Result<Box<dyn Users>, Error> {
+    match config.user_backend {
+        UserBackend::Json   => Ok(Box::new(JsonUsers::from_file(&config.user_db)?)),
+        UserBackend::Sqlite => Ok(Box::new(SqliteUsers::open(&database::database_path(&config.data_dir), &config.user_db)?)),
+    }
+}
+
+
+
+/// A users database that lives in a single JSON file, which is rewritten on every change.
+#[derive(Debug)]
+pub struct JsonUsers {
+    /// The path of the file.
+    path : PathBuf,
+    /// The contents of the file.
+    file : Mutex<UsersFile>,
+}
+
+impl JsonUsers {
+    /// Constructor for the JsonUsers, which loads them from a file.
+    ///
+    /// # Errors
+    /// This function errors if the file could not be read or parsed, if it has insecure permissions or if it is missing one of the reserved users.
+    pub fn from_file<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path: PathBuf = path.into();
+        let file: UsersFile = UsersFile::from_file(&path)?;
+        Ok(Self{ path, file: Mutex::new(file) })
+    }
+
+    /// Locks the contents of the file, ignoring whether another thread panicked while holding them.
+    #[inline]
+    fn file(&self) -> MutexGuard<'_, UsersFile> {
+        self.file.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Changes the contents of the file, which only take effect once they have been written to disk.
+    ///
+    /// # Errors
+    /// This function errors if the given closure errors or if we failed to write the file.
+    fn modify<T>(&self, change: impl FnOnce(&mut UsersFile) -> Result<T, Error>) -> Result<T, Error> {
+        let mut file: MutexGuard<UsersFile> = self.file();
+        let mut new: UsersFile = file.clone();
+        let res: T = change(&mut new)?;
+        new.to_file(&self.path)?;
+        *file = new;
+        Ok(res)
+    }
+}
+
+impl Users for JsonUsers {
+    #[inline]
+    fn get(&self, id: UserId) -> Result<Option<User>, Error> { Ok(self.file().users.get(&id).cloned()) }
+
+    #[inline]
+    fn find(&self, username: &str) -> Result<Option<User>, Error> { Ok(self.file().users.values().find(|u| u.username == username).cloned()) }
+
+    #[inline]
+    fn list(&self) -> Result<Vec<User>, Error> { Ok(self.file().users.values().cloned().collect()) }
+
+    fn insert(&self, mut user: User) -> Result<UserId, Error> {
+        self.modify(|file| {
+            if file.users.values().any(|u| u.username == user.username) { return Err(Error::UsernameTaken{ username: user.username }); }
+            user.id = file.max_id + 1;
+            file.max_id = user.id;
+            file.users.insert(user.id, user);
+            Ok(file.max_id)
+        })
+    }
+
+    fn update(&self, user: &User) -> Result<(), Error> {
+        self.modify(|file| {
+            if !file.users.contains_key(&user.id) { return Err(Error::UnknownUser{ id: user.id }); }
+            if file.users.values().any(|u| u.id != user.id && u.username == user.username) { return Err(Error::UsernameTaken{ username: user.username.clone() }); }
+            file.users.insert(user.id, user.clone());
+            Ok(())
+        })
+    }
+
+
+
+    fn groups(&self) -> Result<Vec<Group>, Error> {
+        let mut groups: Vec<Group> = self.file().groups.values().cloned().collect();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(groups)
+    }
+
+    #[inline]
+    fn group(&self, name: &str) -> Result<Option<Group>, Error> { Ok(self.file().groups.get(name).cloned()) }
+
+    fn set_group(&self, name: &str, permissions: Permissions) -> Result<Group, Error> {
+        if !is_valid_name(name) { return Err(Error::IllegalGroupName{ name: name.into() }); }
+        self.modify(|file| {
+            let group: &mut Group = file.groups.entry(name.into()).or_insert_with(|| Group{ name: name.into(), permissions, members: vec![] });
+            group.permissions = permissions;
+            Ok(group.clone())
+        })
+    }
+
+    fn remove_group(&self, name: &str) -> Result<Group, Error> {
+        self.modify(|file| file.groups.remove(name).ok_or_else(|| Error::UnknownGroup{ name: name.into() }))
+    }
+
+    fn add_member(&self, name: &str, id: UserId) -> Result<Group, Error> {
+        self.modify(|file| {
+            if !file.users.contains_key(&id) { return Err(Error::UnknownUser{ id }); }
+            let group: &mut Group = file.group_mut(name)?;
+            if !group.members.contains(&id) { group.members.push(id); }
+            Ok(group.clone())
+        })
+    }
+
+    fn remove_member(&self, name: &str, id: UserId) -> Result<Group, Error> {
+        self.modify(|file| {
+            let group: &mut Group = file.group_mut(name)?;
+            group.members.retain(|m| *m != id);
+            Ok(group.clone())
+        })
+    }
+
+
+
+    #[inline]
+    fn token(&self, id: &str) -> Result<Option<Token>, Error> { Ok(self.file().tokens.get(id).cloned()) }
+
+    fn tokens_of(&self, user: UserId) -> Result<Vec<Token>, Error> {
+        let mut tokens: Vec<Token> = self.file().tokens.values().filter(|t| t.user == user).cloned().collect();
+        tokens.sort_by_key(|t| t.created);
+        Ok(tokens)
+    }
+
+    fn insert_token(&self, token: &Token) -> Result<(), Error> {
+        self.modify(|file| {
+            if !file.users.contains_key(&token.user) { return Err(Error::UnknownUser{ id: token.user }); }
+            file.tokens.insert(token.id.clone(), token.clone());
+            Ok(())
+        })
+    }
+
+    fn remove_token(&self, id: &str) -> Result<Token, Error> {
+        self.modify(|file| file.tokens.remove(id).ok_or_else(|| Error::UnknownToken{ id: id.into() }))
+    }
+
+
+
+    fn invites(&self) -> Result<Vec<Invite>, Error> {
+        let mut invites: Vec<Invite> = self.file().invites.values().cloned().collect();
+        invites.sort_by_key(|i| i.created);
+        Ok(invites)
+    }
+
+    #[inline]
+    fn invite(&self, id: &str) -> Result<Option<Invite>, Error> { Ok(self.file().invites.get(id).cloned()) }
+
+    fn insert_invite(&self, invite: &Invite) -> Result<(), Error> {
+        self.modify(|file| {
+            file.invites.insert(invite.id.clone(), invite.clone());
+            Ok(())
+        })
+    }
+
+    fn remove_invite(&self, id: &str) -> Result<Invite, Error> {
+        self.modify(|file| file.invites.remove(id).ok_or_else(|| Error::UnknownInvite{ id: id.into() }))
+    }
+
+
+
+    fn revocations(&self) -> Result<Vec<Revocation>, Error> {
+        let mut revocations: Vec<Revocation> = self.file().revocations.values().cloned().collect();
+        revocations.sort_by_key(|r| r.revoked);
+        Ok(revocations)
+    }
+
+    fn insert_revocation(&self, revocation: &Revocation) -> Result<(), Error> {
+        self.modify(|file| {
+            file.revocations.insert(revocation.value.clone(), revocation.clone());
+            Ok(())
+        })
+    }
+
+    fn remove_revocation(&self, value: &str) -> Result<Revocation, Error> {
+        self.modify(|file| file.revocations.remove(value).ok_or_else(|| Error::UnknownRevocation{ value: value.into() }))
+    }
+
+
+
+    fn issued_certs(&self) -> Result<Vec<IssuedCert>, Error> {
+        let mut certs: Vec<IssuedCert> = self.file().issued.values().cloned().collect();
+        certs.sort_by_key(|c| c.issued);
+        Ok(certs)
+    }
+
+    fn insert_issued_cert(&self, cert: &IssuedCert) -> Result<(), Error> {
+        self.modify(|file| {
+            file.issued.insert(cert.serial.clone(), cert.clone());
+            Ok(())
+        })
+    }
+}
+
+
+
+/// A users database that lives in the SQLite metadata database.
+#[derive(Debug)]
+pub struct SqliteUsers {
+    /// The connection to the database.
+    conn : Mutex<Connection>,
+}
+
+impl SqliteUsers {
+    /// Opens the users in the metadata database.
+    ///
+    /// If the database has no users yet, they are imported from the given JSON users file once. After that, the file is no longer used.
+    ///
+    /// # Arguments
+    /// - `path`: The path of the metadata database.
+    /// - `legacy`: The path of the JSON users file to import from.
+    ///
+    /// # Errors
+    /// This function errors if the database could not be opened, if the import failed or if the database is missing one of the reserved users.
+    pub fn open(path: &Path, legacy: &Path) -> Result<Self, Error> {
+        let mut conn: Connection = database::open(path).map_err(|err| Error::DatabaseError{ err })?;
+
+        // Import the users from the JSON file if we don't have any yet
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0)).map_err(|err| Error::QueryError{ err })?;
+        if count == 0 && legacy.exists() {
+            let file: UsersFile = UsersFile::from_file(legacy).map_err(|err| Error::ImportError{ path: legacy.into(), err: Box::new(err) })?;
+            let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+            for user in file.users.values() {
+                write_user(&tx, user).map_err(|err| Error::ImportError{ path: legacy.into(), err: Box::new(Error::QueryError{ err }) })?;
+            }
+            for group in file.groups.values() {
+                write_group(&tx, group).map_err(|err| Error::ImportError{ path: legacy.into(), err: Box::new(Error::QueryError{ err }) })?;
+            }
+            for token in file.tokens.values() {
+                write_token(&tx, token).map_err(|err| Error::ImportError{ path: legacy.into(), err: Box::new(Error::QueryError{ err }) })?;
+            }
+            for invite in file.invites.values() {
+                write_invite(&tx, invite).map_err(|err| Error::ImportError{ path: legacy.into(), err: Box::new(Error::QueryError{ err }) })?;
+            }
+            for revocation in file.revocations.values() {
+                write_revocation(&tx, revocation).map_err(|err| Error::ImportError{ path: legacy.into(), err: Box::new(Error::QueryError{ err }) })?;
+            }
+            for cert in file.issued.values() {
+                write_issued(&tx, cert).map_err(|err| Error::ImportError{ path: legacy.into(), err: Box::new(Error::QueryError{ err }) })?;
+            }
+            tx.commit().map_err(|err| Error::QueryError{ err })?;
+            info!("Imported {} user(s), {} group(s) and {} API token(s) from '{}' into metadata database '{}'; the file is no longer used", file.users.len(), file.groups.len(), file.tokens.len(), legacy.display(), path.display());
+        }
+
+        // Make sure the reserved users exist
+        let res = Self{ conn: Mutex::new(conn) };
+        for id in [ ROOT_ID, GUEST_ID ] {
+            if res.get(id)?.is_none() { return Err(Error::ReservedUserError{ path: path.into(), id }); }
+        }
+        Ok(res)
+    }
+
+    /// Locks the connection to the database, ignoring whether another thread panicked while holding it.
+    #[inline]
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Returns the ID of the user with the given username, if any.
+    fn id_of(conn: &Connection, username: &str) -> Result<Option<UserId>, Error> {
+        conn.query_row("SELECT id FROM users WHERE username = ?1", params![ username ], |row| row.get::<_, i64>(0))
+            .optional()
+            .map(|id| id.map(|id| id as UserId))
+            .map_err(|err| Error::QueryError{ err })
+    }
+}
+
+impl Users for SqliteUsers {
+    fn get(&self, id: UserId) -> Result<Option<User>, Error> {
+        self.conn().query_row(&format!("SELECT {} FROM users WHERE id = ?1", USER_COLUMNS), params![ id as i64 ], user_from_row)
+            .optional()
+            .map_err(|err| Error::QueryError{ err })
+    }
+
+    fn find(&self, username: &str) -> Result<Option<User>, Error> {
+        self.conn().query_row(&format!("SELECT {} FROM users WHERE username = ?1", USER_COLUMNS), params![ username ], user_from_row)
+            .optional()
+            .map_err(|err| Error::QueryError{ err })
+    }
+
+    fn list(&self) -> Result<Vec<User>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM users ORDER BY id", USER_COLUMNS)).map_err(|err| Error::QueryError{ err })?;
+        let rows = stmt.query_map([], user_from_row).map_err(|err| Error::QueryError{ err })?;
+        rows.collect::<Result<Vec<User>, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+    fn insert(&self, mut user: User) -> Result<UserId, Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        if Self::id_of(&tx, &user.username)?.is_some() { return Err(Error::UsernameTaken{ username: user.username }); }
+
+        let max_id: i64 = tx.query_row("SELECT COALESCE(MAX(id), 0) FROM users", [], |row| row.get(0)).map_err(|err| Error::QueryError{ err })?;
+        user.id = max_id as UserId + 1;
+        write_user(&tx, &user).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })?;
+        Ok(user.id)
+    }
+
+    fn update(&self, user: &User) -> Result<(), Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        let exists: bool = tx.query_row("SELECT 1 FROM users WHERE id = ?1", params![ user.id as i64 ], |_| Ok(())).optional().map_err(|err| Error::QueryError{ err })?.is_some();
+        if !exists { return Err(Error::UnknownUser{ id: user.id }); }
+        if Self::id_of(&tx, &user.username)?.map(|id| id != user.id).unwrap_or(false) { return Err(Error::UsernameTaken{ username: user.username.clone() }); }
+
+        write_user(&tx, user).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })
+    }
+
+
+
+    fn groups(&self) -> Result<Vec<Group>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare("SELECT name FROM groups ORDER BY name").map_err(|err| Error::QueryError{ err })?;
+        let names: Vec<String> = stmt.query_map([], |row| row.get(0)).and_then(|rows| rows.collect()).map_err(|err| Error::QueryError{ err })?;
+        names.iter().filter_map(|name| read_group(&conn, name).transpose()).collect::<Result<_, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+    #[inline]
+    fn group(&self, name: &str) -> Result<Option<Group>, Error> { read_group(&self.conn(), name).map_err(|err| Error::QueryError{ err }) }
+
+    fn set_group(&self, name: &str, permissions: Permissions) -> Result<Group, Error> {
+        if !is_valid_name(name) { return Err(Error::IllegalGroupName{ name: name.into() }); }
+        let conn: MutexGuard<Connection> = self.conn();
+        conn.execute(
+            "INSERT INTO groups (name, permissions) VALUES (?1, ?2) ON CONFLICT (name) DO UPDATE SET permissions = excluded.permissions",
+            params![ name, u8::from(permissions) ],
+        ).map_err(|err| Error::QueryError{ err })?;
+        read_group(&conn, name).map_err(|err| Error::QueryError{ err })?.ok_or_else(|| Error::UnknownGroup{ name: name.into() })
+    }
+
+    fn remove_group(&self, name: &str) -> Result<Group, Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        let group: Group = read_group(&tx, name).map_err(|err| Error::QueryError{ err })?.ok_or_else(|| Error::UnknownGroup{ name: name.into() })?;
+        tx.execute("DELETE FROM groups WHERE name = ?1", params![ name ]).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })?;
+        Ok(group)
+    }
+
+    fn add_member(&self, name: &str, id: UserId) -> Result<Group, Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        if read_group(&tx, name).map_err(|err| Error::QueryError{ err })?.is_none() { return Err(Error::UnknownGroup{ name: name.into() }); }
+        let exists: bool = tx.query_row("SELECT 1 FROM users WHERE id = ?1", params![ id as i64 ], |_| Ok(())).optional().map_err(|err| Error::QueryError{ err })?.is_some();
+        if !exists { return Err(Error::UnknownUser{ id }); }
+
+        tx.execute("INSERT OR IGNORE INTO group_members (name, user) VALUES (?1, ?2)", params![ name, id as i64 ]).map_err(|err| Error::QueryError{ err })?;
+        let group: Option<Group> = read_group(&tx, name).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })?;
+        group.ok_or_else(|| Error::UnknownGroup{ name: name.into() })
+    }
+
+    fn remove_member(&self, name: &str, id: UserId) -> Result<Group, Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        if read_group(&tx, name).map_err(|err| Error::QueryError{ err })?.is_none() { return Err(Error::UnknownGroup{ name: name.into() }); }
+
+        tx.execute("DELETE FROM group_members WHERE name = ?1 AND user = ?2", params![ name, id as i64 ]).map_err(|err| Error::QueryError{ err })?;
+        let group: Option<Group> = read_group(&tx, name).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })?;
+        group.ok_or_else(|| Error::UnknownGroup{ name: name.into() })
+    }
+
+    fn groups_of(&self, id: UserId) -> Result<Vec<Group>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare("SELECT name FROM group_members WHERE user = ?1 ORDER BY name").map_err(|err| Error::QueryError{ err })?;
+        let names: Vec<String> = stmt.query_map(params![ id as i64 ], |row| row.get(0)).and_then(|rows| rows.collect()).map_err(|err| Error::QueryError{ err })?;
+        names.iter().filter_map(|name| read_group(&conn, name).transpose()).collect::<Result<_, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+
+
+    fn token(&self, id: &str) -> Result<Option<Token>, Error> {
+        self.conn().query_row(&format!("SELECT {} FROM tokens WHERE id = ?1", TOKEN_COLUMNS), params![ id ], token_from_row)
+            .optional()
+            .map_err(|err| Error::QueryError{ err })
+    }
+
+    fn tokens_of(&self, user: UserId) -> Result<Vec<Token>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM tokens WHERE user = ?1 ORDER BY created, id", TOKEN_COLUMNS)).map_err(|err| Error::QueryError{ err })?;
+        let rows = stmt.query_map(params![ user as i64 ], token_from_row).map_err(|err| Error::QueryError{ err })?;
+        rows.collect::<Result<Vec<Token>, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+    fn insert_token(&self, token: &Token) -> Result<(), Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        let exists: bool = tx.query_row("SELECT 1 FROM users WHERE id = ?1", params![ token.user as i64 ], |_| Ok(())).optional().map_err(|err| Error::QueryError{ err })?.is_some();
+        if !exists { return Err(Error::UnknownUser{ id: token.user }); }
+
+        write_token(&tx, token).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })
+    }
+
+    fn remove_token(&self, id: &str) -> Result<Token, Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        let token: Token = tx.query_row(&format!("SELECT {} FROM tokens WHERE id = ?1", TOKEN_COLUMNS), params![ id ], token_from_row)
+            .optional()
+            .map_err(|err| Error::QueryError{ err })?
+            .ok_or_else(|| Error::UnknownToken{ id: id.into() })?;
+        tx.execute("DELETE FROM tokens WHERE id = ?1", params![ id ]).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })?;
+        Ok(token)
+    }
+
+
+
+    fn invites(&self) -> Result<Vec<Invite>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM invites ORDER BY created, id", INVITE_COLUMNS)).map_err(|err| Error::QueryError{ err })?;
+        let rows = stmt.query_map([], invite_from_row).map_err(|err| Error::QueryError{ err })?;
+        rows.collect::<Result<Vec<Invite>, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+    fn invite(&self, id: &str) -> Result<Option<Invite>, Error> {
+        self.conn().query_row(&format!("SELECT {} FROM invites WHERE id = ?1", INVITE_COLUMNS), params![ id ], invite_from_row)
+            .optional()
+            .map_err(|err| Error::QueryError{ err })
+    }
+
+    fn insert_invite(&self, invite: &Invite) -> Result<(), Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        write_invite(&tx, invite).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })
+    }
+
+    fn remove_invite(&self, id: &str) -> Result<Invite, Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        let invite: Invite = tx.query_row(&format!("SELECT {} FROM invites WHERE id = ?1", INVITE_COLUMNS), params![ id ], invite_from_row)
+            .optional()
+            .map_err(|err| Error::QueryError{ err })?
+            .ok_or_else(|| Error::UnknownInvite{ id: id.into() })?;
+        tx.execute("DELETE FROM invites WHERE id = ?1", params![ id ]).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })?;
+        Ok(invite)
+    }
+
+
+
+    fn revocations(&self) -> Result<Vec<Revocation>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM revocations ORDER BY revoked, value", REVOCATION_COLUMNS)).map_err(|err| Error::QueryError{ err })?;
+        let rows = stmt.query_map([], revocation_from_row).map_err(|err| Error::QueryError{ err })?;
+        rows.collect::<Result<Vec<Revocation>, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+    fn insert_revocation(&self, revocation: &Revocation) -> Result<(), Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        write_revocation(&tx, revocation).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })
+    }
+
+    fn remove_revocation(&self, value: &str) -> Result<Revocation, Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        let revocation: Revocation = tx.query_row(&format!("SELECT {} FROM revocations WHERE value = ?1", REVOCATION_COLUMNS), params![ value ], revocation_from_row)
+            .optional()
+            .map_err(|err| Error::QueryError{ err })?
+            .ok_or_else(|| Error::UnknownRevocation{ value: value.into() })?;
+        tx.execute("DELETE FROM revocations WHERE value = ?1", params![ value ]).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })?;
+        Ok(revocation)
+    }
+
+
+
+    fn issued_certs(&self) -> Result<Vec<IssuedCert>, Error> {
+        let conn: MutexGuard<Connection> = self.conn();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM issued_certs ORDER BY issued, serial", ISSUED_COLUMNS)).map_err(|err| Error::QueryError{ err })?;
+        let rows = stmt.query_map([], issued_from_row).map_err(|err| Error::QueryError{ err })?;
+        rows.collect::<Result<Vec<IssuedCert>, _>>().map_err(|err| Error::QueryError{ err })
+    }
+
+    fn insert_issued_cert(&self, cert: &IssuedCert) -> Result<(), Error> {
+        let mut conn: MutexGuard<Connection> = self.conn();
+        let tx: Transaction = conn.transaction().map_err(|err| Error::QueryError{ err })?;
+        write_issued(&tx, cert).map_err(|err| Error::QueryError{ err })?;
+        tx.commit().map_err(|err| Error::QueryError{ err })
+    }
 }
 
 
@@ -123,9 +1032,184 @@ pub struct User {
     pub id       : UserId,
     /// The username of the user. Must also be unique.
     pub username : String,
-    /// The public certficate(s) file of the user. Will be used to authenticate the connections.
-    pub certs    : PathBuf,
+    /// The public certficate(s) file of the user. Will be used to authenticate the connections. Users without one log in with certificates issued by the certificate authority.
+    #[serde(default)]
+    pub certs    : Option<PathBuf>,
 
     /// The permissions of this user.
     pub permissions : Permissions,
+    /// The limits on what this user may publish.
+    #[serde(default)]
+    pub quota       : Quota,
+
+    /// Whether the user is suspended, in which case it can't log in.
+    #[serde(default)]
+    pub suspended       : bool,
+    /// The moment the suspension of the user ends by itself, if it does.
+    #[serde(default)]
+    pub suspended_until : Option<DateTime<Utc>>,
+
+    /// Limits what the user may do in the current session, e.g., because it logged in with an API token. Never stored.
+    #[serde(skip, default = "default_scope")]
+    pub scope : Permissions,
+}
+
+impl User {
+    /// Returns whether the user is suspended right now.
+    #[inline]
+    pub fn is_suspended(&self) -> bool { self.suspended && self.suspended_until.map(|until| Utc::now() < until).unwrap_or(true) }
+}
+
+
+
+/// A JSON struct describing a group of users.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Group {
+    /// The name of the group. Must be unique.
+    #[serde(skip)]
+    pub name        : String,
+    /// The permissions that the members of this group get on top of their own.
+    pub permissions : Permissions,
+    /// The IDs of the members of this group.
+    #[serde(default)]
+    pub members     : Vec<UserId>,
+}
+
+
+
+
+/// A JSON struct describing an API token, with which a user may log in without a client certificate.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Token {
+    /// The public identifier of the token. Must be unique.
+    #[serde(skip)]
+    pub id          : String,
+    /// The ID of the user that owns the token.
+    pub user        : UserId,
+    /// The SHA-256 hash of the secret part of the token, hex-encoded.
+    pub hash        : String,
+    /// The permissions that a session logged in with the token is limited to.
+    pub permissions : Permissions,
+    /// What the token is used for, as given by its owner.
+    #[serde(default)]
+    pub description : String,
+    /// The moment the token was created.
+    pub created     : DateTime<Utc>,
+    /// The moment after which the token can no longer be used.
+    pub expires     : DateTime<Utc>,
+}
+
+impl Token {
+    /// Returns whether the token can no longer be used.
+    #[inline]
+    pub fn is_expired(&self) -> bool { Utc::now() > self.expires }
+
+    /// Describes the token without its hash.
+    ///
+    /// # Arguments
+    /// - `username`: The name of the user that owns the token.
+    pub fn info(&self, username: impl Into<String>) -> TokenInfo {
+        TokenInfo {
+            id          : self.id.clone(),
+            username    : username.into(),
+            permissions : self.permissions,
+            description : self.description.clone(),
+            created     : self.created,
+            expires     : self.expires,
+        }
+    }
+}
+
+
+
+
+/// A JSON struct describing an invite code, with which a new user can create its own account once.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Invite {
+    /// The public identifier of the code. Must be unique.
+    #[serde(skip)]
+    pub id          : String,
+    /// The SHA-256 hash of the secret part of the code, hex-encoded.
+    pub hash        : String,
+    /// The permissions the new user gets.
+    pub permissions : Permissions,
+    /// The names of the groups the new user is added to.
+    #[serde(default)]
+    pub groups      : Vec<String>,
+    /// Who or what the code is for, as given by the administrator.
+    #[serde(default)]
+    pub description : String,
+    /// The moment the code was created.
+    pub created     : DateTime<Utc>,
+    /// The moment after which the code can no longer be redeemed.
+    pub expires     : DateTime<Utc>,
+}
+
+impl Invite {
+    /// Returns whether the code can no longer be redeemed.
+    #[inline]
+    pub fn is_expired(&self) -> bool { Utc::now() > self.expires }
+
+    /// Describes the code without its hash.
+    pub fn info(&self) -> InviteInfo {
+        InviteInfo {
+            id          : self.id.clone(),
+            permissions : self.permissions,
+            groups      : self.groups.clone(),
+            description : self.description.clone(),
+            created     : self.created,
+            expires     : self.expires,
+        }
+    }
+}
+
+
+
+
+/// A JSON struct describing a certificate on the revocation list.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Revocation {
+    /// The (normalized) fingerprint or serial number of the certificate. Must be unique.
+    #[serde(skip)]
+    pub value   : String,
+    /// Whether the value is a fingerprint or a serial number.
+    pub kind    : RevocationKind,
+    /// Why the certificate was revoked, as given by the administrator.
+    #[serde(default)]
+    pub reason  : String,
+    /// The moment the certificate was revoked.
+    pub revoked : DateTime<Utc>,
+}
+
+impl Revocation {
+    /// Describes the revocation to the CTL.
+    pub fn info(&self) -> RevocationInfo {
+        RevocationInfo {
+            kind    : self.kind,
+            value   : self.value.clone(),
+            reason  : self.reason.clone(),
+            revoked : self.revoked,
+        }
+    }
+}
+
+
+
+
+/// A JSON struct describing a certificate that the certificate authority issued to a user.
+///
+/// The certificate itself is not stored, since it identifies its user by itself; this only lets us warn about it before it expires.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct IssuedCert {
+    /// The (normalized) serial number of the certificate. Must be unique.
+    #[serde(skip)]
+    pub serial  : String,
+    /// The ID of the user the certificate was issued to.
+    pub user    : UserId,
+    /// The subject of the certificate.
+    pub subject : String,
+    /// The moment the certificate was issued.
+    pub issued  : DateTime<Utc>,
+    /// The moment after which the certificate is no longer valid.
+    pub expires : DateTime<Utc>,
 }
@@ -42,6 +42,8 @@ SOCKET="/run/filehost/ctl.sock"
 CONFIG="/etc/filehost/config.json"
 # The default user database location
 USERDB="/etc/filehost/users.json"
+# The default data directory location
+DATA_DIR="/var/lib/filehost"
 
 
 
@@ -56,6 +58,7 @@ server_bin="$SERVER_BIN"
 socket="$SOCKET"
 config="$CONFIG"
 userdb="$USERDB"
+data_dir="$DATA_DIR"
 mode=""
 version="latest"
 
@@ -99,6 +102,10 @@ for arg in "$@"; do
                 # Get the next argument as the value
                 state="userdb"
 
+            elif [[ "$arg" == "-d" || "$arg" == "--data-dir" ]]; then
+                # Get the next argument as the value
+                state="data_dir"
+
             elif [[ "$arg" == '-v' || "$arg" == "--version" ]]; then
                 # Get the next argument as the value
                 state="version"
@@ -136,6 +143,9 @@ for arg in "$@"; do
                 echo "  -u,--userdb <path>"
                 echo "                    Determines the location of the user database file. Default:"
                 echo "                    '$USERDB'"
+                echo "  -d,--data-dir <path>"
+                echo "                    Determines the location of the directory where the packages"
+                echo "                    and their files are stored. Default: '$DATA_DIR'"
                 echo "     --server-cert <path>"
                 echo "                    If given, uses an existing "
                 echo "  -v,--version <version>"
@@ -283,6 +293,20 @@ for arg in "$@"; do
         # Reset the state
         state="start"
     
+    elif [[ "$state" == "data_dir" ]]; then
+        # Treat options first
+        if [[ accept_options -eq 1 && "$arg" =~ ^- ]]; then
+            echo "Missing value for '--data-dir'"
+            error=1
+            continue
+        fi
+
+        # Grab the value
+        data_dir="$arg"
+
+        # Reset the state
+        state="start"
+    
     elif [[ "$state" == "version" ]]; then
         # Treat options first
         if [[ accept_options -eq 1 && "$arg" =~ ^- ]]; then
@@ -345,6 +369,9 @@ elif [[ "$state" == "config" ]]; then
 elif [[ "$state" == "userdb" ]]; then
     echo "Missing value for '--userdb'"
     exit 1
+elif [[ "$state" == "data_dir" ]]; then
+    echo "Missing value for '--data-dir'"
+    exit 1
 elif [[ "$state" == "version" ]]; then
     echo "Missing value for '--version'"
     exit 1
@@ -391,6 +418,8 @@ if [[ "$mode" == "install" ]]; then
     mkdir -p "$(dirname "$socket")" || exit $?
     echo " > '$(dirname "$config")'..."
     mkdir -p "$(dirname "$config")" || exit $?
+    echo " > '$data_dir'..."
+    mkdir -p "$data_dir" || exit $?
 
     if [ -z "$local_ctl" ]; then
         echo "Downloading $REPOSITORY/$version/filehostctl to '$ctl_bin'..."
@@ -416,6 +445,7 @@ if [[ "$mode" == "install" ]]; then
     "user_db": "$userdb",
     "server_cert": "$cert",
     "server_key": "$key",
+    "data_dir": "$data_dir",
 
     "socket_path": "$socket",
     "listen_addr": "127.0.0.1:8719",
@@ -449,6 +479,8 @@ EOT
     chown "$(id -u "$USER"):$(id -g "$USER")" "$userdb"
     chmod 644 "$config"
     chmod 600 "$userdb"
+    chown -R "$(id -u "$USER"):$(id -g "$USER")" "$data_dir"
+    chmod 700 "$data_dir"
 
     echo "Generating systemd service entry to '$SERVICE_ENTRY'..."
     cat <<EOT > "$SERVICE_ENTRY"
@@ -500,10 +532,12 @@ else
     server_bin=$(python3 -c "import json; h = open(\"$config\", \"r\"); print(json.load(h)[\"locations\"][\"server\"]); h.close()") || exit $?
     socket=$(python3 -c "import json; h = open(\"$config\", \"r\"); print(json.load(h)[\"socket_path\"]); h.close()") || exit $?
     userdb=$(python3 -c "import json; h = open(\"$config\", \"r\"); print(json.load(h)[\"user_db\"]); h.close()") || exit $?
+    data_dir=$(python3 -c "import json; h = open(\"$config\", \"r\"); print(json.load(h)[\"data_dir\"]); h.close()") || exit $?
     echo " > CTL binary location: $ctl_bin"
     echo " > Server binary location: $server_bin"
     echo " > Socket folder: $socket"
     echo " > User database location: $userdb"
+    echo " > Data directory: $data_dir"
 
     echo "Removing server from systemd..."
     systemctl stop "$SERVICE_ENTRY_NAME"
@@ -537,6 +571,8 @@ else
     rm -rf "$(dirname "$config")"
     echo " > '$(dirname "$socket")'..."
     rm -rf "$(dirname "$socket")"
+    echo " > '$data_dir'..."
+    rm -rf "$data_dir"
 
     echo ""
     echo "Done."